@@ -0,0 +1,121 @@
+use parking_lot::Mutex;
+
+use base::currency::Currency;
+use arbitrer::{Event, EVENTS};
+
+struct State {
+    // The lowest accepted/requested proportion among placed legs.
+    ratio: f64,
+    // Requested and accepted stakes of placed legs.
+    placed: Vec<(String, Currency, Currency)>
+}
+
+// Bookies sometimes accept less than requested (e.g. a limit is reached). Legs which aren't
+// placed yet are reduced to the same proportion, and legs which are already placed in excess make
+// the combo unbalanced. It's reported once all legs are done. Note that legs are released at once
+// after the final barrier and usually all scale before any acceptance is recorded, so in practice
+// the reduction rarely applies and the report is what catches partial acceptance.
+pub struct Acceptance {
+    state: Mutex<State>
+}
+
+impl Acceptance {
+    pub fn new() -> Acceptance {
+        Acceptance {
+            state: Mutex::new(State {
+                ratio: 1.,
+                placed: Vec::new()
+            })
+        }
+    }
+
+    // The stake to be actually placed instead of the requested one.
+    pub fn scale(&self, stake: Currency) -> Currency {
+        let ratio = self.state.lock().ratio;
+
+        if ratio < 1. { stake * ratio } else { stake }
+    }
+
+    // Returns whether it's the first placed leg by the bookie in the combo.
+    pub fn record(&self, host: &str, requested: Currency, accepted: Currency) -> bool {
+        let mut state = self.state.lock();
+        let first = state.placed.iter().all(|leg| leg.0 != host);
+
+        if Currency(0) < accepted && accepted < requested {
+            let ratio = accepted.0 as f64 / requested.0 as f64;
+
+            if ratio < state.ratio {
+                state.ratio = ratio;
+            }
+        }
+
+        state.placed.push((host.to_owned(), requested, accepted));
+        first
+    }
+
+    // Legs placed in excess of the final proportion: `(host, accepted, expected)`.
+    pub fn excess(&self) -> Vec<(String, Currency, Currency)> {
+        let state = self.state.lock();
+
+        state.placed.iter()
+            .map(|&(ref host, requested, accepted)| (host, accepted, requested * state.ratio))
+            // Allow rounding errors.
+            .filter(|&(_, accepted, expected)| accepted - expected > Currency(1))
+            .map(|(host, accepted, expected)| (host.clone(), accepted, expected))
+            .collect()
+    }
+}
+
+impl Drop for Acceptance {
+    fn drop(&mut self) {
+        let excess = self.excess();
+
+        if excess.is_empty() {
+            return;
+        }
+
+        let legs = excess.iter()
+            .map(|&(ref host, accepted, expected)| {
+                format!("{} by {} instead of {}", accepted, host, expected)
+            })
+            .collect::<Vec<_>>();
+
+        let message = format!("The combo is unbalanced and must be fixed manually: {}",
+                              legs.join(", "));
+
+        error!("{}", message);
+        EVENTS.send(Event::Combo(message));
+    }
+}
+
+#[test]
+fn test_partial_acceptance() {
+    let acceptance = Acceptance::new();
+
+    acceptance.record("a.com", Currency(1000), Currency(1000));
+    acceptance.record("b.com", Currency(600), Currency(600));
+    assert_eq!(acceptance.scale(Currency(400)), Currency(400));
+    acceptance.record("c.com", Currency(400), Currency(400));
+    assert!(acceptance.excess().is_empty());
+
+    let acceptance = Acceptance::new();
+
+    // The first leg is placed in full before the second one is partially accepted.
+    acceptance.record("a.com", Currency(1000), Currency(1000));
+    acceptance.record("b.com", Currency(600), Currency(300));
+
+    // The last leg isn't placed yet, so it's reduced.
+    assert_eq!(acceptance.scale(Currency(400)), Currency(200));
+    acceptance.record("c.com", Currency(400), Currency(200));
+
+    assert_eq!(acceptance.excess(), vec![("a.com".to_owned(), Currency(1000), Currency(500))]);
+}
+
+#[test]
+fn test_first_leg_by_bookie() {
+    let acceptance = Acceptance::new();
+
+    assert!(acceptance.record("a.com", Currency(100), Currency(100)));
+    assert!(acceptance.record("b.com", Currency(100), Currency(100)));
+    assert!(!acceptance.record("a.com", Currency(100), Currency(100)));
+}
@@ -0,0 +1,58 @@
+use time;
+
+// Windows are `("HH:MM", "HH:MM")` in local time and may wrap midnight.
+pub fn is_now(windows: &[(&str, &str)]) -> bool {
+    let tm = time::now();
+    let minute = (tm.tm_hour * 60 + tm.tm_min) as u32;
+
+    contains(windows, minute)
+}
+
+fn contains(windows: &[(&str, &str)], minute: u32) -> bool {
+    windows.iter().any(|&(start, end)| {
+        match (parse_minute(start), parse_minute(end)) {
+            (Some(start), Some(end)) if start <= end => start <= minute && minute < end,
+            (Some(start), Some(end)) => start <= minute || minute < end,
+            _ => {
+                warn!("Invalid blackout window: {} - {}", start, end);
+                false
+            }
+        }
+    })
+}
+
+fn parse_minute(hhmm: &str) -> Option<u32> {
+    let mut parts = hhmm.split(':');
+
+    let hours = parts.next().and_then(|h| h.parse::<u32>().ok());
+    let minutes = parts.next().and_then(|m| m.parse::<u32>().ok());
+
+    match (hours, minutes, parts.next()) {
+        (Some(h), Some(m), None) if h < 24 && m < 60 => Some(h * 60 + m),
+        _ => None
+    }
+}
+
+#[test]
+fn test_contains() {
+    let windows = &[("02:00", "08:30"), ("22:00", "00:30")];
+
+    assert!(contains(windows, 2 * 60));
+    assert!(contains(windows, 8 * 60 + 29));
+    assert!(!contains(windows, 8 * 60 + 30));
+    assert!(contains(windows, 23 * 60));
+    assert!(contains(windows, 10));
+    assert!(!contains(windows, 12 * 60));
+    assert!(!contains(&[("25:00", "26:00")], 60));
+}
+
+#[test]
+fn test_is_now() {
+    let tm = time::now();
+    let minute = (tm.tm_hour * 60 + tm.tm_min) as u32;
+    let start = format!("{:02}:{:02}", minute / 60, minute % 60);
+    let end = format!("{:02}:{:02}", (minute + 2) / 60 % 24, (minute + 2) % 60);
+
+    assert!(is_now(&[(start.as_str(), end.as_str())]));
+    assert!(!is_now(&[]));
+}
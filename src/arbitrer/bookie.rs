@@ -1,22 +1,48 @@
 use std::cmp;
 use std::thread;
 use std::time::Duration;
-use std::sync::atomic::{AtomicIsize, AtomicUsize};
+use std::sync::atomic::{AtomicIsize, AtomicUsize, AtomicBool};
 use std::sync::atomic::Ordering::Relaxed;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::collections::hash_map::Entry;
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
+use regex::{Regex, Captures};
 use time;
 
-use constants::{MIN_RETRY_DELAY, MAX_RETRY_DELAY};
+use constants::{MIN_RETRY_DELAY, MAX_RETRY_DELAY, USE_FREE_BETS, BALANCE_AUDIT_SIZE};
+use constants::DEGRADATION_GRACE;
+use constants::{MAX_CONSECUTIVE_FAILURES, MAX_CONCURRENT_AUTHORIZATIONS};
+use constants::{BET_STATUS_INTERVAL, BET_STATUS_TIMEOUT, PERSIST_RAW_BETS};
+use constants::{ALERT_ON_OFFER_DROP, OFFER_DROP_WINDOW, OFFER_DROP_FRACTION, OFFER_DROP_MIN_PEAK};
+use constants::ALERT_WEBHOOK;
+use constants::{ALLOWED_OUTCOME_COUNTS, SUSPICIOUS_BALANCE, REAUTH_ON_SUSPICIOUS_BALANCE};
+use constants::{NO_DRAW_GAMES, SPURIOUS_DRAW, LIVE_MODES, DEFAULT_LIVE_MODE, MAX_TIME_TO_EVENT};
+use constants::{BALANCE_RESERVES, RESYNC_WINDOW};
+use constants::{SLOW_START_COMBOS, SLOW_START_DURATION, SLOW_START_OUTAGE};
 use base::currency::Currency;
-use arbitrer::matcher;
-use gamblers::{self, BoxedGambler, Message};
+use base::throughput::Throughput;
+use base::drop_alarm::DropAlarm;
+use base::webhook;
+use base::semaphore::Semaphore;
+use base::slow_start::SlowStart;
+use base::session::{self, Exchange};
+use base::clock::{BoxedClock, RealClock};
+use arbitrer::{matcher, Event, EVENTS};
+use gamblers::{self, BoxedGambler, Message, BetReceipt, OpenBet, Features, Placement};
 use gamblers::Message::*;
-use markets::{OID, Offer, Outcome};
+use markets::{OID, Offer, Outcome, OddsStyle, Game, GameSelector, SpuriousDraw, LiveMode, DRAW};
 
 use self::Stage::*;
 
+lazy_static! {
+    static ref AUTH_SLOTS: Option<Semaphore> = MAX_CONCURRENT_AUTHORIZATIONS.map(Semaphore::new);
+
+    // Values of credentials and tokens in forms, queries and JSON.
+    static ref SECRET_RE: Regex =
+        Regex::new(r#"(?i)("?\w*(?:password|secret|token|client_id)\w*"?\s*[:=]\s*"?)[^"&,}\s]+"#)
+            .unwrap();
+}
+
 /*                     Aborted
  *                    ↗     ↑
  * Initial → Preparing → Running
@@ -59,16 +85,52 @@ impl Into<isize> for Stage {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BalanceReason { Hold, Release, Refresh }
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BalanceChange {
+    pub date: u32,
+    pub reason: BalanceReason,
+    pub old: Currency,
+    pub new: Currency
+}
+
+#[derive(Debug)]
+pub enum Placed {
+    Confirmed(BetReceipt),
+    // The bookie hasn't confirmed the bet in time, it's resolved by the reconciliation.
+    Pending(BetReceipt),
+    // With the HTTP traffic of placing if `PERSIST_RAW_BETS` is enabled.
+    Failed(Option<(String, String)>)
+}
+
 pub struct Bookie {
     pub host: String,
     username: String,
     password: String,
     module: &'static str,
     gambler: BoxedGambler,
+    clock: BoxedClock,
+    odds_style: OddsStyle,
     stage: AtomicIsize,
     delay: AtomicUsize,
     balance: AtomicIsize,
-    offers: RwLock<HashMap<OID, Offer>>
+    reserve: Currency,
+    balance_audit: Mutex<VecDeque<BalanceChange>>,
+    free_balance: AtomicIsize,
+    throughput: Throughput,
+    offer_drop: DropAlarm,
+    slow_start: SlowStart,
+    last_failure: AtomicUsize,
+    failures: AtomicUsize,
+    connections: AtomicUsize,
+    // When offers kept over the reconnection have started to be reported again (in ms).
+    resync: AtomicUsize,
+    // When the bookie has reconnected at once last time.
+    reconnected: AtomicUsize,
+    stopping: AtomicBool,
+    offers: RwLock<HashMap<OID, (Offer, u64)>>
 }
 
 impl PartialEq for Bookie {
@@ -81,20 +143,51 @@ impl PartialEq for Bookie {
 impl Bookie {
     pub fn new(host: &str, username: &str, password: &str) -> Bookie {
         let (module, gambler) = gamblers::new(host);
+        Bookie::with_gambler(host, username, password, module, gambler, Box::new(RealClock))
+    }
 
+    pub fn with_gambler(host: &str, username: &str, password: &str,
+                        module: &'static str, gambler: BoxedGambler, clock: BoxedClock) -> Bookie
+    {
         Bookie {
             host: host.to_owned(),
             username: username.to_owned(),
             password: password.to_owned(),
             module: module,
             gambler: gambler,
+            clock: clock,
+            odds_style: gamblers::odds_style(host),
             stage: AtomicIsize::new(Initial.into()),
             delay: AtomicUsize::new(0),
             balance: AtomicIsize::new(0),
+            reserve: balance_reserve(host),
+            balance_audit: Mutex::new(VecDeque::new()),
+            free_balance: AtomicIsize::new(0),
+            throughput: Throughput::new(),
+            offer_drop: DropAlarm::new(OFFER_DROP_WINDOW, OFFER_DROP_FRACTION, OFFER_DROP_MIN_PEAK),
+            slow_start: SlowStart::new(SLOW_START_COMBOS, SLOW_START_DURATION, SLOW_START_OUTAGE),
+            last_failure: AtomicUsize::new(0),
+            failures: AtomicUsize::new(0),
+            connections: AtomicUsize::new(0),
+            resync: AtomicUsize::new(0),
+            reconnected: AtomicUsize::new(0),
+            stopping: AtomicBool::new(false),
             offers: RwLock::new(HashMap::new())
         }
     }
 
+    #[cfg(test)]
+    pub fn with_reserve(mut self, reserve: Currency) -> Bookie {
+        self.reserve = reserve;
+        self
+    }
+
+    #[cfg(test)]
+    pub fn with_slow_start(mut self, slow_start: SlowStart) -> Bookie {
+        self.slow_start = slow_start;
+        self
+    }
+
     #[inline]
     pub fn stage(&self) -> Stage {
         self.stage.load(Relaxed).into()
@@ -102,7 +195,11 @@ impl Bookie {
 
     #[inline]
     fn set_stage(&self, stage: Stage) {
-        self.stage.store(stage.into(), Relaxed);
+        let new: isize = stage.into();
+
+        if self.stage.swap(new, Relaxed) != new {
+            EVENTS.send(Event::Stage(self.host.clone(), stage));
+        }
     }
 
     #[inline]
@@ -110,9 +207,59 @@ impl Bookie {
         Currency(self.balance.load(Relaxed) as i64)
     }
 
+    // The balance less the reserve, i.e. what can be staked.
+    #[inline]
+    pub fn usable_balance(&self) -> Currency {
+        cmp::max(self.balance() - self.reserve, Currency(0))
+    }
+
     #[inline]
     fn set_balance(&self, balance: Currency) {
-        self.balance.store(balance.0 as isize, Relaxed);
+        self.change_balance(BalanceReason::Refresh, balance);
+    }
+
+    // All changes of the balance go through here to be audited.
+    fn change_balance(&self, reason: BalanceReason, amount: Currency) {
+        let amount = amount.0 as isize;
+
+        let (old, new) = match reason {
+            BalanceReason::Hold => {
+                let old = self.balance.fetch_sub(amount, Relaxed);
+                (old, old - amount)
+            },
+            BalanceReason::Release => {
+                let old = self.balance.fetch_add(amount, Relaxed);
+                (old, old + amount)
+            },
+            BalanceReason::Refresh => (self.balance.swap(amount, Relaxed), amount)
+        };
+
+        let mut audit = self.balance_audit.lock();
+
+        if audit.len() == BALANCE_AUDIT_SIZE {
+            audit.pop_front();
+        }
+
+        audit.push_back(BalanceChange {
+            date: self.clock.now(),
+            reason: reason,
+            old: Currency(old as i64),
+            new: Currency(new as i64)
+        });
+    }
+
+    pub fn balance_changes(&self) -> Vec<BalanceChange> {
+        self.balance_audit.lock().iter().cloned().collect()
+    }
+
+    #[inline]
+    pub fn free_balance(&self) -> Currency {
+        Currency(self.free_balance.load(Relaxed) as i64)
+    }
+
+    #[inline]
+    fn set_free_balance(&self, balance: Currency) {
+        self.free_balance.store(balance.0 as isize, Relaxed);
     }
 
     #[inline]
@@ -130,20 +277,90 @@ impl Bookie {
         self.offers.read().len()
     }
 
+    #[inline]
+    pub fn offers_per_minute(&self) -> usize {
+        self.throughput.per_minute(self.clock.now())
+    }
+
     #[inline]
     pub fn hold_stake(&self, stake: Currency) {
-        self.balance.fetch_sub(stake.0 as isize, Relaxed);
+        self.change_balance(BalanceReason::Hold, stake);
     }
 
     #[inline]
     pub fn release_stake(&self, stake: Currency) {
-        self.balance.fetch_add(stake.0 as isize, Relaxed);
+        self.change_balance(BalanceReason::Release, stake);
+    }
+
+    // Free bets are held from the free balance, it's refreshed after placing anyway.
+    #[inline]
+    pub fn hold_free_stake(&self, stake: Currency) {
+        self.free_balance.fetch_sub(stake.0 as isize, Relaxed);
+    }
+
+    #[inline]
+    pub fn release_free_stake(&self, stake: Currency) {
+        self.free_balance.fetch_add(stake.0 as isize, Relaxed);
+    }
+
+    // Stakes are limited for a while after starting, see `SLOW_START_COMBOS`.
+    #[inline]
+    pub fn is_slow_starting(&self) -> bool {
+        self.slow_start.is_active(self.clock.now())
+    }
+
+    #[inline]
+    pub fn record_combo(&self) {
+        self.slow_start.record_combo();
+    }
+
+    #[inline]
+    fn mark_failure(&self) {
+        self.last_failure.store(self.clock.now() as usize, Relaxed);
+    }
+
+    #[inline]
+    pub fn is_cooling_down(&self, now: u32, window: u32) -> bool {
+        let last_failure = self.last_failure.load(Relaxed) as u32;
+        last_failure > 0 && now < last_failure + window
+    }
+
+    // Failed authorizations since the last received message.
+    #[inline]
+    pub fn consecutive_failures(&self) -> usize {
+        self.failures.load(Relaxed)
+    }
+
+    // How many times the bookie has started running, to detect reconnections.
+    #[inline]
+    pub fn connections(&self) -> usize {
+        self.connections.load(Relaxed)
+    }
+
+    #[inline]
+    pub fn is_stopping(&self) -> bool {
+        self.stopping.load(Relaxed)
+    }
+
+    // The gambler is interrupted at the next message, sleeping is interrupted by unparking.
+    pub fn request_stop(&self) {
+        self.stopping.store(true, Relaxed);
+    }
+
+    pub fn reset(&self) {
+        debug_assert!(self.offers.read().is_empty());
+
+        self.set_delay(0);
+        self.failures.store(0, Relaxed);
+        self.offer_drop.reset();
+        self.set_stage(Initial);
+        self.stopping.store(false, Relaxed);
     }
 
     pub fn drain(&self) -> Vec<Offer> {
         let mut offers = self.offers.write();
         // Workaround rust-lang/rust#21114.
-        return offers.drain().map(|(_, o)| o).collect();
+        return offers.drain().map(|(_, (o, _))| o).collect();
     }
 
     pub fn watch<F: Fn(Offer, bool)>(&self, cb: F) {
@@ -154,8 +371,13 @@ impl Bookie {
         impl<'a> Drop for Guard<'a> {
             fn drop(&mut self) {
                 if thread::panicking() {
-                    self.0.set_stage(Aborted);
-                    error!(target: self.0.module, "Aborted due to panic");
+                    if self.0.is_stopping() {
+                        self.0.set_stage(Initial);
+                        info!(target: self.0.module, "Stopped");
+                    } else {
+                        self.0.set_stage(Aborted);
+                        error!(target: self.0.module, "Aborted due to panic");
+                    }
                 }
             }
         }
@@ -163,17 +385,67 @@ impl Bookie {
         let _guard = Guard(self);
 
         self.sleep_if_needed();
+
+        if self.is_stopping() {
+            return;
+        }
+
         self.run(cb);
+
+        if self.is_stopping() {
+            self.set_stage(Initial);
+            info!(target: self.module, "Stopped");
+            return;
+        }
+
+        let failures = self.consecutive_failures();
+
+        if failures >= MAX_CONSECUTIVE_FAILURES {
+            error!(target: self.module, "Aborted after {} failures in a row", failures);
+            self.set_stage(Aborted);
+            return;
+        }
+
+        let now = self.clock.now();
+        let reconnected = self.reconnected.load(Relaxed) as u32;
+
+        if self.stage() == Running && reconnects_at_once(*DEGRADATION_GRACE, reconnected, now) {
+            info!(target: self.module, "Disconnected, reconnecting...");
+            self.reconnected.store(now as usize, Relaxed);
+            self.set_stage(Sleeping(now));
+            return;
+        }
+
         self.schedule_sleep();
     }
 
+    pub fn get_offer(&self, oid: OID) -> Option<Offer> {
+        self.offers.read().get(&oid).map(|&(ref offer, _)| offer.clone())
+    }
+
+    // The next report of the offer is considered as a new one.
+    pub fn forget_offer(&self, oid: OID) {
+        self.offers.write().remove(&oid);
+    }
+
     pub fn glance_offer(&self, offer: &Offer) -> bool {
         let offers = self.offers.read();
-        offers.get(&offer.oid).map_or(false, |o| o == offer)
+        offers.get(&offer.oid).map_or(false, |&(ref o, _)| o == offer)
+    }
+
+    // Whether the offer has been reported by the gambler within the threshold.
+    pub fn is_fresh(&self, offer: &Offer, threshold: Duration) -> bool {
+        let offers = self.offers.read();
+
+        offers.get(&offer.oid).map_or(false, |&(ref o, last_seen)| {
+            o == offer && is_fresh(last_seen, now_ms(), threshold)
+        })
     }
 
     pub fn check_offer(&self, offer: &Offer, outcome: &Outcome, stake: Currency) -> Option<bool> {
-        match self.gambler.check_offer(offer, outcome, stake) {
+        let (native_offer, native_outcome) = self.to_native(offer.clone(), outcome.clone());
+
+        match self.gambler.check_offer(&native_offer, &native_outcome, stake) {
             Ok(true) => Some(true),
             Ok(false) => {
                 warn!(target: self.module, "Offer {} is outdated", offer);
@@ -181,36 +453,183 @@ impl Bookie {
             },
             Err(error) => {
                 error!(target: self.module, "While checking offer: {}\n{:?}", error, error.stack);
+                self.mark_failure();
                 None
             }
         }
     }
 
-    pub fn place_bet(&self, offer: Offer, outcome: Outcome, stake: Currency) -> bool {
-        if cfg!(feature = "place-bets") {
-            if let Err(error) = self.gambler.place_bet(offer, outcome, stake) {
-                error!(target: self.module, "While placing bet: {}\n{:?}", error, error.stack);
-                return false;
+    pub fn check_stake(&self, offer: &Offer, outcome: &Outcome, stake: Currency) -> Option<bool> {
+        let (native_offer, native_outcome) = self.to_native(offer.clone(), outcome.clone());
+
+        match self.gambler.check_stake(&native_offer, &native_outcome, stake) {
+            Ok(true) => Some(true),
+            Ok(false) => {
+                warn!(target: self.module, "The stake {} on {} isn't accepted", stake, offer);
+                Some(false)
+            },
+            Err(error) => {
+                error!(target: self.module, "While checking stake: {}\n{:?}", error, error.stack);
+                self.mark_failure();
+                None
             }
         }
+    }
 
-        if let Err(error) = self.gambler.check_balance().map(|b| self.set_balance(b)) {
-            error!(target: self.module, "While checking balance: {}\n{:?}", error, error.stack);
-            return false;
+    pub fn place_bet(&self, offer: Offer, outcome: Outcome, stake: Currency, free: bool) -> Placed {
+        let receipt = if cfg!(feature = "place-bets") {
+            let (offer, outcome) = self.to_native(offer, outcome);
+
+            let place = || if free {
+                self.gambler.place_free_bet(offer, outcome, stake)
+            } else {
+                self.gambler.place_bet(offer, outcome, stake)
+            };
+
+            let mut raw = None;
+
+            let result = if PERSIST_RAW_BETS {
+                let (result, exchanges) = session::capture(place);
+                raw = Some(format_exchanges(&exchanges));
+                result
+            } else {
+                place()
+            };
+
+            let result = result.and_then(|mut receipt| {
+                if self.features().bet_status {
+                    let placement = try!(gamblers::await_confirmation(&*self.gambler, &receipt,
+                                                                      *BET_STATUS_INTERVAL,
+                                                                      *BET_STATUS_TIMEOUT));
+
+                    if placement == Placement::Pending {
+                        warn!(target: self.module, "Bet #{} isn't confirmed in time, it's pending",
+                              receipt.id.as_ref().unwrap());
+                        receipt.pending = true;
+                    }
+                }
+
+                Ok(receipt)
+            });
+
+            match result {
+                Ok(mut receipt) => {
+                    receipt.coef = self.odds_style.to_decimal(receipt.coef);
+
+                    if let Some((request, response)) = raw {
+                        receipt.raw_request = Some(request);
+                        receipt.raw_response = Some(response);
+                    }
+
+                    receipt
+                },
+                Err(error) => {
+                    error!(target: self.module, "While placing bet: {}\n{:?}", error, error.stack);
+                    self.mark_failure();
+                    return Placed::Failed(raw);
+                }
+            }
+        } else {
+            BetReceipt::new(stake, outcome.1)
+        };
+
+        if !self.refresh_balance() {
+            return Placed::Failed(None);
         }
 
-        true
+        self.refresh_free_balance();
+
+        if receipt.pending {
+            Placed::Pending(receipt)
+        } else {
+            Placed::Confirmed(receipt)
+        }
+    }
+
+    pub fn refresh_balance(&self) -> bool {
+        match self.gambler.check_balance() {
+            Ok(balance) => {
+                self.set_balance(balance);
+                true
+            },
+            Err(error) => {
+                error!(target: self.module, "While checking balance: {}\n{:?}", error, error.stack);
+                false
+            }
+        }
+    }
+
+    pub fn bet_status(&self, bet_id: &str) -> Option<Placement> {
+        match self.gambler.bet_status(bet_id) {
+            Ok(placement) => Some(placement),
+            Err(error) => {
+                warn!(target: self.module, "While polling the status of bet #{}: {}", bet_id, error);
+                None
+            }
+        }
+    }
+
+    #[inline]
+    pub fn features(&self) -> Features {
+        self.gambler.features()
+    }
+
+    pub fn open_bets(&self) -> Option<Vec<OpenBet>> {
+        match self.gambler.open_bets() {
+            Ok(bets) => Some(bets),
+            Err(error) => {
+                error!(target: self.module, "While fetching open bets: {}\n{:?}", error, error.stack);
+                None
+            }
+        }
+    }
+
+    pub fn cashout_value(&self, bet_id: &str) -> Option<Currency> {
+        match self.gambler.cashout_value(bet_id) {
+            Ok(value) => value,
+            Err(error) => {
+                warn!(target: self.module, "While fetching cash-out of #{}: {}", bet_id, error);
+                None
+            }
+        }
+    }
+
+    // Offers are stored with decimal odds, but the gambler expects its own ones back.
+    fn to_native(&self, mut offer: Offer, mut outcome: Outcome) -> (Offer, Outcome) {
+        self.odds_style.denormalize(&mut offer.outcomes);
+        outcome.1 = self.odds_style.from_decimal(outcome.1);
+
+        (offer, outcome)
+    }
+
+    fn refresh_free_balance(&self) {
+        if !USE_FREE_BETS || !self.features().free_bets {
+            return;
+        }
+
+        match self.gambler.check_free_balance() {
+            Ok(balance) => self.set_free_balance(balance),
+            Err(error) => {
+                warn!(target: self.module, "While checking free balance: {}", error);
+                self.set_free_balance(Currency(0));
+            }
+        }
     }
 
     fn sleep_if_needed(&self) {
         if let Sleeping(wakeup) = self.stage() {
-            let now = time::get_time().sec as u32;
+            let mut now = self.clock.now();
 
             if now < wakeup {
                 let delay = wakeup - now;
                 let (hours, mins, secs) = (delay / 3600, delay / 60 % 60, delay % 60);
                 info!(target: self.module, "Sleeping for {:02}:{:02}:{:02}", hours, mins, secs);
-                thread::sleep(Duration::new((wakeup - now) as u64, 0));
+            }
+
+            // Parking instead of sleeping allows to interrupt it.
+            while now < wakeup && !self.is_stopping() {
+                thread::park_timeout(Duration::new((wakeup - now) as u64, 0));
+                now = self.clock.now();
             }
         }
     }
@@ -218,41 +637,140 @@ impl Bookie {
     fn run<F: Fn(Offer, bool)>(&self, cb: F) {
         self.set_stage(Preparing);
 
-        info!(target: self.module, "Authorizating...");
-
-        if let Err(error) = self.gambler.authorize(&self.username, &self.password) {
-            error!(target: self.module, "While authorizating: {}\n{:?}", error, error.stack);
+        if !self.authorize(AUTH_SLOTS.as_ref()) {
+            self.failures.fetch_add(1, Relaxed);
             return;
         }
 
         info!(target: self.module, "Checking balance...");
 
-        if let Err(error) = self.gambler.check_balance().map(|b| self.set_balance(b)) {
-            error!(target: self.module, "While checking balance: {}\n{:?}", error, error.stack);
+        if !self.check_initial_balance(REAUTH_ON_SUSPICIOUS_BALANCE) {
             return;
         }
 
+        self.refresh_free_balance();
+
         info!(target: self.module, "Watching for offers...");
 
+        self.connections.fetch_add(1, Relaxed);
+
+        if self.slow_start.activate(self.clock.now()) {
+            info!(target: self.module, "Slow start: stakes are limited for a while");
+        }
+
+        // Offers kept over the reconnection must be reported again, otherwise they're gone.
+        if self.offer_count() > 0 {
+            self.resync.store(now_ms() as usize, Relaxed);
+        }
+
         self.set_stage(Running);
 
-        if let Err(error) = self.gambler.watch(&|message| {
+        let result = self.gambler.watch(&|message| {
             self.set_delay(0);
+            self.failures.store(0, Relaxed);
+            self.slow_start.touch(self.clock.now());
+
+            if self.is_stopping() {
+                return Err("Stop requested".into());
+            }
 
             // If errors occured at the time of betting.
             if self.stage() != Running {
-                panic!("Some error occured while betting");
+                return Err("Some error occured while betting".into());
             }
 
             self.handle_message(message, &cb);
-        }) {
-            error!(target: self.module, "While watching: {}\n{:?}", error, error.stack);
+            Ok(())
+        });
+
+        if let Err(error) = result {
+            if !self.is_stopping() {
+                error!(target: self.module, "While watching: {}\n{:?}", error, error.stack);
+            }
+        }
+
+        // The connection is over before the window, nothing else is reported anyway.
+        self.sweep_unseen(&cb);
+    }
+
+    // Removes offers kept over the reconnection, but not reported since.
+    fn sweep_unseen<F: Fn(Offer, bool)>(&self, cb: &F) {
+        let since = self.resync.swap(0, Relaxed) as u64;
+
+        if since == 0 {
             return;
         }
+
+        let unseen = {
+            let mut offers = self.offers.write();
+
+            let oids = offers.iter()
+                .filter(|&(_, &(_, last_seen))| last_seen < since)
+                .map(|(&oid, _)| oid)
+                .collect::<Vec<_>>();
+
+            oids.iter().filter_map(|oid| offers.remove(oid)).map(|(o, _)| o).collect::<Vec<_>>()
+        };
+
+        if !unseen.is_empty() {
+            info!(target: self.module, "Removing {} offers unseen since reconnection", unseen.len());
+        }
+
+        for offer in unseen {
+            cb(offer, false);
+        }
+    }
+
+    fn authorize(&self, slots: Option<&Semaphore>) -> bool {
+        // The slot is held only for the authorization itself.
+        let _slot = slots.map(|slots| slots.acquire());
+
+        info!(target: self.module, "Authorizating...");
+
+        if let Err(error) = self.gambler.authorize(&self.username, &self.password) {
+            error!(target: self.module, "While authorizating: {}\n{:?}", error, error.stack);
+            return false;
+        }
+
+        true
+    }
+
+    // A zero balance right after authorization usually means a broken session, not an empty
+    // account, so it's not trusted and, optionally, the authorization is retried once.
+    fn check_initial_balance(&self, reauth: bool) -> bool {
+        let mut balance = match self.gambler.check_balance() {
+            Ok(balance) => balance,
+            Err(error) => {
+                error!(target: self.module, "While checking balance: {}\n{:?}", error, error.stack);
+                return false;
+            }
+        };
+
+        if balance <= *SUSPICIOUS_BALANCE {
+            warn!(target: self.module, "Suspicious balance {} after authorization", balance);
+
+            if reauth {
+                if !self.authorize(AUTH_SLOTS.as_ref()) {
+                    return false;
+                }
+
+                balance = match self.gambler.check_balance() {
+                    Ok(balance) => balance,
+                    Err(error) => {
+                        error!(target: self.module, "While checking balance: {}\n{:?}",
+                               error, error.stack);
+                        return false;
+                    }
+                };
+            }
+        }
+
+        self.set_balance(balance);
+        true
     }
 
     fn schedule_sleep(&self) {
-        let now = time::get_time().sec as u32;
+        let now = self.clock.now();
 
         let min = MIN_RETRY_DELAY.as_secs() as u32;
         let max = MAX_RETRY_DELAY.as_secs() as u32;
@@ -263,32 +781,96 @@ impl Bookie {
         self.set_delay(delay);
     }
 
+    // A sharp fall of the offer count usually means a partial failure of the feed.
+    fn check_offer_drop(&self, count: usize) {
+        if self.stage() != Running {
+            return;
+        }
+
+        if let Some(peak) = self.offer_drop.check(self.clock.now(), count) {
+            let message = format!("{} offers by {} have fallen to {}", peak, self.host, count);
+            warn!(target: self.module, "{}", message);
+
+            if let Some(url) = ALERT_WEBHOOK {
+                webhook::notify(url, &message);
+            }
+        }
+    }
+
     fn handle_message<F: Fn(Offer, bool)>(&self, message: Message, cb: &F) {
+        self.throughput.record(self.clock.now());
+
+        let since = self.resync.load(Relaxed) as u64;
+
+        if since > 0 && !is_fresh(since, now_ms(), *RESYNC_WINDOW) {
+            self.sweep_unseen(cb);
+        }
+
+        let message = match message {
+            Upsert(ref offer) if !live_mode(offer.game, LIVE_MODES, DEFAULT_LIVE_MODE)
+                                    .admits(offer.is_live) => Remove(offer.oid),
+            Upsert(ref offer) if is_too_far(offer.date, self.clock.now(), MAX_TIME_TO_EVENT) => {
+                Remove(offer.oid)
+            },
+            Upsert(offer) => {
+                let oid = offer.oid;
+
+                match check_draw(offer, NO_DRAW_GAMES, SPURIOUS_DRAW) {
+                    Some(offer) => Upsert(offer),
+                    None => Remove(oid)
+                }
+            },
+            message => message
+        };
+
+        // Offers with unreliable matching (e.g. outrights) are dropped before reaching the table.
+        let message = match message {
+            Upsert(ref offer) if !ALLOWED_OUTCOME_COUNTS.contains(&offer.outcomes.len()) => {
+                Remove(offer.oid)
+            },
+            Upsert(mut offer) => {
+                self.odds_style.normalize(&mut offer.outcomes);
+                Upsert(offer)
+            },
+            message => message
+        };
+
+        let now = now_ms();
         let mut offers = self.offers.write();
 
         let (remove, upsert) = match message {
             Upsert(offer) => match offers.entry(offer.oid) {
                 Entry::Vacant(entry) => {
-                    entry.insert(offer.clone());
+                    entry.insert((offer.clone(), now));
                     (None, Some(offer))
                 },
-                Entry::Occupied(ref entry) if entry.get() == &offer => return,
                 Entry::Occupied(mut entry) => {
-                    if matcher::get_headline(&offer) == matcher::get_headline(entry.get()) {
-                        *entry.get_mut() = offer.clone();
+                    if entry.get().0 == offer {
+                        entry.get_mut().1 = now;
+                        return;
+                    }
+
+                    if matcher::get_headline(&offer) == matcher::get_headline(&entry.get().0) {
+                        *entry.get_mut() = (offer.clone(), now);
                         (None, Some(offer))
                     } else {
-                        let stored = entry.insert(offer.clone());
+                        let (stored, _) = entry.insert((offer.clone(), now));
                         (Some(stored), Some(offer))
                     }
                 }
             },
-            Remove(oid) => (offers.remove(&oid), None)
+            Remove(oid) => (offers.remove(&oid).map(|(o, _)| o), None)
         };
 
+        let count = offers.len();
+
         // Drop the guard before calling the callback to prevent possible deadlocks.
         drop(offers);
 
+        if ALERT_ON_OFFER_DROP && remove.is_some() {
+            self.check_offer_drop(count);
+        }
+
         // We should remove before upsert for readding cases.
         if let Some(remove) = remove {
             cb(remove, false);
@@ -299,3 +881,473 @@ impl Bookie {
         }
     }
 }
+
+fn balance_reserve(host: &str) -> Currency {
+    BALANCE_RESERVES.iter()
+        .find(|&&(h, _)| h == host)
+        .map_or(Currency(0), |&(_, reserve)| Currency::from(reserve))
+}
+
+// Draws of games without them come from parsing errors, so either the draw or the whole offer
+// can't be trusted. The offer comes again on every update, so it isn't warned about.
+fn check_draw(mut offer: Offer, games: &[GameSelector], policy: SpuriousDraw) -> Option<Offer> {
+    let draw = offer.outcomes.iter().position(|outcome| outcome.0 == DRAW);
+
+    let idx = match draw {
+        Some(idx) if games.iter().any(|selector| selector.matches(offer.game)) => idx,
+        _ => return Some(offer)
+    };
+
+    match policy {
+        SpuriousDraw::DropDraw => {
+            debug!("{} has a draw, but {:?} has no draws, dropping the draw", offer, offer.game);
+            offer.outcomes.remove(idx);
+            Some(offer)
+        },
+        SpuriousDraw::DropOffer => {
+            debug!("{} has a draw, but {:?} has no draws, dropping the offer", offer, offer.game);
+            None
+        }
+    }
+}
+
+fn live_mode(game: Game, modes: &[(GameSelector, LiveMode)], default: LiveMode) -> LiveMode {
+    modes.iter()
+        .find(|&&(ref selector, _)| selector.matches(game))
+        .map_or(default, |&(_, mode)| mode)
+}
+
+#[inline]
+fn is_too_far(date: u32, now: u32, horizon: u32) -> bool {
+    date > now.saturating_add(horizon)
+}
+
+fn now_ms() -> u64 {
+    time::precise_time_ns() / 1_000_000
+}
+
+// The broken connection is retried at once only if its offers are kept for a while (otherwise
+// they're gone anyway) and at most once per `MIN_RETRY_DELAY` to not hammer a flapping feed.
+fn reconnects_at_once(grace: Duration, reconnected: u32, now: u32) -> bool {
+    grace > Duration::new(0, 0) &&
+        (reconnected == 0 || now >= reconnected + MIN_RETRY_DELAY.as_secs() as u32)
+}
+
+fn is_fresh(last_seen: u64, now: u64, threshold: Duration) -> bool {
+    let threshold = threshold.as_secs() * 1000 + threshold.subsec_nanos() as u64 / 1_000_000;
+    now.saturating_sub(last_seen) <= threshold
+}
+
+// Placing can take several requests (e.g. adding to the betslip first), all of them are kept.
+// Credentials can be among them (e.g. refreshing a token in place), so they're redacted.
+fn format_exchanges(exchanges: &[Exchange]) -> (String, String) {
+    let requests = exchanges.iter()
+        .map(|e| format!("{} {}\n{}", e.method, e.url, e.request.as_ref().map_or("", |r| r)))
+        .map(|request| redact(&request))
+        .collect::<Vec<_>>();
+
+    let responses = exchanges.iter()
+        .map(|e| redact(&format!("{}\n{}", e.status, e.response)))
+        .collect::<Vec<_>>();
+
+    (requests.join("\n\n"), responses.join("\n\n"))
+}
+
+fn redact(text: &str) -> String {
+    SECRET_RE.replace_all(text, |caps: &Captures| format!("{}***", caps.at(1).unwrap()))
+}
+
+#[test]
+fn test_live_mode() {
+    use markets::GameCategory;
+
+    let modes = &[
+        (GameSelector::Game(Game::Football), LiveMode::Both),
+        (GameSelector::Category(GameCategory::ESports), LiveMode::LiveOnly)
+    ];
+
+    let admits = |game, is_live| live_mode(game, modes, LiveMode::PreMatchOnly).admits(is_live);
+
+    assert!(admits(Game::Tennis, false));
+    assert!(!admits(Game::Tennis, true));
+
+    assert!(!admits(Game::Dota2, false));
+    assert!(admits(Game::Dota2, true));
+
+    assert!(admits(Game::Football, false));
+    assert!(admits(Game::Football, true));
+}
+
+#[test]
+fn test_is_too_far() {
+    let (now, week) = (1_000_000, 7 * 24 * 3600);
+
+    assert!(!is_too_far(now + 3600, now, week));
+    assert!(!is_too_far(now + week, now, week));
+    assert!(is_too_far(now + week + 1, now, week));
+
+    // Started ones are left to other filters.
+    assert!(!is_too_far(now - 3600, now, week));
+}
+
+#[test]
+fn test_check_draw() {
+    use markets::{GameCategory, Kind};
+
+    let offer = |game| Offer {
+        oid: 1, date: 0, game: game, kind: Kind::Series, league: None, is_live: false,
+        best_of: None,
+        outcomes: vec![
+            Outcome::new("A", 2.1),
+            Outcome::new(DRAW, 9.),
+            Outcome::new("B", 2.2)
+        ]
+    };
+
+    let games = &[GameSelector::Category(GameCategory::ESports)];
+
+    assert_eq!(check_draw(offer(Game::Dota2), games, SpuriousDraw::DropOffer), None);
+
+    let fixed = check_draw(offer(Game::Dota2), games, SpuriousDraw::DropDraw).unwrap();
+    assert_eq!(fixed.outcomes, vec![Outcome::new("A", 2.1),
+                                    Outcome::new("B", 2.2)]);
+
+    assert_eq!(check_draw(offer(Game::Football), games, SpuriousDraw::DropOffer),
+               Some(offer(Game::Football)));
+}
+
+#[test]
+fn test_reconnects_at_once() {
+    let grace = Duration::from_secs(5);
+    let min = MIN_RETRY_DELAY.as_secs() as u32;
+
+    assert!(!reconnects_at_once(Duration::new(0, 0), 0, 1000));
+    assert!(reconnects_at_once(grace, 0, 1000));
+    assert!(!reconnects_at_once(grace, 1000, 1000));
+    assert!(!reconnects_at_once(grace, 1000, 1000 + min - 1));
+    assert!(reconnects_at_once(grace, 1000, 1000 + min));
+}
+
+#[test]
+fn test_format_exchanges() {
+    let exchange = |url: &str, request: Option<&str>, response: &str| Exchange {
+        method: "POST",
+        url: url.to_owned(),
+        request: request.map(ToOwned::to_owned),
+        status: "200 OK".to_owned(),
+        response: response.to_owned()
+    };
+
+    let exchanges = [
+        exchange("https://a.com/oauth/token",
+                 Some("client_id=web&client_secret=s3cr3t&password=qwerty"),
+                 r#"{"access_token": "abc", "refresh_token":"def", "expires_in": 3600}"#),
+        exchange("https://a.com/bets?token=ghi", Some(r#"{"stake":1,"coef":1.5}"#), r#"{"id":42}"#)
+    ];
+
+    let (requests, responses) = format_exchanges(&exchanges);
+
+    assert_eq!(requests.split("\n\n").collect::<Vec<_>>(), vec![
+        "POST https://a.com/oauth/token\nclient_id=***&client_secret=***&password=***",
+        "POST https://a.com/bets?token=***\n{\"stake\":1,\"coef\":1.5}"
+    ]);
+
+    assert_eq!(responses.split("\n\n").collect::<Vec<_>>(), vec![
+        r#"200 OK
+{"access_token": "***", "refresh_token":"***", "expires_in": 3600}"#,
+        "200 OK\n{\"id\":42}"
+    ]);
+}
+
+#[test]
+fn test_is_fresh() {
+    let threshold = Duration::from_millis(500);
+
+    assert!(is_fresh(10_000, 10_000, threshold));
+    assert!(is_fresh(10_000, 10_500, threshold));
+    assert!(!is_fresh(10_000, 10_501, threshold));
+    assert!(!is_fresh(10_000, 20_000, threshold));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, ATOMIC_USIZE_INIT};
+    use std::sync::atomic::Ordering::Relaxed;
+    use std::time::Duration;
+
+    use base::error::{Result, Error};
+    use base::clock::{Clock, RealClock, TestClock};
+    use base::currency::Currency;
+    use base::semaphore::Semaphore;
+    use gamblers::{Gambler, Message, BetReceipt};
+    use gamblers::Message::Upsert;
+    use markets::{Offer, Outcome, Game, Kind};
+
+    use constants::{MAX_RETRY_DELAY, MAX_CONSECUTIVE_FAILURES};
+
+    use super::{Bookie, BalanceReason, Stage};
+
+    struct MockGambler;
+
+    impl Gambler for MockGambler {
+        fn authorize(&self, _: &str, _: &str) -> Result<()> {
+            Ok(())
+        }
+
+        fn check_balance(&self) -> Result<Currency> {
+            Ok(Currency::from(10.))
+        }
+
+        fn watch(&self, cb: &Fn(Message) -> Result<()>) -> Result<()> {
+            loop {
+                try!(cb(Upsert(Offer {
+                    oid: 1, date: 0, game: Game::Dota2, kind: Kind::Series, league: None,
+                    is_live: false,
+                    best_of: None,
+                    outcomes: vec![Outcome::new("A", 2.),
+                                   Outcome::new("B", 2.)]
+                })));
+
+                thread::sleep(Duration::from_millis(5));
+            }
+        }
+
+        fn place_bet(&self, _: Offer, outcome: Outcome, stake: Currency) -> Result<BetReceipt> {
+            Ok(BetReceipt::new(stake, outcome.1))
+        }
+    }
+
+    lazy_static! {
+        static ref BOOKIE: Bookie = Bookie::with_gambler("mock.com", "", "", "mock",
+                                                         Box::new(MockGambler),
+                                                         Box::new(RealClock));
+    }
+
+    fn spawn() -> thread::JoinHandle<()> {
+        let handle = thread::spawn(|| while !BOOKIE.is_stopping() { BOOKIE.watch(|_, _| {}) });
+
+        while BOOKIE.offer_count() == 0 {
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        handle
+    }
+
+    #[test]
+    fn stop_and_restart() {
+        let handle = spawn();
+
+        BOOKIE.request_stop();
+        assert!(handle.join().is_ok());
+        assert_eq!(BOOKIE.stage(), Stage::Initial);
+        assert_eq!(BOOKIE.drain().len(), 1);
+        assert_eq!(BOOKIE.offer_count(), 0);
+
+        BOOKIE.reset();
+
+        let handle = spawn();
+
+        assert_eq!(BOOKIE.offer_count(), 1);
+
+        BOOKIE.request_stop();
+        assert!(handle.join().is_ok());
+    }
+
+    #[test]
+    fn balance_audit() {
+        let bookie = Bookie::with_gambler("mock.com", "", "", "mock", Box::new(MockGambler),
+                                          Box::new(RealClock));
+
+        bookie.set_balance(Currency(500));
+        bookie.hold_stake(Currency(100));
+        bookie.release_stake(Currency(100));
+
+        let changes = bookie.balance_changes();
+
+        assert_eq!(changes.len(), 3);
+        assert_eq!(changes[1].reason, BalanceReason::Hold);
+        assert_eq!((changes[1].old, changes[1].new), (Currency(500), Currency(400)));
+        assert_eq!(changes[2].reason, BalanceReason::Release);
+        assert_eq!((changes[2].old, changes[2].new), (Currency(400), Currency(500)));
+    }
+
+    #[test]
+    fn cooling_down() {
+        let clock = Arc::new(TestClock::new(1000));
+        let bookie = Bookie::with_gambler("mock.com", "", "", "mock", Box::new(MockGambler),
+                                          Box::new(clock.clone()));
+
+        assert!(!bookie.is_cooling_down(clock.now(), 60));
+
+        bookie.mark_failure();
+        clock.advance(59);
+        assert!(bookie.is_cooling_down(clock.now(), 60));
+
+        clock.advance(2);
+        assert!(!bookie.is_cooling_down(clock.now(), 60));
+    }
+
+    #[test]
+    fn outcome_count_filter() {
+        let bookie = Bookie::with_gambler("mock.com", "", "", "mock", Box::new(MockGambler),
+                                          Box::new(RealClock));
+
+        let make_offer = |oid, count| Offer {
+            oid: oid, date: 0, game: Game::Dota2, kind: Kind::Series, league: None, is_live: false,
+            best_of: None,
+            outcomes: (0..count).map(|i| Outcome::new(&format!("T{}", i), 2.)).collect()
+        };
+
+        bookie.handle_message(Upsert(make_offer(1, 5)), &|_, _| {});
+        assert_eq!(bookie.offer_count(), 0);
+
+        bookie.handle_message(Upsert(make_offer(2, 3)), &|_, _| {});
+        assert_eq!(bookie.offer_count(), 1);
+    }
+
+    static AUTHORIZATIONS: AtomicUsize = ATOMIC_USIZE_INIT;
+
+    // Reports the zero balance until it's authorized twice.
+    struct ZeroBalanceGambler;
+
+    impl Gambler for ZeroBalanceGambler {
+        fn authorize(&self, _: &str, _: &str) -> Result<()> {
+            AUTHORIZATIONS.fetch_add(1, Relaxed);
+            Ok(())
+        }
+
+        fn check_balance(&self) -> Result<Currency> {
+            Ok(match AUTHORIZATIONS.load(Relaxed) {
+                0 | 1 => Currency(0),
+                _ => Currency::from(10.)
+            })
+        }
+
+        fn watch(&self, _: &Fn(Message) -> Result<()>) -> Result<()> {
+            unreachable!();
+        }
+
+        fn place_bet(&self, _: Offer, _: Outcome, _: Currency) -> Result<BetReceipt> {
+            unreachable!();
+        }
+    }
+
+    #[test]
+    fn zero_balance_reauth() {
+        let bookie = Bookie::with_gambler("mock.com", "", "", "mock", Box::new(ZeroBalanceGambler),
+                                          Box::new(RealClock));
+
+        bookie.gambler.authorize("", "").unwrap();
+
+        assert!(bookie.check_initial_balance(false));
+        assert_eq!(AUTHORIZATIONS.load(Relaxed), 1);
+        assert_eq!(bookie.balance(), Currency(0));
+
+        assert!(bookie.check_initial_balance(true));
+        assert_eq!(AUTHORIZATIONS.load(Relaxed), 2);
+        assert_eq!(bookie.balance(), Currency::from(10.));
+    }
+
+    static ACTIVE_AUTHORIZATIONS: AtomicUsize = ATOMIC_USIZE_INIT;
+    static PEAK_AUTHORIZATIONS: AtomicUsize = ATOMIC_USIZE_INIT;
+
+    // Tracks how many authorizations are in progress at once.
+    struct SlowAuthGambler;
+
+    impl Gambler for SlowAuthGambler {
+        fn authorize(&self, _: &str, _: &str) -> Result<()> {
+            let active = ACTIVE_AUTHORIZATIONS.fetch_add(1, Relaxed) + 1;
+            let mut peak = PEAK_AUTHORIZATIONS.load(Relaxed);
+
+            while active > peak {
+                match PEAK_AUTHORIZATIONS.compare_and_swap(peak, active, Relaxed) {
+                    previous if previous == peak => break,
+                    previous => peak = previous
+                }
+            }
+
+            thread::sleep(Duration::from_millis(20));
+            ACTIVE_AUTHORIZATIONS.fetch_sub(1, Relaxed);
+            Ok(())
+        }
+
+        fn check_balance(&self) -> Result<Currency> {
+            unreachable!();
+        }
+
+        fn watch(&self, _: &Fn(Message) -> Result<()>) -> Result<()> {
+            unreachable!();
+        }
+
+        fn place_bet(&self, _: Offer, _: Outcome, _: Currency) -> Result<BetReceipt> {
+            unreachable!();
+        }
+    }
+
+    #[test]
+    fn serialized_authorizations() {
+        let slots = Arc::new(Semaphore::new(1));
+
+        let handles = (0..4).map(|_| {
+            let slots = slots.clone();
+
+            thread::spawn(move || {
+                let bookie = Bookie::with_gambler("mock.com", "", "", "mock",
+                                                  Box::new(SlowAuthGambler),
+                                                  Box::new(RealClock));
+
+                assert!(bookie.authorize(Some(&slots)));
+            })
+        }).collect::<Vec<_>>();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        assert_eq!(PEAK_AUTHORIZATIONS.load(Relaxed), 1);
+    }
+
+    struct FailingGambler;
+
+    impl Gambler for FailingGambler {
+        fn authorize(&self, _: &str, _: &str) -> Result<()> {
+            Err(Error::from("Invalid credentials"))
+        }
+
+        fn check_balance(&self) -> Result<Currency> {
+            unreachable!();
+        }
+
+        fn watch(&self, _: &Fn(Message) -> Result<()>) -> Result<()> {
+            unreachable!();
+        }
+
+        fn place_bet(&self, _: Offer, _: Outcome, _: Currency) -> Result<BetReceipt> {
+            unreachable!();
+        }
+    }
+
+    #[test]
+    fn abort_after_failures() {
+        let clock = Arc::new(TestClock::new(1000));
+        let bookie = Bookie::with_gambler("mock.com", "", "", "mock", Box::new(FailingGambler),
+                                          Box::new(clock.clone()));
+
+        for i in 1..MAX_CONSECUTIVE_FAILURES {
+            bookie.watch(|_, _| {});
+            assert_eq!(bookie.consecutive_failures(), i);
+            assert!(match bookie.stage() { Stage::Sleeping(_) => true, _ => false });
+
+            clock.advance(MAX_RETRY_DELAY.as_secs() as u32);
+        }
+
+        bookie.watch(|_, _| {});
+        assert_eq!(bookie.stage(), Stage::Aborted);
+
+        bookie.reset();
+        assert_eq!(bookie.consecutive_failures(), 0);
+        assert_eq!(bookie.stage(), Stage::Initial);
+    }
+}
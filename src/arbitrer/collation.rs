@@ -0,0 +1,102 @@
+use std::collections::HashMap;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering::Relaxed;
+use parking_lot::Mutex;
+
+use markets::{OID, Outcome};
+use arbitrer::matcher;
+
+struct Entry {
+    etalon: Vec<Outcome>,
+    outcomes: Vec<Outcome>,
+    order: Vec<usize>
+}
+
+// Collations of offers (keyed by the bookie and the offer id) against the etalon. Usually only one
+// offer of the market is changed since the last evaluation, so others are reused as is.
+pub struct Collations {
+    capacity: usize,
+    entries: Mutex<HashMap<(String, OID), Entry>>,
+    hits: AtomicUsize
+}
+
+impl Collations {
+    pub fn new(capacity: usize) -> Collations {
+        Collations {
+            capacity: capacity,
+            entries: Mutex::new(HashMap::new()),
+            hits: AtomicUsize::new(0)
+        }
+    }
+
+    // The same as `matcher::collate_outcomes`, but the collation is recalculated only if either
+    // the offer or the etalon is changed.
+    pub fn collate<'a>(&self, host: &str, oid: OID, etalon: &[Outcome],
+                       outcomes: &'a [Outcome]) -> Vec<&'a Outcome>
+    {
+        let key = (host.to_owned(), oid);
+        let mut entries = self.entries.lock();
+
+        if let Some(entry) = entries.get(&key) {
+            if entry.etalon == etalon && entry.outcomes == outcomes {
+                self.hits.fetch_add(1, Relaxed);
+                return matcher::arrange_outcomes(&entry.order, outcomes);
+            }
+        }
+
+        let order = matcher::collate_order(etalon, outcomes);
+        let collated = matcher::arrange_outcomes(&order, outcomes);
+
+        // Entries of removed offers aren't tracked, so all of them are dropped once in a while.
+        if entries.len() >= self.capacity && !entries.contains_key(&key) {
+            entries.clear();
+        }
+
+        entries.insert(key, Entry {
+            etalon: etalon.to_vec(),
+            outcomes: outcomes.to_vec(),
+            order: order
+        });
+
+        collated
+    }
+
+    #[cfg(test)]
+    #[inline]
+    pub fn hits(&self) -> usize {
+        self.hits.load(Relaxed)
+    }
+}
+
+#[test]
+fn test_matches_full_collation() {
+    let outcome = |title: &str, coef| Outcome::new(title, coef);
+
+    let etalon = vec![outcome("Natus Vincere", 2.1), outcome("Virtus.pro", 1.8)];
+    let updates = vec![
+        ("a.com", vec![outcome("VP", 1.75), outcome("NaVi", 2.2)]),
+        ("b.com", vec![outcome("Virtus Pro", 1.9), outcome("Na`Vi", 2.)]),
+        ("a.com", vec![outcome("VP", 1.75), outcome("NaVi", 2.2)]),
+        ("a.com", vec![outcome("VP", 1.7), outcome("NaVi", 2.3)]),
+        ("b.com", vec![outcome("Virtus Pro", 1.9), outcome("Na`Vi", 2.)])
+    ];
+
+    let collations = Collations::new(16);
+
+    for &(host, ref outcomes) in &updates {
+        let full = matcher::collate_outcomes(&etalon, outcomes);
+        let incremental = collations.collate(host, 1, &etalon, outcomes);
+
+        assert_eq!(incremental, full);
+    }
+
+    assert_eq!(collations.hits(), 2);
+
+    // A changed etalon invalidates the collation.
+    let etalon = vec![outcome("Virtus.pro", 1.8), outcome("Natus Vincere", 2.1)];
+    let outcomes = &updates[4].1;
+
+    assert_eq!(collations.collate("b.com", 1, &etalon, outcomes),
+               matcher::collate_outcomes(&etalon, outcomes));
+    assert_eq!(collations.hits(), 2);
+}
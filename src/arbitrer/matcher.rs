@@ -1,11 +1,28 @@
 use std::char;
+use std::collections::HashMap;
 use std::iter::FilterMap;
 use std::str::Chars;
 
-use markets::{Offer, Game, Kind, Outcome, DRAW};
+use constants::{DRAW_OPTIONAL_GAMES, REQUIRE_LEAGUE_MATCH, MIN_LEAGUE_SIMILARITY};
+use constants::DISTINGUISH_SERIES_FORMATS;
+use constants::{USE_TITLE_ALIASES, DEFAULT_TITLE_ALIASES, TITLE_ALIASES};
+use markets::{Offer, Game, Kind, Outcome, Role, DRAW};
 
 const UNVALID_TOKENS: &[&str] = &["", "de", "fc", "sc", "fk", "city", "club", "state", "st."];
 
+// Titles whose similarities differ less than this are considered equally similar.
+const TITLE_SIM_TIE: f64 = 0.05;
+
+lazy_static! {
+    // Stands in for the draw of two-way offers collated against three-way ones.
+    static ref MISSING_DRAW: Outcome = Outcome::new(DRAW, 0.).role(Role::Draw);
+
+    static ref ALIASES: HashMap<String, &'static str> = DEFAULT_TITLE_ALIASES.iter()
+        .chain(TITLE_ALIASES.iter())
+        .map(|&(alias, canonical)| (alias.to_lowercase(), canonical))
+        .collect();
+}
+
 #[derive(Debug, Clone, Copy)]
 struct Token<'a>(&'a str);
 
@@ -72,27 +89,129 @@ pub type Headline = (u32, Game, Kind, usize);
 
 #[inline]
 pub fn get_headline(offer: &Offer) -> Headline {
-    (round_date(offer.date), offer.game, offer.kind, offer.outcomes.len())
+    (round_date(offer.date), offer.game, offer.kind, get_dimension(offer))
+}
+
+// Suspended outcomes aren't counted, so a book with a suspended leg is matched with the one
+// offering only the active ones.
+#[inline]
+fn get_dimension(offer: &Offer) -> usize {
+    let active = offer.outcomes.iter().filter(|o| o.is_active());
+
+    if DRAW_OPTIONAL_GAMES.iter().any(|selector| selector.matches(offer.game)) {
+        active.filter(|o| o.0 != DRAW).count()
+    } else {
+        active.count()
+    }
+}
+
+#[inline]
+fn is_comparable(outcome: &Outcome) -> bool {
+    outcome.0 != DRAW && outcome.is_active()
 }
 
+// A coarse summary of titles: the first characters of their tokens. Any similarity between two
+// titles requires a common first character of tokens (abbreviations are matched by them too),
+// so offers with disjoint fingerprints are never considered equal by `compare_offers`.
+pub type Fingerprint = u64;
+
+pub fn get_fingerprint(offer: &Offer) -> Fingerprint {
+    offer.outcomes.iter()
+        .filter(|o| o.0 != DRAW)
+        .flat_map(|o| title_tokens(&o.0))
+        .filter_map(|token| token.into_iter().next())
+        .fold(0, |fingerprint, c| fingerprint | 1 << (c as u32 % 64))
+}
+
+#[inline]
+pub fn may_match(left: Fingerprint, right: Fingerprint) -> bool {
+    left & right != 0
+}
+
+// How the matcher sees a token of a title, for debugging.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TokenInfo {
+    pub text: String,
+    pub normalized: String,
+    pub is_abbr: bool,
+    pub len: usize
+}
+
+impl<'a> From<Token<'a>> for TokenInfo {
+    fn from(token: Token) -> TokenInfo {
+        TokenInfo {
+            text: token.0.to_owned(),
+            normalized: token.into_iter().collect(),
+            is_abbr: token.is_abbr(),
+            len: token.len()
+        }
+    }
+}
+
+// Retained tokens of each title (after aliasing and removing stop words), draws are skipped.
+pub fn analyze(offer: &Offer) -> Vec<(String, Vec<TokenInfo>)> {
+    offer.outcomes.iter()
+        .filter(|o| o.0 != DRAW)
+        .map(|o| (o.0.clone(), title_tokens(&o.0).map(TokenInfo::from).collect()))
+        .collect()
+}
+
+// Why offers are (not) considered the same. Scores are the similarity of titles in [0, 1].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comparison {
+    HeadlineMismatch,
+    LeagueMismatch,
+    FormatMismatch,
+    LowSimilarity(f64),
+    Match(f64)
+}
+
+impl Comparison {
+    #[inline]
+    pub fn is_match(&self) -> bool {
+        match *self {
+            Comparison::Match(_) => true,
+            _ => false
+        }
+    }
+}
+
+#[inline]
 pub fn compare_offers(left: &Offer, right: &Offer) -> bool {
+    compare_offers_explain(left, right).is_match()
+}
+
+#[inline]
+pub fn compare_offers_explain(left: &Offer, right: &Offer) -> Comparison {
+    compare(left, right, REQUIRE_LEAGUE_MATCH)
+}
+
+fn compare(left: &Offer, right: &Offer, require_league: bool) -> Comparison {
     debug_assert!(left.outcomes.len() <= 3);
     debug_assert!(right.outcomes.len() <= 3);
 
     if get_headline(left) != get_headline(right) {
-        return false;
+        return Comparison::HeadlineMismatch;
+    }
+
+    if require_league && !leagues_agree(left, right) {
+        return Comparison::LeagueMismatch;
+    }
+
+    if DISTINGUISH_SERIES_FORMATS && !formats_agree(left.best_of, right.best_of) {
+        return Comparison::FormatMismatch;
     }
 
     let mut score = 0.;
-    let max_score = left.outcomes.iter().filter(|o| o.0 != DRAW).count() as f64;
+    let max_score = left.outcomes.iter().filter(|o| is_comparable(o)).count() as f64;
     let mut reserved = [3; 3];
 
     // We receive up to 1.0 points for each title.
-    for (i, left_outcome) in left.outcomes.iter().filter(|o| o.0 != DRAW).enumerate() {
+    for (i, left_outcome) in left.outcomes.iter().filter(|o| is_comparable(o)).enumerate() {
         let mut max_sim = 0.;
         let mut best_match = 0;
 
-        for (k, right_outcome) in right.outcomes.iter().filter(|o| o.0 != DRAW).enumerate() {
+        for (k, right_outcome) in right.outcomes.iter().filter(|o| is_comparable(o)).enumerate() {
             if reserved.contains(&k) {
                 continue;
             }
@@ -110,33 +229,99 @@ pub fn compare_offers(left: &Offer, right: &Offer) -> bool {
         score += max_sim;
     }
 
-    (score / max_score) >= 0.7
+    // Offers consisting of draws (or suspended outcomes) only can't be compared.
+    if max_score == 0. {
+        return Comparison::LowSimilarity(0.);
+    }
+
+    let score = score / max_score;
+
+    if score >= 0.7 {
+        Comparison::Match(score)
+    } else {
+        Comparison::LowSimilarity(score)
+    }
+}
+
+// Offers without a league are considered to agree with any other.
+fn leagues_agree(left: &Offer, right: &Offer) -> bool {
+    match (left.league.as_ref(), right.league.as_ref()) {
+        (Some(left), Some(right)) => titles_sim(left, right) >= MIN_LEAGUE_SIMILARITY,
+        _ => true
+    }
+}
+
+// Offers without the known format are considered to agree with any other.
+#[inline]
+pub fn formats_agree(left: Option<u8>, right: Option<u8>) -> bool {
+    match (left, right) {
+        (Some(left), Some(right)) => left == right,
+        _ => true
+    }
 }
 
 #[inline]
 fn titles_sim(left: &str, right: &str) -> f64 {
-    tokens_sim(left, right).max(tokens_sim(right, left))
+    if USE_TITLE_ALIASES {
+        aliased_titles_sim(left, right)
+    } else {
+        plain_titles_sim(left, right, UNVALID_TOKENS)
+    }
+}
+
+// Canonical titles are spelled out in full, so stop words can be the only difference between
+// them (e.g. "Manchester City" and "Manchester United"). Hence they are kept if any is aliased.
+fn aliased_titles_sim(left: &str, right: &str) -> f64 {
+    match (find_alias(left), find_alias(right)) {
+        (None, None) => plain_titles_sim(left, right, UNVALID_TOKENS),
+        (l, r) => plain_titles_sim(l.unwrap_or(left), r.unwrap_or(right), &[])
+    }
+}
+
+#[inline]
+fn plain_titles_sim(left: &str, right: &str, stop_words: &[&str]) -> f64 {
+    tokens_sim(left, right, stop_words).max(tokens_sim(right, left, stop_words))
+}
+
+#[inline]
+fn find_alias(title: &str) -> Option<&'static str> {
+    ALIASES.get(&title.trim().to_lowercase()).cloned()
+}
+
+// Tokens of the title as they are compared, stop words of aliased titles are retained.
+fn title_tokens<'a>(title: &'a str) -> impl Iterator<Item = Token<'a>> {
+    let alias = if USE_TITLE_ALIASES { find_alias(title) } else { None };
+
+    match alias {
+        Some(canonical) => get_tokens(canonical, &[]),
+        None => get_tokens(title, UNVALID_TOKENS)
+    }
 }
 
 #[inline]
 fn coefs_sim(lhs: f64, rhs: f64) -> f64 {
+    // Both selections can be suspended.
+    if lhs + rhs == 0. {
+        return 1.;
+    }
+
     1. - (lhs - rhs).abs() / (lhs + rhs) // ultra formula :|
 }
 
 // Calculates how much tokens from the left string fits to the right one
-fn tokens_sim(left: &str, right: &str) -> f64 {
+fn tokens_sim(left: &str, right: &str, stop_words: &[&str]) -> f64 {
     let mut score = 0.;
 
-    for lhs in get_tokens(left) {
+    for lhs in get_tokens(left, stop_words) {
         let mut max_score = 0.0_f64;
 
-        for rhs in get_tokens(right) {
+        for rhs in get_tokens(right, stop_words) {
             let score = if lhs == rhs {
                 1.
             } else if lhs.len() > 3 && lhs.starts_with(rhs) {
                 rhs.len() as f64 / lhs.len() as f64
             } else if lhs.is_abbr() {
-                abbreviation_sim(lhs, right)
+                abbreviation_sim(lhs, right, stop_words)
             } else {
                 0.
             };
@@ -147,23 +332,67 @@ fn tokens_sim(left: &str, right: &str) -> f64 {
         score += max_score;
     }
 
-    score / get_tokens(left).count() as f64
+    score / get_tokens(left, stop_words).count() as f64
 }
 
-fn get_tokens<'a>(title: &'a str) -> impl Iterator<Item = Token<'a>> {
+fn get_tokens<'a, 'b>(title: &'a str, stop_words: &'b [&'b str])
+    -> impl Iterator<Item = Token<'a>> + 'b
+    where 'a: 'b
+{
     title
         .split(|c: char| c.is_whitespace() || c == '-' || c == '/')
-        .filter(|s| !UNVALID_TOKENS.contains(&s.to_lowercase().as_str()))
+        .flat_map(Segments)
+        .filter(move |s| !stop_words.contains(&s.to_lowercase().as_str()))
         .map(Token::from)
         .filter(|token| !token.is_empty())
 }
 
-fn abbreviation_sim(abbr: Token, title: &str) -> f64 {
+// Scripts without spaces (CJK) are compared character by character, so every such character
+// is a separate segment. Other characters are left as is.
+struct Segments<'a>(&'a str);
+
+impl<'a> Iterator for Segments<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        let mut chars = self.0.char_indices();
+
+        let first = match chars.next() {
+            Some((_, c)) => c,
+            None => return None
+        };
+
+        let end = if is_cjk(first) {
+            first.len_utf8()
+        } else {
+            chars.find(|&(_, c)| is_cjk(c)).map_or(self.0.len(), |(index, _)| index)
+        };
+
+        let (segment, rest) = self.0.split_at(end);
+        self.0 = rest;
+
+        Some(segment)
+    }
+}
+
+#[inline]
+fn is_cjk(c: char) -> bool {
+    match c {
+        '\u{3040}'...'\u{30ff}' |     // Hiragana and Katakana.
+        '\u{3400}'...'\u{4dbf}' |     // CJK Unified Ideographs Extension A.
+        '\u{4e00}'...'\u{9fff}' |     // CJK Unified Ideographs.
+        '\u{ac00}'...'\u{d7af}' |     // Hangul Syllables.
+        '\u{f900}'...'\u{faff}' => true,  // CJK Compatibility Ideographs.
+        _ => false
+    }
+}
+
+fn abbreviation_sim(abbr: Token, title: &str, stop_words: &[&str]) -> f64 {
     let mut abbr_it = abbr.into_iter();
     let mut letter = abbr_it.next().unwrap();
     let mut matched = 0usize;
 
-    for token in get_tokens(title) {
+    for token in get_tokens(title, stop_words) {
         let first_char = token.into_iter().next().unwrap();
 
         if letter == first_char {
@@ -184,30 +413,108 @@ fn round_date(ts: u32) -> u32 {
     (ts + 15 * 60) / (30 * 60) * (30 * 60)
 }
 
+// Dates (inclusive) considered the same as the date by matching.
+pub fn get_date_range(ts: u32) -> (u32, u32) {
+    let rounded = round_date(ts);
+    (rounded.saturating_sub(15 * 60), rounded + 15 * 60 - 1)
+}
+
+// Whether the offer has an outcome similar to the title, e.g. placed on the same fixture before.
+pub fn has_title(offer: &Offer, title: &str) -> bool {
+    offer.outcomes.iter()
+        .filter(|o| o.0 != DRAW)
+        .any(|o| titles_sim(&o.0, title) >= 0.7)
+}
+
 // Sorts outcomes according to some etalon offer.
+// A two-way offer collated against a three-way etalon gets a draw with zero coef.
+// Outcomes are matched by similarity, roles only break ties between equally similar titles.
 pub fn collate_outcomes<'a>(etalon: &[Outcome], outcomes: &'a [Outcome]) -> Vec<&'a Outcome> {
-    let mut result = outcomes.iter().collect::<Vec<_>>();
+    arrange_outcomes(&collate_order(etalon, outcomes), outcomes)
+}
+
+// The same as `collate_outcomes`, but returns indices of outcomes. The index equal to the number
+// of outcomes stands for the missing draw.
+pub fn collate_order(etalon: &[Outcome], outcomes: &[Outcome]) -> Vec<usize> {
+    let mut candidates = outcomes.iter().collect::<Vec<_>>();
+
+    // Either the draw is missing or the etalon has a suspended leg the other book doesn't offer.
+    if candidates.len() < etalon.len() {
+        debug_assert!(!outcomes.iter().any(|o| o.0 == DRAW) ||
+                      etalon.iter().any(|o| !o.is_active()));
+        candidates.push(&*MISSING_DRAW);
+    }
+
+    // Each outcome is assigned at most once, even if several ones have the same title.
+    let mut used = vec![false; candidates.len()];
+    let mut order = Vec::with_capacity(candidates.len());
+
+    for outcome in etalon {
+        let index = draw_outcome(outcome, &candidates, &used)
+            .or_else(|| most_similar_outcome(outcome, &candidates, &used));
+
+        if let Some(index) = index {
+            used[index] = true;
+            order.push(index);
+        }
+    }
 
-    for (i, outcome) in etalon.iter().enumerate() {
-        let index = i + most_similar_outcome(outcome, &result[i..]);
+    for index in 0..candidates.len() {
+        if !used[index] {
+            order.push(index);
+        }
+    }
+
+    order
+}
 
-        result.swap(i, index);
+pub fn arrange_outcomes<'a>(order: &[usize], outcomes: &'a [Outcome]) -> Vec<&'a Outcome> {
+    order.iter().map(|&index| outcomes.get(index).unwrap_or(&*MISSING_DRAW)).collect()
+}
+
+// Draws are titled differently by books, but the role identifies them unambiguously.
+fn draw_outcome(lhs: &Outcome, outcomes: &[&Outcome], used: &[bool]) -> Option<usize> {
+    if lhs.2 != Some(Role::Draw) {
+        return None;
     }
 
-    result
+    (0..outcomes.len()).find(|&i| !used[i] && outcomes[i].2 == Some(Role::Draw))
 }
 
-// Finds most similar outcome and returns its index in slice.
-fn most_similar_outcome(lhs: &Outcome, outcomes: &[&Outcome]) -> usize {
-    let mut max_sim = 0.;
-    let mut index = 0;
+// Home and away are swapped by some books, so they can't override titles.
+#[inline]
+fn roles_agree(lhs: &Outcome, rhs: &Outcome) -> bool {
+    match (lhs.2, rhs.2) {
+        (Some(Role::Other), _) | (None, _) => false,
+        (left, right) => left == right
+    }
+}
+
+// Finds most similar unused outcome and returns its index in slice.
+fn most_similar_outcome(lhs: &Outcome, outcomes: &[&Outcome], used: &[bool]) -> Option<usize> {
+    let title_sims = outcomes.iter().map(|rhs| titles_sim(&lhs.0, &rhs.0)).collect::<Vec<_>>();
+
+    let best_title_sim = (0..outcomes.len())
+        .filter(|&i| !used[i])
+        .fold(-1., |max, i| title_sims[i].max(max));
+
+    let mut max_sim = -1.;
+    let mut index = None;
 
     for (i, rhs) in outcomes.iter().enumerate() {
-        let sim = titles_sim(&lhs.0, &rhs.0) * 0.8 + coefs_sim(lhs.1, rhs.1) * 0.2;
+        if used[i] {
+            continue;
+        }
+
+        let mut sim = title_sims[i] * 0.8 + coefs_sim(lhs.1, rhs.1) * 0.2;
+
+        if best_title_sim - title_sims[i] < TITLE_SIM_TIE && roles_agree(lhs, rhs) {
+            sim += 1.;
+        }
 
         if sim > max_sim {
             max_sim = sim;
-            index = i;
+            index = Some(i);
         }
     }
 
@@ -218,16 +525,20 @@ fn most_similar_outcome(lhs: &Outcome, outcomes: &[&Outcome]) -> usize {
 mod tests {
     use time;
 
-    use markets::{DRAW, Offer, Outcome, Game, Kind};
+    use markets::{DRAW, Offer, Outcome, Role, Game, Kind};
     use super::{compare_offers, collate_outcomes, titles_sim, round_date, abbreviation_sim, Token};
+    use super::{aliased_titles_sim, UNVALID_TOKENS};
+    use super::{get_date_range, has_title, leagues_agree, get_fingerprint, may_match, compare};
+    use super::{compare_offers_explain, Comparison, analyze, collate_order};
 
     macro_rules! offer {
         ( $( $team_name:expr, $coef:expr ),* ) => { Offer {
             date: 123,
             outcomes: vec![
-                $( Outcome($team_name.to_string(), $coef), )*
+                $( Outcome::new($team_name, $coef), )*
             ],
-            oid: 123, game: Game::Darts, kind: Kind::Series
+            oid: 123, game: Game::Darts, kind: Kind::Series, league: None, is_live: false,
+            best_of: None
         }}
     }
 
@@ -305,6 +616,31 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn fingerprints_of_matched_offers() {
+        let pairs = [
+            (offer!("Gilles Simon", 1.48, "Julien Benneteau", 2.93),
+             offer!("G. Simon", 1.41, "J. Benneteau", 2.74)),
+            (offer!("North Carolina Tar Heels", 1.24, "North Carolina State Wolfpack", 4.62),
+             offer!("North Carolina", 1.22, "NC State", 4.6)),
+            (offer!("Belgrano", 1.85, "Sarmiento de Junin", 5., DRAW, 3.),
+             offer!("Belgrano de Cordoba", 1.75, DRAW, 3.34, "Sarmiento", 5.72)),
+            (offer!("Internazionale Milano", 2.08, "Fiorentina", 3.96, DRAW, 3.58),
+             offer!("Inter Milan", 2.06, DRAW, 3.55, "Fiorentina", 3.79))
+        ];
+
+        for &(ref left, ref right) in &pairs {
+            assert!(compare_offers(left, right));
+            assert!(may_match(get_fingerprint(left), get_fingerprint(right)));
+        }
+
+        let left = offer!("Astralis", 1.5, "Fnatic", 2.5);
+        let right = offer!("Cloud9", 1.5, "Liquid", 2.5);
+
+        assert!(!may_match(get_fingerprint(&left), get_fingerprint(&right)));
+        assert!(!may_match(get_fingerprint(&offer!(DRAW, 1.)), get_fingerprint(&offer!(DRAW, 1.))));
+    }
+
     #[test]
     fn compare_offers_with_similar_beginnings() {
         assert!(!compare_offers(
@@ -451,6 +787,65 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn compare_two_way_and_three_way_offers() {
+        let mut two_way = offer!("Natus Vincere", 1.5, "Fnatic", 2.6);
+        let mut three_way = offer!("Natus Vincere", 1.6, DRAW, 9., "Fnatic", 2.4);
+
+        assert!(!compare_offers(&two_way, &three_way));
+
+        two_way.game = Game::CounterStrike;
+        three_way.game = Game::CounterStrike;
+
+        assert!(compare_offers(&two_way, &three_way));
+        assert!(compare_offers(&three_way, &two_way));
+    }
+
+    #[test]
+    fn compare_offers_with_suspended_legs() {
+        let two_way = offer!("Phil Taylor", 1.5, "Gary Anderson", 2.6);
+        let suspended = offer!("Phil Taylor", 1.6, DRAW, 0., "Gary Anderson", 2.4);
+        let three_way = offer!("Phil Taylor", 1.6, DRAW, 9., "Gary Anderson", 2.4);
+
+        assert!(!compare_offers(&two_way, &three_way));
+        assert!(compare_offers(&two_way, &suspended));
+        assert!(compare_offers(&suspended, &two_way));
+
+        assert_eq!(collate_outcomes(&suspended.outcomes, &two_way.outcomes), vec![
+            &two_way.outcomes[0],
+            &Outcome::new(DRAW, 0.),
+            &two_way.outcomes[1]
+        ]);
+
+        // Only active outcomes are compared.
+        let left = offer!("Phil Taylor", 1.6, "Michael van Gerwen", 0., "Gary Anderson", 2.4);
+        let right = offer!("Phil Taylor", 1.5, "Gary Anderson", 2.6);
+
+        assert_eq!(compare_offers_explain(&left, &right), Comparison::Match(1.));
+    }
+
+    #[test]
+    fn collate_two_way_against_three_way() {
+        assert_eq!(
+            collate_outcomes(
+                &[
+                    Outcome::new("Natus Vincere", 1.6),
+                    Outcome::new(DRAW, 9.),
+                    Outcome::new("Fnatic", 2.4)
+                ],
+                &[
+                    Outcome::new("Natus Vincere", 1.5),
+                    Outcome::new("Fnatic", 2.6)
+                ]
+            ),
+            vec![
+                &Outcome::new("Natus Vincere", 1.5),
+                &Outcome::new(DRAW, 0.),
+                &Outcome::new("Fnatic", 2.6)
+            ]
+        );
+    }
+
     #[test]
     fn test_round_date() {
         fn to_unix(time: &str) -> u32 {
@@ -472,67 +867,292 @@ mod tests {
         assert_eq!(
             collate_outcomes(
                 &[
-                    Outcome("Wolfsberger Ac".to_string(), 18.),
-                    Outcome(DRAW.to_string(), 4.15),
-                    Outcome("FK Austria Wien".to_string(), 1.25)
+                    Outcome::new("Wolfsberger Ac", 18.),
+                    Outcome::new(DRAW, 4.15),
+                    Outcome::new("FK Austria Wien", 1.25)
                 ],
                 &[
-                    Outcome("Wolfsberger AC".to_string(), 2.61),
-                    Outcome(DRAW.to_string(), 3.28),
-                    Outcome("Austria Wien".to_string(), 2.81)
+                    Outcome::new("Wolfsberger AC", 2.61),
+                    Outcome::new(DRAW, 3.28),
+                    Outcome::new("Austria Wien", 2.81)
                 ]
             ),
             vec![
-                &Outcome("Wolfsberger AC".to_string(), 2.61),
-                &Outcome(DRAW.to_string(), 3.28),
-                &Outcome("Austria Wien".to_string(), 2.81)
+                &Outcome::new("Wolfsberger AC", 2.61),
+                &Outcome::new(DRAW, 3.28),
+                &Outcome::new("Austria Wien", 2.81)
             ]
         );
 
         assert_eq!(
             collate_outcomes(
                 &[
-                    Outcome("Kansas".to_string(), 17.89),
-                    Outcome("Kansas State".to_string(), 1.02)
+                    Outcome::new("Kansas", 17.89),
+                    Outcome::new("Kansas State", 1.02)
                 ],
                 &[
-                    Outcome("Kansas State Wildcats".to_string(), 1.03),
-                    Outcome("Kansas Jayhawks".to_string(), 20.)
+                    Outcome::new("Kansas State Wildcats", 1.03),
+                    Outcome::new("Kansas Jayhawks", 20.)
                 ]
             ),
             vec![
-                &Outcome("Kansas Jayhawks".to_string(), 20.),
-                &Outcome("Kansas State Wildcats".to_string(), 1.03)
+                &Outcome::new("Kansas Jayhawks", 20.),
+                &Outcome::new("Kansas State Wildcats", 1.03)
             ]
         );
 
         assert_eq!(
             collate_outcomes(
                 &[
-                    Outcome("Mississippi State".to_string(), 3.98),
-                    Outcome("Mississippi Rebels".to_string(), 1.296)
+                    Outcome::new("Mississippi State", 3.98),
+                    Outcome::new("Mississippi Rebels", 1.296)
                 ],
                 &[
-                    Outcome("Mississippi".to_string(), 1.27),
-                    Outcome("Mississippi".to_string(), 4.03)
+                    Outcome::new("Mississippi", 1.27),
+                    Outcome::new("Mississippi", 4.03)
                 ]
             ),
             vec![
-                &Outcome("Mississippi".to_string(), 4.03),
-                &Outcome("Mississippi".to_string(), 1.27)
+                &Outcome::new("Mississippi", 4.03),
+                &Outcome::new("Mississippi", 1.27)
             ]
         );
     }
 
+    #[test]
+    fn collate_duplicate_titles() {
+        let etalon = [
+            Outcome::new("Mississippi", 1.27),
+            Outcome::new("Mississippi", 4.03)
+        ];
+
+        let outcomes = [
+            Outcome::new("Mississippi", 4.1),
+            Outcome::new("Mississippi", 1.25)
+        ];
+
+        let collated = collate_outcomes(&etalon, &outcomes);
+
+        assert_eq!(collated, vec![&outcomes[1], &outcomes[0]]);
+
+        // Indistinguishable outcomes are still assigned one-to-one.
+        let same = [
+            Outcome::new("Mississippi", 2.),
+            Outcome::new("Mississippi", 2.)
+        ];
+
+        let collated = collate_outcomes(&etalon, &same);
+
+        assert_eq!(collated.len(), 2);
+        assert!(collated[0] as *const _ != collated[1] as *const _);
+    }
+
+    #[test]
+    fn collate_by_roles() {
+        let etalon = [
+            Outcome::new("Mississippi", 1.27).role(Role::Home),
+            Outcome::new("Mississippi", 4.03).role(Role::Away)
+        ];
+
+        // Coefs have been flipped since, so similarity alone picks the wrong order.
+        let outcomes = [
+            Outcome::new("Mississippi", 1.25).role(Role::Away),
+            Outcome::new("Mississippi", 4.1).role(Role::Home)
+        ];
+
+        assert_eq!(collate_outcomes(&etalon, &outcomes), vec![&outcomes[1], &outcomes[0]]);
+
+        let unlabelled = [
+            Outcome::new("Mississippi", 1.25),
+            Outcome::new("Mississippi", 4.1)
+        ];
+
+        let collated = collate_outcomes(&etalon, &unlabelled);
+        assert!(collated[0] as *const _ == &unlabelled[0] as *const _);
+        assert!(collated[1] as *const _ == &unlabelled[1] as *const _);
+
+        let three_way = [
+            Outcome::new("Natus Vincere", 1.6).role(Role::Home),
+            Outcome::new(DRAW, 9.).role(Role::Draw),
+            Outcome::new("Fnatic", 2.4).role(Role::Away)
+        ];
+
+        let two_way = [
+            Outcome::new("Fnatic", 2.6).role(Role::Away),
+            Outcome::new("Natus Vincere", 1.5).role(Role::Home)
+        ];
+
+        assert_eq!(collate_outcomes(&three_way, &two_way), vec![
+            &two_way[1], &Outcome::new(DRAW, 0.), &two_way[0]
+        ]);
+    }
+
+    #[test]
+    fn collate_by_titles_despite_roles() {
+        let etalon = [
+            Outcome::new("Natus Vincere", 1.6).role(Role::Home),
+            Outcome::new("Fnatic", 2.4).role(Role::Away)
+        ];
+
+        // The book has swapped home and away, titles must win.
+        let outcomes = [
+            Outcome::new("Natus Vincere", 1.5).role(Role::Away),
+            Outcome::new("Fnatic", 2.6).role(Role::Home)
+        ];
+
+        assert_eq!(collate_order(&etalon, &outcomes), vec![0, 1]);
+    }
+
     #[test]
     fn compare_titles() {
         assert!(titles_sim("HC La Chaux De Fonds", "SCL Tigers") <= 0.3);
     }
 
+    #[test]
+    fn compare_aliases() {
+        assert_eq!(aliased_titles_sim("Man Utd", "Manchester United"), 1.);
+        assert_eq!(aliased_titles_sim("navi", "Natus Vincere"), 1.);
+        assert_eq!(aliased_titles_sim("Spurs", "Tottenham Hotspur"), 1.);
+
+        // "City" is a stop word, but it's the only difference between canonical titles.
+        assert!(aliased_titles_sim("Man Utd", "Manchester City") < 0.7);
+        assert!(aliased_titles_sim("Man Utd", "Man City") < 0.7);
+        assert_eq!(aliased_titles_sim("Man City", "Manchester City FC"), 1.);
+
+        // Titles without aliases are compared as usual.
+        assert_eq!(aliased_titles_sim("Lokomotiv", "FC Lokomotiv"),
+                   titles_sim("Lokomotiv", "FC Lokomotiv"));
+    }
+
+    #[test]
+    fn compare_leagues() {
+        let with_league = |league: Option<&str>| {
+            let mut offer = offer!("Arsenal", 1.9, "Chelsea", 2.1);
+            offer.league = league.map(str::to_owned);
+            offer
+        };
+
+        let premier = with_league(Some("England. Premier League"));
+        let reserves = with_league(Some("England. U23 Development League"));
+        let youth = with_league(Some("UEFA Youth League"));
+
+        // Teams are the same, so only leagues prevent the match.
+        assert!(compare(&premier, &reserves, false).is_match());
+        assert_eq!(compare(&premier, &reserves, true), Comparison::LeagueMismatch);
+        assert_eq!(compare(&reserves, &youth, true), Comparison::LeagueMismatch);
+        assert!(compare(&premier, &with_league(Some("Premier League")), true).is_match());
+        assert!(compare(&premier, &with_league(None), true).is_match());
+
+        assert!(leagues_agree(&premier, &with_league(Some("Premier League"))));
+        assert!(!leagues_agree(&premier, &reserves));
+        assert!(!leagues_agree(&premier, &youth));
+        assert!(!leagues_agree(&reserves, &youth));
+        assert!(leagues_agree(&premier, &with_league(None)));
+    }
+
+    #[test]
+    fn explain_comparisons() {
+        let left = offer!("Natus Vincere", 1.6, "Virtus.pro", 2.3);
+
+        assert_eq!(compare_offers_explain(&left, &left), Comparison::Match(1.));
+
+        let mut later = left.clone();
+        later.date += 2 * 3600;
+        assert_eq!(compare_offers_explain(&left, &later), Comparison::HeadlineMismatch);
+
+        let mut other_game = left.clone();
+        other_game.game = Game::Dota2;
+        assert_eq!(compare_offers_explain(&left, &other_game), Comparison::HeadlineMismatch);
+
+        let mut bo5 = left.clone();
+        bo5.best_of = Some(5);
+        let mut bo3 = left.clone();
+        bo3.best_of = Some(3);
+        assert_eq!(compare_offers_explain(&bo3, &bo5), Comparison::FormatMismatch);
+
+        let other = offer!("Natus Vincere", 1.6, "Fnatic", 2.3);
+
+        match compare_offers_explain(&left, &other) {
+            Comparison::LowSimilarity(score) => assert_eq!(score, 0.5),
+            comparison => panic!("Unexpected {:?}", comparison)
+        }
+    }
+
+    #[test]
+    fn analyze_titles() {
+        let analysis = analyze(&offer!("Sporting Club de Braga", 2.1, DRAW, 3.2, "PSG FC", 3.5));
+        let summary = analysis.iter()
+            .map(|&(ref title, ref tokens)| {
+                (title.as_str(), tokens.iter()
+                    .map(|t| (t.normalized.as_str(), t.is_abbr, t.len))
+                    .collect::<Vec<_>>())
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(summary, vec![
+            ("Sporting Club de Braga", vec![("sporting", false, 8), ("braga", false, 5)]),
+            ("PSG FC", vec![("psg", true, 3)])
+        ]);
+
+        assert_eq!(analysis[1].1[0].text, "PSG");
+    }
+
+    #[test]
+    fn compare_series_formats() {
+        let best_of = |count| {
+            let mut offer = offer!("Natus Vincere", 1.6, "Virtus.pro", 2.3);
+            offer.best_of = count;
+            offer
+        };
+
+        assert!(!compare_offers(&best_of(Some(3)), &best_of(Some(5))));
+        assert!(compare_offers(&best_of(Some(3)), &best_of(Some(3))));
+        assert!(compare_offers(&best_of(Some(5)), &best_of(None)));
+        assert!(compare_offers(&best_of(None), &best_of(None)));
+    }
+
+    #[test]
+    fn compare_cjk_titles() {
+        assert_eq!(titles_sim("北京国安", "北京国安足球俱乐部"), 1.);
+        assert_eq!(titles_sim("北京国安", "上海申花"), 0.);
+        assert!(titles_sim("浦和レッズ", "浦和レッドダイヤモンズ") >= 0.7);
+        assert!(titles_sim("FC東京", "ガンバ大阪") < 0.7);
+
+        assert!(compare_offers(
+            &offer!("北京国安", 2.1, "上海申花", 1.8),
+            &offer!("北京国安足球俱乐部", 2.05, "上海申花", 1.85)
+        ));
+
+        assert!(!compare_offers(
+            &offer!("北京国安", 2.1, "上海申花", 1.8),
+            &offer!("广州恒大", 2.05, "山东鲁能", 1.85)
+        ));
+    }
+
     #[test]
     fn compare_abbrs() {
-        assert_eq!(abbreviation_sim(Token::from("KL"), "Kek Lol"), 1.);
-        assert_eq!(abbreviation_sim(Token::from("KL"), "Kek Shmek Lol"), 1.);
-        assert_eq!(abbreviation_sim(Token::from("KKL"), "Kek Lol"), 1./3.);
+        assert_eq!(abbreviation_sim(Token::from("KL"), "Kek Lol", UNVALID_TOKENS), 1.);
+        assert_eq!(abbreviation_sim(Token::from("KL"), "Kek Shmek Lol", UNVALID_TOKENS), 1.);
+        assert_eq!(abbreviation_sim(Token::from("KKL"), "Kek Lol", UNVALID_TOKENS), 1./3.);
+    }
+
+    #[test]
+    fn date_range() {
+        let (from, to) = get_date_range(3600 + 10 * 60);
+
+        assert_eq!(round_date(from), round_date(3600 + 10 * 60));
+        assert_eq!(round_date(to), round_date(3600 + 10 * 60));
+        assert!(round_date(from - 1) != round_date(from));
+        assert!(round_date(to + 1) != round_date(to));
+    }
+
+    #[test]
+    fn title_of_fixture() {
+        let offer = offer!("Natus Vincere", 1.5, DRAW, 4.2, "Team Liquid", 2.7);
+
+        assert!(has_title(&offer, "Natus Vincere"));
+        assert!(has_title(&offer, "Liquid"));
+        assert!(!has_title(&offer, "Virtus.pro"));
+        assert!(!has_title(&offer, DRAW));
     }
 }
@@ -1,38 +1,120 @@
-use std::thread;
+use std::thread::{self, JoinHandle};
 use std::sync::mpsc::{self, Sender, Receiver};
 use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering::Relaxed;
+use std::time::{Duration, Instant};
+use std::borrow::Cow;
+use std::path::Path;
+use std::collections::HashMap;
+use parking_lot::{Mutex, RwLock};
+use log::LogLevel;
 use time;
 
-use constants::{TABLE_CAPACITY, CHECK_TIMEOUT, BASE_STAKE, MAX_STAKE, MIN_PROFIT, MAX_PROFIT};
-use constants::ACCOUNTS;
+use constants::{TABLE_CAPACITY, CHECK_TIMEOUT, LIVE_CHECK_TIMEOUT};
+use constants::{MIN_BOOKIE_BALANCE, PREFER_FEWER_BOOKIES, FEWER_BOOKIES_TOLERANCE, MAX_COEF};
+use constants::{MIN_LEG_COEF, MAX_DATE_DELTA, SLOW_START_FRACTION, MIN_MATCH_AGE};
+use constants::{STRATEGIES, STRATEGY_OBJECTIVE, STRATEGY_TIE_TOLERANCE};
+use constants::{EQUAL_PROFIT_STAKES, MAX_STAKE_INFLATION, WITHIN_BOOK_ARBS};
+use constants::DROP_NEGATIVE_OVERROUND;
+use constants::INCREMENTAL_COLLATION;
+use constants::{USE_FREE_BETS, MAX_OPEN_COMBOS, MARGIN_BUFFER, STALENESS_THRESHOLD};
+use constants::PLACEMENT_THREADS;
+use constants::{MAX_CHECK_DURATION, REPORT_COOLDOWN, DEGRADATION_GRACE};
+use constants::{LARGE_COMBO_STAKE, CONFIRMATION_WINDOW, VOID_RISKS};
+use constants::{EVENT_SUBSCRIBERS, EVENT_QUEUE_SIZE, FUZZY_DEDUPLICATION, LOG_FAIR_ODDS};
+use constants::{SKIP_FAILED_BOOKIES, FAILURE_COOLDOWN, TRACE_PLACEMENTS, TRACE_DIR};
+use constants::{ACCOUNTS, BLACKOUT_WINDOWS, BOOKIE_PRIORITIES, RECONCILIATION_PERIOD};
+use constants::{CHECK_CASHOUTS, CASHOUT_ALERT_FRACTION};
+use constants::{CHECK_CLOCK_SKEW, MAX_CLOCK_SKEW, CLOCK_CHECK_PERIOD};
+use constants::{OFFER_SINK, OFFER_SINK_QUEUE, GLANCE_RETRIES, GLANCE_RETRY_INTERVAL};
+use base::error::Result;
 use base::currency::Currency;
+use base::profit::Profit;
 use base::barrier::Barrier;
-use markets::{Offer, Outcome, DRAW};
+use base::semaphore::Semaphore;
+use base::pool::{Pool, Job};
+use base::skew::{ClockSkew, CLOCK_SKEW};
+use base::cooldown::Cooldown;
+use base::confirmation::Confirmation;
+use base::broadcast::Broadcast;
+use base::timers::Periodic;
+use base::session;
+use markets::{OID, Offer, Outcome, DRAW};
+use gamblers::{OpenBet, BetStatus, Placement};
 use combo::{self, Combo, Bet};
 
 pub use self::bookie::Bookie;
 pub use self::bookie::Stage as BookieStage;
+pub use self::bookie::BalanceChange;
 pub use self::table::Table;
+pub use self::thresholds::Thresholds;
+pub use self::matcher::TokenInfo;
 
-use self::opportunity::{Strategy, MarkedOutcome};
+pub use self::opportunity::{Strategy, Objective};
+
+use self::opportunity::MarkedOutcome;
+use self::trace::PlacementTrace;
+use self::collation::Collations;
+use self::sink::Sink;
+use self::acceptance::Acceptance;
+use self::timeline::{PlacementTimeline, Phase};
+use self::bookie::Placed;
 
 #[derive(Clone)]
 pub struct MarkedOffer(pub &'static Bookie, pub Offer);
 
+// Notable changes to be shown on the status page immediately.
+#[derive(Debug, Clone)]
+pub enum Event {
+    Opportunity(String),
+    Combo(String),
+    Stage(String, BookieStage)
+}
+
 mod matcher;
 mod bookie;
 mod table;
 mod opportunity;
+mod blackout;
+mod trace;
+mod collation;
+mod thresholds;
+mod sink;
+mod acceptance;
+mod timeline;
 
 lazy_static! {
     pub static ref BOOKIES: Vec<Bookie> = init_bookies();
     pub static ref TABLE: Table = Table::new(TABLE_CAPACITY);
+    pub static ref EVENTS: Broadcast<Event> = Broadcast::new(EVENT_QUEUE_SIZE, EVENT_SUBSCRIBERS);
+    static ref COMBO_SLOTS: Option<Semaphore> = MAX_OPEN_COMBOS.map(Semaphore::new);
+    static ref PLACEMENT_POOL: Pool = Pool::new("placement", PLACEMENT_THREADS);
+    static ref GAMBLERS: Mutex<HashMap<String, JoinHandle<()>>> = Mutex::new(HashMap::new());
+    static ref CHANNEL: Mutex<Option<Sender<Offer>>> = Mutex::new(None);
+    static ref REPORTED: Cooldown<MarketKey> = Cooldown::new(REPORT_COOLDOWN);
+    static ref CONFIRMED: Confirmation<MarketKey> = Confirmation::new(CONFIRMATION_WINDOW);
+    static ref COLLATIONS: Collations = Collations::new(TABLE_CAPACITY);
+    static ref THRESHOLDS: RwLock<Thresholds> = RwLock::new(Thresholds::new());
+    static ref SINK: Option<Sink> = OFFER_SINK.and_then(|target| {
+        Sink::new(target, OFFER_SINK_QUEUE)
+            .map_err(|error| error!("The offer sink is disabled: {}", error))
+            .ok()
+    });
 }
 
+type MarketKey = (matcher::Headline, Vec<OID>);
+
 pub fn run() {
     let (tx, rx) = mpsc::channel();
 
     accumulation(tx);
+    reconciliation();
+
+    if CHECK_CLOCK_SKEW {
+        clock_check();
+    }
+
     resolution(rx);
 }
 
@@ -42,32 +124,222 @@ fn init_bookies() -> Vec<Bookie> {
 
 fn accumulation(chan: Sender<Offer>) {
     for bookie in BOOKIES.iter() {
-        let tx = chan.clone();
+        spawn_gambler(bookie, chan.clone());
+    }
+
+    *CHANNEL.lock() = Some(chan);
+}
+
+fn spawn_gambler(bookie: &'static Bookie, chan: Sender<Offer>) {
+    let handle = thread::Builder::new()
+        .name(bookie.host.clone())
+        .spawn(move || run_gambler(bookie, chan))
+        .unwrap();
+
+    GAMBLERS.lock().insert(bookie.host.clone(), handle);
+}
+
+#[inline]
+pub fn thresholds() -> Thresholds {
+    *THRESHOLDS.read()
+}
+
+// Applied to the next evaluated market. Invalid values are rejected as a whole.
+pub fn update_thresholds(pairs: &[(String, String)]) -> Result<Thresholds> {
+    let updated = try!(thresholds::update(&THRESHOLDS, pairs));
+    info!("Thresholds are updated: {:?}", updated);
+    Ok(updated)
+}
+
+// Stops the gambler (its offers are drained) and spawns it again. Returns false if there is no
+// such bookie. The restart itself is performed in the background.
+pub fn restart_gambler(host: &str) -> bool {
+    let bookie = match BOOKIES.iter().find(|b| b.host == host) {
+        Some(bookie) => bookie,
+        None => return false
+    };
+
+    thread::Builder::new()
+        .name(format!("{} supervisor", host))
+        .spawn(move || {
+            info!("Restarting {}...", bookie.host);
+
+            let handle = match GAMBLERS.lock().remove(&bookie.host) {
+                Some(handle) => handle,
+                None => {
+                    warn!("{} is already being restarted", bookie.host);
+                    return;
+                }
+            };
+
+            bookie.request_stop();
+            handle.thread().unpark();
+            let _ = handle.join();
+
+            bookie.reset();
+
+            let chan = CHANNEL.lock().clone().expect("Accumulation isn't started");
+            spawn_gambler(bookie, chan);
+        })
+        .unwrap();
+
+    true
+}
+
+fn reconciliation() {
+    thread::Builder::new()
+        .name("reconciliation".to_owned())
+        .spawn(|| for _ in Periodic::new(RECONCILIATION_PERIOD) {
+            for bookie in BOOKIES.iter().filter(|b| b.stage() == BookieStage::Running) {
+                if !bookie.features().open_bets {
+                    continue;
+                }
+
+                reconcile(bookie);
+            }
+        })
+        .unwrap();
+}
+
+fn clock_check() {
+    thread::Builder::new()
+        .name("clock check".to_owned())
+        .spawn(|| for _ in Periodic::new(CLOCK_CHECK_PERIOD) {
+            is_clock_sane(&CLOCK_SKEW);
+        })
+        .unwrap();
+}
+
+fn is_clock_sane(skew: &ClockSkew) -> bool {
+    if skew.is_within(MAX_CLOCK_SKEW) {
+        return true;
+    }
+
+    error!("The local clock is off by {}s from bookies' ones, no bets are placed until fixed",
+           skew.get().unwrap());
+
+    false
+}
+
+fn reconcile(bookie: &Bookie) {
+    let open = match bookie.open_bets() {
+        Some(bets) => bets,
+        None => return
+    };
+
+    let now = time::get_time().sec as u32;
+    let placed = combo::load_receipt_ids(&bookie.host, now);
+    let pending = combo::load_pending_receipt_ids(&bookie.host, now);
+
+    for id in &pending {
+        let placement = if open.iter().any(|bet| bet.id == *id) {
+            Some(Placement::Confirmed)
+        } else {
+            bookie.bet_status(id)
+        };
+
+        match placement {
+            Some(Placement::Confirmed) => {
+                info!("Pending bet #{} by {} is confirmed", id, bookie.host);
+                combo::confirm_receipt(&bookie.host, id);
+            },
+            Some(Placement::Rejected(reason)) => {
+                let message = format!("Pending bet #{} by {} is rejected: {}", id, bookie.host,
+                                      reason);
+
+                error!("{}", message);
+                EVENTS.send(Event::Combo(message));
+
+                // The bet isn't resolved again, the stake is returned to the balance.
+                combo::reject_receipt(&bookie.host, id);
+                bookie.refresh_balance();
+            },
+            // It's checked again by the next reconciliation.
+            _ => debug!("Pending bet #{} by {} is still pending", id, bookie.host)
+        }
+    }
+
+    let confirmed = placed.iter().filter(|id| !pending.contains(id));
+
+    for id in confirmed.filter(|id| open.iter().all(|bet| bet.id != **id)) {
+        warn!("Bet #{} by {} is placed, but not reported as open", id, bookie.host);
+    }
+
+    for bet in open.iter().filter(|bet| !placed.contains(&bet.id)) {
+        warn!("Bet #{} ({} for {} at {}) by {} is unknown", bet.id, bet.stake, bet.selection,
+              bet.coef, bookie.host);
+    }
+
+    if CHECK_CASHOUTS && bookie.features().cashout {
+        check_cashouts(bookie, &open, &placed);
+    }
+}
+
+fn check_cashouts(bookie: &Bookie, open: &[OpenBet], placed: &[String]) {
+    let ours = open.iter().filter(|bet| bet.status == BetStatus::Open && placed.contains(&bet.id));
+
+    for bet in ours {
+        let value = match bookie.cashout_value(&bet.id) {
+            Some(value) => value,
+            None => continue
+        };
 
-        thread::Builder::new()
-            .name(bookie.host.clone())
-            .spawn(move || run_gambler(bookie, tx))
-            .unwrap();
+        let payout = bet.stake * bet.coef;
+
+        if is_worth_cashout(value, payout, CASHOUT_ALERT_FRACTION) {
+            let message = format!("Bet #{} ({} for {}) by {} can be cashed out for {} (payout: {})",
+                                  bet.id, bet.stake, bet.selection, bookie.host, value, payout);
+
+            warn!("{}", message);
+            EVENTS.send(Event::Combo(message));
+        }
     }
 }
 
+#[inline]
+fn is_worth_cashout(value: Currency, payout: Currency, fraction: f64) -> bool {
+    value > payout * fraction
+}
+
 fn run_gambler(bookie: &'static Bookie, chan: Sender<Offer>) {
     struct Guard(&'static Bookie);
 
     impl Drop for Guard {
         fn drop(&mut self) {
-            degradation(self.0);
+            let bookie = self.0;
+            let is_final = bookie.is_stopping() || bookie.stage() == BookieStage::Aborted;
+
+            if is_final || *DEGRADATION_GRACE == Duration::new(0, 0) {
+                degradation(bookie);
+            } else {
+                defer_degradation(bookie, *DEGRADATION_GRACE);
+            }
         }
     }
 
-    loop {
+    while !bookie.is_stopping() && bookie.stage() != BookieStage::Aborted {
         let _guard = Guard(bookie);
 
         bookie.watch(|offer, upsert| {
+            if let Some(ref sink) = *SINK {
+                sink.publish(&bookie.host, &offer, upsert);
+            }
+
             let marked = MarkedOffer(bookie, offer.clone());
 
-            if upsert {
-                if TABLE.update_offer(marked) >= 2 {
+            // Such offers are upserted again on every update, so only the first drop of the offer
+            // from the table is warned about.
+            if upsert && DROP_NEGATIVE_OVERROUND && has_negative_overround(&offer) {
+                if is_in_table(&marked) {
+                    warn!("Dropping {} by {}: the overround is negative", offer, bookie.host);
+                    TABLE.remove_offer(&marked);
+                } else {
+                    debug!("Skipping {} by {}: the overround is negative", offer, bookie.host);
+                }
+            } else if upsert {
+                let market_len = TABLE.update_offer(marked);
+
+                if market_len >= 2 || WITHIN_BOOK_ARBS && is_within_book_arb(&offer) {
                     chan.send(offer).unwrap();
                 }
             } else {
@@ -87,33 +359,251 @@ fn degradation(bookie: &'static Bookie) {
     }
 }
 
+// Offers are drained only if the bookie hasn't resumed running within the grace period.
+fn defer_degradation(bookie: &'static Bookie, grace: Duration) {
+    let connections = bookie.connections();
+
+    thread::spawn(move || {
+        thread::sleep(grace);
+
+        if bookie.connections() == connections {
+            degradation(bookie);
+        } else {
+            debug!("{} has reconnected, its offers are kept", bookie.host);
+        }
+    });
+}
+
+#[derive(Debug, PartialEq)]
+enum Refresh { Keep, Replace(Offer), Remove }
+
+// Gone offers are removed, lagging ones are replaced by the bookie's latest ones. Otherwise,
+// the live check decides whether the offer is still actual. A failed check (e.g. a network
+// error) proves nothing, so the offer is kept then.
+fn plan_refresh<F>(offer: &Offer, latest: Option<Offer>, is_live: F) -> Refresh
+    where F: FnOnce() -> Option<bool>
+{
+    match latest {
+        None => Refresh::Remove,
+        Some(latest) if latest != *offer => Refresh::Replace(latest),
+        Some(_) if is_live() == Some(false) => Refresh::Remove,
+        Some(_) => Refresh::Keep
+    }
+}
+
+// Revalidates offers of the market containing the offer by the host with the oid (ids are unique
+// only within a bookie). Returns the refreshed market (empty if all offers are removed) or `None`
+// if there is no such market.
+pub fn refresh_market(host: &str, oid: OID) -> Option<Vec<MarkedOffer>> {
+    let is_target = |m: &MarkedOffer| m.0.host == host && m.1.oid == oid;
+
+    let market = match TABLE.iter().find(|market| market.iter().any(|m| is_target(m))) {
+        Some(market) => market.to_vec(),
+        None => return None
+    };
+
+    let stake = thresholds().base_stake;
+    let mut remaining = Vec::with_capacity(market.len());
+
+    for marked in market {
+        let bookie = marked.0;
+        let offer = &marked.1;
+
+        // Any outdated outcome is definitive, failed checks leave the result unknown.
+        let is_live = || {
+            let mut live = Some(true);
+
+            for outcome in offer.outcomes.iter().filter(|outcome| outcome.is_active()) {
+                match bookie.check_offer(offer, outcome, stake) {
+                    Some(false) => return Some(false),
+                    Some(true) => {},
+                    None => live = None
+                }
+            }
+
+            live
+        };
+
+        match plan_refresh(offer, bookie.get_offer(offer.oid), is_live) {
+            Refresh::Keep => remaining.push(marked.clone()),
+            Refresh::Replace(latest) => {
+                info!("{} by {} is replaced by {}", offer, bookie.host, latest);
+                TABLE.remove_offer(&marked);
+                TABLE.update_offer(MarkedOffer(bookie, latest.clone()));
+                remaining.push(MarkedOffer(bookie, latest));
+            },
+            Refresh::Remove => {
+                info!("{} by {} is removed as outdated", offer, bookie.host);
+                TABLE.remove_offer(&marked);
+                bookie.forget_offer(offer.oid);
+            }
+        }
+    }
+
+    Some(remaining)
+}
+
+// How the matcher tokenizes titles of offers (by all bookies) with the id.
+pub fn analyze_offer(oid: OID) -> Vec<(MarkedOffer, Vec<(String, Vec<TokenInfo>)>)> {
+    TABLE.iter()
+        .flat_map(|market| market.to_vec())
+        .filter(|marked| marked.1.oid == oid)
+        .map(|marked| {
+            let analysis = matcher::analyze(&marked.1);
+            (marked, analysis)
+        })
+        .collect()
+}
+
 fn resolution(chan: Receiver<Offer>) {
     for offer in chan {
         if let Some(market) = TABLE.get_market(&offer) {
-            realize_market(&*market);
+            realize_market(&*market, market.matched_at(), TABLE.now());
         }
     }
 
     info!("Channel has hung up!");
 }
 
-fn realize_market(market: &[MarkedOffer]) {
-    if market.len() < 2 {
+// The time is taken from the clock of the table, the same one dating matches.
+fn realize_market(market: &[MarkedOffer], matched_at: u32, now: u32) {
+    let healthy = if SKIP_FAILED_BOOKIES {
+        exclude_failed(market, now, FAILURE_COOLDOWN)
+    } else {
+        None
+    };
+
+    let market = healthy.as_ref().map_or(market, |healthy| &healthy[..]);
+
+    let within_book = market.len() == 1 && WITHIN_BOOK_ARBS && is_within_book_arb(&market[0].1);
+
+    if market.len() < 2 && !within_book {
         return;
     }
 
+    if within_book {
+        debug!("  The offer is mispriced by {} itself", market[0].0.host);
+    }
+
     if let Some(marked) = market.iter().find(|m| m.0.stage() != BookieStage::Running) {
         warn!("Bookie {} isn't running, but the table contains offer(s) by it", marked.0.host);
         return;
     }
 
+    let plan = match evaluate_market(market, &Conditions::current(now, matched_at)) {
+        Some(plan) => plan,
+        None => return
+    };
+
+    let pairs = plan.outcomes.iter().map(|o| (&market[o.market], o)).collect::<Vec<_>>();
+    let check_timeout = if plan.is_live { *LIVE_CHECK_TIMEOUT } else { *CHECK_TIMEOUT };
+
+    place_bets(&pairs, &plan.stakes, plan.free_leg, check_timeout, plan.timeline,
+               COMBO_SLOTS.as_ref());
+}
+
+// Recently failed bookies are often flaky for a while, so their offers are excluded. Returns
+// `None` if there is nothing to exclude.
+fn exclude_failed(market: &[MarkedOffer], now: u32, cooldown: u32) -> Option<Vec<MarkedOffer>> {
+    if !market.iter().any(|m| m.0.is_cooling_down(now, cooldown)) {
+        return None;
+    }
+
+    Some(market.iter().filter(|m| !m.0.is_cooling_down(now, cooldown)).cloned().collect())
+}
+
+// What the evaluation of a market depends on besides the market itself. In production these are
+// the constants and the global state, tests substitute their own.
+struct Conditions<'a> {
+    now: u32,
+    matched_at: u32,
+    min_match_age: u32,
+    thresholds: Thresholds,
+    min_leg_coef: f64,
+    max_date_delta: u32,
+    void_risks: &'a [(&'a str, f64)],
+    large_combo_stake: Currency,
+    confirmations: &'a Confirmation<MarketKey>,
+    clock_skew: Option<&'a ClockSkew>,
+    blackout_windows: &'a [(&'a str, &'a str)],
+    has_bets: fn(&[MarkedOffer]) -> bool
+}
+
+impl Conditions<'static> {
+    fn current(now: u32, matched_at: u32) -> Conditions<'static> {
+        Conditions {
+            now: now,
+            matched_at: matched_at,
+            min_match_age: MIN_MATCH_AGE,
+            thresholds: thresholds(),
+            min_leg_coef: MIN_LEG_COEF,
+            max_date_delta: MAX_DATE_DELTA,
+            void_risks: VOID_RISKS,
+            large_combo_stake: *LARGE_COMBO_STAKE,
+            confirmations: &*CONFIRMED,
+            clock_skew: if CHECK_CLOCK_SKEW { Some(&*CLOCK_SKEW) } else { None },
+            blackout_windows: BLACKOUT_WINDOWS,
+            has_bets: has_bets_on_market
+        }
+    }
+}
+
+// Bets chosen to be placed on the market. Their stakes are held already.
+struct Plan<'a> {
+    outcomes: Vec<MarkedOutcome<'a>>,
+    stakes: Vec<Currency>,
+    free_leg: Option<usize>,
+    is_live: bool,
+    timeline: Arc<PlacementTimeline>
+}
+
+fn evaluate_market<'a>(market: &'a [MarkedOffer], conditions: &Conditions) -> Option<Plan<'a>> {
+    let now = conditions.now;
+    let balances = market.iter().map(|m| m.0.usable_balance()).collect::<Vec<_>>();
+
+    if let Some(idx) = find_underfunded(&balances, *MIN_BOOKIE_BALANCE) {
+        debug!("Skipping the market: {} has only {}", market[idx].0.host, balances[idx]);
+        return None;
+    }
+
+    let dates = market.iter().map(|m| m.1.date).collect::<Vec<_>>();
+
+    if let Some((early, late)) = find_date_disagreement(&dates, conditions.max_date_delta) {
+        debug!("Skipping the market: {} by {} starts {}s later than {} by {}",
+               market[late].1, market[late].0.host, dates[late] - dates[early],
+               market[early].1, market[early].0.host);
+        return None;
+    }
+
+    // Clamped coefs are used only to evaluate the market, bets are placed on the original ones.
+    let clamped = market.iter().map(|marked| {
+        let clamped = opportunity::clamp_coefs(&marked.1.outcomes, MAX_COEF);
+
+        if let Cow::Owned(_) = clamped {
+            warn!("Clamping coefs of {} by {} to x{}", marked.1, marked.0.host, MAX_COEF);
+        }
+
+        clamped
+    }).collect::<Vec<_>>();
+
     let mut table: Vec<Vec<_>> = Vec::with_capacity(market.len());
-    let etalon = &market[0].1.outcomes;
 
-    table.push(etalon.iter().collect());
+    // Prefer a three-way etalon, because two-way offers can be collated against it.
+    let etalon_idx = (0..market.len()).fold(0, |best, idx| {
+        if clamped[idx].len() > clamped[best].len() { idx } else { best }
+    });
+
+    let etalon = &clamped[etalon_idx];
 
-    for marked in &market[1..] {
-        table.push(matcher::collate_outcomes(etalon, &marked.1.outcomes));
+    for (idx, offer_outcomes) in clamped.iter().enumerate() {
+        if idx == etalon_idx {
+            table.push(etalon.iter().collect());
+        } else if INCREMENTAL_COLLATION {
+            let MarkedOffer(bookie, ref offer) = market[idx];
+            table.push(COLLATIONS.collate(&bookie.host, offer.oid, etalon, offer_outcomes));
+        } else {
+            table.push(matcher::collate_outcomes(etalon, offer_outcomes));
+        }
     }
 
     debug!("Checking market:");
@@ -122,196 +612,633 @@ fn realize_market(market: &[MarkedOffer]) {
         debug!("    {} by {}", offer, bookie.host);
     }
 
+    if !opportunity::is_hedgeable(&table) {
+        debug!("  Some outcomes are suspended (or lay-only) by all bookies");
+        return None;
+    }
+
     let margin = opportunity::calc_margin(&table);
 
-    if margin >= 1. {
-        debug!("  Opportunity doesn't exist (effective margin: {:.2})", margin);
-        return;
+    if LOG_FAIR_ODDS {
+        log_fair_odds(market, &table);
+    }
+
+    if !opportunity::is_pursuable(margin, MARGIN_BUFFER) {
+        debug!("  Opportunity doesn't exist (effective margin: {:.3})", margin);
+        return None;
+    }
+
+    let ranks = rank_bookies(market);
+    let strategy = STRATEGIES.first().cloned().unwrap_or(Strategy::Unbiased);
+
+    let (strategy, outcomes) = if STRATEGIES.len() > 1 {
+        opportunity::find_best_multi(&table, &ranks, STRATEGIES, STRATEGY_OBJECTIVE,
+                                     STRATEGY_TIE_TOLERANCE, &balances)
+    } else if PREFER_FEWER_BOOKIES {
+        let outcomes = opportunity::find_best_narrow(&table, &ranks, FEWER_BOOKIES_TOLERANCE,
+                                                     WITHIN_BOOK_ARBS, strategy);
+        (strategy, outcomes)
+    } else {
+        (strategy, opportunity::find_best(&table, &ranks, strategy))
+    };
+
+    let mut outcomes = unclamp_outcomes(market, &clamped, outcomes);
+
+    if let Some(idx) = find_heavy_favorite(&outcomes, conditions.min_leg_coef) {
+        debug!("  Skipping the opportunity: {} by {} (x{:.2}) is below x{:.2}",
+               outcomes[idx].outcome.0, market[outcomes[idx].market].0.host,
+               outcomes[idx].outcome.1, conditions.min_leg_coef);
+        return None;
     }
 
-    let outcomes = opportunity::find_best(&table, Strategy::Unbiased);
     let mut min_profit = 1. / 0.;
     let mut max_profit = 0.;
 
-    info!("  Opportunity exists [{:?}] {:?} (effective margin: {:.2}), unbiased strategy:",
-          (market[0].1).game, (market[0].1).kind, margin);
+    // A persisting opportunity is reported once per the cooldown.
+    let key: MarketKey = (matcher::get_headline(&market[0].1),
+                          market.iter().map(|m| m.1.oid).collect());
+    let report = REPORTED.check(key.clone(), now);
+    let level = if report { LogLevel::Info } else { LogLevel::Debug };
+
+    if report {
+        EVENTS.send(Event::Opportunity(format!("[{:?}] {:?} (effective margin: {:.2})",
+                                               (market[0].1).game, (market[0].1).kind, margin)));
+    }
+
+    log!(level, "  Opportunity exists [{:?}] {:?} (effective margin: {:.2}), {} strategy:",
+         (market[0].1).game, (market[0].1).kind, margin,
+         format!("{:?}", strategy).to_lowercase());
 
     for &MarkedOutcome { market: m, outcome, rate, profit } in &outcomes {
         let host = &market[m].0.host;
 
-        info!("    Place {:.2} on {} by {} (coef: x{:.2}, profit: {:+.1}%)",
-              rate, outcome.0, host, outcome.1, profit * 100.);
+        log!(level, "    Place {:.2} on {} by {} (coef: x{:.2}, profit: {})",
+             rate, outcome.0, host, outcome.1, Profit(profit));
+
+        let profit = opportunity::risk_adjusted(profit, void_risk(conditions.void_risks, host));
 
         if profit < min_profit { min_profit = profit }
         if profit > max_profit { max_profit = profit }
     }
 
-    if MIN_PROFIT <= min_profit && min_profit <= MAX_PROFIT {
-        // TODO(loyd): drop offers instead of whole market.
-        if !no_bets_on_market(market) {
-            return;
+    let is_live = market.iter().any(|m| m.1.is_live);
+    let thresholds = if is_live { conditions.thresholds.for_live() } else { conditions.thresholds };
+
+    if !thresholds.accepts(min_profit) {
+        if max_profit > thresholds.max_profit {
+            warn!("Suspiciously high profit ({})", Profit(max_profit));
+        } else {
+            debug!("  Too small profit (min: {}, max: {})", Profit(min_profit), Profit(max_profit));
         }
 
-        let pairs = outcomes.iter().map(|o| (&market[o.market], o)).collect::<Vec<_>>();
+        return None;
+    }
 
-        let stakes = match distribute_currency(&pairs) {
-            Some(stakes) => stakes,
-            None => return
-        };
+    let timeline = Arc::new(PlacementTimeline::new());
 
-        place_bets(&pairs, &stakes);
-    } else if max_profit > MAX_PROFIT {
-        warn!("Suspiciously high profit ({:+.1}%)", max_profit * 100.);
-    } else {
-        debug!("  Too small profit (min: {:+.1}%, max: {:+.1}%)",
-               min_profit * 100., max_profit * 100.);
+    // TODO(loyd): drop offers instead of whole market.
+    if (conditions.has_bets)(market) {
+        return None;
     }
-}
 
-fn no_bets_on_market(market: &[MarkedOffer]) -> bool {
-    // TODO(loyd): what about bulk checking?
-    !market.iter().any(|marked| combo::contains(&marked.0.host, marked.1.oid))
-}
+    if blackout::is_now(conditions.blackout_windows) {
+        info!("  Blackout window, the bets aren't placed");
+        return None;
+    }
 
-fn distribute_currency(pairs: &[(&MarkedOffer, &MarkedOutcome)]) -> Option<Vec<Currency>> {
-    let mut base_rate = pairs[0].1.rate;
+    let match_age = conditions.now.saturating_sub(conditions.matched_at);
 
-    for &(_, marked_outcome) in pairs {
-        if marked_outcome.rate < base_rate { base_rate = marked_outcome.rate }
+    if match_age < conditions.min_match_age {
+        info!("  The match isn't confirmed yet ({}s of {}s), the bets aren't placed",
+              match_age, conditions.min_match_age);
+        return None;
     }
 
-    let mut stakes = Vec::with_capacity(pairs.len());
+    if conditions.clock_skew.map_or(false, |skew| !is_clock_sane(skew)) {
+        return None;
+    }
 
-    for &(marked_offer, marked_outcome) in pairs {
-        let bookie = marked_offer.0;
-        let stake = marked_outcome.rate / base_rate * *BASE_STAKE;
+    // The free leg changes the rates, so it's chosen before distributing.
+    let free_leg = if USE_FREE_BETS {
+        choose_free_leg(market, &outcomes, thresholds.base_stake)
+    } else {
+        None
+    };
 
-        if stake > *MAX_STAKE {
-            warn!("Too high stake ({})", stake);
-            return None;
+    if let Some(idx) = free_leg {
+        opportunity::apply_free_bet(&mut outcomes, idx);
+
+        info!("  Using free bet on {} by {}, adjusted profits:",
+              outcomes[idx].outcome.0, market[outcomes[idx].market].0.host);
+
+        for marked in &outcomes {
+            info!("    {} (profit: {})", marked.outcome.0, Profit(marked.profit));
         }
+    }
 
-        let balance = bookie.balance();
+    let stakes = {
+        let pairs = outcomes.iter().map(|o| (&market[o.market], o)).collect::<Vec<_>>();
 
-        if stake > balance {
-            warn!("Not enough money on {} ({}, but required {})", bookie.host, balance, stake);
-            return None;
+        match distribute_currency(&pairs, &thresholds, free_leg) {
+            Some(stakes) => stakes,
+            None => return None
         }
+    };
 
-        stakes.push(stake);
-    }
+    // Large combos aren't placed on a single, possibly erroneous, evaluation.
+    let total = stakes.iter().fold(Currency(0), |sum, &stake| sum + stake);
+
+    if total > conditions.large_combo_stake && !conditions.confirmations.confirm(key, now) {
+        info!("  Large combo ({}), waiting for confirmation", total);
 
-    for (&(marked, _), &stake) in pairs.iter().zip(stakes.iter()) {
-        marked.0.hold_stake(stake);
+        for (idx, (marked, &stake)) in outcomes.iter().zip(stakes.iter()).enumerate() {
+            release_stake(market[marked.market].0, stake, free_leg == Some(idx));
+        }
+
+        return None;
     }
 
-    Some(stakes)
+    Some(Plan {
+        outcomes: outcomes,
+        stakes: stakes,
+        free_leg: free_leg,
+        is_live: is_live,
+        timeline: timeline
+    })
 }
 
-fn save_combo(pairs: &[(&MarkedOffer, &MarkedOutcome)], stakes: &[Currency]) {
-    debug_assert_eq!(pairs.len(), stakes.len());
+fn is_within_book_arb(offer: &Offer) -> bool {
+    let outcomes = opportunity::clamp_coefs(&offer.outcomes, MAX_COEF);
+    opportunity::is_within_book_arb(&outcomes, MARGIN_BUFFER)
+}
 
-    combo::save(Combo {
-        date: time::get_time().sec as u32,
-        game: format!("{:?}", (pairs[0].0).1.game),
-        kind: format!("{:?}", (pairs[0].0).1.kind),
-        bets: pairs.iter().zip(stakes.iter()).map(|(&(m, o), stake)| Bet {
-            host: m.0.host.clone(),
-            id: m.1.oid,
-            title: if o.outcome.0 == DRAW { None } else { Some(o.outcome.0.clone()) },
-            expiry: m.1.date,
-            coef: o.outcome.1,
-            stake: *stake,
-            profit: o.profit,
-            placed: false
-        }).collect()
-    });
+// Gamblers look up the outcome in the offer, so it must be the original one, not a clamped copy.
+fn unclamp_outcomes<'a>(market: &'a [MarkedOffer], clamped: &[Cow<[Outcome]>],
+                        outcomes: Vec<MarkedOutcome>) -> Vec<MarkedOutcome<'a>>
+{
+    outcomes.into_iter()
+        .map(|marked| {
+            let original = &market[marked.market].1.outcomes;
+
+            // Clamping preserves the order, the title is the fallback for the collated ones.
+            let idx = clamped[marked.market].iter()
+                .position(|outcome| outcome as *const _ == marked.outcome as *const _)
+                .or_else(|| original.iter().position(|outcome| outcome.0 == marked.outcome.0))
+                .expect("The chosen outcome isn't in the offer");
+
+            MarkedOutcome {
+                market: marked.market,
+                outcome: &original[idx],
+                rate: marked.rate,
+                profit: marked.profit
+            }
+        })
+        .collect()
 }
 
-fn place_bets(pairs: &[(&MarkedOffer, &MarkedOutcome)], stakes: &[Currency]) {
-    debug_assert_eq!(pairs.len(), stakes.len());
+fn is_in_table(marked: &MarkedOffer) -> bool {
+    TABLE.get_market(&marked.1).map_or(false, |market| {
+        market.iter().any(|stored| stored.0 == marked.0 && stored.1.oid == marked.1.oid)
+    })
+}
 
-    let barrier = Arc::new(Barrier::new(pairs.len() as u32 + 1));
+fn has_negative_overround(offer: &Offer) -> bool {
+    opportunity::overround(&offer.outcomes).map_or(false, |overround| overround < 1.)
+}
 
-    for (&(marked_offer, marked_outcome), &stake) in pairs.iter().zip(stakes.iter()) {
-        let bookie = marked_offer.0;
-        let offer = marked_offer.1.clone();
-        let outcome = marked_outcome.outcome.clone();
-        let barrier = barrier.clone();
+fn log_fair_odds(market: &[MarkedOffer], table: &[Vec<&Outcome>]) {
+    let fair = opportunity::fair_odds(table);
 
-        thread::spawn(move || {
-            place_bet(bookie, offer, outcome, stake, &*barrier);
-        });
-    }
+    let line = fair.iter().map(|p| format!("{:.1}%", p * 100.)).collect::<Vec<_>>();
+    info!("  Fair probabilities: {}", line.join(" "));
 
-    // Yes, twice: `glance + check` and `glance`.
-    if !barrier.wait_timeout(*CHECK_TIMEOUT) || !barrier.wait_timeout(*CHECK_TIMEOUT) {
-        warn!("The time is up");
-        return;
-    }
+    for (marked, row) in market.iter().zip(table.iter()) {
+        let quotes = row.iter().zip(fair.iter())
+            .map(|(outcome, p)| format!("x{:.2} (fair x{:.2})", outcome.1, 1. / p))
+            .collect::<Vec<_>>();
 
-    save_combo(&pairs, &stakes);
+        info!("    {}: {}", marked.0.host, quotes.join(" "));
+    }
+}
 
-    // Feuer Frei!
-    barrier.wait();
+fn void_risk(risks: &[(&str, f64)], host: &str) -> f64 {
+    risks.iter()
+        .find(|&&(h, _)| h == host)
+        .map_or(0., |&(_, risk)| risk)
 }
 
-fn place_bet(bookie: &'static Bookie, offer: Offer, outcome: Outcome, stake: Currency,
-             barrier: &Barrier)
-{
-    struct Guard {
-        bookie: &'static Bookie,
-        hold: Option<Currency>,
-        done: bool
-    }
+fn rank_bookies(market: &[MarkedOffer]) -> Vec<u32> {
+    let priority = |host: &str| BOOKIE_PRIORITIES.iter()
+        .find(|&&(h, _)| h == host)
+        .map_or(0, |&(_, priority)| priority);
 
-    impl Drop for Guard {
-        fn drop(&mut self) {
-            if !self.done {
-                degradation(self.bookie);
-            }
+    let mut order = (0..market.len()).collect::<Vec<_>>();
 
-            if let Some(stake) = self.hold {
-                self.bookie.release_stake(stake);
-            }
-        }
-    }
+    order.sort_by_key(|&idx| {
+        let bookie = market[idx].0;
+        (-priority(&bookie.host), -bookie.balance().0, bookie.host.clone())
+    });
 
-    let mut guard = Guard {
-        bookie: bookie,
-        hold: Some(stake),
-        done: false
-    };
+    let mut ranks = vec![0; market.len()];
 
-    if !bookie.glance_offer(&offer) {
-        error!("Ooops, one of the offers is rotten before the check!");
-        guard.done = true;
-        return;
+    for (rank, &idx) in order.iter().enumerate() {
+        ranks[idx] = rank as u32;
     }
 
-    match bookie.check_offer(&offer, &outcome, stake) {
-        Some(true) => {},
-        Some(false) => {
-            guard.done = true;
-            return;
+    ranks
+}
+
+fn find_underfunded(balances: &[Currency], floor: Currency) -> Option<usize> {
+    balances.iter().position(|&balance| balance < floor)
+}
+
+// The earliest and the latest offers if they're too far apart.
+fn find_date_disagreement(dates: &[u32], max_delta: u32) -> Option<(usize, usize)> {
+    let early = (0..dates.len()).min_by_key(|&idx| dates[idx]);
+    let late = (0..dates.len()).max_by_key(|&idx| dates[idx]);
+
+    match (early, late) {
+        (Some(early), Some(late)) if dates[late] - dates[early] > max_delta => Some((early, late)),
+        _ => None
+    }
+}
+
+fn find_heavy_favorite(outcomes: &[MarkedOutcome], floor: f64) -> Option<usize> {
+    outcomes.iter().position(|marked| marked.outcome.1 < floor)
+}
+
+fn has_bets_on_market(market: &[MarkedOffer]) -> bool {
+    has_bets_with(market, &combo::contains, &combo::load_titles)
+}
+
+// Lookups of bets are passed to be substituted by tests.
+fn has_bets_with(market: &[MarkedOffer], contains: &Fn(&str, u64) -> bool,
+                 load_titles: &Fn(&str, &str, u32, u32) -> Vec<String>) -> bool
+{
+    // TODO(loyd): what about bulk checking?
+    market.iter().any(|marked| {
+        contains(&marked.0.host, marked.1.oid) ||
+            FUZZY_DEDUPLICATION && has_bets_on_fixture(marked, load_titles)
+    })
+}
+
+// Bookies can reissue the same market with a new id.
+fn has_bets_on_fixture(marked: &MarkedOffer,
+                       load_titles: &Fn(&str, &str, u32, u32) -> Vec<String>) -> bool
+{
+    let offer = &marked.1;
+    let (from, to) = matcher::get_date_range(offer.date);
+    let titles = load_titles(&marked.0.host, &format!("{:?}", offer.game), from, to);
+
+    titles.iter().any(|title| matcher::has_title(offer, title))
+}
+
+// The free leg (if any) is held from the free balance.
+fn distribute_currency(pairs: &[(&MarkedOffer, &MarkedOutcome)], thresholds: &Thresholds,
+                       free_leg: Option<usize>) -> Option<Vec<Currency>>
+{
+    let coefs = pairs.iter().map(|&(_, o)| o.outcome.1).collect::<Vec<_>>();
+    let coefs = match free_leg {
+        Some(idx) => opportunity::free_bet_coefs(&coefs, idx),
+        None => coefs
+    };
+
+    let proposed = if EQUAL_PROFIT_STAKES {
+        opportunity::stakes_by_equal_profit(&coefs, thresholds.base_stake, MAX_STAKE_INFLATION)
+    } else {
+        let rates = pairs.iter().map(|&(_, o)| o.rate).collect::<Vec<_>>();
+        opportunity::stakes_by_rates(&rates, thresholds.base_stake)
+    };
+
+    // Legs on slow starting bookies are capped, the other ones are reduced in proportion.
+    let cap = thresholds.base_stake * SLOW_START_FRACTION;
+
+    let ratio = pairs.iter().zip(proposed.iter())
+        .filter(|&(&(marked, _), &stake)| stake > cap && marked.0.is_slow_starting())
+        .map(|(&(marked, _), &stake)| {
+            info!("  Slow start of {}: the stake {} is limited to {}", marked.0.host, stake, cap);
+            cap.0 as f64 / stake.0 as f64
+        })
+        .fold(1., f64::min);
+
+    let proposed = if ratio < 1. {
+        proposed.into_iter().map(|stake| stake * ratio).collect()
+    } else {
+        proposed
+    };
+
+    let mut stakes = Vec::with_capacity(pairs.len());
+
+    for (idx, (&(marked_offer, _), &stake)) in pairs.iter().zip(proposed.iter()).enumerate() {
+        let bookie = marked_offer.0;
+
+        if stake > thresholds.max_stake {
+            warn!("Too high stake ({})", stake);
+            return None;
+        }
+
+        if free_leg == Some(idx) {
+            if stake > bookie.free_balance() {
+                warn!("Not enough free bets on {} ({}, but required {})",
+                      bookie.host, bookie.free_balance(), stake);
+                return None;
+            }
+
+            stakes.push(stake);
+            continue;
+        }
+
+        // Several legs can be placed on the same bookie.
+        let planned = pairs[..idx].iter().zip(stakes.iter()).enumerate()
+            .filter(|&(i, (&(m, _), _))| m.0 == bookie && free_leg != Some(i))
+            .fold(Currency(0), |sum, (_, (_, &stake))| sum + stake);
+
+        let balance = bookie.usable_balance() - planned;
+
+        if stake > balance {
+            warn!("Not enough money on {} ({} besides the reserve, but required {})",
+                  bookie.host, balance, stake);
+            return None;
+        }
+
+        stakes.push(stake);
+    }
+
+    let legs = stakes.iter().cloned().zip(coefs.iter().cloned()).collect::<Vec<_>>();
+
+    // The free stake isn't an outlay.
+    let free_stake = free_leg.map_or(Currency(0), |idx| stakes[idx]);
+    let profit = opportunity::guaranteed_profit(&legs) + free_stake;
+
+    if profit < thresholds.min_absolute_profit {
+        info!("  Too low absolute profit ({}), the bets aren't placed", profit);
+        return None;
+    }
+
+    for (idx, (&(marked, _), &stake)) in pairs.iter().zip(stakes.iter()).enumerate() {
+        if free_leg == Some(idx) {
+            marked.0.hold_free_stake(stake);
+        } else {
+            marked.0.hold_stake(stake);
+        }
+    }
+
+    Some(stakes)
+}
+
+// The first leg whose bookie has enough free bets for the stake re-solved for the free bet.
+fn choose_free_leg(market: &[MarkedOffer], outcomes: &[MarkedOutcome],
+                   base: Currency) -> Option<usize>
+{
+    let coefs = outcomes.iter().map(|o| o.outcome.1).collect::<Vec<_>>();
+
+    (0..outcomes.len()).find(|&idx| {
+        let bookie = market[outcomes[idx].market].0;
+
+        if bookie.free_balance() <= Currency(0) {
+            return false;
+        }
+
+        let rates = opportunity::balanced_rates(&opportunity::free_bet_coefs(&coefs, idx));
+        bookie.free_balance() >= opportunity::stakes_by_rates(&rates, base)[idx]
+    })
+}
+
+fn release_stake(bookie: &Bookie, stake: Currency, free: bool) {
+    if free {
+        bookie.release_free_stake(stake);
+    } else {
+        bookie.release_stake(stake);
+    }
+}
+
+fn save_combo(pairs: &[(&MarkedOffer, &MarkedOutcome)], stakes: &[Currency]) {
+    debug_assert_eq!(pairs.len(), stakes.len());
+
+    combo::save(Combo {
+        date: time::get_time().sec as u32,
+        game: format!("{:?}", (pairs[0].0).1.game),
+        kind: format!("{:?}", (pairs[0].0).1.kind),
+        bets: pairs.iter().zip(stakes.iter()).map(|(&(m, o), stake)| Bet {
+            host: m.0.host.clone(),
+            id: m.1.oid,
+            title: if o.outcome.0 == DRAW { None } else { Some(o.outcome.0.clone()) },
+            expiry: m.1.date,
+            coef: o.outcome.1,
+            stake: *stake,
+            profit: o.profit,
+            placed: false
+        }).collect()
+    });
+}
+
+fn place_bets(pairs: &[(&MarkedOffer, &MarkedOutcome)], stakes: &[Currency],
+              free_leg: Option<usize>, check_timeout: Duration, timeline: Arc<PlacementTimeline>,
+              slots: Option<&'static Semaphore>)
+{
+    debug_assert_eq!(pairs.len(), stakes.len());
+
+    // The slot is shared by legs, so it's released once the last of them is done.
+    let slot = match slots.map(Semaphore::try_acquire) {
+        Some(None) => {
+            warn!("Skipping the combo: {} combos are already in progress",
+                  MAX_OPEN_COMBOS.unwrap());
+
+            for (idx, (&(marked, _), &stake)) in pairs.iter().zip(stakes.iter()).enumerate() {
+                release_stake(marked.0, stake, free_leg == Some(idx));
+            }
+
+            return;
+        },
+        slot => Arc::new(slot)
+    };
+
+    let barrier = Arc::new(Barrier::new(pairs.len() as u32 + 1));
+    let aborted = Arc::new(AtomicBool::new(false));
+    let acceptance = Arc::new(Acceptance::new());
+
+    let margin = pairs.iter().map(|&(_, o)| 1. / o.outcome.1).sum::<f64>();
+
+    let trace = if TRACE_PLACEMENTS {
+        let name = format!("{}-{}.log", time::get_time().sec, (pairs[0].0).1.oid);
+        Some(Arc::new(PlacementTrace::new(Path::new(TRACE_DIR).join(name))))
+    } else {
+        None
+    };
+
+    let mut legs: Vec<Job> = Vec::with_capacity(pairs.len());
+
+    for (idx, (&(marked_offer, marked_outcome), &stake)) in pairs.iter().zip(stakes.iter()).enumerate() {
+        let bookie = marked_offer.0;
+        let mut offer = Some(marked_offer.1.clone());
+        let mut outcome = Some(marked_outcome.outcome.clone());
+        let barrier = barrier.clone();
+        let aborted = aborted.clone();
+        let acceptance = acceptance.clone();
+        let free = free_leg == Some(idx);
+        let rest_margin = margin - 1. / marked_outcome.outcome.1;
+        let trace = trace.clone();
+        let timeline = timeline.clone();
+        let mut slot = Some(slot.clone());
+
+        legs.push(Box::new(move || {
+            if trace.is_some() {
+                session::start_tracing();
+            }
+
+            let (offer, outcome) = (offer.take().unwrap(), outcome.take().unwrap());
+            place_bet(bookie, offer, outcome, stake, free, rest_margin, check_timeout,
+                      &*barrier, &*aborted, &*acceptance, &*timeline, idx);
+
+            if let Some(ref trace) = trace {
+                trace.add(&bookie.host, session::finish_tracing());
+            }
+
+            slot.take();
+        }));
+    }
+
+    if !PLACEMENT_POOL.try_execute(legs) {
+        warn!("Skipping the combo: all {} placement threads are busy", PLACEMENT_THREADS);
+
+        for (idx, (&(marked, _), &stake)) in pairs.iter().zip(stakes.iter()).enumerate() {
+            release_stake(marked.0, stake, free_leg == Some(idx));
+        }
+
+        return;
+    }
+
+    let started = Instant::now();
+
+    // Yes, twice: `glance + check` and `glance`.
+    if !barrier.wait_timeout(check_timeout) || !barrier.wait_timeout(check_timeout) {
+        warn!("The time is up");
+        return;
+    }
+
+    // Odds on fast bookies could drift while waiting for slow ones.
+    let actual = confirm_if_slow(started.elapsed(), *MAX_CHECK_DURATION, || {
+        pairs.iter().all(|&(marked, _)| marked.0.glance_offer(&marked.1))
+    });
+
+    if actual {
+        save_combo(&pairs, &stakes);
+
+        let hosts = pairs.iter().map(|&(m, _)| m.0.host.as_str()).collect::<Vec<_>>();
+        EVENTS.send(Event::Combo(format!("[{:?}] {:?} by {}", (pairs[0].0).1.game,
+                                         (pairs[0].0).1.kind, hosts.join(", "))));
+    } else {
+        warn!("Some offers have changed during the slow check, aborting the combo");
+        aborted.store(true, Relaxed);
+    }
+
+    // Feuer Frei!
+    barrier.wait();
+}
+
+fn confirm_if_slow<F: FnOnce() -> bool>(elapsed: Duration, threshold: Duration, reglance: F) -> bool {
+    elapsed <= threshold || reglance()
+}
+
+fn glance_patiently(bookie: &Bookie, offer: &Offer, budget: Duration) -> bool {
+    retry_glance(|| bookie.glance_offer(offer), GLANCE_RETRIES, *GLANCE_RETRY_INTERVAL, budget)
+}
+
+// Retries are stopped early if the next one would exceed the budget.
+fn retry_glance<F: FnMut() -> bool>(mut glance: F, retries: u32, interval: Duration,
+                                    budget: Duration) -> bool
+{
+    let started = Instant::now();
+
+    for _ in 0..retries {
+        if glance() {
+            return true;
+        }
+
+        if started.elapsed() + interval > budget {
+            return false;
+        }
+
+        thread::sleep(interval);
+    }
+
+    glance()
+}
+
+fn place_bet(bookie: &'static Bookie, offer: Offer, outcome: Outcome, stake: Currency, free: bool,
+             rest_margin: f64, check_timeout: Duration, barrier: &Barrier, aborted: &AtomicBool,
+             acceptance: &Acceptance, timeline: &PlacementTimeline, leg: usize)
+{
+    struct Guard {
+        bookie: &'static Bookie,
+        hold: Option<Currency>,
+        free: bool,
+        done: bool
+    }
+
+    impl Drop for Guard {
+        fn drop(&mut self) {
+            if !self.done {
+                degradation(self.bookie);
+            }
+
+            if let Some(stake) = self.hold {
+                release_stake(self.bookie, stake, self.free);
+            }
+        }
+    }
+
+    let mut guard = Guard {
+        bookie: bookie,
+        hold: Some(stake),
+        free: free,
+        done: false
+    };
+
+    timeline.mark(leg, &bookie.host, Phase::ChecksStarted);
+
+    if !glance_patiently(bookie, &offer, check_timeout / 2) {
+        error!("Ooops, one of the offers is rotten before the check!");
+        guard.done = true;
+        return;
+    }
+
+    // Fresh odds aren't refreshed, but limits of the stake are checked anyway.
+    let checked = if bookie.is_fresh(&offer, *STALENESS_THRESHOLD) {
+        debug!("The offer {} by {} is fresh, only the stake is checked", offer, bookie.host);
+        bookie.check_stake(&offer, &outcome, stake)
+    } else if !bookie.features().check_offer {
+        debug!("{} doesn't support checking offers, the recheck is skipped", bookie.host);
+        Some(true)
+    } else {
+        bookie.check_offer(&offer, &outcome, stake)
+    };
+
+    match checked {
+        Some(true) => {},
+        Some(false) => {
+            guard.done = true;
+            return;
         },
         None => return
     }
 
     // Either the time is up or some thread fails.
-    if !barrier.wait_timeout(*CHECK_TIMEOUT) {
+    if !barrier.wait_timeout(check_timeout) {
         guard.done = true;
         return;
     }
 
-    if !bookie.glance_offer(&offer) {
+    if !glance_patiently(bookie, &offer, check_timeout / 2) {
         error!("Ooops, one of the offers is rotten after the check!");
         guard.done = true;
         return;
     }
 
+    timeline.mark(leg, &bookie.host, Phase::ChecksCompleted);
+
     // Some thread fails.
-    if !barrier.wait_timeout(*CHECK_TIMEOUT) {
+    if !barrier.wait_timeout(check_timeout) {
         guard.done = true;
         return;
     }
@@ -319,16 +1246,1001 @@ fn place_bet(bookie: &'static Bookie, offer: Offer, outcome: Outcome, stake: Cur
     // Wait the combo saving.
     barrier.wait();
 
+    if aborted.load(Relaxed) {
+        guard.done = true;
+        return;
+    }
+
     let oid = offer.oid;
     let title = outcome.0.clone();
     let opt_title = if title == DRAW { None } else { Some(title.as_str()) };
 
-    if !bookie.place_bet(offer, outcome, stake) {
-        return;
+    // Other legs could have been accepted partially. Free bets cannot be reduced.
+    let requested = stake;
+    let stake = if free { requested } else { acceptance.scale(requested) };
+
+    if stake < requested {
+        info!("Reducing the stake on {} from {} to {}", bookie.host, requested, stake);
+        bookie.release_stake(requested - stake);
+        guard.hold = Some(stake);
     }
 
+    timeline.mark(leg, &bookie.host, Phase::PlacementStarted);
+
+    let receipt = match bookie.place_bet(offer, outcome, stake, free) {
+        Placed::Confirmed(receipt) => {
+            timeline.mark(leg, &bookie.host, Phase::PlacementConfirmed);
+            receipt
+        },
+        Placed::Pending(receipt) => {
+            warn!("The bet on {} isn't confirmed yet, it's left to the reconciliation",
+                  bookie.host);
+            receipt
+        },
+        Placed::Failed(raw) => {
+            if let Some((request, response)) = raw {
+                combo::save_raw_bet(&bookie.host, oid, opt_title, &request, &response);
+            }
+
+            return;
+        }
+    };
+
     guard.hold = None;
     guard.done = true;
 
-    combo::mark_as_placed(&bookie.host, oid, opt_title);
+    if receipt.stake != stake {
+        warn!("{} has accepted {} instead of {}", bookie.host, receipt.stake, stake);
+    }
+
+    // Only placed legs end the slow start of the bookie, several legs on it count once.
+    if acceptance.record(&bookie.host, requested, receipt.stake) {
+        bookie.record_combo();
+    }
+
+    if rest_margin + 1. / receipt.coef >= 1. {
+        error!("{} has accepted x{:.2}, the combo isn't an arb anymore", bookie.host, receipt.coef);
+    }
+
+    combo::mark_as_placed(&bookie.host, oid, opt_title, &receipt);
+}
+
+#[test]
+fn test_has_negative_overround() {
+    use markets::{Game, Kind};
+
+    let offer = |coefs: &[f64]| Offer {
+        date: 0,
+        oid: 1,
+        game: Game::Football,
+        kind: Kind::Series,
+        league: None,
+        is_live: false,
+        best_of: None,
+        outcomes: coefs.iter().enumerate()
+            .map(|(i, &coef)| Outcome::new(&i.to_string(), coef))
+            .collect()
+    };
+
+    assert!(has_negative_overround(&offer(&[3.2, 3.9, 3.1])));
+    assert!(!has_negative_overround(&offer(&[2.5, 3.2, 2.9])));
+    assert!(!has_negative_overround(&offer(&[2.1, 0.])));
+}
+
+#[test]
+fn test_plan_refresh() {
+    use markets::{Game, Kind};
+
+    let offer = |coef| Offer {
+        date: 0,
+        oid: 1,
+        game: Game::Football,
+        kind: Kind::Series,
+        league: None,
+        is_live: false,
+        best_of: None,
+        outcomes: vec![Outcome::new("A", coef),
+                       Outcome::new("B", 2.)]
+    };
+
+    assert_eq!(plan_refresh(&offer(2.), Some(offer(2.)), || Some(true)), Refresh::Keep);
+    assert_eq!(plan_refresh(&offer(2.), Some(offer(1.8)), || Some(true)),
+               Refresh::Replace(offer(1.8)));
+    assert_eq!(plan_refresh(&offer(2.), Some(offer(2.)), || Some(false)), Refresh::Remove);
+    assert_eq!(plan_refresh(&offer(2.), None, || Some(true)), Refresh::Remove);
+
+    // A transient error of the check doesn't remove the offer.
+    assert_eq!(plan_refresh(&offer(2.), Some(offer(2.)), || None), Refresh::Keep);
+}
+
+#[test]
+fn test_is_worth_cashout() {
+    assert!(is_worth_cashout(Currency(480), Currency(500), 0.95));
+    assert!(!is_worth_cashout(Currency(470), Currency(500), 0.95));
+}
+
+#[test]
+fn test_is_clock_sane() {
+    let skew = ClockSkew::new();
+    skew.record(1000, 1000 + MAX_CLOCK_SKEW as i64);
+    assert!(is_clock_sane(&skew));
+
+    let skew = ClockSkew::new();
+    skew.record(1000, 1000 - MAX_CLOCK_SKEW as i64 - 1);
+    assert!(!is_clock_sane(&skew));
+}
+
+#[test]
+fn test_find_underfunded() {
+    let floor = Currency::from(1.00);
+
+    assert_eq!(find_underfunded(&[Currency::from(5.), Currency::from(3.)], floor), None);
+    assert_eq!(find_underfunded(&[Currency::from(5.), Currency::from(0.05)], floor), Some(1));
+    assert_eq!(find_underfunded(&[Currency::from(1.), Currency::from(1.)], floor), None);
+}
+
+#[test]
+fn test_find_date_disagreement() {
+    let max_delta = 20 * 60;
+
+    assert_eq!(find_date_disagreement(&[], max_delta), None);
+    assert_eq!(find_date_disagreement(&[3600, 3600 + 10 * 60], max_delta), None);
+    assert_eq!(find_date_disagreement(&[3600, 3600 + 20 * 60], max_delta), None);
+    assert_eq!(find_date_disagreement(&[3600 + 45 * 60, 3600, 3600 + 5 * 60], max_delta),
+               Some((1, 0)));
+}
+
+#[test]
+fn test_find_heavy_favorite() {
+    let favorite = Outcome::new("A", 1.02);
+    let outsider = Outcome::new("B", 60.);
+
+    let legs = [
+        MarkedOutcome { market: 0, outcome: &favorite, rate: 0.98, profit: 0.0004 },
+        MarkedOutcome { market: 1, outcome: &outsider, rate: 0.02, profit: 0.0004 }
+    ];
+
+    assert_eq!(find_heavy_favorite(&legs, 1.10), Some(0));
+    assert_eq!(find_heavy_favorite(&legs, 1.02), None);
+    assert_eq!(find_heavy_favorite(&legs[1..], 1.10), None);
+}
+
+#[test]
+fn test_confirm_if_slow() {
+    use std::cell::Cell;
+
+    let threshold = Duration::from_millis(500);
+    let calls = &Cell::new(0);
+    let reglance = |result| move || { calls.set(calls.get() + 1); result };
+
+    assert!(confirm_if_slow(Duration::from_millis(100), threshold, reglance(false)));
+    assert_eq!(calls.get(), 0);
+
+    assert!(confirm_if_slow(Duration::from_millis(700), threshold, reglance(true)));
+    assert!(!confirm_if_slow(Duration::from_millis(700), threshold, reglance(false)));
+    assert_eq!(calls.get(), 2);
+}
+
+#[test]
+fn test_retry_glance() {
+    use std::cell::Cell;
+
+    let interval = Duration::from_millis(10);
+    let budget = Duration::from_millis(500);
+    let calls = Cell::new(0);
+
+    // Absent on the first glance, but present on the retry.
+    let reappearing = || { calls.set(calls.get() + 1); calls.get() > 1 };
+    assert!(retry_glance(reappearing, 2, interval, budget));
+    assert_eq!(calls.get(), 2);
+
+    calls.set(0);
+    let absent = || { calls.set(calls.get() + 1); false };
+    assert!(!retry_glance(&absent, 2, interval, budget));
+    assert_eq!(calls.get(), 3);
+
+    calls.set(0);
+    assert!(!retry_glance(&absent, 2, interval, Duration::from_millis(5)));
+    assert_eq!(calls.get(), 1);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::cell::RefCell;
+    use std::sync::Arc;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::atomic::Ordering::Relaxed;
+    use std::time::Duration;
+    use parking_lot::RwLock;
+
+    use constants::{CHECK_TIMEOUT, STALENESS_THRESHOLD, MIN_RETRY_DELAY, SLOW_START_FRACTION};
+    use constants::{MAX_CLOCK_SKEW, DEGRADATION_GRACE};
+    use base::barrier::Barrier;
+    use base::confirmation::Confirmation;
+    use base::skew::ClockSkew;
+    use base::currency::Currency;
+    use base::slow_start::SlowStart;
+    use base::semaphore::Semaphore;
+    use base::clock::{Clock, RealClock, TestClock};
+    use gamblers::chaos::{Chaos, Script, Operation, Action};
+    use markets::{Offer, Outcome, Game, Kind};
+
+    use super::{Bookie, BookieStage, MarkedOffer, Thresholds, place_bet, defer_degradation};
+    use super::{release_stake, place_bets};
+    use super::{distribute_currency, unclamp_outcomes, evaluate_market, Conditions, MarketKey};
+    use super::{exclude_failed, has_bets_with};
+    use constants::FUZZY_DEDUPLICATION;
+    use super::opportunity::{self, MarkedOutcome, Strategy};
+    use super::acceptance::Acceptance;
+    use super::timeline::{PlacementTimeline, Phase};
+    use super::thresholds::update;
+
+    fn make_offer() -> Offer {
+        Offer {
+            oid: 1, date: 0, game: Game::Dota2, kind: Kind::Series, league: None, is_live: false,
+            best_of: None,
+            outcomes: vec![Outcome::new("A", 2.1),
+                           Outcome::new("B", 2.1)]
+        }
+    }
+
+    fn make_bookie(script: &Arc<Script>) -> Bookie {
+        Bookie::with_gambler("chaos", "", "", "chaos", Box::new(Chaos::with_script(script.clone())),
+                             Box::new(RealClock))
+    }
+
+    lazy_static! {
+        static ref TIMEOUT_SCRIPT: Arc<Script> = Arc::new(Script::new(vec![make_offer()]));
+        static ref TIMEOUT_BOOKIE: Bookie = make_bookie(&TIMEOUT_SCRIPT);
+        static ref FAILURE_SCRIPT: Arc<Script> = Arc::new(Script::new(vec![make_offer()]));
+        static ref FAILURE_BOOKIE: Bookie = make_bookie(&FAILURE_SCRIPT);
+        static ref ABORT_SCRIPT: Arc<Script> = Arc::new(Script::new(vec![make_offer()]));
+        static ref ABORT_BOOKIE: Bookie = make_bookie(&ABORT_SCRIPT);
+        static ref TIMELINE_SCRIPT: Arc<Script> = Arc::new(Script::new(vec![make_offer()]));
+        static ref TIMELINE_BOOKIE: Bookie = make_bookie(&TIMELINE_SCRIPT);
+        static ref FRESH_SCRIPT: Arc<Script> = Arc::new(Script::new(vec![make_offer()]));
+        static ref FRESH_BOOKIE: Bookie = make_bookie(&FRESH_SCRIPT);
+    }
+
+    // Receives offers and waits for them to become stale to be checked.
+    fn prepare(bookie: &'static Bookie, stake: Currency) -> Currency {
+        bookie.watch(|_, _| {});
+        assert_eq!(bookie.offer_count(), 1);
+
+        thread::sleep(*STALENESS_THRESHOLD + Duration::from_millis(100));
+
+        let balance = bookie.balance();
+        bookie.hold_stake(stake);
+        balance
+    }
+
+    // Bets reach the gambler only with the `place-bets` feature, otherwise nothing to check.
+    fn assert_not_placed(script: &Script) {
+        if cfg!(feature = "place-bets") {
+            assert_eq!(script.placed_count(), 0);
+        }
+    }
+
+    // Places the leg in the background, the current thread plays the rest of the combo.
+    fn spawn_leg(bookie: &'static Bookie, stake: Currency, barrier: &Arc<Barrier>,
+                 aborted: &Arc<AtomicBool>) -> thread::JoinHandle<()>
+    {
+        spawn_traced_leg(bookie, stake, barrier, aborted, &Arc::new(PlacementTimeline::new()))
+    }
+
+    fn spawn_traced_leg(bookie: &'static Bookie, stake: Currency, barrier: &Arc<Barrier>,
+                        aborted: &Arc<AtomicBool>,
+                        timeline: &Arc<PlacementTimeline>) -> thread::JoinHandle<()>
+    {
+        let (barrier, aborted, timeline) = (barrier.clone(), aborted.clone(), timeline.clone());
+        let outcome = make_offer().outcomes[0].clone();
+
+        thread::spawn(move || {
+            place_bet(bookie, make_offer(), outcome, stake, false, 0.4, *CHECK_TIMEOUT,
+                      &*barrier, &*aborted, &Acceptance::new(), &*timeline, 0)
+        })
+    }
+
+    #[test]
+    fn slow_check_times_out() {
+        let stake = Currency::from(1.);
+        let balance = prepare(&*TIMEOUT_BOOKIE, stake);
+        let delay = CHECK_TIMEOUT.as_secs() * 1000 + 300;
+
+        TIMEOUT_SCRIPT.push(Operation::CheckOffer, Action::Delay(delay));
+
+        let barrier = Arc::new(Barrier::new(2));
+        let aborted = Arc::new(AtomicBool::new(false));
+        let handle = spawn_leg(&*TIMEOUT_BOOKIE, stake, &barrier, &aborted);
+
+        assert!(!barrier.wait_timeout(*CHECK_TIMEOUT));
+        handle.join().unwrap();
+
+        assert_not_placed(&*TIMEOUT_SCRIPT);
+        assert_eq!(TIMEOUT_BOOKIE.balance(), balance);
+    }
+
+    #[test]
+    fn failed_check_degrades_bookie() {
+        let stake = Currency::from(1.);
+        let balance = prepare(&*FAILURE_BOOKIE, stake);
+
+        FAILURE_SCRIPT.push(Operation::CheckOffer, Action::Fail);
+
+        let barrier = Arc::new(Barrier::new(2));
+        let aborted = Arc::new(AtomicBool::new(false));
+        let handle = spawn_leg(&*FAILURE_BOOKIE, stake, &barrier, &aborted);
+
+        handle.join().unwrap();
+        assert!(!barrier.wait_timeout(Duration::from_millis(100)));
+
+        assert_not_placed(&*FAILURE_SCRIPT);
+        assert_eq!(FAILURE_BOOKIE.balance(), balance);
+        assert_eq!(FAILURE_BOOKIE.offer_count(), 0);
+    }
+
+    #[test]
+    fn fresh_offer_checks_stake() {
+        let stake = Currency::from(1.);
+
+        FRESH_BOOKIE.watch(|_, _| {});
+        let balance = FRESH_BOOKIE.balance();
+        FRESH_BOOKIE.hold_stake(stake);
+
+        // The odds aren't rechecked, but the failed stake check still degrades the bookie.
+        FRESH_SCRIPT.push(Operation::CheckStake, Action::Fail);
+
+        let barrier = Arc::new(Barrier::new(2));
+        let aborted = Arc::new(AtomicBool::new(false));
+        let handle = spawn_leg(&*FRESH_BOOKIE, stake, &barrier, &aborted);
+
+        handle.join().unwrap();
+        assert!(!barrier.wait_timeout(Duration::from_millis(100)));
+
+        assert_not_placed(&*FRESH_SCRIPT);
+        assert_eq!(FRESH_BOOKIE.balance(), balance);
+        assert_eq!(FRESH_BOOKIE.offer_count(), 0);
+    }
+
+    #[test]
+    fn aborted_combo_is_not_placed() {
+        let stake = Currency::from(1.);
+        let balance = prepare(&*ABORT_BOOKIE, stake);
+
+        let barrier = Arc::new(Barrier::new(2));
+        let aborted = Arc::new(AtomicBool::new(false));
+        let handle = spawn_leg(&*ABORT_BOOKIE, stake, &barrier, &aborted);
+
+        assert!(barrier.wait_timeout(*CHECK_TIMEOUT));
+        assert!(barrier.wait_timeout(*CHECK_TIMEOUT));
+        aborted.store(true, Relaxed);
+        barrier.wait();
+        handle.join().unwrap();
+
+        assert_not_placed(&*ABORT_SCRIPT);
+        assert_eq!(ABORT_BOOKIE.balance(), balance);
+        assert_eq!(ABORT_BOOKIE.offer_count(), 1);
+    }
+
+    #[test]
+    fn timeline_records_phases_in_order() {
+        let stake = Currency::from(1.);
+        prepare(&*TIMELINE_BOOKIE, stake);
+
+        TIMELINE_SCRIPT.push(Operation::CheckOffer, Action::Delay(50));
+
+        let barrier = Arc::new(Barrier::new(2));
+        let aborted = Arc::new(AtomicBool::new(false));
+        let timeline = Arc::new(PlacementTimeline::new());
+        let handle = spawn_traced_leg(&*TIMELINE_BOOKIE, stake, &barrier, &aborted, &timeline);
+
+        assert!(barrier.wait_timeout(*CHECK_TIMEOUT));
+        assert!(barrier.wait_timeout(*CHECK_TIMEOUT));
+        assert_eq!(timeline.leg(0).iter().map(|&(phase, _)| phase).collect::<Vec<_>>(),
+                   vec![Phase::ChecksStarted, Phase::ChecksCompleted]);
+
+        // The placement itself isn't simulated, the combo isn't saved.
+        aborted.store(true, Relaxed);
+        barrier.wait();
+        handle.join().unwrap();
+
+        let leg = timeline.leg(0);
+        assert_eq!(leg.len(), 2);
+        assert!(leg[1].1 - leg[0].1 >= 50);
+    }
+
+    #[test]
+    fn reconnect_after_failed_watch() {
+        let script = Arc::new(Script::new(vec![make_offer()]));
+        let clock = Arc::new(TestClock::new(1000));
+        let bookie = Bookie::with_gambler("chaos", "", "", "chaos",
+                                          Box::new(Chaos::with_script(script.clone())),
+                                          Box::new(clock.clone()));
+
+        script.push(Operation::Watch, Action::Fail);
+
+        bookie.watch(|_, _| {});
+        assert!(match bookie.stage() { BookieStage::Sleeping(_) => true, _ => false });
+        assert_eq!(bookie.offer_count(), 0);
+
+        clock.advance(MIN_RETRY_DELAY.as_secs() as u32);
+
+        bookie.watch(|_, _| {});
+        assert_eq!(bookie.offer_count(), 1);
+    }
+
+    #[test]
+    fn immediate_reconnection() {
+        let script = Arc::new(Script::new(vec![make_offer()]));
+        let clock = Arc::new(TestClock::new(1000));
+        let bookie = Bookie::with_gambler("chaos", "", "", "chaos",
+                                          Box::new(Chaos::with_script(script.clone())),
+                                          Box::new(clock.clone()));
+
+        let min = MIN_RETRY_DELAY.as_secs() as u32;
+
+        // Without the grace offers are dropped anyway, so there is no reason to hurry.
+        let at_once = *DEGRADATION_GRACE > Duration::new(0, 0);
+
+        bookie.watch(|_, _| {});
+        let wakeup = if at_once { clock.now() } else { clock.now() + min };
+        assert_eq!(bookie.stage(), BookieStage::Sleeping(wakeup));
+
+        // The next retry is delayed in any case.
+        clock.advance(wakeup - clock.now());
+        bookie.watch(|_, _| {});
+        assert!(match bookie.stage() { BookieStage::Sleeping(at) => at > clock.now(), _ => false });
+    }
+
+    #[test]
+    fn unseen_offers_are_swept_after_reconnection() {
+        let mut other = make_offer();
+        other.oid = 2;
+
+        let script = Arc::new(Script::new(vec![make_offer(), other]));
+        let clock = Arc::new(TestClock::new(1000));
+        let bookie = Bookie::with_gambler("chaos", "", "", "chaos",
+                                          Box::new(Chaos::with_script(script.clone())),
+                                          Box::new(clock.clone()));
+
+        bookie.watch(|_, _| {});
+        assert_eq!(bookie.offer_count(), 2);
+
+        script.set_offers(vec![make_offer()]);
+        clock.advance(MIN_RETRY_DELAY.as_secs() as u32);
+        thread::sleep(Duration::from_millis(5));
+
+        let removed = RefCell::new(Vec::new());
+        bookie.watch(|offer, upsert| if !upsert { removed.borrow_mut().push(offer.oid) });
+
+        assert_eq!(bookie.offer_count(), 1);
+        assert_eq!(*removed.borrow(), vec![2]);
+    }
+
+    lazy_static! {
+        static ref GRACE_SCRIPT: Arc<Script> = Arc::new(Script::new(vec![make_offer()]));
+        static ref GRACE_CLOCK: Arc<TestClock> = Arc::new(TestClock::new(1000));
+        static ref GRACE_BOOKIE: Bookie = Bookie::with_gambler(
+            "chaos", "", "", "chaos", Box::new(Chaos::with_script(GRACE_SCRIPT.clone())),
+            Box::new(GRACE_CLOCK.clone()));
+    }
+
+    #[test]
+    fn degradation_grace() {
+        let grace = Duration::from_millis(100);
+        let reconnect = || {
+            GRACE_CLOCK.advance(MIN_RETRY_DELAY.as_secs() as u32);
+            GRACE_BOOKIE.watch(|_, _| {});
+        };
+
+        GRACE_BOOKIE.watch(|_, _| {});
+        assert_eq!(GRACE_BOOKIE.offer_count(), 1);
+
+        // A quick reconnection preserves offers.
+        defer_degradation(&*GRACE_BOOKIE, grace);
+        reconnect();
+        thread::sleep(grace * 2);
+        assert_eq!(GRACE_BOOKIE.offer_count(), 1);
+
+        // A slow one doesn't.
+        defer_degradation(&*GRACE_BOOKIE, grace);
+        thread::sleep(grace * 2);
+        assert_eq!(GRACE_BOOKIE.offer_count(), 0);
+    }
+
+    lazy_static! {
+        static ref PLAIN_SCRIPT: Arc<Script> = Arc::new(Script::new(vec![make_offer()]));
+        static ref PLAIN_BOOKIE: Bookie = make_bookie(&PLAIN_SCRIPT);
+        static ref RESERVE_SCRIPT: Arc<Script> = Arc::new(Script::new(vec![make_offer()]));
+        static ref RESERVE_BOOKIE: Bookie = make_bookie(&RESERVE_SCRIPT)
+            .with_reserve(Currency::from(98.));
+    }
+
+    #[test]
+    fn reserve_limits_stakes() {
+        PLAIN_BOOKIE.watch(|_, _| {});
+        RESERVE_BOOKIE.watch(|_, _| {});
+
+        assert_eq!(RESERVE_BOOKIE.balance(), Currency::from(100.));
+        assert_eq!(RESERVE_BOOKIE.usable_balance(), Currency::from(2.));
+
+        let offers = [MarkedOffer(&*PLAIN_BOOKIE, make_offer()),
+                      MarkedOffer(&*RESERVE_BOOKIE, make_offer())];
+        let outcomes = [Outcome::new("A", 3.),
+                        Outcome::new("B", 3.)];
+
+        let legs = outcomes.iter().enumerate()
+            .map(|(idx, outcome)| MarkedOutcome {
+                market: idx, outcome: outcome, rate: 0.5, profit: 0.5
+            })
+            .collect::<Vec<_>>();
+
+        let pairs = offers.iter().zip(legs.iter()).collect::<Vec<_>>();
+        let mut thresholds = Thresholds::new();
+
+        thresholds.base_stake = Currency::from(1.5);
+        let stakes = distribute_currency(&pairs, &thresholds, None).unwrap();
+        assert_eq!(stakes, vec![Currency::from(1.5), Currency::from(1.5)]);
+
+        PLAIN_BOOKIE.release_stake(stakes[0]);
+        RESERVE_BOOKIE.release_stake(stakes[1]);
+
+        // Affordable by the raw balance, but not by the usable one.
+        thresholds.base_stake = Currency::from(3.);
+        assert_eq!(distribute_currency(&pairs, &thresholds, None), None);
+    }
+
+    lazy_static! {
+        static ref FULL_SCRIPT: Arc<Script> = Arc::new(Script::new(vec![make_offer()]));
+        static ref FULL_BOOKIE: Bookie = make_bookie(&FULL_SCRIPT);
+        static ref SLOW_SCRIPT: Arc<Script> = Arc::new(Script::new(vec![make_offer()]));
+        static ref SLOW_BOOKIE: Bookie = make_bookie(&SLOW_SCRIPT)
+            .with_slow_start(SlowStart::new(1, 0, 60 * 60));
+    }
+
+    #[test]
+    fn slow_start_limits_stakes() {
+        FULL_BOOKIE.watch(|_, _| {});
+        SLOW_BOOKIE.watch(|_, _| {});
+
+        assert!(!FULL_BOOKIE.is_slow_starting());
+        assert!(SLOW_BOOKIE.is_slow_starting());
+
+        let offers = [MarkedOffer(&*FULL_BOOKIE, make_offer()),
+                      MarkedOffer(&*SLOW_BOOKIE, make_offer())];
+        let outcomes = [Outcome::new("A", 5.),
+                        Outcome::new("B", 5.)];
+
+        let legs = outcomes.iter().enumerate()
+            .map(|(idx, outcome)| MarkedOutcome {
+                market: idx, outcome: outcome, rate: 0.5, profit: 0.5
+            })
+            .collect::<Vec<_>>();
+
+        let pairs = offers.iter().zip(legs.iter()).collect::<Vec<_>>();
+        let mut thresholds = Thresholds::new();
+        thresholds.base_stake = Currency::from(2.);
+
+        // The whole combo is reduced to keep it balanced.
+        let cap = Currency::from(2.) * SLOW_START_FRACTION;
+        let stakes = distribute_currency(&pairs, &thresholds, None).unwrap();
+        assert_eq!(stakes, vec![cap, cap]);
+
+        FULL_BOOKIE.release_stake(stakes[0]);
+        SLOW_BOOKIE.release_stake(stakes[1]);
+
+        // Only placed combos count.
+        assert!(SLOW_BOOKIE.is_slow_starting());
+
+        // The slow start is over after the first placed combo.
+        SLOW_BOOKIE.record_combo();
+        assert!(!SLOW_BOOKIE.is_slow_starting());
+
+        let stakes = distribute_currency(&pairs, &thresholds, None).unwrap();
+        assert_eq!(stakes, vec![Currency::from(2.), Currency::from(2.)]);
+    }
+
+    lazy_static! {
+        static ref PROFIT_SCRIPT: Arc<Script> = Arc::new(Script::new(vec![make_offer()]));
+        static ref PROFIT_BOOKIE: Bookie = make_bookie(&PROFIT_SCRIPT);
+    }
+
+    #[test]
+    fn low_absolute_profit_is_rejected() {
+        PROFIT_BOOKIE.watch(|_, _| {});
+
+        let offers = [MarkedOffer(&*PROFIT_BOOKIE, make_offer()),
+                      MarkedOffer(&*PROFIT_BOOKIE, make_offer())];
+        let outcomes = &offers[0].1.outcomes;
+
+        let legs = outcomes.iter().enumerate()
+            .map(|(idx, outcome)| MarkedOutcome {
+                market: idx, outcome: outcome, rate: 0.5, profit: 0.05
+            })
+            .collect::<Vec<_>>();
+
+        let pairs = offers.iter().zip(legs.iter()).collect::<Vec<_>>();
+        let balance = PROFIT_BOOKIE.usable_balance();
+        let mut thresholds = Thresholds::new();
+
+        // x2.1 on both legs yields 5% of the stake whichever wins.
+        thresholds.min_absolute_profit = Currency::from(1.);
+        assert_eq!(distribute_currency(&pairs, &thresholds, None), None);
+        assert_eq!(PROFIT_BOOKIE.usable_balance(), balance);
+
+        thresholds.min_absolute_profit = Currency(0);
+        let stakes = distribute_currency(&pairs, &thresholds, None).unwrap();
+        assert_eq!(PROFIT_BOOKIE.usable_balance(), balance - stakes[0] - stakes[1]);
+
+        PROFIT_BOOKIE.release_stake(stakes[0]);
+        PROFIT_BOOKIE.release_stake(stakes[1]);
+    }
+
+    lazy_static! {
+        static ref NO_SLOTS: Semaphore = Semaphore::new(0);
+        static ref BUSY_SCRIPT: Arc<Script> = Arc::new(Script::new(vec![make_offer()]));
+        static ref BUSY_BOOKIE: Bookie = make_bookie(&BUSY_SCRIPT);
+    }
+
+    #[test]
+    fn combo_is_skipped_without_slots() {
+        BUSY_BOOKIE.watch(|_, _| {});
+
+        let offers = [MarkedOffer(&*BUSY_BOOKIE, make_offer()),
+                      MarkedOffer(&*BUSY_BOOKIE, make_offer())];
+        let outcomes = &offers[0].1.outcomes;
+
+        let legs = outcomes.iter().enumerate()
+            .map(|(idx, outcome)| MarkedOutcome {
+                market: idx, outcome: outcome, rate: 0.5, profit: 0.05
+            })
+            .collect::<Vec<_>>();
+
+        let pairs = offers.iter().zip(legs.iter()).collect::<Vec<_>>();
+        let stakes = vec![Currency::from(1.), Currency::from(1.)];
+        let balance = BUSY_BOOKIE.balance();
+
+        for &stake in &stakes {
+            BUSY_BOOKIE.hold_stake(stake);
+        }
+
+        place_bets(&pairs, &stakes, None, *CHECK_TIMEOUT, Arc::new(PlacementTimeline::new()),
+                   Some(&*NO_SLOTS));
+
+        assert_eq!(BUSY_BOOKIE.balance(), balance);
+        assert_not_placed(&BUSY_SCRIPT);
+    }
+
+    lazy_static! {
+        static ref FREE_SCRIPT: Arc<Script> = Arc::new(Script::new(vec![make_offer()]));
+        static ref FREE_BOOKIE: Bookie = make_bookie(&FREE_SCRIPT);
+        static ref PAID_SCRIPT: Arc<Script> = Arc::new(Script::new(vec![make_offer()]));
+        static ref PAID_BOOKIE: Bookie = make_bookie(&PAID_SCRIPT);
+    }
+
+    #[test]
+    fn free_leg_is_held_from_free_balance() {
+        FREE_BOOKIE.watch(|_, _| {});
+        PAID_BOOKIE.watch(|_, _| {});
+        FREE_BOOKIE.release_free_stake(Currency::from(5.));
+
+        let offers = [MarkedOffer(&*FREE_BOOKIE, make_offer()),
+                      MarkedOffer(&*PAID_BOOKIE, make_offer())];
+        let outcomes = [Outcome::new("A", 2.),
+                        Outcome::new("B", 3.)];
+
+        let mut legs = outcomes.iter().enumerate()
+            .map(|(idx, outcome)| MarkedOutcome {
+                market: idx, outcome: outcome, rate: 0.5, profit: 0.
+            })
+            .collect::<Vec<_>>();
+
+        // The free leg pays x1, so it takes three times the stake of the other one.
+        opportunity::apply_free_bet(&mut legs, 0);
+
+        let pairs = offers.iter().zip(legs.iter()).collect::<Vec<_>>();
+        let (balance, paid_balance) = (FREE_BOOKIE.balance(), PAID_BOOKIE.balance());
+        let mut thresholds = Thresholds::new();
+        thresholds.base_stake = Currency::from(1.);
+
+        let stakes = distribute_currency(&pairs, &thresholds, Some(0)).unwrap();
+        assert_eq!(stakes, vec![Currency::from(3.), Currency::from(1.)]);
+
+        assert_eq!(FREE_BOOKIE.balance(), balance);
+        assert_eq!(FREE_BOOKIE.free_balance(), Currency::from(2.));
+        assert_eq!(PAID_BOOKIE.balance(), paid_balance - stakes[1]);
+
+        // There are not enough free bets for the next combo.
+        assert_eq!(distribute_currency(&pairs, &thresholds, Some(0)), None);
+
+        FREE_BOOKIE.release_free_stake(stakes[0]);
+        PAID_BOOKIE.release_stake(stakes[1]);
+    }
+
+    #[test]
+    fn bets_are_placed_on_unclamped_outcomes() {
+        let mut erroneous = make_offer();
+        erroneous.outcomes[0].1 = 9999.;
+        erroneous.outcomes[1].1 = 2.;
+
+        let market = [MarkedOffer(&*PLAIN_BOOKIE, erroneous),
+                      MarkedOffer(&*FULL_BOOKIE, make_offer())];
+        let clamped = market.iter()
+            .map(|marked| opportunity::clamp_coefs(&marked.1.outcomes, 20.))
+            .collect::<Vec<_>>();
+
+        let table = clamped.iter().map(|outcomes| outcomes.iter().collect()).collect::<Vec<_>>();
+        let chosen = opportunity::find_best(&table, &[0, 1], Strategy::Unbiased);
+
+        assert_eq!(chosen[0].market, 0);
+        assert_eq!(chosen[0].outcome.1, 20.);
+
+        let outcomes = unclamp_outcomes(&market, &clamped, chosen);
+
+        // Gamblers look the outcome up in the offer.
+        assert_eq!(outcomes[0].outcome.1, 9999.);
+        assert!(market[0].1.outcomes.iter().position(|o| o == outcomes[0].outcome).is_some());
+        assert_eq!(outcomes[1].outcome, &market[1].1.outcomes[1]);
+    }
+
+    lazy_static! {
+        static ref LEFT_SCRIPT: Arc<Script> = Arc::new(Script::new(vec![]));
+        static ref LEFT_BOOKIE: Bookie = make_funded_bookie(&LEFT_SCRIPT);
+        static ref RIGHT_SCRIPT: Arc<Script> = Arc::new(Script::new(vec![]));
+        static ref RIGHT_BOOKIE: Bookie = make_funded_bookie(&RIGHT_SCRIPT);
+    }
+
+    // Offers of the market are made up, the bookie has only the balance.
+    fn make_funded_bookie(script: &Arc<Script>) -> Bookie {
+        let bookie = make_bookie(script);
+        bookie.watch(|_, _| {});
+        bookie
+    }
+
+    fn make_market(left: (f64, f64), right: (f64, f64)) -> Vec<MarkedOffer> {
+        let offer = |(a, b): (f64, f64)| Offer {
+            outcomes: vec![Outcome::new("A", a),
+                           Outcome::new("B", b)],
+            ..make_offer()
+        };
+
+        vec![MarkedOffer(&*LEFT_BOOKIE, offer(left)), MarkedOffer(&*RIGHT_BOOKIE, offer(right))]
+    }
+
+    fn no_bets(_: &[MarkedOffer]) -> bool {
+        false
+    }
+
+    // Everything is allowed, tests restrict one thing at a time.
+    fn make_conditions(confirmations: &Confirmation<MarketKey>) -> Conditions {
+        let mut thresholds = Thresholds::new();
+        thresholds.min_profit = 0.01;
+        thresholds.max_profit = 0.5;
+        thresholds.base_stake = Currency::from(1.);
+        thresholds.max_stake = Currency::from(5.);
+        thresholds.min_absolute_profit = Currency(0);
+
+        Conditions {
+            now: 1000,
+            matched_at: 0,
+            min_match_age: 0,
+            thresholds: thresholds,
+            min_leg_coef: 1.,
+            max_date_delta: 20 * 60,
+            void_risks: &[],
+            large_combo_stake: Currency::from(100.),
+            confirmations: confirmations,
+            clock_skew: None,
+            blackout_windows: &[],
+            has_bets: no_bets
+        }
+    }
+
+    // Whether bets would be placed on the market. Held stakes are released.
+    fn is_placed(market: &[MarkedOffer], conditions: &Conditions) -> bool {
+        evaluate_market(market, conditions).map_or(false, |plan| {
+            for (idx, (marked, &stake)) in plan.outcomes.iter().zip(plan.stakes.iter()).enumerate() {
+                release_stake(market[marked.market].0, stake, plan.free_leg == Some(idx));
+            }
+
+            true
+        })
+    }
+
+    lazy_static! {
+        static ref COOLING_SCRIPT: Arc<Script> = Arc::new(Script::new(vec![]));
+        static ref COOLING_CLOCK: Arc<TestClock> = Arc::new(TestClock::new(1000));
+        static ref COOLING_BOOKIE: Bookie = Bookie::with_gambler(
+            "chaos", "", "", "chaos", Box::new(Chaos::with_script(COOLING_SCRIPT.clone())),
+            Box::new(COOLING_CLOCK.clone()));
+    }
+
+    #[test]
+    fn cooling_down_bookie_is_excluded() {
+        let confirmations = Confirmation::new(30);
+        let conditions = make_conditions(&confirmations);
+        let mut market = make_market((2.2, 1.8), (1.8, 2.2));
+        market[1].0 = &*COOLING_BOOKIE;
+
+        COOLING_BOOKIE.watch(|_, _| {});
+        assert!(exclude_failed(&market, COOLING_CLOCK.now(), 60).is_none());
+        assert!(is_placed(&market, &conditions));
+
+        // The failed check starts the cooldown.
+        COOLING_SCRIPT.push(Operation::CheckOffer, Action::Fail);
+        let offer = &market[1].1;
+        assert_eq!(COOLING_BOOKIE.check_offer(offer, &offer.outcomes[0], Currency(100)), None);
+
+        COOLING_CLOCK.advance(10);
+        let healthy = exclude_failed(&market, COOLING_CLOCK.now(), 60).unwrap();
+        assert_eq!(healthy.len(), 1);
+        assert_eq!(healthy[0].1.outcomes[0].1, 2.2);
+        assert!(!is_placed(&healthy, &conditions));
+
+        COOLING_CLOCK.advance(60);
+        assert!(exclude_failed(&market, COOLING_CLOCK.now(), 60).is_none());
+    }
+
+    #[test]
+    fn bets_are_found_by_id_and_fixture() {
+        // (id, date, title) of bets placed by the bookie of the first offer.
+        let has_bets = |market: &[MarkedOffer], bets: &[(u64, u32, &str)]| {
+            let host = &market[0].0.host;
+
+            has_bets_with(&market[..1], &|h: &str, id: u64| {
+                h == host && bets.iter().any(|bet| bet.0 == id)
+            }, &|h: &str, game: &str, from: u32, to: u32| {
+                bets.iter()
+                    .filter(|bet| h == host && game == "Dota2" && from <= bet.1 && bet.1 <= to)
+                    .map(|bet| bet.2.to_owned())
+                    .collect::<Vec<_>>()
+            })
+        };
+
+        let mut market = make_market((2.2, 1.8), (1.8, 2.2));
+        market[0].1.outcomes = vec![Outcome::new("Natus Vincere", 2.2),
+                                    Outcome::new("Virtus.pro", 1.8)];
+        let (oid, date) = (market[0].1.oid, market[0].1.date);
+
+        // Both ways find the same bet.
+        assert!(has_bets(&market[..], &[(oid, date, "Natus Vincere")]));
+
+        // The id is enough even if titles have changed.
+        assert!(has_bets(&market[..], &[(oid, date, "Team Spirit")]));
+
+        // The reissued market is found by the fixture only.
+        assert_eq!(has_bets(&market[..], &[(oid + 1, date, "Virtus.pro")]), FUZZY_DEDUPLICATION);
+
+        // Neither the id nor the fixture (another date) is the same.
+        assert!(!has_bets(&market[..], &[(oid + 1, date + 3600, "Virtus.pro")]));
+        assert!(!has_bets(&market[..], &[]));
+    }
+
+    #[test]
+    fn date_delta_skips_market() {
+        let confirmations = Confirmation::new(30);
+        let conditions = make_conditions(&confirmations);
+        let mut market = make_market((2.2, 1.8), (1.8, 2.2));
+
+        assert!(is_placed(&market, &conditions));
+
+        market[1].1.date += conditions.max_date_delta + 1;
+        assert!(!is_placed(&market, &conditions));
+
+        market[1].1.date -= 1;
+        assert!(is_placed(&market, &conditions));
+    }
+
+    #[test]
+    fn fresh_match_isnt_placed() {
+        let confirmations = Confirmation::new(30);
+        let mut conditions = make_conditions(&confirmations);
+        let market = make_market((2.2, 1.8), (1.8, 2.2));
+
+        conditions.min_match_age = 60;
+        conditions.matched_at = conditions.now - 10;
+        assert!(!is_placed(&market, &conditions));
+
+        conditions.matched_at = conditions.now - 60;
+        assert!(is_placed(&market, &conditions));
+    }
+
+    #[test]
+    fn clock_skew_disables_placement() {
+        let skew = ClockSkew::new();
+        let confirmations = Confirmation::new(30);
+        let mut conditions = make_conditions(&confirmations);
+        let market = make_market((2.2, 1.8), (1.8, 2.2));
+
+        conditions.clock_skew = Some(&skew);
+        assert!(is_placed(&market, &conditions));
+
+        skew.record(1000, 1000 + 2 * MAX_CLOCK_SKEW as i64);
+        assert!(!is_placed(&market, &conditions));
+
+        // Unless the skew isn't checked.
+        conditions.clock_skew = None;
+        assert!(is_placed(&market, &conditions));
+    }
+
+    #[test]
+    fn large_combo_is_placed_on_confirmation() {
+        let confirmations = Confirmation::new(30);
+        let mut conditions = make_conditions(&confirmations);
+        let market = make_market((2.2, 1.8), (1.8, 2.2));
+
+        conditions.large_combo_stake = Currency::from(1.);
+        assert!(!is_placed(&market, &conditions));
+
+        conditions.now += 10;
+        assert!(is_placed(&market, &conditions));
+
+        // The confirmation is consumed.
+        conditions.now += 10;
+        assert!(!is_placed(&market, &conditions));
+
+        // And expires.
+        conditions.now += 60;
+        assert!(!is_placed(&market, &conditions));
+    }
+
+    #[test]
+    fn void_risk_decides_placement() {
+        let confirmations = Confirmation::new(30);
+        let mut conditions = make_conditions(&confirmations);
+        let market = make_market((2.2, 1.8), (1.8, 2.2));
+
+        // About 10% whichever wins.
+        conditions.void_risks = &[("chaos", 0.5)];
+        assert!(is_placed(&market, &conditions));
+
+        conditions.void_risks = &[("chaos", 0.95)];
+        assert!(!is_placed(&market, &conditions));
+
+        conditions.void_risks = &[("other.com", 0.95)];
+        assert!(is_placed(&market, &conditions));
+    }
+
+    #[test]
+    fn heavy_favorite_is_skipped() {
+        let confirmations = Confirmation::new(30);
+        let mut conditions = make_conditions(&confirmations);
+        let market = make_market((1.02, 40.), (1.01, 60.));
+
+        // Stakes are highly skewed, as is the margin.
+        conditions.thresholds.min_profit = 0.001;
+        conditions.thresholds.base_stake = Currency::from(0.1);
+        conditions.thresholds.max_stake = Currency::from(10.);
+
+        assert!(is_placed(&market, &conditions));
+
+        conditions.min_leg_coef = 1.10;
+        assert!(!is_placed(&market, &conditions));
+    }
+
+    #[test]
+    fn updated_thresholds_decide_placement() {
+        let confirmations = Confirmation::new(30);
+        let mut conditions = make_conditions(&confirmations);
+        let market = make_market((2.2, 1.8), (1.8, 2.2));
+
+        let thresholds = RwLock::new(conditions.thresholds);
+        let pair = |key: &str, value: &str| (key.to_owned(), value.to_owned());
+
+        // About 10% whichever wins.
+        conditions.thresholds = update(&thresholds, &[pair("min_profit", "0.15")]).unwrap();
+        assert!(!is_placed(&market, &conditions));
+
+        conditions.thresholds = update(&thresholds, &[pair("min_profit", "0.05")]).unwrap();
+        assert!(is_placed(&market, &conditions));
+
+        // Rejected values don't change anything.
+        assert!(update(&thresholds, &[pair("max_profit", "inf")]).is_err());
+        assert!(update(&thresholds, &[pair("min_profit", "-1"), pair("max_profit", "2")]).is_err());
+        assert_eq!(*thresholds.read(), conditions.thresholds);
+
+        conditions.thresholds = update(&thresholds, &[pair("max_profit", "0.08")]).unwrap();
+        assert!(!is_placed(&market, &conditions));
+    }
 }
@@ -1,11 +1,28 @@
 #![allow(dead_code)]
 
-use markets::Outcome;
+use std::cmp;
+use std::borrow::Cow;
+
+use base::currency::Currency;
+use markets::{Outcome, coef_eq};
 
 use self::Strategy::*;
 
+// Subsets of bookies are enumerated exhaustively, so larger tables aren't narrowed.
+const MAX_NARROWED_ROWS: usize = 12;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Strategy { Unbiased, Favorite, Rebel }
 
+// Chooses between strategies giving effectively equal least profits.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Objective {
+    // The lowest total stake, the smallest leg being the base (see `stakes_by_rates`).
+    MinStake,
+    // The largest share of the stake on the bookie with the highest balance.
+    MaxBalance
+}
+
 pub struct MarkedOutcome<'a> {
     pub market: usize,
     pub outcome: &'a Outcome,
@@ -14,25 +31,82 @@ pub struct MarkedOutcome<'a> {
 }
 
 pub fn calc_margin(table: &[Vec<&Outcome>]) -> f64 {
+    find_best_coefs(table).iter().map(|x| 1. / x).sum()
+}
+
+// Margin-free probabilities of outcomes, i.e. normalized implied probabilities of the best coefs.
+pub fn fair_odds(table: &[Vec<&Outcome>]) -> Vec<f64> {
+    let line = find_best_coefs(table);
+    let margin = line.iter().map(|x| 1. / x).sum::<f64>();
+
+    line.iter().map(|x| 1. / x / margin).collect()
+}
+
+fn find_best_coefs(table: &[Vec<&Outcome>]) -> Vec<f64> {
     debug_assert!(table.len() > 0);
     debug_assert!(table[0].len() <= 3);
 
-    let mut line = &mut [0.; 3][..table[0].len()];
+    let mut line = vec![0.; table[0].len()];
 
     for column in table {
         for (best, outcome) in line.iter_mut().zip(column.iter()) {
-            if *best < outcome.1 {
+            if outcome.is_backable() && *best < outcome.1 {
                 *best = outcome.1;
             }
         }
     }
 
-    line.iter().map(|x| 1. / x).sum()
+    line
+}
+
+// Only combos covering every outcome are safe: the uncovered one would lose all legs.
+// So a selection suspended (or lay-only) by some bookies must be backed by others.
+pub fn is_hedgeable(table: &[Vec<&Outcome>]) -> bool {
+    (0..table[0].len()).all(|idx| table.iter().any(|row| row[idx].is_backable()))
+}
+
+// Guards against feed errors: a single x9999 tick mustn't dominate the margin.
+pub fn clamp_coefs(outcomes: &[Outcome], ceiling: f64) -> Cow<[Outcome]> {
+    if outcomes.iter().all(|outcome| outcome.1 <= ceiling) {
+        return Cow::Borrowed(outcomes);
+    }
+
+    Cow::Owned(outcomes.iter()
+        .map(|outcome| {
+            let mut clamped = outcome.clone();
+            clamped.1 = clamped.1.min(ceiling);
+            clamped
+        })
+        .collect())
 }
 
-pub fn find_best<'a>(table: &[Vec<&'a Outcome>], strategy: Strategy) -> Vec<MarkedOutcome<'a>> {
+#[inline]
+pub fn is_pursuable(margin: f64, buffer: f64) -> bool {
+    margin < 1. - buffer
+}
+
+// The sum of implied probabilities of the offer, `None` if some outcome isn't quoted.
+pub fn overround(outcomes: &[Outcome]) -> Option<f64> {
+    if outcomes.len() < 2 || outcomes.iter().any(|outcome| outcome.1 <= 0.) {
+        return None;
+    }
+
+    Some(outcomes.iter().map(|outcome| 1. / outcome.1).sum())
+}
+
+// Backing all outcomes of the offer by the bookie itself is profitable.
+pub fn is_within_book_arb(outcomes: &[Outcome], buffer: f64) -> bool {
+    outcomes.iter().all(Outcome::is_backable) &&
+        overround(outcomes).map_or(false, |overround| is_pursuable(overround, buffer))
+}
+
+// `ranks` are used to break ties between equal coefs: the lower rank wins.
+pub fn find_best<'a>(table: &[Vec<&'a Outcome>], ranks: &[u32],
+                     strategy: Strategy) -> Vec<MarkedOutcome<'a>>
+{
     debug_assert!(table.len() > 0);
     debug_assert!(table[0].len() > 0);
+    debug_assert_eq!(table.len(), ranks.len());
 
     let mut table_iter = table.into_iter();
 
@@ -49,7 +123,18 @@ pub fn find_best<'a>(table: &[Vec<&'a Outcome>], strategy: Strategy) -> Vec<Mark
         debug_assert_eq!(outcomes.len(), table[0].len());
 
         for (best, outcome) in line.iter_mut().zip(outcomes.iter()) {
-            if best.outcome.1 < outcome.1 {
+            // Lay-only selections are never chosen if there are backable ones.
+            let better = if !outcome.is_backable() {
+                false
+            } else if !best.outcome.is_backable() {
+                true
+            } else if coef_eq(best.outcome.1, outcome.1) {
+                ranks[index + 1] < ranks[best.market]
+            } else {
+                best.outcome.1 < outcome.1
+            };
+
+            if better {
                 best.market = index + 1;
                 best.outcome = outcome;
             }
@@ -92,13 +177,226 @@ pub fn find_best<'a>(table: &[Vec<&'a Outcome>], strategy: Strategy) -> Vec<Mark
     line
 }
 
+// Runs all the strategies and picks the line with the highest least profit. Least profits within
+// `tolerance` are considered equal and the objective decides between them (the first one wins
+// a complete tie).
+pub fn find_best_multi<'a>(table: &[Vec<&'a Outcome>], ranks: &[u32], strategies: &[Strategy],
+                           objective: Objective, tolerance: f64,
+                           balances: &[Currency]) -> (Strategy, Vec<MarkedOutcome<'a>>)
+{
+    debug_assert!(strategies.len() > 0);
+    debug_assert_eq!(table.len(), balances.len());
+
+    let candidates = strategies.iter()
+        .map(|&strategy| (strategy, find_best(table, ranks, strategy)))
+        .collect::<Vec<_>>();
+
+    let best_profit = candidates.iter()
+        .map(|&(_, ref line)| least_profit(line))
+        .fold(-1. / 0., f64::max);
+
+    let richest = (0..balances.len()).fold(0, |best, idx| {
+        if balances[idx] > balances[best] { idx } else { best }
+    });
+
+    let mut chosen: Option<(f64, Strategy, Vec<MarkedOutcome>)> = None;
+
+    for (strategy, line) in candidates {
+        if least_profit(&line) < best_profit - tolerance {
+            continue;
+        }
+
+        let score = score_objective(&line, objective, richest);
+
+        if chosen.as_ref().map_or(true, |&(best, _, _)| score > best + 1e-9) {
+            chosen = Some((score, strategy, line));
+        }
+    }
+
+    let (_, strategy, line) = chosen.unwrap();
+
+    (strategy, line)
+}
+
+fn least_profit(line: &[MarkedOutcome]) -> f64 {
+    line.iter().map(|marked| marked.profit).fold(1. / 0., f64::min)
+}
+
+// The higher, the better.
+fn score_objective(line: &[MarkedOutcome], objective: Objective, richest: usize) -> f64 {
+    match objective {
+        Objective::MinStake => {
+            let total = line.iter().map(|marked| marked.rate).sum::<f64>();
+            let base = line.iter().map(|marked| marked.rate).fold(1. / 0., f64::min);
+
+            -total / base
+        },
+        Objective::MaxBalance => {
+            line.iter()
+                .filter(|marked| marked.market == richest)
+                .map(|marked| marked.rate)
+                .sum()
+        }
+    }
+}
+
+// Chooses the smallest subset of the table's rows (bookies) whose margin is at most
+// `tolerance` worse than the margin of the whole table. Among subsets of the same size the one
+// with the lowest margin wins, then the one with the lowest sum of ranks. A single bookie is
+// chosen only if `within_book` is set.
+pub fn choose_bookies(table: &[Vec<&Outcome>], ranks: &[u32], tolerance: f64,
+                      within_book: bool) -> Vec<usize>
+{
+    debug_assert!(table.len() > 0);
+    debug_assert_eq!(table.len(), ranks.len());
+
+    if table.len() > MAX_NARROWED_ROWS {
+        return (0..table.len()).collect();
+    }
+
+    let subset_of = |mask: u32| (0..table.len()).filter(|i| mask & (1 << i) != 0).collect();
+    let margin_of = |subset: &Vec<usize>| {
+        calc_margin(&subset.iter().map(|&i| table[i].clone()).collect::<Vec<_>>())
+    };
+
+    let full = (1u32 << table.len()) - 1;
+    let limit = margin_of(&subset_of(full)) + tolerance + 1e-9;
+
+    let min_size = if within_book { 1 } else { 2 };
+
+    for size in min_size..table.len() as u32 {
+        let best = (1..full)
+            .filter(|mask| mask.count_ones() == size)
+            .map(|mask| subset_of(mask))
+            .map(|subset| {
+                let margin = margin_of(&subset);
+                let rank = subset.iter().map(|&i| ranks[i]).sum::<u32>();
+                (subset, margin, rank)
+            })
+            .filter(|&(_, margin, _)| margin <= limit)
+            .min_by(|a, b| (a.1, a.2).partial_cmp(&(b.1, b.2)).unwrap());
+
+        if let Some((subset, _, _)) = best {
+            return subset;
+        }
+    }
+
+    subset_of(full)
+}
+
+// Same as `find_best`, but only the rows chosen by `choose_bookies` are used.
+pub fn find_best_narrow<'a>(table: &[Vec<&'a Outcome>], ranks: &[u32], tolerance: f64,
+                            within_book: bool, strategy: Strategy) -> Vec<MarkedOutcome<'a>>
+{
+    let subset = choose_bookies(table, ranks, tolerance, within_book);
+    let subtable = subset.iter().map(|&i| table[i].clone()).collect::<Vec<_>>();
+    let subranks = subset.iter().map(|&i| ranks[i]).collect::<Vec<_>>();
+
+    let mut line = find_best(&subtable, &subranks, strategy);
+
+    for marked in &mut line {
+        marked.market = subset[marked.market];
+    }
+
+    line
+}
+
+// The stake of a free bet isn't returned, so the leg pays `coef - 1` and costs us nothing.
+pub fn free_bet_coefs(coefs: &[f64], free_idx: usize) -> Vec<f64> {
+    debug_assert!(free_idx < coefs.len());
+
+    coefs.iter().enumerate()
+        .map(|(idx, &coef)| if idx == free_idx { coef - 1. } else { coef })
+        .collect()
+}
+
+// Rates balancing returns of the coefs whichever leg wins.
+pub fn balanced_rates(coefs: &[f64]) -> Vec<f64> {
+    let margin = coefs.iter().map(|coef| 1. / coef).sum::<f64>();
+    coefs.iter().map(|coef| 1. / coef / margin).collect()
+}
+
+// Re-solves rates for the adjusted coef of the free leg and recomputes profits.
+pub fn apply_free_bet(line: &mut [MarkedOutcome], free_idx: usize) {
+    let coefs = line.iter().map(|marked| marked.outcome.1).collect::<Vec<_>>();
+    let coefs = free_bet_coefs(&coefs, free_idx);
+    let rates = balanced_rates(&coefs);
+
+    let outlay = rates.iter().enumerate()
+        .filter(|&(idx, _)| idx != free_idx)
+        .map(|(_, rate)| rate)
+        .sum::<f64>();
+
+    for ((marked, &rate), coef) in line.iter_mut().zip(rates.iter()).zip(coefs) {
+        marked.rate = rate;
+        marked.profit = rate * coef / outlay - 1.;
+    }
+}
+
+// The expected profit of the leg if its bet is voided (and nothing is won) with the probability.
+pub fn risk_adjusted(profit: f64, void_risk: f64) -> f64 {
+    debug_assert!(0. <= void_risk && void_risk <= 1.);
+    profit * (1. - void_risk)
+}
+
+// Stakes proportional to the rates, the smallest one is the base.
+pub fn stakes_by_rates(rates: &[f64], base: Currency) -> Vec<Currency> {
+    let base_rate = rates.iter().cloned().fold(1. / 0., f64::min);
+    rates.iter().map(|rate| rate / base_rate * base).collect()
+}
+
+// Stakes giving returns as equal as possible after rounding to cents whichever leg wins.
+// The stake on the highest coef is searched above the base, but at most by the fraction of it
+// (at least by a cent), so the exposure isn't inflated for the sake of rounding.
+pub fn stakes_by_equal_profit(coefs: &[f64], base: Currency, max_inflation: f64) -> Vec<Currency> {
+    let leader = (0..coefs.len()).fold(0, |best, idx| {
+        if coefs[idx] > coefs[best] { idx } else { best }
+    });
+
+    let mut best: Option<(f64, Vec<Currency>)> = None;
+
+    let extra = cmp::max(1, (base.0 as f64 * max_inflation) as i64);
+
+    for first in base.0..base.0 + extra {
+        let total = first as f64 * coefs[leader];
+
+        let stakes = coefs.iter()
+            .map(|coef| Currency((total / coef).round() as i64))
+            .collect::<Vec<_>>();
+
+        let returns = stakes.iter().zip(coefs.iter())
+            .map(|(stake, coef)| stake.0 as f64 * coef)
+            .collect::<Vec<_>>();
+
+        let max = returns.iter().cloned().fold(0., f64::max);
+        let min = returns.iter().cloned().fold(1. / 0., f64::min);
+
+        if best.as_ref().map_or(true, |&(spread, _)| max - min < spread - 1e-9) {
+            best = Some((max - min, stakes));
+        }
+    }
+
+    best.map_or_else(Vec::new, |(_, stakes)| stakes)
+}
+
+// The least absolute profit among the legs (stake and coef each) whichever of them wins.
+pub fn guaranteed_profit(legs: &[(Currency, f64)]) -> Currency {
+    let outlay = legs.iter().fold(Currency(0), |sum, &(stake, _)| sum + stake);
+
+    legs.iter()
+        .map(|&(stake, coef)| stake * coef - outlay)
+        .min()
+        .unwrap_or(Currency(0))
+}
+
 macro_rules! assert_approx_eq {
     ($lhs:expr, $rhs:expr) => { assert!(($lhs - $rhs).abs() < 0.01) }
 }
 
 #[test]
 fn test_calc_margin_single() {
-    let market = [Outcome("X".to_owned(), 2.3), Outcome("Y".to_owned(), 1.35)];
+    let market = [Outcome::new("X", 2.3),
+                  Outcome::new("Y", 1.35)];
     let table = [market.iter().collect()];
 
     assert_approx_eq!(calc_margin(&table), 1.18);
@@ -106,9 +404,12 @@ fn test_calc_margin_single() {
 
 #[test]
 fn test_calc_margin_multiple() {
-    let marked_1 = [Outcome("X".to_owned(), 2.3), Outcome("Y".to_owned(), 1.05)];
-    let marked_2 = [Outcome("X".to_owned(), 1.2), Outcome("Y".to_owned(), 1.05)];
-    let marked_3 = [Outcome("X".to_owned(), 1.3), Outcome("Y".to_owned(), 1.35)];
+    let marked_1 = [Outcome::new("X", 2.3),
+                    Outcome::new("Y", 1.05)];
+    let marked_2 = [Outcome::new("X", 1.2),
+                    Outcome::new("Y", 1.05)];
+    let marked_3 = [Outcome::new("X", 1.3),
+                    Outcome::new("Y", 1.35)];
 
     let table = [
         marked_1.iter().collect(),
@@ -121,9 +422,12 @@ fn test_calc_margin_multiple() {
 
 #[test]
 fn test_find_best_unbiased() {
-    let marked_1 = [Outcome("X".to_owned(), 2.3), Outcome("Y".to_owned(), 1.2)];
-    let marked_2 = [Outcome("X".to_owned(), 1.3), Outcome("Y".to_owned(), 1.1)];
-    let marked_3 = [Outcome("X".to_owned(), 1.1), Outcome("Y".to_owned(), 3.3)];
+    let marked_1 = [Outcome::new("X", 2.3),
+                    Outcome::new("Y", 1.2)];
+    let marked_2 = [Outcome::new("X", 1.3),
+                    Outcome::new("Y", 1.1)];
+    let marked_3 = [Outcome::new("X", 1.1),
+                    Outcome::new("Y", 3.3)];
 
     let table = [
         marked_1.iter().collect(),
@@ -131,7 +435,7 @@ fn test_find_best_unbiased() {
         marked_3.iter().collect()
     ];
 
-    let opp = find_best(&table, Unbiased);
+    let opp = find_best(&table, &[0, 1, 2], Unbiased);
 
     assert_eq!(opp.len(), 2);
     assert_eq!(opp[0].outcome.0, "X");
@@ -146,9 +450,12 @@ fn test_find_best_unbiased() {
 
 #[test]
 fn test_find_best_favorite() {
-    let marked_1 = [Outcome("X".to_owned(), 2.3), Outcome("Y".to_owned(), 1.2)];
-    let marked_2 = [Outcome("X".to_owned(), 1.3), Outcome("Y".to_owned(), 1.1)];
-    let marked_3 = [Outcome("X".to_owned(), 1.1), Outcome("Y".to_owned(), 3.3)];
+    let marked_1 = [Outcome::new("X", 2.3),
+                    Outcome::new("Y", 1.2)];
+    let marked_2 = [Outcome::new("X", 1.3),
+                    Outcome::new("Y", 1.1)];
+    let marked_3 = [Outcome::new("X", 1.1),
+                    Outcome::new("Y", 3.3)];
 
     let table = [
         marked_1.iter().collect(),
@@ -156,7 +463,7 @@ fn test_find_best_favorite() {
         marked_3.iter().collect()
     ];
 
-    let opp = find_best(&table, Favorite);
+    let opp = find_best(&table, &[0, 1, 2], Favorite);
 
     assert_eq!(opp.len(), 2);
     assert_eq!(opp[0].outcome.0, "X");
@@ -171,9 +478,12 @@ fn test_find_best_favorite() {
 
 #[test]
 fn test_find_best_rebel() {
-    let marked_1 = [Outcome("X".to_owned(), 2.3), Outcome("Y".to_owned(), 1.2)];
-    let marked_2 = [Outcome("X".to_owned(), 1.3), Outcome("Y".to_owned(), 1.1)];
-    let marked_3 = [Outcome("X".to_owned(), 1.1), Outcome("Y".to_owned(), 3.3)];
+    let marked_1 = [Outcome::new("X", 2.3),
+                    Outcome::new("Y", 1.2)];
+    let marked_2 = [Outcome::new("X", 1.3),
+                    Outcome::new("Y", 1.1)];
+    let marked_3 = [Outcome::new("X", 1.1),
+                    Outcome::new("Y", 3.3)];
 
     let table = [
         marked_1.iter().collect(),
@@ -181,7 +491,7 @@ fn test_find_best_rebel() {
         marked_3.iter().collect()
     ];
 
-    let opp = find_best(&table, Rebel);
+    let opp = find_best(&table, &[0, 1, 2], Rebel);
 
     assert_eq!(opp.len(), 2);
     assert_eq!(opp[0].outcome.0, "X");
@@ -193,3 +503,314 @@ fn test_find_best_rebel() {
     assert_approx_eq!(opp[1].rate, 0.57);
     assert_approx_eq!(opp[1].profit, 0.86);
 }
+
+#[test]
+fn test_apply_free_bet() {
+    let marked_1 = [Outcome::new("X", 2.3),
+                    Outcome::new("Y", 1.2)];
+    let marked_2 = [Outcome::new("X", 1.1),
+                    Outcome::new("Y", 3.3)];
+
+    let table = [
+        marked_1.iter().collect(),
+        marked_2.iter().collect()
+    ];
+
+    let mut opp = find_best(&table, &[0, 1], Unbiased);
+
+    apply_free_bet(&mut opp, 0);
+
+    // Returns are balanced again for x1.3 instead of x2.3.
+    assert_approx_eq!(opp[0].rate, 0.72);
+    assert_approx_eq!(opp[0].profit, 2.3);
+    assert_approx_eq!(opp[1].rate, 0.28);
+    assert_approx_eq!(opp[1].profit, 2.3);
+}
+
+#[test]
+fn test_is_pursuable() {
+    assert!(is_pursuable(0.995, 0.));
+    assert!(!is_pursuable(0.995, 0.01));
+    assert!(is_pursuable(0.985, 0.01));
+    assert!(!is_pursuable(1., 0.));
+}
+
+#[test]
+fn test_find_best_tie() {
+    let marked_1 = [Outcome::new("X", 2.3),
+                    Outcome::new("Y", 1.2)];
+    let marked_2 = [Outcome::new("X", 2.3),
+                    Outcome::new("Y", 1.1)];
+    let marked_3 = [Outcome::new("X", 1.1),
+                    Outcome::new("Y", 3.3)];
+
+    let table = [
+        marked_1.iter().collect(),
+        marked_2.iter().collect(),
+        marked_3.iter().collect()
+    ];
+
+    assert_eq!(find_best(&table, &[0, 1, 2], Unbiased)[0].market, 0);
+    assert_eq!(find_best(&table, &[1, 0, 2], Unbiased)[0].market, 1);
+    assert_eq!(find_best(&table, &[2, 1, 0], Unbiased)[0].market, 1);
+}
+
+#[test]
+fn test_find_best_narrow() {
+    let marked_1 = [
+        Outcome::new("X", 3.0),
+        Outcome::new("D", 1.5),
+        Outcome::new("Y", 1.5)
+    ];
+    let marked_2 = [
+        Outcome::new("X", 3.0),
+        Outcome::new("D", 4.0),
+        Outcome::new("Y", 2.0)
+    ];
+    let marked_3 = [
+        Outcome::new("X", 2.0),
+        Outcome::new("D", 3.0),
+        Outcome::new("Y", 4.0)
+    ];
+
+    let table = [
+        marked_1.iter().collect(),
+        marked_2.iter().collect(),
+        marked_3.iter().collect()
+    ];
+
+    let wide = find_best(&table, &[0, 1, 2], Unbiased);
+    let narrow = find_best_narrow(&table, &[0, 1, 2], 0., false, Unbiased);
+
+    assert_eq!(wide.iter().map(|m| m.market).collect::<Vec<_>>(), vec![0, 1, 2]);
+    assert_eq!(narrow.iter().map(|m| m.market).collect::<Vec<_>>(), vec![1, 1, 2]);
+
+    for (w, n) in wide.iter().zip(narrow.iter()) {
+        assert_approx_eq!(w.profit, n.profit);
+    }
+
+    assert_eq!(choose_bookies(&table, &[0, 1, 2], 0., false), vec![1, 2]);
+}
+
+#[test]
+fn test_choose_bookies_within_book() {
+    let marked_1 = [Outcome::new("X", 2.5), Outcome::new("Y", 2.5)];
+    let marked_2 = [Outcome::new("X", 1.8), Outcome::new("Y", 1.8)];
+    let table = [marked_1.iter().collect(), marked_2.iter().collect()];
+
+    assert_eq!(choose_bookies(&table, &[0, 1], 0., true), vec![0]);
+    assert_eq!(choose_bookies(&table, &[0, 1], 0., false), vec![0, 1]);
+}
+
+#[test]
+fn test_choose_bookies_limit() {
+    let marked = [Outcome::new("X", 2.5), Outcome::new("Y", 2.5)];
+    let table = (0..MAX_NARROWED_ROWS + 1)
+        .map(|_| marked.iter().collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    let ranks = vec![0; table.len()];
+
+    assert_eq!(choose_bookies(&table, &ranks, 0., true).len(), table.len());
+    assert_eq!(choose_bookies(&table[1..], &ranks[1..], 0., false), vec![0, 1]);
+}
+
+#[test]
+fn test_find_best_multi() {
+    let marked_1 = [Outcome::new("X", 2.3),
+                    Outcome::new("Y", 1.2)];
+    let marked_2 = [Outcome::new("X", 1.3),
+                    Outcome::new("Y", 1.1)];
+    let marked_3 = [Outcome::new("X", 1.1),
+                    Outcome::new("Y", 3.3)];
+
+    let table = [
+        marked_1.iter().collect(),
+        marked_2.iter().collect(),
+        marked_3.iter().collect()
+    ];
+
+    let ranks = [0, 1, 2];
+    let balances = [Currency(10000), Currency(500), Currency(500)];
+
+    // The unbiased strategy has the highest least profit.
+    let (strategy, _) = find_best_multi(&table, &ranks, &[Favorite, Unbiased, Rebel],
+                                        Objective::MinStake, 0.001, &balances);
+    assert_eq!(strategy, Unbiased);
+
+    // Both guarantee only the stake back, so the objective decides.
+    let (strategy, line) = find_best_multi(&table, &ranks, &[Favorite, Rebel],
+                                           Objective::MinStake, 0.001, &balances);
+    assert_eq!(strategy, Rebel);
+    assert_approx_eq!(line[0].rate, 0.43);
+
+    let (strategy, line) = find_best_multi(&table, &ranks, &[Favorite, Rebel],
+                                           Objective::MaxBalance, 0.001, &balances);
+    assert_eq!(strategy, Favorite);
+    assert_approx_eq!(line[0].rate, 0.7);
+}
+
+#[test]
+fn test_clamp_coefs() {
+    let marked_1 = [Outcome::new("X", 9999.),
+                    Outcome::new("Y", 1.04)];
+    let marked_2 = [Outcome::new("X", 12.),
+                    Outcome::new("Y", 1.05)];
+
+    let raw = [marked_1.iter().collect(), marked_2.iter().collect()];
+    assert!(is_pursuable(calc_margin(&raw), 0.));
+
+    let clamped_1 = clamp_coefs(&marked_1, 20.);
+    let clamped_2 = clamp_coefs(&marked_2, 20.);
+
+    assert_eq!(clamped_1[0].1, 20.);
+    assert!(match clamped_2 { Cow::Borrowed(_) => true, Cow::Owned(_) => false });
+
+    let table = [clamped_1.iter().collect(), clamped_2.iter().collect()];
+    assert!(!is_pursuable(calc_margin(&table), 0.));
+}
+
+#[test]
+fn test_guaranteed_profit() {
+    // 2% is enough by percentage, but it's only pennies on small stakes.
+    let small = [(Currency(100), 2.04), (Currency(100), 2.04)];
+    assert_eq!(guaranteed_profit(&small), Currency(4));
+    assert!(guaranteed_profit(&small) < Currency::from(0.50));
+
+    let large = [(Currency(5000), 2.04), (Currency(4900), 2.1)];
+    assert_eq!(guaranteed_profit(&large), Currency(300));
+}
+
+#[test]
+fn test_risk_adjusted() {
+    let min_profit = 0.02;
+    let profit = 0.025;
+
+    assert!(risk_adjusted(profit, 0.) >= min_profit);
+    assert!(risk_adjusted(profit, 0.3) < min_profit);
+    assert_approx_eq!(risk_adjusted(profit, 0.3), 0.0175);
+}
+
+#[test]
+fn test_fair_odds() {
+    let first = [Outcome::new("X", 1.5), Outcome::new("Y", 2.2)];
+    let second = [Outcome::new("X", 1.4),
+                  Outcome::new("Y", 2.5)];
+    let table = [first.iter().collect(), second.iter().collect()];
+
+    // The best coefs are x1.5 and x2.5, so 66.7% and 40% are normalized.
+    let fair = fair_odds(&table);
+
+    assert_eq!(fair.len(), 2);
+    assert_approx_eq!(fair[0], 0.625);
+    assert_approx_eq!(fair[1], 0.375);
+    assert_approx_eq!(fair.iter().sum::<f64>(), 1.);
+}
+
+#[test]
+fn test_stakes_by_equal_profit() {
+    let coefs = [2.1, 2.05];
+    let rates = coefs.iter().map(|coef| 1. / coef).collect::<Vec<_>>();
+
+    let by_rates = stakes_by_rates(&rates, Currency(100));
+    let by_profit = stakes_by_equal_profit(&coefs, Currency(100), 1.);
+
+    assert_eq!(by_rates, vec![Currency(100), Currency(102)]);
+    assert_eq!(by_profit, vec![Currency(123), Currency(126)]);
+
+    // The inflation is capped at the cost of less even returns.
+    let capped = stakes_by_equal_profit(&coefs, Currency(100), 0.1);
+    assert_eq!(capped, vec![Currency(109), Currency(112)]);
+    assert!(capped.iter().zip(by_rates.iter()).all(|(&c, &r)| c.0 as f64 <= r.0 as f64 * 1.1));
+
+    let profits = |stakes: &[Currency]| {
+        let outlay = stakes.iter().fold(Currency(0), |sum, &stake| sum + stake);
+        stakes.iter().zip(coefs.iter()).map(|(&s, &c)| s * c - outlay).collect::<Vec<_>>()
+    };
+
+    // Rounding makes profits of the rates mode uneven.
+    assert_eq!(profits(&by_rates), vec![Currency(8), Currency(7)]);
+    assert_eq!(profits(&by_profit), vec![Currency(9), Currency(9)]);
+    assert_eq!(guaranteed_profit(&[(Currency(123), 2.1), (Currency(126), 2.05)]), Currency(9));
+}
+
+#[test]
+fn test_within_book_arb() {
+    let mispriced = [Outcome::new("A", 2.1),
+                     Outcome::new("B", 2.05)];
+    let fair = [Outcome::new("A", 1.95), Outcome::new("B", 1.9)];
+    let missing = [Outcome::new("A", 2.1),
+                   Outcome::new("B", 0.)];
+
+    assert!(is_within_book_arb(&mispriced, 0.005));
+    assert!(!is_within_book_arb(&fair, 0.005));
+    assert!(!is_within_book_arb(&missing, 0.005));
+    assert!(!is_within_book_arb(&mispriced[..1], 0.005));
+    assert_eq!(overround(&missing), None);
+
+    // The market of the single offer is evaluated as usual.
+    let table = vec![mispriced.iter().collect::<Vec<_>>()];
+    let best = find_best(&table, &[0], Unbiased);
+
+    assert!(is_pursuable(calc_margin(&table), 0.005));
+    assert_eq!(best.len(), 2);
+    assert!(best.iter().all(|marked| marked.market == 0 && marked.profit > 0.));
+}
+
+#[test]
+fn test_suspended_draw() {
+    let suspended = [
+        Outcome::new("A", 2.6),
+        Outcome::new("X", 0.),
+        Outcome::new("B", 3.1)
+    ];
+
+    let active = [
+        Outcome::new("A", 2.4),
+        Outcome::new("X", 3.9),
+        Outcome::new("B", 2.9)
+    ];
+
+    // The draw is backed by the bookie which doesn't suspend it.
+    let table = vec![suspended.iter().collect::<Vec<_>>(), active.iter().collect()];
+    assert!(is_hedgeable(&table));
+    assert!(is_pursuable(calc_margin(&table), 0.));
+
+    let best = find_best(&table, &[0, 1], Unbiased);
+    let markets = best.iter().map(|marked| marked.market).collect::<Vec<_>>();
+
+    assert_eq!(markets, vec![0, 1, 0]);
+    assert!(best.iter().all(|marked| marked.outcome.is_active()));
+
+    // Two legs don't hedge the draw.
+    let table = vec![suspended.iter().collect::<Vec<_>>(), suspended.iter().collect()];
+    assert!(!is_hedgeable(&table));
+    assert!(!is_pursuable(calc_margin(&table), 0.));
+}
+
+#[test]
+fn test_lay_only_outcomes() {
+    use constants::REQUIRE_BACKABLE_LEGS;
+
+    if !REQUIRE_BACKABLE_LEGS {
+        return;
+    }
+
+    let exchange = [Outcome::new("X", 3.5).lay_only(),
+                    Outcome::new("Y", 1.9)];
+    let bookie = [Outcome::new("X", 2.3),
+                  Outcome::new("Y", 1.8)];
+
+    // The higher coef on X can only be laid, so it's backed by the other bookie.
+    let table = vec![exchange.iter().collect::<Vec<_>>(), bookie.iter().collect()];
+    assert!(is_hedgeable(&table));
+    assert_approx_eq!(calc_margin(&table), 1. / 2.3 + 1. / 1.9);
+
+    let best = find_best(&table, &[0, 1], Unbiased);
+    assert_eq!(best.iter().map(|marked| marked.market).collect::<Vec<_>>(), vec![1, 0]);
+    assert!(best.iter().all(|marked| marked.outcome.is_backable()));
+
+    // Nobody backs X.
+    let table = vec![exchange.iter().collect::<Vec<_>>()];
+    assert!(!is_hedgeable(&table));
+    assert!(!is_within_book_arb(&exchange, 0.));
+}
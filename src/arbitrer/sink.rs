@@ -0,0 +1,211 @@
+use std::io::{self, Write};
+use std::net::TcpStream;
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, Instant};
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::mpsc::{self, SyncSender, Receiver, TrySendError};
+use serde_json as json;
+use time;
+
+use base::error::{Result, Error};
+use markets::Offer;
+
+const RECONNECT_DELAY: u64 = 5;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Target {
+    Tcp(String),
+    Unix(PathBuf)
+}
+
+impl Target {
+    // Either `tcp://host:port` or `unix:///path/to/socket`.
+    fn parse(target: &str) -> Result<Target> {
+        if target.starts_with("tcp://") {
+            Ok(Target::Tcp(target[6..].to_owned()))
+        } else if target.starts_with("unix://") {
+            Ok(Target::Unix(PathBuf::from(&target[7..])))
+        } else {
+            Err(Error::from(format!("Unsupported offer sink: {}", target)))
+        }
+    }
+
+    fn connect(&self) -> io::Result<Box<Write + Send>> {
+        Ok(match *self {
+            Target::Tcp(ref address) => Box::new(try!(TcpStream::connect(address.as_str()))),
+            Target::Unix(ref path) => Box::new(try!(UnixStream::connect(path)))
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct Record<'a> {
+    event: &'static str,
+    host: &'a str,
+    // In milliseconds.
+    timestamp: u64,
+    oid: u64,
+    date: u32,
+    game: String,
+    kind: String,
+    league: Option<&'a str>,
+    is_live: bool,
+    best_of: Option<u8>,
+    outcomes: Vec<(&'a str, f64)>
+}
+
+// Publishes the offer stream as newline-delimited JSON. Messages are queued and written by
+// a separate thread, so the intake is never stalled: they are dropped if the queue is full.
+pub struct Sink {
+    tx: SyncSender<String>,
+    dropped: AtomicUsize
+}
+
+impl Sink {
+    pub fn new(target: &str, capacity: usize) -> Result<Sink> {
+        let target = try!(Target::parse(target));
+        let (sink, rx) = Sink::channel(capacity);
+
+        thread::Builder::new()
+            .name("offer sink".to_owned())
+            .spawn(move || write_lines(rx, &target))
+            .unwrap();
+
+        Ok(sink)
+    }
+
+    fn channel(capacity: usize) -> (Sink, Receiver<String>) {
+        let (tx, rx) = mpsc::sync_channel(capacity);
+
+        (Sink { tx: tx, dropped: AtomicUsize::new(0) }, rx)
+    }
+
+    pub fn publish(&self, host: &str, offer: &Offer, upsert: bool) {
+        let now = time::get_time();
+
+        let record = Record {
+            event: if upsert { "upsert" } else { "remove" },
+            host: host,
+            timestamp: now.sec as u64 * 1000 + now.nsec as u64 / 1_000_000,
+            oid: offer.oid,
+            date: offer.date,
+            game: format!("{:?}", offer.game),
+            kind: format!("{:?}", offer.kind),
+            league: offer.league.as_ref().map(|league| league.as_str()),
+            is_live: offer.is_live,
+            best_of: offer.best_of,
+            outcomes: offer.outcomes.iter().map(|o| (o.0.as_str(), o.1)).collect()
+        };
+
+        let line = json::to_string(&record).unwrap();
+
+        match self.tx.try_send(line) {
+            Ok(_) | Err(TrySendError::Disconnected(_)) => {},
+            Err(TrySendError::Full(_)) => {
+                // Report only the first drop of a series.
+                if self.dropped.fetch_add(1, Relaxed) == 0 {
+                    warn!("The offer sink is lagging, dropping messages");
+                }
+
+                return;
+            }
+        }
+
+        let dropped = self.dropped.swap(0, Relaxed);
+
+        if dropped > 0 {
+            warn!("The offer sink has dropped {} messages", dropped);
+        }
+    }
+}
+
+fn write_lines(rx: Receiver<String>, target: &Target) {
+    let mut stream: Option<Box<Write + Send>> = None;
+    let mut last_attempt: Option<Instant> = None;
+
+    for line in rx {
+        if stream.is_none() {
+            // Lines are dropped while the target is unavailable.
+            if last_attempt.map_or(false, |at| at.elapsed() < Duration::new(RECONNECT_DELAY, 0)) {
+                continue;
+            }
+
+            last_attempt = Some(Instant::now());
+
+            match target.connect() {
+                Ok(connected) => stream = Some(connected),
+                Err(error) => {
+                    warn!("Cannot connect to the offer sink {:?}: {}", target, error);
+                    continue;
+                }
+            }
+        }
+
+        let result = stream.as_mut().unwrap().write_all(format!("{}\n", line).as_bytes());
+
+        if let Err(error) = result {
+            warn!("Cannot write to the offer sink {:?}: {}", target, error);
+            stream = None;
+        }
+    }
+}
+
+#[test]
+fn test_target() {
+    assert_eq!(Target::parse("tcp://127.0.0.1:9000").unwrap(),
+               Target::Tcp("127.0.0.1:9000".to_owned()));
+    assert_eq!(Target::parse("unix:///tmp/offers.sock").unwrap(),
+               Target::Unix(PathBuf::from("/tmp/offers.sock")));
+    assert!(Target::parse("amqp://localhost").is_err());
+}
+
+#[test]
+fn test_publish() {
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+    use markets::{Outcome, Game, Kind};
+
+    let offer = |oid, coef| Offer {
+        oid: oid, date: 100, game: Game::Dota2, kind: Kind::Series, league: None, is_live: false,
+        best_of: None,
+        outcomes: vec![Outcome::new("A", coef),
+                       Outcome::new("B", 2.)]
+    };
+
+    let stream = [(offer(1, 1.5), true), (offer(2, 1.6), true), (offer(1, 1.5), false)];
+
+    // Nobody reads the queue, so the last message is dropped instead of blocking.
+    let (sink, rx) = Sink::channel(2);
+
+    for &(ref offer, upsert) in &stream {
+        sink.publish("bookie.com", offer, upsert);
+    }
+
+    assert_eq!(rx.try_iter().count(), 2);
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let sink = Sink::new(&format!("tcp://{}", address), 16).unwrap();
+
+    for &(ref offer, upsert) in &stream {
+        sink.publish("bookie.com", offer, upsert);
+    }
+
+    let reader = BufReader::new(listener.accept().unwrap().0);
+
+    for (line, &(ref offer, upsert)) in reader.lines().take(stream.len()).zip(&stream) {
+        let record: json::Value = json::from_str(&line.unwrap()).unwrap();
+
+        assert_eq!(record.find("event").unwrap().as_str(),
+                   Some(if upsert { "upsert" } else { "remove" }));
+        assert_eq!(record.find("host").unwrap().as_str(), Some("bookie.com"));
+        assert_eq!(record.find("oid").unwrap().as_u64(), Some(offer.oid));
+        assert_eq!(record.find("game").unwrap().as_str(), Some("Dota2"));
+
+        let outcomes = record.find("outcomes").unwrap().as_array().unwrap();
+        assert_eq!(outcomes[0].as_array().unwrap()[1].as_f64(), Some(offer.outcomes[0].1));
+    }
+}
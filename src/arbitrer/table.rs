@@ -2,14 +2,27 @@ use std::ops::Deref;
 use std::hash::{BuildHasher, Hasher, Hash};
 use std::collections::hash_map::RandomState;
 use parking_lot::{Mutex, MutexGuard};
+use log::LogLevel;
 
+use base::clock::{BoxedClock, RealClock};
+use constants::DISTINGUISH_SERIES_FORMATS;
 use markets::Offer;
-use arbitrer::matcher;
+use arbitrer::matcher::{self, Fingerprint, Comparison};
 use arbitrer::MarkedOffer;
+#[cfg(test)]
+use arbitrer::Bookie;
 
 
+// Markets with similar (but not enough) titles are logged while tuning the matcher.
+const NEAR_MISS_SCORE: f64 = 0.4;
+
+// Headlines round dates to this step, so an offer whose date is updated across the boundary
+// comes to a neighboring entry. The market is looked for there before starting a new one.
+const DATE_STEP: u32 = 30 * 60;
+
 pub struct Table {
     rand_state: RandomState,
+    clock: BoxedClock,
     entries: Box<[Mutex<Entry>]>
 }
 
@@ -17,7 +30,12 @@ type Entry = Vec<Bucket>;
 
 struct Bucket {
     badge: Offer,
-    market: Vec<MarkedOffer>
+    fingerprint: Fingerprint,
+    market: Vec<MarkedOffer>,
+    // The series format known by any offer of the market, the badge itself can lack it.
+    best_of: Option<u8>,
+    // Since when the offers of the market haven't changed, a momentary match is likely a fluke.
+    matched_at: u32
 }
 
 pub struct MarketGuard<'a> {
@@ -25,6 +43,13 @@ pub struct MarketGuard<'a> {
     index: usize
 }
 
+impl<'a> MarketGuard<'a> {
+    #[inline]
+    pub fn matched_at(&self) -> u32 {
+        self.guard[self.index].matched_at
+    }
+}
+
 impl<'a> Deref for MarketGuard<'a> {
     type Target = [MarkedOffer];
 
@@ -68,8 +93,13 @@ impl<'a> Iterator for Iter<'a> {
 
 impl Table {
     pub fn new(capacity: usize) -> Table {
+        Table::with_clock(capacity, Box::new(RealClock))
+    }
+
+    pub fn with_clock(capacity: usize, clock: BoxedClock) -> Table {
         Table {
             rand_state: RandomState::new(),
+            clock: clock,
             entries: (0..capacity)
                 .map(|_| Mutex::new(Vec::new()))
                 .collect::<Vec<_>>()
@@ -77,48 +107,81 @@ impl Table {
         }
     }
 
-    pub fn get_market(&self, offer: &Offer) -> Option<MarketGuard> {
-        let entry = self.get_entry(offer);
-        let index = entry.iter().position(|bucket| matcher::compare_offers(offer, &bucket.badge));
+    #[inline]
+    pub fn now(&self) -> u32 {
+        self.clock.now()
+    }
 
-        index.map(|index| MarketGuard { guard: entry, index: index })
+    pub fn get_market(&self, offer: &Offer) -> Option<MarketGuard> {
+        self.find_market(offer, matcher::get_fingerprint(offer))
+            .map(|(entry, index)| MarketGuard { guard: entry, index: index })
     }
 
     pub fn update_offer(&self, marked: MarkedOffer) -> usize {
-        let mut entry = self.get_entry(&marked.1);
+        let fingerprint = matcher::get_fingerprint(&marked.1);
 
-        if let Some(bucket) = entry.iter_mut().find(|b| matcher::compare_offers(&marked.1, &b.badge)) {
-            let market_len = bucket.market.len();
-            debug_assert!(market_len > 0);
+        let (mut entry, index) = match self.find_market(&marked.1, fingerprint) {
+            Some(found) => found,
+            None => {
+                let mut entry = self.get_entry(&marked.1);
+
+                // The entry has been unlocked for a while, so the market could be added.
+                let index = find_bucket(&entry, &marked.1, fingerprint);
+
+                match index {
+                    Some(index) => (entry, index),
+                    None => {
+                        debug!("Market [{} by {}] is added", marked.1, marked.0.host);
+
+                        if log_enabled!(LogLevel::Trace) {
+                            log_near_misses(&entry, &marked.1);
+                        }
+
+                        entry.push(Bucket {
+                            badge: marked.1.clone(),
+                            fingerprint: fingerprint,
+                            best_of: marked.1.best_of,
+                            market: vec![marked],
+                            matched_at: self.clock.now()
+                        });
+
+                        return 1;
+                    }
+                }
+            }
+        };
 
-            if let Some(stored) = bucket.market.iter_mut().find(|stored| stored.0 == marked.0) {
-                debug!("{} by {} is updated", marked.1, marked.0.host);
-                *stored = marked;
+        let bucket = &mut entry[index];
+        let market_len = bucket.market.len();
+        debug_assert!(market_len > 0);
 
-                return market_len;
+        if let Some(stored) = bucket.market.iter_mut().find(|stored| stored.0 == marked.0) {
+            debug!("{} by {} is updated", marked.1, marked.0.host);
+
+            // The bookie can reissue the offer with a new id.
+            if stored.1.oid != marked.1.oid {
+                bucket.matched_at = self.clock.now();
             }
 
-            debug!("{} by {} is added", marked.1, marked.0.host);
-            bucket.market.push(marked);
+            *stored = marked;
+            bucket.best_of = known_format(&bucket.market);
 
-            return market_len + 1;
+            return market_len;
         }
 
-        debug!("Market [{} by {}] is added", marked.1, marked.0.host);
-
-        entry.push(Bucket {
-            badge: marked.1.clone(),
-            market: vec![marked]
-        });
+        debug!("{} by {} is added", marked.1, marked.0.host);
+        bucket.market.push(marked);
+        bucket.best_of = known_format(&bucket.market);
+        bucket.matched_at = self.clock.now();
 
-        1
+        market_len + 1
     }
 
     pub fn remove_offer(&self, marked: &MarkedOffer) {
-        let mut entry = self.get_entry(&marked.1);
+        let fingerprint = matcher::get_fingerprint(&marked.1);
 
-        let market_index = match entry.iter().position(|b| matcher::compare_offers(&marked.1, &b.badge)) {
-            Some(index) => index,
+        let (mut entry, market_index) = match self.find_market(&marked.1, fingerprint) {
+            Some(found) => found,
             None => {
                 warn!("Cannot remove non-existent offer {} by {}: no suitable market",
                       marked.1, marked.0.host);
@@ -127,7 +190,8 @@ impl Table {
         };
 
         let remove_market = {
-            let market = &mut entry[market_index].market;
+            let bucket = &mut entry[market_index];
+            let market = &mut bucket.market;
 
             let index = match market.iter().position(|stored| stored.0 == marked.0) {
                 Some(index) => index,
@@ -141,6 +205,8 @@ impl Table {
 
             if market.len() > 1 {
                 market.swap_remove(index);
+                bucket.best_of = known_format(market);
+                bucket.matched_at = self.clock.now();
                 false
             } else {
                 true
@@ -161,6 +227,39 @@ impl Table {
         }
     }
 
+    // Looks for the market in the offer's own entry, then in the ones of neighboring dates.
+    // Only one entry is locked at a time, they can share the mutex.
+    fn find_market(&self, offer: &Offer,
+                   fingerprint: Fingerprint) -> Option<(MutexGuard<Entry>, usize)>
+    {
+        let entry = self.get_entry(offer);
+        let index = find_bucket(&entry, offer, fingerprint);
+
+        if let Some(index) = index {
+            return Some((entry, index));
+        }
+
+        drop(entry);
+
+        let steps = [offer.date.checked_sub(DATE_STEP), offer.date.checked_add(DATE_STEP)];
+
+        for date in steps.iter().filter_map(|&date| date) {
+            // Compared as if it were in the neighboring entry.
+            let shifted = Offer { date: date, ..offer.clone() };
+
+            let entry = self.get_entry(&shifted);
+            let index = find_bucket(&entry, &shifted, fingerprint);
+
+            if let Some(index) = index {
+                debug!("{} is found in the market [{}] of the neighboring date",
+                       offer, entry[index].badge);
+                return Some((entry, index));
+            }
+        }
+
+        None
+    }
+
     fn get_entry(&self, offer: &Offer) -> MutexGuard<Entry> {
         let state = &mut self.rand_state.build_hasher();
         matcher::get_headline(offer).hash(state);
@@ -169,3 +268,223 @@ impl Table {
         self.entries[hash as usize % self.entries.len()].lock()
     }
 }
+
+fn log_near_misses(entry: &[Bucket], offer: &Offer) {
+    for bucket in entry {
+        let comparison = matcher::compare_offers_explain(offer, &bucket.badge);
+
+        let is_near_miss = match comparison {
+            Comparison::LowSimilarity(score) => score >= NEAR_MISS_SCORE,
+            Comparison::LeagueMismatch | Comparison::FormatMismatch => true,
+            _ => false
+        };
+
+        if is_near_miss {
+            trace!("{} doesn't match [{}]: {:?}", offer, bucket.badge, comparison);
+        }
+    }
+}
+
+// Fingerprints are much cheaper than `compare_offers`, so most buckets are skipped by them.
+// The badge can lack the series format, so the one known by the market is checked as well.
+fn find_bucket(entry: &[Bucket], offer: &Offer, fingerprint: Fingerprint) -> Option<usize> {
+    entry.iter().position(|bucket| {
+        matcher::may_match(fingerprint, bucket.fingerprint)
+            && matcher::compare_offers(offer, &bucket.badge)
+            && (!DISTINGUISH_SERIES_FORMATS || matcher::formats_agree(offer.best_of, bucket.best_of))
+    })
+}
+
+#[inline]
+fn known_format(market: &[MarkedOffer]) -> Option<u8> {
+    market.iter().filter_map(|marked| marked.1.best_of).next()
+}
+
+#[cfg(test)]
+const WORDS: &[&str] = &[
+    "Arsenal", "Benfica", "Celtic", "Dynamo", "Everton", "Feyenoord", "Galatasaray", "Hajduk",
+    "Inter", "Juventus", "Kairat", "Lokomotiv", "Monaco", "Napoli", "Olympiacos", "Porto",
+    "Qarabag", "Rangers", "Sevilla", "Torino", "Udinese", "Valencia", "Wolfsburg", "Xanthi",
+    "Young Boys", "Zenit"
+];
+
+#[cfg(test)]
+fn make_buckets(count: usize) -> (Vec<Offer>, Vec<Bucket>) {
+    use markets::{Outcome, Game, Kind, DRAW};
+
+    let offers = (0..count).map(|i| Offer {
+        oid: i as u64,
+        date: 0,
+        game: Game::Football,
+        kind: Kind::Series,
+        outcomes: vec![
+            Outcome::new(&format!("{} {}", WORDS[i % 26], WORDS[i / 26 % 26]), 2.),
+            Outcome::new(DRAW, 3.),
+            Outcome::new(&format!("{} {}", WORDS[(i + 7) % 26], WORDS[i / 13 % 26]), 4.)
+        ],
+        league: None,
+        is_live: false,
+        best_of: None
+    }).collect::<Vec<_>>();
+
+    let mut buckets = Vec::new();
+
+    for offer in &offers {
+        if !buckets.iter().any(|b: &Bucket| matcher::compare_offers(offer, &b.badge)) {
+            buckets.push(Bucket {
+                badge: offer.clone(),
+                fingerprint: matcher::get_fingerprint(offer),
+                best_of: None,
+                market: Vec::new(),
+                matched_at: 0
+            });
+        }
+    }
+
+    (offers, buckets)
+}
+
+#[test]
+fn test_find_bucket() {
+    let (offers, buckets) = make_buckets(26 * 26);
+
+    for offer in &offers {
+        let exhaustive = buckets.iter().position(|b| matcher::compare_offers(offer, &b.badge));
+        let filtered = find_bucket(&buckets, offer, matcher::get_fingerprint(offer));
+
+        assert!(exhaustive.is_some());
+        assert_eq!(filtered, exhaustive);
+    }
+}
+
+#[cfg(test)]
+lazy_static! {
+    // Bookies never watched, only to mark offers.
+    static ref FIRST: Bookie = make_bookie("first.com");
+    static ref SECOND: Bookie = make_bookie("second.com");
+    static ref THIRD: Bookie = make_bookie("third.com");
+}
+
+#[cfg(test)]
+fn make_bookie(host: &str) -> Bookie {
+    use gamblers::chaos::Chaos;
+
+    Bookie::with_gambler(host, "", "", "chaos", Box::new(Chaos::new()), Box::new(RealClock))
+}
+
+#[test]
+fn test_neighboring_dates() {
+    use markets::{Outcome, Game, Kind};
+
+    let offer = |oid, date| Offer {
+        oid: oid, date: date, game: Game::Dota2, kind: Kind::Series, league: None, is_live: false,
+        best_of: None,
+        outcomes: vec![Outcome::new("Natus Vincere", 1.6),
+                       Outcome::new("Virtus.pro", 2.3)]
+    };
+
+    // Dates are rounded to 1_800_000, the second one is across the boundary.
+    let (early, late) = (1_800_000 + 800, 1_800_000 + 1000);
+    assert!(matcher::get_headline(&offer(1, early)) != matcher::get_headline(&offer(2, late)));
+
+    let table = Table::new(16);
+
+    assert_eq!(table.update_offer(MarkedOffer(&*FIRST, offer(1, early))), 1);
+    assert_eq!(table.update_offer(MarkedOffer(&*SECOND, offer(2, late))), 2);
+    assert_eq!(table.iter().count(), 1);
+    assert_eq!(table.get_market(&offer(2, late)).unwrap().len(), 2);
+
+    table.remove_offer(&MarkedOffer(&*SECOND, offer(2, late)));
+    assert_eq!(table.get_market(&offer(1, early)).unwrap().len(), 1);
+
+    table.remove_offer(&MarkedOffer(&*FIRST, offer(1, early)));
+    assert_eq!(table.iter().count(), 0);
+}
+
+#[test]
+fn test_series_formats() {
+    use markets::{Outcome, Game, Kind};
+
+    let offer = |oid, best_of| Offer {
+        oid: oid, date: 1_800_000, game: Game::Dota2, kind: Kind::Series, league: None,
+        is_live: false, best_of: best_of,
+        outcomes: vec![Outcome::new("Natus Vincere", 1.6),
+                       Outcome::new("Virtus.pro", 2.3)]
+    };
+
+    let table = Table::new(16);
+
+    // The badge doesn't know the format, but the market does after the bo3 offer joins it.
+    assert_eq!(table.update_offer(MarkedOffer(&*FIRST, offer(1, None))), 1);
+    assert_eq!(table.update_offer(MarkedOffer(&*SECOND, offer(2, Some(3)))), 2);
+    assert_eq!(table.update_offer(MarkedOffer(&*THIRD, offer(3, Some(5)))), 1);
+    assert_eq!(table.iter().count(), 2);
+
+    // Without the bo3 offer the market is of any format again.
+    table.remove_offer(&MarkedOffer(&*THIRD, offer(3, Some(5))));
+    table.remove_offer(&MarkedOffer(&*SECOND, offer(2, Some(3))));
+    assert_eq!(table.update_offer(MarkedOffer(&*THIRD, offer(3, Some(5)))), 2);
+}
+
+#[test]
+fn test_match_age() {
+    use std::sync::Arc;
+    use base::clock::TestClock;
+    use markets::{Outcome, Game, Kind};
+
+    let offer = |oid, coef| Offer {
+        oid: oid, date: 1_800_000, game: Game::Dota2, kind: Kind::Series, league: None,
+        is_live: false, best_of: None,
+        outcomes: vec![Outcome::new("Natus Vincere", coef),
+                       Outcome::new("Virtus.pro", 2.3)]
+    };
+
+    let clock = Arc::new(TestClock::new(1000));
+    let table = Table::with_clock(16, Box::new(clock.clone()));
+    let matched_at = || table.get_market(&offer(1, 1.6)).unwrap().matched_at();
+
+    table.update_offer(MarkedOffer(&*FIRST, offer(1, 1.6)));
+    clock.advance(10);
+    table.update_offer(MarkedOffer(&*SECOND, offer(2, 1.7)));
+    assert_eq!(matched_at(), 1010);
+    assert_eq!(table.now(), 1010);
+
+    // Changed odds of the same offers confirm the match.
+    clock.advance(10);
+    table.update_offer(MarkedOffer(&*FIRST, offer(1, 1.65)));
+    table.update_offer(MarkedOffer(&*SECOND, offer(2, 1.75)));
+    assert_eq!(matched_at(), 1010);
+
+    // A reissued offer is a churn.
+    clock.advance(10);
+    table.update_offer(MarkedOffer(&*SECOND, offer(3, 1.75)));
+    assert_eq!(matched_at(), 1030);
+
+    clock.advance(10);
+    table.remove_offer(&MarkedOffer(&*SECOND, offer(3, 1.75)));
+    assert_eq!(matched_at(), 1040);
+}
+
+#[bench]
+fn bench_find_bucket(b: &mut ::test::Bencher) {
+    let (offers, buckets) = make_buckets(500);
+
+    b.iter(|| {
+        offers.iter()
+            .filter_map(|offer| find_bucket(&buckets, offer, matcher::get_fingerprint(offer)))
+            .count()
+    });
+}
+
+#[bench]
+fn bench_find_bucket_exhaustive(b: &mut ::test::Bencher) {
+    let (offers, buckets) = make_buckets(500);
+
+    b.iter(|| {
+        offers.iter()
+            .filter_map(|offer| {
+                buckets.iter().position(|b| matcher::compare_offers(offer, &b.badge))
+            })
+            .count()
+    });
+}
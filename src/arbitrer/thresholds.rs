@@ -0,0 +1,155 @@
+use parking_lot::RwLock;
+
+use constants::{MIN_PROFIT, MAX_PROFIT, LIVE_MIN_PROFIT, LIVE_MAX_PROFIT, BASE_STAKE, MAX_STAKE};
+use constants::MIN_ABSOLUTE_PROFIT;
+use base::error::{Result, Error};
+use base::currency::Currency;
+
+// Thresholds which can be tuned at runtime, the constants are only the initial values.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Thresholds {
+    pub min_profit: f64,
+    pub max_profit: f64,
+    pub live_min_profit: f64,
+    pub live_max_profit: f64,
+    pub base_stake: Currency,
+    pub max_stake: Currency,
+    pub min_absolute_profit: Currency
+}
+
+impl Thresholds {
+    pub fn new() -> Thresholds {
+        Thresholds {
+            min_profit: MIN_PROFIT,
+            max_profit: MAX_PROFIT,
+            live_min_profit: LIVE_MIN_PROFIT,
+            live_max_profit: LIVE_MAX_PROFIT,
+            base_stake: *BASE_STAKE,
+            max_stake: *MAX_STAKE,
+            min_absolute_profit: *MIN_ABSOLUTE_PROFIT
+        }
+    }
+
+    #[inline]
+    pub fn accepts(&self, min_profit: f64) -> bool {
+        self.min_profit <= min_profit && min_profit <= self.max_profit
+    }
+
+    // Markets with in-play offers are evaluated against their own profit range.
+    pub fn for_live(&self) -> Thresholds {
+        Thresholds {
+            min_profit: self.live_min_profit,
+            max_profit: self.live_max_profit,
+            ..*self
+        }
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        let value = try!(value.parse::<f64>());
+
+        if !value.is_finite() {
+            return Err(Error::from(format!("{} must be finite", key)));
+        }
+
+        match key {
+            "min_profit" => self.min_profit = value,
+            "max_profit" => self.max_profit = value,
+            "live_min_profit" => self.live_min_profit = value,
+            "live_max_profit" => self.live_max_profit = value,
+            "base_stake" => self.base_stake = Currency::from(value),
+            "max_stake" => self.max_stake = Currency::from(value),
+            "min_absolute_profit" => self.min_absolute_profit = Currency::from(value),
+            _ => return Err(Error::from(format!("Unknown threshold: {}", key)))
+        }
+
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.min_profit < 0. || self.live_min_profit < 0. {
+            return Err(Error::from("Profits must not be negative"));
+        }
+
+        if !(self.min_profit < self.max_profit) {
+            return Err(Error::from("min_profit must be less than max_profit"));
+        }
+
+        if !(self.live_min_profit < self.live_max_profit) {
+            return Err(Error::from("live_min_profit must be less than live_max_profit"));
+        }
+
+        if self.base_stake <= Currency(0) || self.max_stake <= Currency(0) {
+            return Err(Error::from("Stakes must be positive"));
+        }
+
+        if self.base_stake > self.max_stake {
+            return Err(Error::from("base_stake must not exceed max_stake"));
+        }
+
+        // The compiled limit is a safety net, it can be lowered only.
+        if self.max_stake > *MAX_STAKE {
+            return Err(Error::from(format!("max_stake must not exceed {}", *MAX_STAKE)));
+        }
+
+        if self.min_absolute_profit < Currency(0) {
+            return Err(Error::from("min_absolute_profit must not be negative"));
+        }
+
+        Ok(())
+    }
+}
+
+// Either all the pairs are applied or none of them.
+pub fn update(thresholds: &RwLock<Thresholds>, pairs: &[(String, String)]) -> Result<Thresholds> {
+    let mut current = thresholds.write();
+    let mut updated = *current;
+
+    for &(ref key, ref value) in pairs {
+        try!(updated.set(key, value));
+    }
+
+    try!(updated.validate());
+    *current = updated;
+
+    Ok(updated)
+}
+
+#[test]
+fn test_update() {
+    let thresholds = RwLock::new(Thresholds::new());
+    let pair = |key: &str, value: &str| (key.to_owned(), value.to_owned());
+
+    let min_profit = (MIN_PROFIT + MAX_PROFIT) / 2.;
+    assert!(thresholds.read().accepts(min_profit));
+
+    let raised = format!("{}", min_profit + 0.01);
+    assert!(update(&thresholds, &[pair("min_profit", &raised)]).is_ok());
+    assert!(!thresholds.read().accepts(min_profit));
+
+    assert!(update(&thresholds, &[pair("base_stake", "3"), pair("max_stake", "4")]).is_ok());
+    assert_eq!(thresholds.read().base_stake, Currency::from(3.));
+
+    assert!(update(&thresholds, &[pair("min_absolute_profit", "0.2")]).is_ok());
+    assert_eq!(thresholds.read().min_absolute_profit, Currency::from(0.2));
+
+    assert!(update(&thresholds, &[pair("live_min_profit", "0.05")]).is_ok());
+    assert_eq!(thresholds.read().for_live().min_profit, 0.05);
+    assert_eq!(thresholds.read().for_live().base_stake, Currency::from(3.));
+
+    let before = *thresholds.read();
+
+    assert!(update(&thresholds, &[pair("max_profit", "0")]).is_err());
+    assert!(update(&thresholds, &[pair("live_max_profit", "0.01")]).is_err());
+    assert!(update(&thresholds, &[pair("max_stake", "4"), pair("base_stake", "-1")]).is_err());
+    assert!(update(&thresholds, &[pair("max_stake", "2")]).is_err());
+    assert!(update(&thresholds, &[pair("min_absolute_profit", "-1")]).is_err());
+    assert!(update(&thresholds, &[pair("min_profit", "-0.01")]).is_err());
+    assert!(update(&thresholds, &[pair("live_min_profit", "-0.01")]).is_err());
+    assert!(update(&thresholds, &[pair("max_profit", "inf")]).is_err());
+    assert!(update(&thresholds, &[pair("max_profit", "NaN")]).is_err());
+    assert!(update(&thresholds, &[pair("max_stake", "1000")]).is_err());
+    assert!(update(&thresholds, &[pair("min_profit", "abc")]).is_err());
+    assert!(update(&thresholds, &[pair("margin", "1")]).is_err());
+
+    assert_eq!(*thresholds.read(), before);
+}
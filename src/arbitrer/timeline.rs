@@ -0,0 +1,111 @@
+use parking_lot::Mutex;
+use time;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Phase {
+    ChecksStarted,
+    ChecksCompleted,
+    PlacementStarted,
+    PlacementConfirmed
+}
+
+// Timestamps (in milliseconds) of phases of placing one combo, to find out where the time goes.
+// The timeline starts once the opportunity is detected, legs mark their phases concurrently.
+// It's logged once all legs are done. Marks are keyed by the leg index, because legs of
+// within-book arbs share the host.
+pub struct PlacementTimeline {
+    detected: u64,
+    marks: Mutex<Vec<(usize, String, Phase, u64)>>
+}
+
+impl PlacementTimeline {
+    pub fn new() -> PlacementTimeline {
+        PlacementTimeline {
+            detected: now_ms(),
+            marks: Mutex::new(Vec::new())
+        }
+    }
+
+    pub fn mark(&self, leg: usize, host: &str, phase: Phase) {
+        self.marks.lock().push((leg, host.to_owned(), phase, now_ms()));
+    }
+
+    // Phases of the leg in the order of marking with milliseconds since the detection.
+    pub fn leg(&self, leg: usize) -> Vec<(Phase, u64)> {
+        self.marks.lock().iter()
+            .filter(|m| m.0 == leg)
+            .map(|&(_, _, phase, date)| (phase, date.saturating_sub(self.detected)))
+            .collect()
+    }
+
+    fn summary(&self) -> String {
+        let mut legs = self.marks.lock().iter()
+            .map(|m| (m.0, m.1.clone()))
+            .collect::<Vec<_>>();
+
+        legs.sort();
+        legs.dedup();
+
+        legs.iter()
+            .map(|&(leg, ref host)| {
+                let phases = self.leg(leg).iter()
+                    .map(|&(phase, elapsed)| format!("{:?} +{}ms", phase, elapsed))
+                    .collect::<Vec<_>>();
+
+                format!("#{} {}: {}", leg, host, phases.join(", "))
+            })
+            .collect::<Vec<_>>()
+            .join("; ")
+    }
+}
+
+impl Drop for PlacementTimeline {
+    fn drop(&mut self) {
+        if self.marks.lock().is_empty() {
+            return;
+        }
+
+        info!("The placement timeline (detected at {}): {}", self.detected, self.summary());
+    }
+}
+
+fn now_ms() -> u64 {
+    let now = time::get_time();
+    now.sec as u64 * 1000 + now.nsec as u64 / 1_000_000
+}
+
+#[test]
+fn test_timeline() {
+    use std::thread;
+    use std::time::Duration;
+
+    let timeline = PlacementTimeline::new();
+
+    timeline.mark(0, "a.com", Phase::ChecksStarted);
+    timeline.mark(1, "b.com", Phase::ChecksStarted);
+    timeline.mark(2, "a.com", Phase::ChecksStarted);
+    thread::sleep(Duration::from_millis(20));
+    timeline.mark(0, "a.com", Phase::ChecksCompleted);
+    timeline.mark(0, "a.com", Phase::PlacementStarted);
+    thread::sleep(Duration::from_millis(20));
+    timeline.mark(0, "a.com", Phase::PlacementConfirmed);
+
+    let leg = timeline.leg(0);
+    let phases = leg.iter().map(|&(phase, _)| phase).collect::<Vec<_>>();
+
+    assert_eq!(phases, vec![Phase::ChecksStarted, Phase::ChecksCompleted,
+                            Phase::PlacementStarted, Phase::PlacementConfirmed]);
+
+    assert!(leg.windows(2).all(|pair| pair[0].1 <= pair[1].1));
+    assert!(leg[3].1 - leg[0].1 >= 40);
+
+    // Legs of a within-book arb aren't merged.
+    assert_eq!(timeline.leg(1).len(), 1);
+    assert_eq!(timeline.leg(2).len(), 1);
+    assert!(timeline.leg(3).is_empty());
+
+    let summary = timeline.summary();
+    assert!(summary.starts_with("#0 a.com: ChecksStarted +"));
+    assert!(summary.contains("; #1 b.com: ChecksStarted +"));
+    assert!(summary.contains("; #2 a.com: ChecksStarted +"));
+}
@@ -0,0 +1,90 @@
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::PathBuf;
+use parking_lot::Mutex;
+
+use base::session::Exchange;
+
+// Collects the HTTP traffic of all legs of one combo and dumps it once all legs are done.
+pub struct PlacementTrace {
+    path: PathBuf,
+    legs: Mutex<Vec<(String, Vec<Exchange>)>>
+}
+
+impl PlacementTrace {
+    pub fn new(path: PathBuf) -> PlacementTrace {
+        PlacementTrace {
+            path: path,
+            legs: Mutex::new(Vec::new())
+        }
+    }
+
+    pub fn add(&self, host: &str, exchanges: Vec<Exchange>) {
+        self.legs.lock().push((host.to_owned(), exchanges));
+    }
+
+    fn dump(&self) -> ::std::io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            try!(fs::create_dir_all(dir));
+        }
+
+        let mut file = try!(File::create(&self.path));
+
+        for &(ref host, ref exchanges) in &*self.legs.lock() {
+            try!(writeln!(file, "=== {} ===", host));
+
+            for exchange in exchanges {
+                try!(writeln!(file, "> {} {}", exchange.method, exchange.url));
+
+                if let Some(ref request) = exchange.request {
+                    try!(writeln!(file, "{}", request));
+                }
+
+                try!(writeln!(file, "< {}\n{}\n", exchange.status, exchange.response));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for PlacementTrace {
+    fn drop(&mut self) {
+        match self.dump() {
+            Ok(_) => info!("The placement trace is saved to {}", self.path.display()),
+            Err(error) => warn!("Cannot save the placement trace: {}", error)
+        }
+    }
+}
+
+#[test]
+fn test_dump() {
+    use std::env;
+    use std::io::Read;
+    use base::session;
+
+    let path = env::temp_dir().join("aladdin-test-trace").join("combo.log");
+    let trace = PlacementTrace::new(path.clone());
+
+    session::start_tracing();
+
+    session::record(Exchange {
+        method: "POST",
+        url: "https://betway.com/betapi/v4/placeBets".to_owned(),
+        request: Some(r#"{"stakePerLine":100}"#.to_owned()),
+        status: "200 OK".to_owned(),
+        response: r#"{"success":true}"#.to_owned()
+    });
+
+    trace.add("betway.com", session::finish_tracing());
+    drop(trace);
+
+    let mut dump = String::new();
+    File::open(&path).unwrap().read_to_string(&mut dump).unwrap();
+
+    assert!(dump.contains("=== betway.com ==="));
+    assert!(dump.contains("> POST https://betway.com/betapi/v4/placeBets"));
+    assert!(dump.contains(r#"{"stakePerLine":100}"#));
+
+    fs::remove_file(&path).unwrap();
+}
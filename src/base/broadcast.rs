@@ -0,0 +1,75 @@
+use std::sync::mpsc::{self, SyncSender, Receiver};
+use parking_lot::Mutex;
+
+// Fans values out to subscribers. Both gone and slow (with the full queue) subscribers are dropped.
+pub struct Broadcast<T: Clone> {
+    capacity: usize,
+    limit: usize,
+    subscribers: Mutex<Vec<SyncSender<T>>>
+}
+
+impl<T: Clone> Broadcast<T> {
+    pub fn new(capacity: usize, limit: usize) -> Broadcast<T> {
+        Broadcast {
+            capacity: capacity,
+            limit: limit,
+            subscribers: Mutex::new(Vec::new())
+        }
+    }
+
+    // Returns `None` if there are too many subscribers already.
+    pub fn subscribe(&self) -> Option<Receiver<T>> {
+        let mut subscribers = self.subscribers.lock();
+
+        if subscribers.len() >= self.limit {
+            return None;
+        }
+
+        let (tx, rx) = mpsc::sync_channel(self.capacity);
+        subscribers.push(tx);
+
+        Some(rx)
+    }
+
+    pub fn send(&self, value: T) {
+        let mut subscribers = self.subscribers.lock();
+        subscribers.retain(|tx| tx.try_send(value.clone()).is_ok());
+    }
+
+    #[cfg(test)]
+    #[inline]
+    pub fn subscriber_count(&self) -> usize {
+        self.subscribers.lock().len()
+    }
+}
+
+#[test]
+fn test_send() {
+    let broadcast = Broadcast::new(2, 3);
+    let first = broadcast.subscribe().unwrap();
+    let second = broadcast.subscribe().unwrap();
+
+    broadcast.send(1);
+    assert_eq!(first.try_recv(), Ok(1));
+    assert_eq!(second.try_recv(), Ok(1));
+}
+
+#[test]
+fn test_dropping_subscribers() {
+    let broadcast = Broadcast::new(2, 2);
+    let gone = broadcast.subscribe().unwrap();
+    let slow = broadcast.subscribe().unwrap();
+
+    assert!(broadcast.subscribe().is_none());
+
+    drop(gone);
+    broadcast.send(1);
+    assert_eq!(broadcast.subscriber_count(), 1);
+
+    broadcast.send(2);
+    broadcast.send(3);
+    assert_eq!(broadcast.subscriber_count(), 0);
+    assert_eq!(slow.try_recv(), Ok(1));
+
+    assert!(broadcast.subscribe().is_some());
+}
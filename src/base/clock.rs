@@ -0,0 +1,64 @@
+use std::sync::Arc;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering::Relaxed;
+use time;
+
+// Time-dependent code asks a clock instead of `time::get_time()` to be testable without sleeping.
+pub trait Clock {
+    // Unix timestamp in seconds.
+    fn now(&self) -> u32;
+}
+
+pub type BoxedClock = Box<Clock + Send + Sync>;
+
+pub struct RealClock;
+
+impl Clock for RealClock {
+    #[inline]
+    fn now(&self) -> u32 {
+        time::get_time().sec as u32
+    }
+}
+
+// Stands still until it's set or advanced explicitly.
+pub struct TestClock(AtomicUsize);
+
+impl TestClock {
+    pub fn new(now: u32) -> TestClock {
+        TestClock(AtomicUsize::new(now as usize))
+    }
+
+    pub fn set(&self, now: u32) {
+        self.0.store(now as usize, Relaxed);
+    }
+
+    pub fn advance(&self, secs: u32) {
+        self.0.fetch_add(secs as usize, Relaxed);
+    }
+}
+
+impl Clock for TestClock {
+    #[inline]
+    fn now(&self) -> u32 {
+        self.0.load(Relaxed) as u32
+    }
+}
+
+impl<C: Clock> Clock for Arc<C> {
+    #[inline]
+    fn now(&self) -> u32 {
+        (**self).now()
+    }
+}
+
+#[test]
+fn test_test_clock() {
+    let clock = Arc::new(TestClock::new(1000));
+    let shared: BoxedClock = Box::new(clock.clone());
+
+    clock.advance(30);
+    assert_eq!(shared.now(), 1030);
+
+    clock.set(10);
+    assert_eq!(shared.now(), 10);
+}
@@ -0,0 +1,48 @@
+use std::hash::Hash;
+use std::collections::HashMap;
+use parking_lot::Mutex;
+
+// Requires keys to be seen twice within `window` seconds before confirming them.
+pub struct Confirmation<K: Hash + Eq> {
+    window: u32,
+    pending: Mutex<HashMap<K, u32>>
+}
+
+impl<K: Hash + Eq> Confirmation<K> {
+    pub fn new(window: u32) -> Confirmation<K> {
+        Confirmation {
+            window: window,
+            pending: Mutex::new(HashMap::new())
+        }
+    }
+
+    // Returns true if the key has been already seen within the window.
+    pub fn confirm(&self, key: K, now: u32) -> bool {
+        let window = self.window;
+        let mut pending = self.pending.lock();
+
+        pending.retain(|_, &mut since| now < since + window);
+
+        if pending.remove(&key).is_some() {
+            return true;
+        }
+
+        pending.insert(key, now);
+        false
+    }
+}
+
+#[test]
+fn test_confirm() {
+    let confirmation = Confirmation::new(10);
+
+    assert!(!confirmation.confirm("a", 1000));
+    assert!(confirmation.confirm("a", 1005));
+
+    // The confirmation is consumed.
+    assert!(!confirmation.confirm("a", 1006));
+    assert!(!confirmation.confirm("a", 1016));
+    assert!(confirmation.confirm("a", 1017));
+
+    assert!(!confirmation.confirm("b", 1017));
+}
@@ -0,0 +1,45 @@
+use std::hash::Hash;
+use std::collections::HashMap;
+use parking_lot::Mutex;
+
+// Remembers keys for `period` seconds to do something once per the period.
+pub struct Cooldown<K: Hash + Eq> {
+    period: u32,
+    entries: Mutex<HashMap<K, u32>>
+}
+
+impl<K: Hash + Eq> Cooldown<K> {
+    pub fn new(period: u32) -> Cooldown<K> {
+        Cooldown {
+            period: period,
+            entries: Mutex::new(HashMap::new())
+        }
+    }
+
+    // Returns true if the key hasn't been seen within the period.
+    pub fn check(&self, key: K, now: u32) -> bool {
+        let period = self.period;
+        let mut entries = self.entries.lock();
+
+        entries.retain(|_, &mut since| now < since + period);
+
+        if entries.contains_key(&key) {
+            return false;
+        }
+
+        entries.insert(key, now);
+        true
+    }
+}
+
+#[test]
+fn test_check() {
+    let cooldown = Cooldown::new(60);
+
+    assert!(cooldown.check("a", 1000));
+    assert!(!cooldown.check("a", 1000));
+    assert!(!cooldown.check("a", 1059));
+    assert!(cooldown.check("b", 1059));
+    assert!(cooldown.check("a", 1060));
+    assert!(!cooldown.check("b", 1060));
+}
@@ -0,0 +1,72 @@
+use std::collections::VecDeque;
+use parking_lot::Mutex;
+
+// Detects a sharp fall of some count (e.g. of offers) relative to its peak within the window.
+pub struct DropAlarm {
+    window: u32,
+    fraction: f64,
+    min_peak: usize,
+    samples: Mutex<VecDeque<(u32, usize)>>
+}
+
+impl DropAlarm {
+    pub fn new(window: u32, fraction: f64, min_peak: usize) -> DropAlarm {
+        DropAlarm {
+            window: window,
+            fraction: fraction,
+            min_peak: min_peak,
+            samples: Mutex::new(VecDeque::new())
+        }
+    }
+
+    // Returns the peak if the count has fallen by more than the fraction of it. The history is
+    // forgotten after that to alert once per drop.
+    pub fn check(&self, now: u32, count: usize) -> Option<usize> {
+        let mut samples = self.samples.lock();
+
+        while samples.front().map_or(false, |&(date, _)| date + self.window <= now) {
+            samples.pop_front();
+        }
+
+        // One sample per second is enough.
+        match samples.back_mut() {
+            Some(last) if last.0 == now => last.1 = count,
+            _ => samples.push_back((now, count))
+        }
+
+        let peak = samples.iter().map(|&(_, count)| count).max().unwrap_or(0);
+
+        if peak < self.min_peak || count as f64 >= peak as f64 * (1. - self.fraction) {
+            return None;
+        }
+
+        samples.clear();
+        samples.push_back((now, count));
+
+        Some(peak)
+    }
+
+    pub fn reset(&self) {
+        self.samples.lock().clear();
+    }
+}
+
+#[test]
+fn test_check() {
+    let alarm = DropAlarm::new(60, 0.5, 10);
+
+    assert_eq!(alarm.check(1000, 100), None);
+    assert_eq!(alarm.check(1010, 60), None);
+    assert_eq!(alarm.check(1020, 40), Some(100));
+
+    // Fires once per drop.
+    assert_eq!(alarm.check(1021, 30), None);
+
+    // Slow decline isn't a drop.
+    assert_eq!(alarm.check(1100, 30), None);
+    assert_eq!(alarm.check(1200, 14), None);
+
+    // Small counts are too noisy.
+    assert_eq!(alarm.check(1300, 9), None);
+    assert_eq!(alarm.check(1301, 1), None);
+}
@@ -12,6 +12,7 @@ use serde_json::Error as JsonError;
 use hyper::status::StatusCode;
 use url::ParseError as UrlParseError;
 use websocket::result::WebSocketError;
+use rusqlite::Error as SqliteError;
 use backtrace::Backtrace;
 
 use self::ErrorKind::*;
@@ -30,7 +31,8 @@ pub struct Error {
 pub enum ErrorKind {
     Network(BoxedError),
     Status(StatusCode),
-    Unexpected(BoxedError)
+    Unexpected(BoxedError),
+    Step(&'static str, Box<Error>)
 }
 
 impl Error {
@@ -47,7 +49,8 @@ impl Display for Error {
         match self.kind {
             Network(ref e) => write!(f, "Network error: {}", e),
             Status(ref e) => write!(f, "Bad status code: {}", e),
-            Unexpected(ref e) => write!(f, "Unexpected error: {}", e)
+            Unexpected(ref e) => write!(f, "Unexpected error: {}", e),
+            Step(step, ref e) => write!(f, "Failed at the \"{}\" step: {}", step, e)
         }
     }
 }
@@ -57,7 +60,8 @@ impl StdError for Error {
         match self.kind {
             Network(ref err) => err.description(),
             Status(ref code) => code.canonical_reason().unwrap_or("Strange status code"),
-            Unexpected(ref err) => err.description()
+            Unexpected(ref err) => err.description(),
+            Step(_, ref err) => err.description()
         }
     }
 }
@@ -102,4 +106,5 @@ impl_boxed!(Unexpected, ParseBoolError);
 impl_boxed!(Unexpected, TimeParseError);
 impl_boxed!(Unexpected, UrlParseError);
 impl_boxed!(Unexpected, WebSocketError);
+impl_boxed!(Unexpected, SqliteError);
 impl_boxed!(Unexpected, String);
@@ -1,30 +1,43 @@
 use std::env;
-use std::collections::VecDeque;
-use log::{self, Log, LogRecord, LogLevel, LogMetadata, SetLoggerError};
+use std::str;
+use std::cmp;
+use std::io::{self, Write};
+use std::fs::{self, OpenOptions};
+use std::path::PathBuf;
+use std::collections::{VecDeque, HashMap};
+use log::{self, Log, LogRecord, LogLevel, LogLevelFilter, LogMetadata, SetLoggerError};
+use log::MaxLogLevelFilter;
 use env_logger::{LogBuilder as EnvLogBuilder, Logger as EnvLogger};
-use parking_lot::{RwLock, RwLockReadGuard};
+use parking_lot::{Mutex, RwLock, RwLockReadGuard};
 use time;
 
-use constants::HISTORY_SIZE;
+use constants::{HISTORY_SIZE, PERSIST_HISTORY, LOG_FILE, LOG_FILE_SIZE, LOG_FILE_COUNT};
 
 struct Logger(EnvLogger);
 
 impl Log for Logger {
     fn enabled(&self, metadata: &LogMetadata) -> bool {
-        self.0.enabled(metadata)
+        overridden(metadata.target(), metadata.level()).unwrap_or_else(|| self.0.enabled(metadata))
     }
 
     fn log(&self, record: &LogRecord) {
-        self.0.log(record);
+        // Overridden targets bypass the filters of `RUST_LOG`.
+        match overridden(record.target(), record.level()) {
+            Some(true) => { let _ = writeln!(io::stderr(), "{}", format(record)); },
+            Some(false) => {},
+            None => self.0.log(record)
+        }
 
         if self.enabled(record.metadata()) && record.level() <= LogLevel::Warn {
-            save_to_history(Message {
+            let line = save_to_history(Message {
                 level: record.level(),
                 module: trim_target(record.target()).to_string(),
                 date: time::get_time().sec as u32,
                 data: truncate(format!("{}", record.args())),
                 count: 1
             });
+
+            persist(&line);
         }
     }
 }
@@ -72,6 +85,31 @@ fn truncate(mut data: String) -> String {
     data
 }
 
+// Bodies can be binary or in another charset, so they are converted lossy and marked if so.
+pub fn truncate_for_log(bytes: &[u8], max: usize) -> String {
+    let mut result = match str::from_utf8(bytes) {
+        Ok(text) => {
+            let mut end = cmp::min(max, text.len());
+
+            while !text.is_char_boundary(end) {
+                end -= 1;
+            }
+
+            text[..end].to_owned()
+        },
+        Err(_) => {
+            let end = cmp::min(max, bytes.len());
+            format!("[non-utf8] {}", String::from_utf8_lossy(&bytes[..end]))
+        }
+    };
+
+    if bytes.len() > max {
+        result.push_str(&format!("... ({} bytes total)", bytes.len()));
+    }
+
+    result
+}
+
 pub struct Message {
     pub level: LogLevel,
     pub module: String,
@@ -80,17 +118,77 @@ pub struct Message {
     pub count: u32
 }
 
+struct LogFile {
+    path: PathBuf,
+    max_size: u64,
+    keep: usize
+}
+
+impl LogFile {
+    fn append(&self, line: &str) -> io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            try!(fs::create_dir_all(dir));
+        }
+
+        let size = fs::metadata(&self.path).map(|meta| meta.len()).unwrap_or(0);
+
+        if size > 0 && size + line.len() as u64 + 1 > self.max_size {
+            try!(self.rotate());
+        }
+
+        let mut file = try!(OpenOptions::new().create(true).append(true).open(&self.path));
+
+        writeln!(file, "{}", line)
+    }
+
+    fn rotate(&self) -> io::Result<()> {
+        if self.keep == 0 {
+            return fs::remove_file(&self.path);
+        }
+
+        for index in (1..self.keep).rev() {
+            let older = self.rotated(index);
+
+            if older.exists() {
+                try!(fs::rename(older, self.rotated(index + 1)));
+            }
+        }
+
+        fs::rename(&self.path, self.rotated(1))
+    }
+
+    fn rotated(&self, index: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", index));
+        PathBuf::from(path)
+    }
+}
+
 lazy_static! {
     static ref HISTORY: RwLock<VecDeque<Message>> = RwLock::new(VecDeque::new());
+
+    // Levels of (trimmed) targets overriding `RUST_LOG`, e.g. to debug a single bookie.
+    static ref TARGET_LEVELS: RwLock<HashMap<String, LogLevelFilter>> = RwLock::new(HashMap::new());
+
+    // The global filter is raised while some target is overridden above `RUST_LOG`.
+    static ref MAX_LEVEL: Mutex<Option<(MaxLogLevelFilter, LogLevelFilter)>> = Mutex::new(None);
+
+    static ref FILE: Mutex<LogFile> = Mutex::new(LogFile {
+        path: PathBuf::from(LOG_FILE),
+        max_size: LOG_FILE_SIZE,
+        keep: LOG_FILE_COUNT
+    });
 }
 
-fn save_to_history(message: Message) {
+// Returns the line to persist. It's written after releasing the lock, so readers of the history
+// don't wait for the disk.
+fn save_to_history(message: Message) -> String {
     let mut history = HISTORY.write();
 
     if let Some(last) = history.back_mut() {
         if (&last.module, &last.data) == (&message.module, &message.data) {
             last.count += 1;
-            return;
+            return format_line(last);
         }
     }
 
@@ -98,13 +196,71 @@ fn save_to_history(message: Message) {
         history.pop_front();
     }
 
+    let line = format_line(&message);
     history.push_back(message);
+
+    line
+}
+
+// Repeated messages are appended again with the increased count.
+fn persist(line: &str) {
+    if !PERSIST_HISTORY {
+        return;
+    }
+
+    // The logger cannot be used here, so failures are reported directly.
+    if let Err(error) = FILE.lock().append(line) {
+        let _ = writeln!(io::stderr(), "Cannot write to {}: {}", LOG_FILE, error);
+    }
+}
+
+fn format_line(message: &Message) -> String {
+    let date = time::at_utc(time::Timespec::new(message.date as i64, 0));
+
+    let shortcut = match message.level {
+        LogLevel::Error => "E",
+        LogLevel::Warn => "W",
+        LogLevel::Info => "I",
+        LogLevel::Debug => "D",
+        LogLevel::Trace => "T"
+    };
+
+    format!("{} {} [{}] x{} {}", date.strftime("%F %T").unwrap(), message.module, shortcut,
+            message.count, message.data)
 }
 
 pub fn acquire_history() -> RwLockReadGuard<'static, VecDeque<Message>> {
     HISTORY.read()
 }
 
+// `None` if the level of the target is left to `RUST_LOG`.
+fn overridden(target: &str, level: LogLevel) -> Option<bool> {
+    TARGET_LEVELS.read().get(trim_target(target)).map(|&filter| level <= filter)
+}
+
+// Overrides the level of the target, e.g. `betway`, or returns it to `RUST_LOG` if `None`.
+pub fn set_target_level(target: &str, level: Option<LogLevelFilter>) {
+    let mut levels = TARGET_LEVELS.write();
+
+    match level {
+        Some(level) => levels.insert(target.to_owned(), level),
+        None => levels.remove(target)
+    };
+
+    if let Some((ref max_level, base)) = *MAX_LEVEL.lock() {
+        max_level.set(levels.values().cloned().fold(base, cmp::max));
+    }
+}
+
+pub fn target_levels() -> Vec<(String, LogLevelFilter)> {
+    let mut levels = TARGET_LEVELS.read().iter()
+        .map(|(target, &level)| (target.clone(), level))
+        .collect::<Vec<_>>();
+
+    levels.sort();
+    levels
+}
+
 pub fn init() -> Result<(), SetLoggerError> {
     let mut env_log_builder = EnvLogBuilder::new();
 
@@ -116,6 +272,81 @@ pub fn init() -> Result<(), SetLoggerError> {
 
     log::set_logger(|max_log_level| {
         max_log_level.set(env_logger.filter());
+        *MAX_LEVEL.lock() = Some((max_log_level, env_logger.filter()));
         Box::new(Logger(env_logger))
     })
 }
+
+#[test]
+fn test_log_file() {
+    use std::io::Read;
+
+    let dir = env::temp_dir().join("aladdin-test-logger");
+    let _ = fs::remove_dir_all(&dir);
+
+    let file = LogFile {
+        path: dir.join("aladdin.log"),
+        max_size: 80,
+        keep: 1
+    };
+
+    let mut message = Message {
+        level: LogLevel::Error,
+        module: "arbitrer".to_owned(),
+        date: 1476612000,
+        data: "Cannot place the bet".to_owned(),
+        count: 1
+    };
+
+    let line = format_line(&message);
+    assert_eq!(line, "2016-10-16 10:00:00 arbitrer [E] x1 Cannot place the bet");
+
+    file.append(&line).unwrap();
+
+    message.count = 2;
+    file.append(&format_line(&message)).unwrap();
+
+    let read = |path| {
+        let mut content = String::new();
+        fs::File::open(path).unwrap().read_to_string(&mut content).unwrap();
+        content
+    };
+
+    assert_eq!(read(file.rotated(1)), format!("{}\n", line));
+    assert_eq!(read(file.path.clone()), format!("{}\n", format_line(&message)));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_target_levels() {
+    let target = "aladdin::gamblers::logger_test";
+
+    assert_eq!(overridden(target, LogLevel::Debug), None);
+
+    set_target_level("logger_test", Some(LogLevelFilter::Trace));
+    assert_eq!(overridden(target, LogLevel::Debug), Some(true));
+    assert_eq!(overridden("aladdin::gamblers::other", LogLevel::Debug), None);
+    assert!(target_levels().contains(&("logger_test".to_owned(), LogLevelFilter::Trace)));
+
+    set_target_level("logger_test", Some(LogLevelFilter::Error));
+    assert_eq!(overridden(target, LogLevel::Warn), Some(false));
+    assert_eq!(overridden(target, LogLevel::Error), Some(true));
+
+    set_target_level("logger_test", None);
+    assert_eq!(overridden(target, LogLevel::Warn), None);
+}
+
+#[test]
+fn test_truncate_for_log() {
+    assert_eq!(truncate_for_log(b"{\"ok\": true}", 64), "{\"ok\": true}");
+    assert_eq!(truncate_for_log(b"abcdef", 3), "abc... (6 bytes total)");
+
+    // The cut doesn't split multibyte characters.
+    let text = "\u{442}\u{435}\u{441}\u{442}";
+    assert_eq!(truncate_for_log(text.as_bytes(), 3), "\u{442}... (8 bytes total)");
+
+    assert_eq!(truncate_for_log(b"ok\xff\xfe", 64), "[non-utf8] ok\u{fffd}\u{fffd}");
+    assert_eq!(truncate_for_log(b"\x80\x81\x82\x83", 2),
+               "[non-utf8] \u{fffd}\u{fffd}... (4 bytes total)");
+}
@@ -6,3 +6,16 @@ pub mod parsing;
 pub mod currency;
 pub mod websocket;
 pub mod barrier;
+pub mod throughput;
+pub mod semaphore;
+pub mod steps;
+pub mod clock;
+pub mod cooldown;
+pub mod confirmation;
+pub mod broadcast;
+pub mod profit;
+pub mod drop_alarm;
+pub mod webhook;
+pub mod pool;
+pub mod skew;
+pub mod slow_start;
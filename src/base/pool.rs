@@ -0,0 +1,108 @@
+use std::thread;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+use std::sync::mpsc::{self, Sender, Receiver};
+use parking_lot::Mutex;
+
+pub type Job = Box<FnMut() + Send>;
+
+// A fixed set of threads. Jobs are accepted in groups only if all of them can run at once,
+// so jobs waiting for each other (e.g. on a barrier) never starve.
+pub struct Pool {
+    idle: Arc<Mutex<usize>>,
+    sender: Mutex<Sender<Job>>
+}
+
+impl Pool {
+    pub fn new(name: &str, size: usize) -> Pool {
+        let (sender, receiver) = mpsc::channel();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let idle = Arc::new(Mutex::new(size));
+
+        for idx in 0..size {
+            let receiver = receiver.clone();
+            let idle = idle.clone();
+
+            thread::Builder::new()
+                .name(format!("{} #{}", name, idx))
+                .spawn(move || work(&receiver, &idle))
+                .unwrap();
+        }
+
+        Pool {
+            idle: idle,
+            sender: Mutex::new(sender)
+        }
+    }
+
+    // Returns false (and drops the jobs) if there are not enough idle threads.
+    pub fn try_execute(&self, jobs: Vec<Job>) -> bool {
+        let mut idle = self.idle.lock();
+
+        if *idle < jobs.len() {
+            return false;
+        }
+
+        *idle -= jobs.len();
+
+        let sender = self.sender.lock();
+
+        for job in jobs {
+            sender.send(job).unwrap();
+        }
+
+        true
+    }
+}
+
+fn work(receiver: &Mutex<Receiver<Job>>, idle: &Mutex<usize>) {
+    loop {
+        let mut job = match receiver.lock().recv() {
+            Ok(job) => job,
+            Err(_) => return
+        };
+
+        // The thread is returned even if the job panics.
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| job()));
+
+        *idle.lock() += 1;
+    }
+}
+
+#[test]
+fn test_try_execute() {
+    use std::sync::Barrier;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering::Relaxed;
+    use std::time::Duration;
+
+    let pool = Pool::new("test", 3);
+    let barrier = Arc::new(Barrier::new(4));
+    let done = Arc::new(AtomicUsize::new(0));
+
+    let job = || -> Job {
+        let barrier = barrier.clone();
+        let done = done.clone();
+
+        Box::new(move || {
+            // All legs run at once.
+            barrier.wait();
+            done.fetch_add(1, Relaxed);
+        })
+    };
+
+    assert!(!pool.try_execute(vec![job(), job(), job(), job()]));
+    assert!(pool.try_execute(vec![job(), job(), job()]));
+
+    // All threads are busy.
+    assert!(!pool.try_execute(vec![job()]));
+
+    barrier.wait();
+
+    while done.load(Relaxed) < 3 || *pool.idle.lock() < 3 {
+        thread::sleep(Duration::from_millis(1));
+    }
+
+    assert_eq!(done.load(Relaxed), 3);
+    assert!(pool.try_execute(vec![Box::new(|| {})]));
+}
@@ -0,0 +1,35 @@
+use std::fmt::{Display, Formatter, Result as FmtResult};
+
+use constants::PROFIT_DECIMALS;
+
+// Displays the rate as a signed percentage rounded toward zero, so that a profit is never shown
+// better than it is (e.g. +0.049% isn't shown as +0.1%).
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct Profit(pub f64);
+
+impl Display for Profit {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        let decimals = f.precision().unwrap_or(PROFIT_DECIMALS);
+        let scale = 10f64.powi(decimals as i32);
+
+        // The epsilon compensates errors of the scaling itself (e.g. 0.29 * 100 = 28.999...).
+        let scaled = self.0 * 100. * scale;
+        let truncated = (scaled + scaled.signum() * 1e-6).trunc() / scale;
+
+        // Avoid "-0.0%" for a hair negative profit.
+        let truncated = if truncated == 0. { 0. } else { truncated };
+
+        write!(f, "{:+.*}%", decimals, truncated)
+    }
+}
+
+#[test]
+fn test_toward_zero() {
+    assert_eq!(format!("{}", Profit(0.00049)), "+0.0%");
+    assert_eq!(format!("{}", Profit(0.0299)), "+2.9%");
+    assert_eq!(format!("{}", Profit(-0.0299)), "-2.9%");
+    assert_eq!(format!("{}", Profit(-0.00049)), "+0.0%");
+    assert_eq!(format!("{}", Profit(0.0029)), "+0.2%");
+    assert_eq!(format!("{:.2}", Profit(0.00049)), "+0.04%");
+    assert_eq!(format!("{:.0}", Profit(0.05)), "+5%");
+}
@@ -0,0 +1,61 @@
+use parking_lot::{Mutex, Condvar};
+
+pub struct Semaphore {
+    permits: Mutex<u32>,
+    cvar: Condvar
+}
+
+pub struct SemaphoreGuard<'a>(&'a Semaphore);
+
+impl<'a> Drop for SemaphoreGuard<'a> {
+    fn drop(&mut self) {
+        let mut permits = self.0.permits.lock();
+        *permits += 1;
+        self.0.cvar.notify_one();
+    }
+}
+
+impl Semaphore {
+    pub fn new(permits: u32) -> Semaphore {
+        Semaphore {
+            permits: Mutex::new(permits),
+            cvar: Condvar::new()
+        }
+    }
+
+    pub fn acquire(&self) -> SemaphoreGuard {
+        let mut permits = self.permits.lock();
+
+        while *permits == 0 {
+            self.cvar.wait(&mut permits);
+        }
+
+        *permits -= 1;
+
+        SemaphoreGuard(self)
+    }
+
+    pub fn try_acquire(&self) -> Option<SemaphoreGuard> {
+        let mut permits = self.permits.lock();
+
+        if *permits == 0 {
+            return None;
+        }
+
+        *permits -= 1;
+
+        Some(SemaphoreGuard(self))
+    }
+}
+
+#[test]
+fn test_try_acquire() {
+    let semaphore = Semaphore::new(1);
+
+    let guard = semaphore.try_acquire();
+    assert!(guard.is_some());
+    assert!(semaphore.try_acquire().is_none());
+
+    drop(guard);
+    assert!(semaphore.try_acquire().is_some());
+}
@@ -1,21 +1,32 @@
 #![allow(dead_code)]
 
-use std::io::Read;
+use std::io::{self, Read};
+use std::cmp;
+use std::mem;
 use std::time::Duration;
+use std::cell::RefCell;
 use parking_lot::RwLock;
 use time;
 use url::form_urlencoded::Serializer as UrlSerializer;
 use hyper::error::{Error as HyperError, Result as HyperResult};
 use hyper::client::{Client, RedirectPolicy, Response};
+use hyper::status::StatusCode;
 use hyper::header::{Headers, SetCookie, Cookie, UserAgent, Accept, ContentType, qitem, CookiePair};
+use hyper::header::Date;
 use kuchiki;
 use kuchiki::NodeRef;
-use kuchiki::traits::ParserExt;
+use kuchiki::traits::TendrilSink;
+use encoding::DecoderTrap;
+use encoding::label::encoding_from_whatwg_label;
 use serde::{Serialize, Deserialize};
 use serde_json as json;
-use hyper::mime::Mime;
+use hyper::mime::{Mime, Attr};
 
+use constants::{FAST_TIMEOUTS, LONG_TIMEOUTS, MAX_BODY_SIZE, MAX_LOGGED_BODY, SEED_COOKIES};
+use constants::{HEADERS, REMOVED_HEADERS};
 use base::error::{Result, Error};
+use base::logger::truncate_for_log;
+use base::skew::CLOCK_SKEW;
 
 header! { (XRequestedWith, "X-Requested-With") => [String] }
 
@@ -25,8 +36,58 @@ const WRITE_TIMEOUT: u64 = 5;
 
 const USER_AGENT: &str = "Lynx/2.8.8rel.2 libwww-FM/2.14 SSL-MM/1.4.1 OpenSSL/1.0.2h";
 
+// A request/response pair recorded while tracing is enabled on the current thread.
+#[derive(Debug, Clone)]
+pub struct Exchange {
+    pub method: &'static str,
+    pub url: String,
+    pub request: Option<String>,
+    pub status: String,
+    pub response: String
+}
+
+thread_local! {
+    static TRACE: RefCell<Option<Vec<Exchange>>> = RefCell::new(None);
+}
+
+pub fn start_tracing() {
+    TRACE.with(|trace| *trace.borrow_mut() = Some(Vec::new()));
+}
+
+pub fn finish_tracing() -> Vec<Exchange> {
+    TRACE.with(|trace| trace.borrow_mut().take().unwrap_or_else(Vec::new))
+}
+
+#[inline]
+pub fn is_tracing() -> bool {
+    TRACE.with(|trace| trace.borrow().is_some())
+}
+
+// Collects exchanges made by `f` separately, they're still recorded to the outer trace if any.
+pub fn capture<T, F: FnOnce() -> T>(f: F) -> (T, Vec<Exchange>) {
+    let outer = TRACE.with(|trace| mem::replace(&mut *trace.borrow_mut(), Some(Vec::new())));
+    let result = f();
+    let captured = finish_tracing();
+
+    if let Some(mut outer) = outer {
+        outer.extend(captured.iter().cloned());
+        TRACE.with(|trace| *trace.borrow_mut() = Some(outer));
+    }
+
+    (result, captured)
+}
+
+pub fn record(exchange: Exchange) {
+    TRACE.with(|trace| {
+        if let Some(ref mut exchanges) = *trace.borrow_mut() {
+            exchanges.push(exchange);
+        }
+    });
+}
+
 pub struct Session {
     host: String,
+    headers: Headers,
     cookie: RwLock<Cookie>,
     client: Client
 }
@@ -39,11 +100,55 @@ impl Session {
         client.set_write_timeout(Some(Duration::from_secs(WRITE_TIMEOUT)));
         client.set_redirect_policy(RedirectPolicy::FollowNone);
 
-        Session {
+        let mut headers = Headers::new();
+
+        headers.set(UserAgent(USER_AGENT.to_owned()));
+        headers.set(XRequestedWith("XMLHttpRequest".to_owned()));
+        headers.set(ContentType(mime!(Application/Json)));
+
+        // Let's accept everything!
+        headers.set(Accept(vec![
+            qitem(mime!(Text/Plain)),
+            qitem(mime!(Text/Html)),
+            qitem(mime!(Application/Json)),
+            qitem(mime!(_/_))
+        ]));
+
+        let session = Session {
             host: host.to_string(),
+            headers: headers,
             client: client,
             cookie: RwLock::new(Cookie(vec![]))
-        }
+        };
+
+        let session = SEED_COOKIES.iter()
+            .filter(|entry| entry.0 == host)
+            .flat_map(|entry| entry.1.iter())
+            .fold(session, |session, &(name, value, domain)| {
+                session.seed_cookie(name, value, domain)
+            });
+
+        with_host_headers(session, host, HEADERS, REMOVED_HEADERS)
+    }
+
+    pub fn seed_cookie(self, name: &str, value: &str, domain: &str) -> Session {
+        let mut cookie = CookiePair::new(name.to_owned(), value.to_owned());
+        cookie.domain = Some(domain.to_owned());
+
+        self.set_cookies(&[cookie]);
+        self
+    }
+
+    // Adds the header to every request of the session (e.g. `Referer` or an API key).
+    pub fn default_header(mut self, name: &'static str, value: &str) -> Session {
+        self.headers.set_raw(name, vec![value.as_bytes().to_vec()]);
+        self
+    }
+
+    // Some bookies reject even defaults (e.g. `X-Requested-With`).
+    pub fn without_header(mut self, name: &str) -> Session {
+        self.headers.remove_raw(name);
+        self
     }
 
     pub fn get_cookie(&self, cookie_name: &str) -> Option<String> {
@@ -104,6 +209,20 @@ impl Into<Mime> for Type {
     }
 }
 
+// Timeouts (read, write) in seconds: fast for bet-critical calls, long for login and long-polling.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Profile { Fast, Normal, Long }
+
+impl Profile {
+    pub fn timeouts(&self) -> (u64, u64) {
+        match *self {
+            Profile::Fast => FAST_TIMEOUTS,
+            Profile::Normal => (READ_TIMEOUT, WRITE_TIMEOUT),
+            Profile::Long => LONG_TIMEOUTS
+        }
+    }
+}
+
 pub struct RequestBuilder<'a> {
     session: &'a Session,
     headers: Headers,
@@ -114,24 +233,10 @@ pub struct RequestBuilder<'a> {
 
 impl<'a> RequestBuilder<'a> {
     pub fn new(url: String, session: &Session) -> RequestBuilder {
-        let mut headers = Headers::new();
-
-        headers.set(UserAgent(USER_AGENT.to_owned()));
-        headers.set(XRequestedWith("XMLHttpRequest".to_owned()));
-        headers.set(ContentType(mime!(Application/Json)));
-
-        // Let's accept everything!
-        headers.set(Accept(vec![
-            qitem(mime!(Text/Plain)),
-            qitem(mime!(Text/Html)),
-            qitem(mime!(Application/Json)),
-            qitem(mime!(_/_))
-        ]));
-
         RequestBuilder {
             url: url,
             session: session,
-            headers: headers,
+            headers: session.headers.clone(),
             timeouts: None,
             follow_redirects: false
         }
@@ -149,6 +254,13 @@ impl<'a> RequestBuilder<'a> {
         self
     }
 
+    #[inline]
+    pub fn profile(self, profile: Profile) -> RequestBuilder<'a> {
+        // The normal profile is used by the session's own client.
+        let timeouts = if profile == Profile::Normal { None } else { Some(profile.timeouts()) };
+        self.timeouts(timeouts)
+    }
+
     #[inline]
     pub fn headers(mut self, headers: &[(&'static str, &str)]) -> RequestBuilder<'a> {
         for &(name, value) in headers {
@@ -213,22 +325,60 @@ impl<'a> RequestBuilder<'a> {
                 continue;
             }
 
-            // TODO(universome): actually we need to follow redirects when possible.
-            // now it's almost always should be error, but cybbet relies on 302.
-            if response.status.is_redirection() {
-                if !self.follow_redirects {
-                    return Err(Error::from("Was redirected, but have no redirect policy"));
-                }
-
-                return R::read(response);
+            if is_tracing() {
+                let status = response.status;
+                let (bytes, charset) = try!(read_body(response));
+                let text = decode(bytes.clone(), charset.as_ref().map(|c| c.as_str()));
+
+                // Undecodable bodies are recorded anyway, they are the most interesting ones.
+                let logged = match text {
+                    Ok(ref text) => truncate_for_log(text.as_bytes(), MAX_LOGGED_BODY),
+                    Err(_) => truncate_for_log(&bytes, MAX_LOGGED_BODY)
+                };
+
+                record(Exchange {
+                    method: if body.is_none() { "GET" } else { "POST" },
+                    url: self.url.clone(),
+                    request: body.clone(),
+                    status: status.to_string(),
+                    response: logged
+                });
+
+                try!(self.check_status(status));
+                return R::parse(try!(text));
             }
 
-            if !response.status.is_success() {
-                return Err(Error::from(response.status));
+            try!(self.check_status(response.status));
+            return R::read(response);
+        }
+    }
+
+    fn check_status(&self, status: StatusCode) -> Result<()> {
+        // TODO(universome): actually we need to follow redirects when possible.
+        // now it's almost always should be error, but cybbet relies on 302.
+        if status.is_redirection() {
+            if !self.follow_redirects {
+                return Err(Error::from("Was redirected, but have no redirect policy"));
             }
 
-            return R::read(response);
+            return Ok(());
+        }
+
+        if !status.is_success() {
+            return Err(Error::from(status));
         }
+
+        Ok(())
+    }
+
+    fn outgoing_headers(&self) -> Headers {
+        self.session.actualize_cookies();
+
+        let mut headers = self.headers.clone();
+        let cookie = self.session.cookie.read().clone();
+        headers.set(cookie);
+
+        headers
     }
 
     fn _send(&self, client: &Client, body: Option<&str>) -> HyperResult<Response> {
@@ -239,12 +389,11 @@ impl<'a> RequestBuilder<'a> {
             None => client.get(&self.url)
         };
 
-        self.session.actualize_cookies();
-        let mut headers = self.headers.clone();
-        let cookie = self.session.cookie.read().clone();
-        headers.set(cookie);
+        let response = try!(builder.headers(self.outgoing_headers()).send());
 
-        let response = try!(builder.headers(headers).send());
+        if let Some(&Date(ref date)) = response.headers.get::<Date>() {
+            CLOCK_SKEW.record(date.0.to_timespec().sec, time::get_time().sec);
+        }
 
         if !response.status.is_success() && !response.status.is_redirection() {
             return Ok(response);
@@ -258,34 +407,143 @@ impl<'a> RequestBuilder<'a> {
     }
 }
 
+fn with_host_headers(session: Session, host: &str, headers: &[(&str, &[(&'static str, &str)])],
+                     removed: &[(&str, &[&str])]) -> Session
+{
+    let session = headers.iter()
+        .filter(|entry| entry.0 == host)
+        .flat_map(|entry| entry.1.iter())
+        .fold(session, |session, &(name, value)| session.default_header(name, value));
+
+    removed.iter()
+        .filter(|entry| entry.0 == host)
+        .flat_map(|entry| entry.1.iter())
+        .fold(session, |session, &name| session.without_header(name))
+}
+
 pub trait Receivable: Sized {
     fn read(response: Response) -> Result<Self>;
+    fn parse(text: String) -> Result<Self>;
 }
 
 impl Receivable for String {
-    #[inline]
-    fn read(mut response: Response) -> Result<String> {
-        let mut string = String::new();
-        try!(response.read_to_string(&mut string));
+    fn read(response: Response) -> Result<String> {
+        let (bytes, charset) = try!(read_body(response));
+        decode(bytes, charset.as_ref().map(|charset| charset.as_str()))
+    }
 
-        Ok(string)
+    #[inline]
+    fn parse(text: String) -> Result<String> {
+        Ok(text)
     }
 }
 
 impl<T: Deserialize> Receivable for T {
     #[inline]
     default fn read(response: Response) -> Result<T> {
-        Ok(try!(json::from_reader(response)))
+        Ok(try!(json::from_reader(Limited::new(response, MAX_BODY_SIZE))))
+    }
+
+    #[inline]
+    default fn parse(text: String) -> Result<T> {
+        Ok(try!(json::from_str(&text)))
     }
 }
 
 impl Receivable for NodeRef {
     #[inline]
     fn read(response: Response) -> Result<NodeRef> {
-        Ok(try!(kuchiki::parse_html().from_http(response)))
+        // Read as a string to respect the charset.
+        String::read(response).and_then(NodeRef::parse)
+    }
+
+    #[inline]
+    fn parse(text: String) -> Result<NodeRef> {
+        Ok(kuchiki::parse_html().one(text))
+    }
+}
+
+// Fails once the inner reader produces more than `limit` bytes.
+struct Limited<R> {
+    inner: R,
+    limit: u64,
+    remaining: u64
+}
+
+impl<R: Read> Limited<R> {
+    fn new(inner: R, limit: u64) -> Limited<R> {
+        Limited {
+            inner: inner,
+            limit: limit,
+            remaining: limit
+        }
+    }
+}
+
+impl<R: Read> Read for Limited<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        // The body of exactly `limit` bytes is fine, so check whether there is something else.
+        if self.remaining == 0 {
+            return match try!(self.inner.read(&mut [0])) {
+                0 => Ok(0),
+                _ => Err(io::Error::new(io::ErrorKind::Other,
+                                        format!("The body exceeds {} bytes", self.limit)))
+            };
+        }
+
+        let max = cmp::min(buf.len() as u64, self.remaining) as usize;
+        let read = try!(self.inner.read(&mut buf[..max]));
+        self.remaining -= read as u64;
+
+        Ok(read)
     }
 }
 
+// Returns the raw body along with the charset declared by the response.
+fn read_body(mut response: Response) -> Result<(Vec<u8>, Option<String>)> {
+    let charset = response.headers.get::<ContentType>()
+        .and_then(|content_type| content_type.get_param(Attr::Charset))
+        .map(|charset| charset.to_string());
+
+    let mut bytes = Vec::new();
+    try!(Limited::new(&mut response, MAX_BODY_SIZE).read_to_end(&mut bytes));
+
+    Ok((bytes, charset))
+}
+
+// Transcodes the body to UTF-8, which is the default if the charset is unspecified. Legacy pages
+// often don't specify it at all, so invalid sequences are replaced then instead of failing.
+fn decode(bytes: Vec<u8>, charset: Option<&str>) -> Result<String> {
+    let charset = match charset {
+        Some(charset) => charset.to_lowercase(),
+        None => return Ok(match String::from_utf8(bytes) {
+            Ok(text) => text,
+            Err(err) => {
+                let bytes = err.into_bytes();
+                warn!("Invalid UTF-8 in the response without a charset: {}",
+                      truncate_for_log(&bytes, 200));
+                String::from_utf8_lossy(&bytes).into_owned()
+            }
+        })
+    };
+
+    if charset == "utf-8" || charset == "utf8" {
+        return String::from_utf8(bytes).map_err(|err| {
+            let excerpt = truncate_for_log(&err.into_bytes(), 200);
+            Error::from(format!("Invalid UTF-8 in the response: {}", excerpt))
+        });
+    }
+
+    let encoding = try!(encoding_from_whatwg_label(&charset)
+        .ok_or_else(|| format!("Unsupported charset: {}", charset)));
+
+    encoding.decode(&bytes, DecoderTrap::Replace).map_err(|e| Error::from(e.into_owned()))
+}
+
 pub trait Sendable {
     fn to_string(&self) -> Result<String>;
 }
@@ -310,3 +568,104 @@ impl<'a> Sendable for Vec<(&'a str, &'a str)> {
         Ok(UrlSerializer::new(String::new()).extend_pairs(self.iter()).finish())
     }
 }
+
+#[test]
+fn test_capture() {
+    let exchange = |url: &str| Exchange {
+        method: "GET",
+        url: url.to_owned(),
+        request: None,
+        status: "200 OK".to_owned(),
+        response: String::new()
+    };
+
+    let ((), captured) = capture(|| record(exchange("/inner")));
+    assert_eq!(captured.len(), 1);
+    assert!(!is_tracing());
+
+    start_tracing();
+    record(exchange("/outer"));
+
+    let ((), captured) = capture(|| record(exchange("/inner")));
+    assert_eq!(captured.len(), 1);
+
+    let urls = finish_tracing().into_iter().map(|e| e.url).collect::<Vec<_>>();
+    assert_eq!(urls, vec!["/outer".to_owned(), "/inner".to_owned()]);
+}
+
+#[test]
+fn test_profile() {
+    let session = Session::new("example.com");
+
+    assert_eq!(session.request("/").profile(Profile::Fast).timeouts, Some(FAST_TIMEOUTS));
+    assert_eq!(session.request("/").profile(Profile::Long).timeouts, Some(LONG_TIMEOUTS));
+    assert_eq!(session.request("/").profile(Profile::Normal).timeouts, None);
+    assert_eq!(Profile::Normal.timeouts(), (READ_TIMEOUT, WRITE_TIMEOUT));
+}
+
+#[test]
+fn test_decode() {
+    let cp1251 = vec![0xcf, 0xf0, 0xe8, 0xe2, 0xe5, 0xf2];
+
+    assert_eq!(decode(cp1251, Some("windows-1251")).unwrap(), "Привет");
+    assert_eq!(decode("Привет".as_bytes().to_vec(), None).unwrap(), "Привет");
+    assert_eq!(decode("Привет".as_bytes().to_vec(), Some("UTF-8")).unwrap(), "Привет");
+    assert!(decode(vec![0xcf, 0xf0], Some("utf-8")).is_err());
+    assert_eq!(decode(vec![b'o', 0xcf, b'k'], None).unwrap(), "o\u{fffd}k");
+}
+
+#[test]
+fn test_default_headers() {
+    let session = Session::new("example.com")
+        .default_header("Referer", "https://example.com/")
+        .without_header("X-Requested-With");
+
+    let request = session.request("/");
+
+    assert_eq!(request.headers.get_raw("Referer"), Some(&[b"https://example.com/".to_vec()][..]));
+    assert!(request.headers.get_raw("X-Requested-With").is_none());
+    assert!(request.headers.get::<UserAgent>().is_some());
+}
+
+#[test]
+fn test_host_headers() {
+    let headers: &[(&str, &[(&str, &str)])] = &[
+        ("example.com", &[("Referer", "https://example.com/")]),
+        ("other.com", &[("X-Api-Key", "42")])
+    ];
+
+    let removed: &[(&str, &[&str])] = &[("example.com", &["X-Requested-With"])];
+
+    let session = with_host_headers(Session::new("example.com"), "example.com", headers, removed);
+    let request = session.request("/");
+
+    assert_eq!(request.headers.get_raw("Referer"), Some(&[b"https://example.com/".to_vec()][..]));
+    assert!(request.headers.get_raw("X-Api-Key").is_none());
+    assert!(request.headers.get_raw("X-Requested-With").is_none());
+}
+
+#[test]
+fn test_seed_cookie() {
+    let session = Session::new("example.com").seed_cookie("consent", "yes", "example.com");
+
+    let headers = session.request("/").outgoing_headers();
+    let cookie = headers.get::<Cookie>().unwrap();
+
+    assert_eq!(cookie.len(), 1);
+    assert_eq!((&*cookie[0].name, &*cookie[0].value), ("consent", "yes"));
+}
+
+#[test]
+fn test_limited() {
+    let body = b"{\"balance\": 42}";
+    let read = |limit| {
+        let mut bytes = Vec::new();
+        Limited::new(&body[..], limit).read_to_end(&mut bytes).map(|_| bytes)
+    };
+
+    assert_eq!(read(1024).unwrap(), &body[..]);
+    assert_eq!(read(body.len() as u64).unwrap(), &body[..]);
+    assert!(read(body.len() as u64 - 1).is_err());
+
+    assert!(json::from_reader::<_, json::Value>(Limited::new(&body[..], 8)).is_err());
+}
@@ -0,0 +1,73 @@
+use std::collections::VecDeque;
+use parking_lot::Mutex;
+
+const SAMPLE_COUNT: usize = 16;
+
+lazy_static! {
+    // Fed by `Date` headers of all responses.
+    pub static ref CLOCK_SKEW: ClockSkew = ClockSkew::new();
+}
+
+// The offset of the local clock from the servers' ones. A median of recent samples is used,
+// so a single bookie with a broken clock (or a cached response) doesn't matter.
+pub struct ClockSkew {
+    samples: Mutex<VecDeque<i64>>
+}
+
+impl ClockSkew {
+    pub fn new() -> ClockSkew {
+        ClockSkew {
+            samples: Mutex::new(VecDeque::with_capacity(SAMPLE_COUNT))
+        }
+    }
+
+    pub fn record(&self, server: i64, local: i64) {
+        let mut samples = self.samples.lock();
+
+        if samples.len() == SAMPLE_COUNT {
+            samples.pop_front();
+        }
+
+        samples.push_back(local - server);
+    }
+
+    // Positive if the local clock is ahead.
+    pub fn get(&self) -> Option<i64> {
+        let mut samples = self.samples.lock().iter().cloned().collect::<Vec<_>>();
+
+        if samples.is_empty() {
+            return None;
+        }
+
+        samples.sort();
+        Some(samples[samples.len() / 2])
+    }
+
+    // Nothing is known until the first response, which is considered fine.
+    pub fn is_within(&self, bound: u32) -> bool {
+        self.get().map_or(true, |skew| skew.abs() <= bound as i64)
+    }
+}
+
+#[test]
+fn test_clock_skew() {
+    let skew = ClockSkew::new();
+
+    assert_eq!(skew.get(), None);
+    assert!(skew.is_within(0));
+
+    skew.record(1000, 1002);
+    skew.record(1000, 998);
+    skew.record(1000, 1001);
+    skew.record(100, 1000);
+
+    assert_eq!(skew.get(), Some(2));
+    assert!(skew.is_within(5));
+
+    for _ in 0..SAMPLE_COUNT {
+        skew.record(1000, 1600);
+    }
+
+    assert_eq!(skew.get(), Some(600));
+    assert!(!skew.is_within(5));
+}
@@ -0,0 +1,91 @@
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering::Relaxed;
+
+// A period after (re)activation of something (e.g. a bookie) while it's trusted less: until
+// `combos` combos are recorded and `duration` seconds pass. Only the first activation and ones
+// after an outage of at least `outage` seconds (since the last `touch`) start it.
+pub struct SlowStart {
+    combos: usize,
+    duration: u32,
+    outage: u32,
+    activated: AtomicUsize,
+    last_seen: AtomicUsize,
+    recorded: AtomicUsize
+}
+
+impl SlowStart {
+    pub fn new(combos: usize, duration: u32, outage: u32) -> SlowStart {
+        SlowStart {
+            combos: combos,
+            duration: duration,
+            outage: outage,
+            activated: AtomicUsize::new(0),
+            last_seen: AtomicUsize::new(0),
+            recorded: AtomicUsize::new(0)
+        }
+    }
+
+    // Returns whether the slow start has begun anew.
+    pub fn activate(&self, now: u32) -> bool {
+        let last_seen = self.last_seen.swap(now as usize, Relaxed) as u32;
+        let is_first = self.activated.load(Relaxed) == 0;
+
+        if !is_first && now < last_seen + self.outage {
+            return false;
+        }
+
+        self.recorded.store(0, Relaxed);
+        self.activated.store(now as usize, Relaxed);
+
+        self.is_active(now)
+    }
+
+    #[inline]
+    pub fn touch(&self, now: u32) {
+        self.last_seen.store(now as usize, Relaxed);
+    }
+
+    #[inline]
+    pub fn record_combo(&self) {
+        self.recorded.fetch_add(1, Relaxed);
+    }
+
+    pub fn is_active(&self, now: u32) -> bool {
+        let activated = self.activated.load(Relaxed) as u32;
+
+        activated > 0 && (self.recorded.load(Relaxed) < self.combos ||
+                          now < activated + self.duration)
+    }
+}
+
+#[test]
+fn test_slow_start() {
+    let slow_start = SlowStart::new(2, 600, 3600);
+
+    assert!(!slow_start.is_active(1000));
+    assert!(slow_start.activate(1000));
+
+    slow_start.record_combo();
+    slow_start.record_combo();
+
+    // Both the combos and the duration are required.
+    assert!(slow_start.is_active(1500));
+    assert!(!slow_start.is_active(1600));
+
+    // A quick reconnection doesn't restart it.
+    slow_start.touch(2000);
+    assert!(!slow_start.activate(2100));
+    assert!(!slow_start.is_active(2100));
+
+    assert!(slow_start.activate(6000));
+    assert!(slow_start.is_active(7000));
+
+    slow_start.record_combo();
+    slow_start.record_combo();
+    assert!(!slow_start.is_active(7000));
+
+    // Zeros disable it.
+    let disabled = SlowStart::new(0, 0, 3600);
+    assert!(!disabled.activate(1000));
+    assert!(!disabled.is_active(1000));
+}
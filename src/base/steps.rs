@@ -0,0 +1,31 @@
+use base::error::{Result, Error, ErrorKind};
+
+// Multi-step flows (e.g. "add to betslip", then "place") are sequences of steps, each of which
+// produces a typed result for the next one. A failure is wrapped into `ErrorKind::Step`, so it's
+// clear at which step the flow has been broken.
+pub fn step<T, F: FnOnce() -> Result<T>>(name: &'static str, f: F) -> Result<T> {
+    f().map_err(|error| Error::new(ErrorKind::Step(name, Box::new(error))))
+}
+
+#[cfg(test)]
+fn run_flow(fail_at: u32) -> Result<u32> {
+    let slip = try!(step("add to betslip", || {
+        if fail_at == 1 { Err(Error::from("no lines")) } else { Ok(42) }
+    }));
+
+    step("place", || {
+        if fail_at == 2 { Err(Error::from("rejected")) } else { Ok(slip + 1) }
+    })
+}
+
+#[test]
+fn test_step() {
+    let failed_step = |fail_at| match run_flow(fail_at).unwrap_err().kind {
+        ErrorKind::Step(name, _) => name,
+        _ => unreachable!()
+    };
+
+    assert_eq!(failed_step(1), "add to betslip");
+    assert_eq!(failed_step(2), "place");
+    assert_eq!(run_flow(0).unwrap(), 43);
+}
@@ -0,0 +1,71 @@
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering::Relaxed;
+
+const WINDOW: u32 = 60;
+
+struct Slot {
+    second: AtomicUsize,
+    count: AtomicUsize
+}
+
+// Rolling per-second counter over the last minute. It's a metric, so races between
+// concurrent `record` calls on the same second are tolerated.
+pub struct Throughput {
+    slots: Box<[Slot]>
+}
+
+impl Throughput {
+    pub fn new() -> Throughput {
+        Throughput {
+            slots: (0..WINDOW)
+                .map(|_| Slot { second: AtomicUsize::new(0), count: AtomicUsize::new(0) })
+                .collect::<Vec<_>>()
+                .into_boxed_slice()
+        }
+    }
+
+    pub fn record(&self, now: u32) {
+        let slot = &self.slots[(now % WINDOW) as usize];
+
+        if slot.second.swap(now as usize, Relaxed) == now as usize {
+            slot.count.fetch_add(1, Relaxed);
+        } else {
+            slot.count.store(1, Relaxed);
+        }
+    }
+
+    pub fn per_minute(&self, now: u32) -> usize {
+        self.slots.iter()
+            .filter(|slot| {
+                let second = slot.second.load(Relaxed) as u32;
+                second <= now && now - second < WINDOW
+            })
+            .map(|slot| slot.count.load(Relaxed))
+            .sum()
+    }
+}
+
+#[test]
+fn test_per_minute() {
+    let throughput = Throughput::new();
+    let start = 1_000_000;
+
+    for i in 0..120 {
+        throughput.record(start + i / 2);
+    }
+
+    assert_eq!(throughput.per_minute(start + 59), 120);
+    assert_eq!(throughput.per_minute(start + 89), 60);
+    assert_eq!(throughput.per_minute(start + 200), 0);
+}
+
+#[test]
+fn test_slot_reuse() {
+    let throughput = Throughput::new();
+
+    throughput.record(100);
+    throughput.record(100);
+    throughput.record(100 + WINDOW);
+
+    assert_eq!(throughput.per_minute(100 + WINDOW), 1);
+}
@@ -0,0 +1,38 @@
+use std::thread;
+use url::Url;
+
+use base::error::Result;
+use base::session::Session;
+
+#[derive(Serialize)]
+struct Payload<'a> {
+    text: &'a str
+}
+
+// Posts `{"text": "..."}` to the HTTPS endpoint in the background.
+pub fn notify(url: &str, text: &str) {
+    let url = match Url::parse(url) {
+        Ok(url) => url,
+        Err(error) => {
+            warn!("Invalid webhook {}: {}", url, error);
+            return;
+        }
+    };
+
+    let host = url.host_str().unwrap_or("").to_owned();
+    let path = match url.query() {
+        Some(query) => format!("{}?{}", url.path(), query),
+        None => url.path().to_owned()
+    };
+
+    let text = text.to_owned();
+
+    thread::spawn(move || {
+        let session = Session::new(&host);
+        let result: Result<String> = session.request(&path).post(Payload { text: &text });
+
+        if let Err(error) = result {
+            warn!("While notifying {}: {}", host, error);
+        }
+    });
+}
@@ -1,5 +1,4 @@
 use std::fmt::{Debug};
-use std::str;
 use serde_json as json;
 use serde::{Serialize, Deserialize};
 use websocket::message::{Message, Type};
@@ -10,7 +9,9 @@ use websocket::client::Client;
 use websocket::dataframe::DataFrame;
 use websocket::stream::WebSocketStream;
 
+use constants::MAX_LOGGED_BODY;
 use base::error::{Result, Error};
+use base::logger::truncate_for_log;
 
 pub struct Connection(Client<DataFrame, Sender<WebSocketStream>, Receiver<WebSocketStream>>);
 
@@ -48,7 +49,8 @@ impl Connection {
                     try!(self.0.send_message(&Message::pong(message.payload)));
                 },
                 Type::Text => {
-                    trace!("Received text message: {:?}", str::from_utf8(&*message.payload));
+                    trace!("Received text message: {}",
+                           truncate_for_log(&*message.payload, MAX_LOGGED_BODY));
 
                     match json::from_reader::<&[u8], T>(&*message.payload) {
                         Ok(m) => return Ok(m),
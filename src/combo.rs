@@ -1,8 +1,11 @@
+use std::path::Path;
 use parking_lot::Mutex;
-use rusqlite::{Connection, Row};
+use rusqlite::{Connection, Row, DatabaseName};
 
 use constants::DATABASE;
+use base::error::Result;
 use base::currency::Currency;
+use gamblers::BetReceipt;
 
 #[derive(Debug)]
 pub struct Combo {
@@ -24,13 +27,48 @@ pub struct Bet {
     pub placed: bool
 }
 
+// Totals of combos, `placed` counts combos with all legs placed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Summary {
+    pub combos: u32,
+    pub placed: u32,
+    pub staked: Currency,
+    // The least return of placed combos minus their stakes, i.e. the profit whichever leg wins.
+    pub profit: Currency
+}
+
+impl Summary {
+    fn new() -> Summary {
+        Summary { combos: 0, placed: 0, staked: Currency(0), profit: Currency(0) }
+    }
+
+    pub fn success_rate(&self) -> Option<f64> {
+        if self.combos == 0 {
+            return None;
+        }
+
+        Some(self.placed as f64 / self.combos as f64)
+    }
+}
+
+// A leg referencing a missing bet or combo, e.g. after manual edits of the database.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DanglingLeg {
+    pub combo: i64,
+    pub idx: u32,
+    pub bet: i64
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stats {
+    pub total: Summary,
+    pub by_game: Vec<(String, Summary)>
+}
+
 lazy_static! {
     static ref DB: Mutex<Connection> = {
         let db = Connection::open(DATABASE).unwrap();
-
-        db.execute(&format!("CREATE TABLE IF NOT EXISTS {}", BET_SCHEMA), &[]).unwrap();
-        db.execute(&format!("CREATE TABLE IF NOT EXISTS {}", COMBO_SCHEMA), &[]).unwrap();
-
+        init(&db);
         Mutex::new(db)
     };
 }
@@ -49,12 +87,145 @@ const BET_SCHEMA: &str = "bet(
 const COMBO_SCHEMA: &str = "combo(
     date    INTEGER NOT NULL,
     game    TEXT    NOT NULL,
-    kind    TEXT    NOT NULL,
-    bet_1   INTEGER NOT NULL,
-    bet_2   INTEGER NOT NULL,
-    bet_3   INTEGER
+    kind    TEXT    NOT NULL
+)";
+
+const COMBO_BET_SCHEMA: &str = "combo_bet(
+    combo   INTEGER NOT NULL,
+    idx     INTEGER NOT NULL,
+    bet     INTEGER NOT NULL,
+    PRIMARY KEY (combo, idx)
 )";
 
+const RECEIPT_SCHEMA: &str = "receipt(
+    bet     INTEGER NOT NULL,
+    id      TEXT,
+    stake   REAL    NOT NULL,
+    coef    REAL    NOT NULL,
+    date    INTEGER NOT NULL
+)";
+
+// Steps upgrading the database to the next version, which is kept in `PRAGMA user_version`.
+// Databases created before the versioning are at zero, so the steps must be idempotent.
+const MIGRATIONS: &[fn(&Connection)] = &[
+    create_tables,
+    add_raw_bets,
+    add_indexes,
+    add_damaged_combos,
+    add_pending_receipts
+];
+
+fn init(db: &Connection) {
+    migrate(db, MIGRATIONS);
+}
+
+fn migrate(db: &Connection, migrations: &[fn(&Connection)]) {
+    let version = schema_version(db);
+
+    if version > migrations.len() {
+        warn!("The database (v{}) is newer than supported (v{})", version, migrations.len());
+        return;
+    }
+
+    for (idx, migration) in migrations.iter().enumerate().skip(version) {
+        info!("Migrating the database to v{}...", idx + 1);
+
+        migration(db);
+        db.execute_batch(&format!("PRAGMA user_version = {}", idx + 1)).unwrap();
+    }
+}
+
+fn schema_version(db: &Connection) -> usize {
+    db.query_row("PRAGMA user_version", &[], |row| row.get::<_, i64>(0)).unwrap() as usize
+}
+
+// v1.
+fn create_tables(db: &Connection) {
+    db.execute(&format!("CREATE TABLE IF NOT EXISTS {}", BET_SCHEMA), &[]).unwrap();
+    db.execute(&format!("CREATE TABLE IF NOT EXISTS {}", COMBO_SCHEMA), &[]).unwrap();
+    db.execute(&format!("CREATE TABLE IF NOT EXISTS {}", COMBO_BET_SCHEMA), &[]).unwrap();
+    db.execute(&format!("CREATE TABLE IF NOT EXISTS {}", RECEIPT_SCHEMA), &[]).unwrap();
+
+    if has_legacy_combo(db) {
+        migrate_legacy_combo(db);
+    }
+}
+
+// v2.
+fn add_raw_bets(db: &Connection) {
+    if !has_column(db, "bet", "raw_request") {
+        db.execute_batch("
+            ALTER TABLE bet ADD COLUMN raw_request TEXT;
+            ALTER TABLE bet ADD COLUMN raw_response TEXT;
+        ").unwrap();
+    }
+}
+
+// v3. Bets are looked up by every incoming market (`contains` and `load_titles`).
+fn add_indexes(db: &Connection) {
+    db.execute_batch("
+        CREATE INDEX IF NOT EXISTS bet_host_id ON bet(host, id);
+        CREATE INDEX IF NOT EXISTS bet_host_expiry ON bet(host, expiry);
+        CREATE INDEX IF NOT EXISTS combo_bet_bet ON combo_bet(bet);
+    ").unwrap();
+}
+
+// v4. Combos with missing bets are marked instead of being rewritten.
+fn add_damaged_combos(db: &Connection) {
+    if !has_column(db, "combo", "damaged") {
+        db.execute_batch("
+            ALTER TABLE combo ADD COLUMN damaged BOOLEAN NOT NULL DEFAULT 0;
+        ").unwrap();
+    }
+}
+
+// v5. Bets unconfirmed by the bookie in time are resolved by the reconciliation.
+fn add_pending_receipts(db: &Connection) {
+    if !has_column(db, "receipt", "pending") {
+        db.execute_batch("
+            ALTER TABLE receipt ADD COLUMN pending BOOLEAN NOT NULL DEFAULT 0;
+        ").unwrap();
+    }
+}
+
+fn has_column(db: &Connection, table: &str, column: &str) -> bool {
+    let mut stmt = db.prepare(&format!("PRAGMA table_info({})", table)).unwrap();
+    let mut rows = stmt.query(&[]).unwrap();
+
+    while let Some(row) = rows.next() {
+        if row.unwrap().get::<_, String>(1) == column {
+            return true;
+        }
+    }
+
+    false
+}
+
+fn has_legacy_combo(db: &Connection) -> bool {
+    has_column(db, "combo", "bet_1")
+}
+
+// Previously legs were stored in the `bet_1`, `bet_2` and `bet_3` columns of `combo`.
+fn migrate_legacy_combo(db: &Connection) {
+    info!("Migrating combos to the join table...");
+
+    db.execute_batch(&format!("
+        BEGIN;
+
+        INSERT INTO combo_bet(combo, idx, bet) SELECT rowid, 0, bet_1 FROM combo;
+        INSERT INTO combo_bet(combo, idx, bet) SELECT rowid, 1, bet_2 FROM combo;
+        INSERT INTO combo_bet(combo, idx, bet) SELECT rowid, 2, bet_3 FROM combo
+            WHERE bet_3 IS NOT NULL;
+
+        CREATE TABLE new_{};
+        INSERT INTO new_combo(rowid, date, game, kind) SELECT rowid, date, game, kind FROM combo;
+        DROP TABLE combo;
+        ALTER TABLE new_combo RENAME TO combo;
+
+        COMMIT;
+    ", COMBO_SCHEMA)).unwrap();
+}
+
 pub fn contains(host: &str, id: u64) -> bool {
     let db = DB.lock();
     let mut stmt = db.prepare_cached("SELECT id FROM bet WHERE host = ? AND id = ?").unwrap();
@@ -62,18 +233,55 @@ pub fn contains(host: &str, id: u64) -> bool {
     stmt.exists(&[&host, &(id as i64)]).unwrap()
 }
 
+// Titles of bets by the host on the game within the dates, used to find the same fixture if
+// a bookie has changed its id.
+pub fn load_titles(host: &str, game: &str, from: u32, to: u32) -> Vec<String> {
+    load_titles_from(&DB.lock(), host, game, from, to)
+}
+
+fn load_titles_from(db: &Connection, host: &str, game: &str, from: u32, to: u32) -> Vec<String> {
+    let mut stmt = db.prepare_cached("
+        SELECT b.title FROM bet b
+            INNER JOIN combo_bet cb ON cb.bet = b.rowid
+            INNER JOIN combo c ON c.rowid = cb.combo
+        WHERE b.host = ? AND c.game = ? AND b.expiry BETWEEN ? AND ? AND b.title IS NOT NULL
+    ").unwrap();
+
+    let mut rows = stmt.query(&[&host, &game, &(from as i64), &(to as i64)]).unwrap();
+    let mut titles = Vec::new();
+
+    while let Some(row) = rows.next() {
+        titles.push(row.unwrap().get(0));
+    }
+
+    titles
+}
+
 pub fn save(combo: Combo) {
+    save_to(&mut DB.lock(), combo);
+}
+
+fn save_to(db: &mut Connection, combo: Combo) {
     // TODO(loyd): use cache.
     const INSERT_BET: &str = "INSERT INTO bet(host, id, title, expiry, coef, stake, profit, placed)
                               VALUES (:host, :id, :title, :expiry, :coef, :stake, :profit, :placed)";
 
-    const INSERT_COMBO: &str = "INSERT INTO combo(date, game, kind, bet_1, bet_2, bet_3)
-                                VALUES (:date, :game, :kind, :bet_1, :bet_2, :bet_3)";
+    const INSERT_COMBO: &str = "INSERT INTO combo(date, game, kind) VALUES (:date, :game, :kind)";
+
+    const INSERT_COMBO_BET: &str = "INSERT INTO combo_bet(combo, idx, bet)
+                                    VALUES (:combo, :idx, :bet)";
 
-    let mut db = DB.lock();
     let tx = db.transaction().unwrap();
 
-    let row_ids = combo.bets.iter().map(|bet| {
+    tx.execute_named(INSERT_COMBO, &[
+        (":date", &(combo.date as i64)),
+        (":game", &combo.game),
+        (":kind", &combo.kind)
+    ]).unwrap();
+
+    let combo_id = tx.last_insert_rowid();
+
+    for (idx, bet) in combo.bets.iter().enumerate() {
         let stake: f64 = bet.stake.into();
 
         tx.execute_named(INSERT_BET, &[
@@ -87,80 +295,596 @@ pub fn save(combo: Combo) {
             (":placed", &bet.placed)
         ]).unwrap();
 
-        tx.last_insert_rowid()
-    }).collect::<Vec<_>>();
+        let bet_id = tx.last_insert_rowid();
 
-    tx.execute_named(INSERT_COMBO, &[
-        (":date", &(combo.date as i64)),
-        (":game", &combo.game),
-        (":kind", &combo.kind),
-        (":bet_1", &row_ids[0]),
-        (":bet_2", &row_ids[1]),
-        (":bet_3", &row_ids.get(2).map(|x| *x))
-    ]).unwrap();
+        tx.execute_named(INSERT_COMBO_BET, &[
+            (":combo", &combo_id),
+            (":idx", &(idx as i64)),
+            (":bet", &bet_id)
+        ]).unwrap();
+    }
 
     tx.commit().unwrap();
 }
 
-pub fn mark_as_placed(host: &str, id: u64, title: Option<&str>) {
-    let db = DB.lock();
+pub fn mark_as_placed(host: &str, id: u64, title: Option<&str>, receipt: &BetReceipt) {
+    mark_as_placed_in(&DB.lock(), host, id, title, receipt);
+}
 
-    let mut stmt = db.prepare_cached("UPDATE bet SET placed = 1
+fn mark_as_placed_in(db: &Connection, host: &str, id: u64, title: Option<&str>,
+                     receipt: &BetReceipt)
+{
+    let mut stmt = db.prepare_cached("UPDATE bet SET placed = 1, raw_request = ?, raw_response = ?
                                       WHERE host = ? AND id = ? AND ifnull(title, '') = ?").unwrap();
 
-    let updated = stmt.execute(&[&host, &(id as i64), &title.unwrap_or("")]).unwrap();
+    let updated = stmt.execute(&[&receipt.raw_request, &receipt.raw_response,
+                                 &host, &(id as i64), &title.unwrap_or("")]).unwrap();
 
     debug_assert_eq!(updated, 1);
+
+    let mut stmt = db.prepare_cached("INSERT INTO receipt(bet, id, stake, coef, date, pending)
+                                      SELECT rowid, ?, ?, ?, ?, ? FROM bet
+                                      WHERE host = ? AND id = ? AND ifnull(title, '') = ?").unwrap();
+
+    let stake: f64 = receipt.stake.into();
+
+    stmt.execute(&[&receipt.id, &stake, &receipt.coef, &(receipt.date as i64), &receipt.pending,
+                   &host, &(id as i64), &title.unwrap_or("")]).unwrap();
+}
+
+// Keeps the traffic of a failed placement, the bet isn't marked as placed.
+pub fn save_raw_bet(host: &str, id: u64, title: Option<&str>, request: &str, response: &str) {
+    save_raw_bet_in(&DB.lock(), host, id, title, request, response);
 }
 
-impl<'a, 'b> From<Row<'a, 'b>> for Combo {
-    fn from(row: Row) -> Combo {
+fn save_raw_bet_in(db: &Connection, host: &str, id: u64, title: Option<&str>, request: &str,
+                   response: &str)
+{
+    let mut stmt = db.prepare_cached("UPDATE bet SET raw_request = ?, raw_response = ?
+                                      WHERE host = ? AND id = ? AND ifnull(title, '') = ?").unwrap();
+
+    let updated = stmt.execute(&[&request, &response, &host, &(id as i64), &title.unwrap_or("")])
+        .unwrap();
+
+    debug_assert_eq!(updated, 1);
+}
+
+impl<'a, 'b> From<Row<'a, 'b>> for Bet {
+    fn from(row: Row) -> Bet {
         // XXX(loyd): this code relies on column ordering.
-        let bets = (0..3)
-            .take_while(|i| *i < 2 || row.get::<_, Option<i64>>(3 + i).is_some())
-            .map(|i| {
-                let o = 6 + i * 8;
-
-                Bet {
-                    host:   row.get(o),
-                    id:     row.get::<_, i64>(o + 1) as u64,
-                    title:  row.get(o + 2),
-                    expiry: row.get::<_, i64>(o + 3) as u32,
-                    coef:   row.get(o + 4),
-                    stake:  Currency::from(row.get::<_, f64>(o + 5)),
-                    profit: row.get(o + 6),
-                    placed: row.get(o + 7)
-                }
-            })
-            .collect();
-
-        Combo {
-            date: row.get::<_, i64>("date") as u32,
-            game: row.get("game"),
-            kind: row.get("kind"),
-            bets: bets
+        let o = 4;
+
+        Bet {
+            host:   row.get(o),
+            id:     row.get::<_, i64>(o + 1) as u64,
+            title:  row.get(o + 2),
+            expiry: row.get::<_, i64>(o + 3) as u32,
+            coef:   row.get(o + 4),
+            stake:  Currency::from(row.get::<_, f64>(o + 5)),
+            profit: row.get(o + 6),
+            placed: row.get(o + 7)
         }
     }
 }
 
+// Makes a consistent snapshot of the live database using the online backup API.
+pub fn backup(path: &Path) -> Result<()> {
+    backup_from(&DB.lock(), path)
+}
+
+fn backup_from(db: &Connection, path: &Path) -> Result<()> {
+    db.backup(DatabaseName::Main, path, None).map_err(From::from)
+}
+
 pub fn load_recent(count: u32) -> Vec<Combo> {
-    let db = DB.lock();
+    load_recent_from(&DB.lock(), count)
+}
 
+fn load_recent_from(db: &Connection, count: u32) -> Vec<Combo> {
+    // Rows are grouped by combos and ordered by legs inside them.
+    // Legs with missing bets are joined as nulls to skip their combos explicitly.
     let mut stmt = db.prepare_cached("
-        SELECT * FROM combo
-            INNER JOIN bet b1 ON bet_1 = b1.rowid
-            INNER JOIN bet b2 ON bet_2 = b2.rowid
-            LEFT  JOIN bet b3 ON bet_3 = b3.rowid
-        ORDER BY combo.rowid DESC
-        LIMIT ?
+        SELECT c.rowid, c.date, c.game, c.kind, b.* FROM (
+            SELECT rowid, * FROM combo WHERE NOT damaged ORDER BY rowid DESC LIMIT ?
+        ) c
+            INNER JOIN combo_bet cb ON cb.combo = c.rowid
+            LEFT JOIN bet b ON cb.bet = b.rowid
+        ORDER BY c.rowid DESC, cb.idx
     ").unwrap();
 
     let mut rows = stmt.query(&[&(count as i64)]).unwrap();
-    let mut combos = Vec::new();
+    let mut combos: Vec<(i64, Combo)> = Vec::new();
+    let mut broken = Vec::new();
 
     while let Some(row) = rows.next() {
-        combos.push(Combo::from(row.unwrap()))
+        let row = row.unwrap();
+        let combo_id = row.get::<_, i64>(0);
+
+        if combos.last().map_or(true, |&(id, _)| id != combo_id) {
+            combos.push((combo_id, Combo {
+                date: row.get::<_, i64>(1) as u32,
+                game: row.get(2),
+                kind: row.get(3),
+                bets: Vec::new()
+            }));
+        }
+
+        if row.get::<_, Option<String>>(4).is_none() {
+            broken.push(combo_id);
+            continue;
+        }
+
+        combos.last_mut().unwrap().1.bets.push(Bet::from(row));
     }
 
-    combos
+    combos.into_iter()
+        .filter(|&(id, _)| {
+            let is_broken = broken.contains(&id);
+
+            if is_broken {
+                warn!("Skipping the combo #{}: some of its bets are missing", id);
+            }
+
+            !is_broken
+        })
+        .map(|(_, combo)| combo)
+        .collect()
+}
+
+// Finds legs referencing missing bets or combos and optionally removes them.
+// Combos left without legs are removed too.
+pub fn verify_integrity(repair: bool) -> Vec<DanglingLeg> {
+    verify_integrity_in(&DB.lock(), repair)
+}
+
+fn verify_integrity_in(db: &Connection, repair: bool) -> Vec<DanglingLeg> {
+    let mut stmt = db.prepare("
+        SELECT cb.combo, cb.idx, cb.bet FROM combo_bet cb
+            LEFT JOIN bet b ON cb.bet = b.rowid
+            LEFT JOIN combo c ON cb.combo = c.rowid
+        WHERE (b.rowid IS NULL OR c.rowid IS NULL) AND NOT ifnull(c.damaged, 0)
+        ORDER BY cb.combo, cb.idx
+    ").unwrap();
+
+    let mut rows = stmt.query(&[]).unwrap();
+    let mut dangling = Vec::new();
+
+    while let Some(row) = rows.next() {
+        let row = row.unwrap();
+
+        dangling.push(DanglingLeg {
+            combo: row.get(0),
+            idx: row.get::<_, i64>(1) as u32,
+            bet: row.get(2)
+        });
+    }
+
+    for leg in &dangling {
+        warn!("The leg #{} of the combo #{} references the missing bet #{}",
+              leg.idx, leg.combo, leg.bet);
+    }
+
+    // Combos are kept as is to be investigated, but excluded from the history and statistics.
+    // Legs of missing combos aren't part of anything, so they are removed.
+    if repair && !dangling.is_empty() {
+        db.execute_batch("
+            UPDATE combo SET damaged = 1 WHERE rowid IN (
+                SELECT cb.combo FROM combo_bet cb
+                    LEFT JOIN bet b ON cb.bet = b.rowid
+                WHERE b.rowid IS NULL
+            );
+
+            DELETE FROM combo_bet WHERE combo NOT IN (SELECT rowid FROM combo);
+        ").unwrap();
+
+        info!("Combos with {} dangling legs are marked as damaged", dangling.len());
+    }
+
+    dangling
+}
+
+pub fn aggregate_stats(since: u32) -> Stats {
+    aggregate_stats_from(&DB.lock(), since)
+}
+
+fn aggregate_stats_from(db: &Connection, since: u32) -> Stats {
+    // Receipts are the actual stakes and coefs, so only placed legs contribute to the sums.
+    let mut stmt = db.prepare_cached("
+        SELECT c.game, COUNT(*), SUM(b.placed), SUM(ifnull(r.stake, 0)), MIN(r.stake * r.coef)
+        FROM combo c
+            INNER JOIN combo_bet cb ON cb.combo = c.rowid
+            INNER JOIN bet b ON cb.bet = b.rowid
+            LEFT JOIN receipt r ON r.bet = b.rowid
+        WHERE c.date >= ? AND NOT c.damaged
+        GROUP BY c.rowid
+        ORDER BY c.game
+    ").unwrap();
+
+    let mut rows = stmt.query(&[&(since as i64)]).unwrap();
+    let mut stats = Stats { total: Summary::new(), by_game: Vec::new() };
+
+    while let Some(row) = rows.next() {
+        let row = row.unwrap();
+        let game: String = row.get(0);
+        let is_placed = row.get::<_, i64>(1) == row.get::<_, i64>(2);
+        let staked = Currency::from(row.get::<_, f64>(3));
+        let min_return = row.get::<_, Option<f64>>(4).map(Currency::from);
+
+        if stats.by_game.last().map_or(true, |&(ref g, _)| *g != game) {
+            stats.by_game.push((game, Summary::new()));
+        }
+
+        let summary = &mut stats.by_game.last_mut().unwrap().1;
+
+        summary.combos += 1;
+        summary.staked = summary.staked + staked;
+
+        if is_placed {
+            summary.placed += 1;
+            summary.profit = summary.profit + min_return.unwrap_or(Currency(0)) - staked;
+        }
+    }
+
+    for &(_, ref summary) in &stats.by_game {
+        stats.total.combos += summary.combos;
+        stats.total.placed += summary.placed;
+        stats.total.staked = stats.total.staked + summary.staked;
+        stats.total.profit = stats.total.profit + summary.profit;
+    }
+
+    stats
+}
+
+pub fn load_receipt_ids(host: &str, now: u32) -> Vec<String> {
+    load_receipt_ids_from(&DB.lock(), host, now, false)
+}
+
+pub fn load_pending_receipt_ids(host: &str, now: u32) -> Vec<String> {
+    load_receipt_ids_from(&DB.lock(), host, now, true)
+}
+
+fn load_receipt_ids_from(db: &Connection, host: &str, now: u32, pending: bool) -> Vec<String> {
+    let mut stmt = db.prepare_cached("
+        SELECT receipt.id FROM receipt
+            INNER JOIN bet ON receipt.bet = bet.rowid
+        WHERE bet.host = ? AND bet.expiry > ? AND receipt.id IS NOT NULL
+            AND (receipt.pending OR NOT ?)
+    ").unwrap();
+
+    let mut rows = stmt.query(&[&host, &(now as i64), &pending]).unwrap();
+    let mut ids = Vec::new();
+
+    while let Some(row) = rows.next() {
+        ids.push(row.unwrap().get(0));
+    }
+
+    ids
+}
+
+pub fn confirm_receipt(host: &str, id: &str) {
+    confirm_receipt_in(&DB.lock(), host, id);
+}
+
+fn confirm_receipt_in(db: &Connection, host: &str, id: &str) {
+    let mut stmt = db.prepare_cached("
+        UPDATE receipt SET pending = 0
+        WHERE id = ? AND bet IN (SELECT rowid FROM bet WHERE host = ?)
+    ").unwrap();
+
+    stmt.execute(&[&id, &host]).unwrap();
+}
+
+// The bet is considered as not placed anymore.
+pub fn reject_receipt(host: &str, id: &str) {
+    reject_receipt_in(&DB.lock(), host, id);
+}
+
+fn reject_receipt_in(db: &Connection, host: &str, id: &str) {
+    db.execute("
+        UPDATE bet SET placed = 0
+        WHERE host = ? AND rowid IN (SELECT bet FROM receipt WHERE id = ?)
+    ", &[&host, &id]).unwrap();
+
+    db.execute("
+        DELETE FROM receipt
+        WHERE id = ? AND bet IN (SELECT rowid FROM bet WHERE host = ?)
+    ", &[&id, &host]).unwrap();
+}
+
+#[cfg(test)]
+fn make_combo(date: u32, legs: usize) -> Combo {
+    Combo {
+        date: date,
+        game: "Dota2".to_owned(),
+        kind: "Series".to_owned(),
+        bets: (0..legs).map(|i| Bet {
+            host: format!("bookie{}.com", i),
+            id: date as u64 * 10 + i as u64,
+            title: if i == 1 { None } else { Some(format!("Team {}", i)) },
+            expiry: date + 3600,
+            coef: 2. + i as f64,
+            stake: Currency::from(1. + i as f64),
+            profit: 0.05,
+            placed: false
+        }).collect()
+    }
+}
+
+#[test]
+fn test_save_and_load_combos() {
+    let mut db = Connection::open_in_memory().unwrap();
+    init(&db);
+
+    for (date, legs) in vec![(100, 2), (200, 3), (300, 4)] {
+        save_to(&mut db, make_combo(date, legs));
+    }
+
+    let combos = load_recent_from(&db, 10);
+
+    assert_eq!(combos.len(), 3);
+
+    for (combo, &(date, legs)) in combos.iter().zip(&[(300, 4), (200, 3), (100, 2)]) {
+        let expected = make_combo(date, legs);
+
+        assert_eq!(combo.date, date);
+        assert_eq!(combo.bets.len(), legs);
+
+        for (bet, expected) in combo.bets.iter().zip(&expected.bets) {
+            assert_eq!(bet.host, expected.host);
+            assert_eq!(bet.id, expected.id);
+            assert_eq!(bet.title, expected.title);
+            assert_eq!(bet.stake, expected.stake);
+        }
+    }
+
+    assert_eq!(load_recent_from(&db, 1)[0].bets.len(), 4);
+}
+
+#[test]
+fn test_dangling_leg() {
+    let mut db = Connection::open_in_memory().unwrap();
+    init(&db);
+
+    for (date, legs) in vec![(100, 2), (200, 3), (300, 2)] {
+        save_to(&mut db, make_combo(date, legs));
+    }
+
+    assert!(verify_integrity_in(&db, false).is_empty());
+
+    // The first leg of the second combo.
+    db.execute("DELETE FROM bet WHERE id = 2000", &[]).unwrap();
+
+    let combos = load_recent_from(&db, 10);
+    assert_eq!(combos.iter().map(|c| c.date).collect::<Vec<_>>(), vec![300, 100]);
+
+    let dangling = verify_integrity_in(&db, false);
+    assert_eq!(dangling.len(), 1);
+    assert_eq!((dangling[0].idx, dangling[0].bet), (0, 3));
+
+    assert_eq!(verify_integrity_in(&db, true), dangling);
+    assert!(verify_integrity_in(&db, false).is_empty());
+
+    // The damaged combo is excluded, but not rewritten.
+    let combos = load_recent_from(&db, 10);
+    assert_eq!(combos.iter().map(|c| c.date).collect::<Vec<_>>(), vec![300, 100]);
+    assert_eq!(aggregate_stats_from(&db, 0).total.combos, 2);
+
+    let legs = db.query_row("SELECT count(*) FROM combo_bet WHERE combo = 2", &[],
+                            |row| row.get::<_, i64>(0)).unwrap();
+    assert_eq!(legs, 3);
+}
+
+#[test]
+fn test_migrate_legacy_combo() {
+    let db = Connection::open_in_memory().unwrap();
+
+    db.execute_batch(&format!("
+        CREATE TABLE {};
+        CREATE TABLE combo(
+            date    INTEGER NOT NULL,
+            game    TEXT    NOT NULL,
+            kind    TEXT    NOT NULL,
+            bet_1   INTEGER NOT NULL,
+            bet_2   INTEGER NOT NULL,
+            bet_3   INTEGER
+        );
+        INSERT INTO bet VALUES ('a.com', 1, 'A', 0, 2.1, 1.0, 0.05, 0);
+        INSERT INTO bet VALUES ('b.com', 2, 'B', 0, 2.2, 1.0, 0.05, 0);
+        INSERT INTO bet VALUES ('c.com', 3, NULL, 0, 9.0, 0.2, 0.05, 0);
+        INSERT INTO combo VALUES (10, 'Dota2', 'Series', 1, 2, NULL);
+        INSERT INTO combo VALUES (20, 'Dota2', 'Series', 1, 2, 3);
+    ", BET_SCHEMA)).unwrap();
+
+    init(&db);
+
+    assert!(!has_legacy_combo(&db));
+
+    let combos = load_recent_from(&db, 10);
+
+    assert_eq!(combos.len(), 2);
+    assert_eq!(combos[0].date, 20);
+    assert_eq!(combos[0].bets.iter().map(|b| b.id).collect::<Vec<_>>(), vec![1, 2, 3]);
+    assert_eq!(combos[1].bets.iter().map(|b| b.id).collect::<Vec<_>>(), vec![1, 2]);
+}
+
+#[test]
+fn test_migrate() {
+    let db = Connection::open_in_memory().unwrap();
+
+    migrate(&db, &MIGRATIONS[..1]);
+    assert_eq!(schema_version(&db), 1);
+    assert!(!has_column(&db, "bet", "raw_request"));
+
+    db.execute("INSERT INTO bet VALUES ('a.com', 1, 'A', 0, 2.1, 1.0, 0.05, 0)", &[]).unwrap();
+
+    init(&db);
+    assert_eq!(schema_version(&db), MIGRATIONS.len());
+    assert!(has_column(&db, "bet", "raw_request"));
+
+    let indexes = db.query_row("SELECT count(*) FROM sqlite_master WHERE type = 'index' AND
+                                name IN ('bet_host_id', 'bet_host_expiry', 'combo_bet_bet')",
+                               &[], |row| row.get::<_, i64>(0)).unwrap();
+    assert_eq!(indexes, 3);
+
+    // Nothing is applied twice, even if the version is lost.
+    init(&db);
+    db.execute_batch("PRAGMA user_version = 0").unwrap();
+    init(&db);
+
+    assert_eq!(schema_version(&db), MIGRATIONS.len());
+
+    let count = db.query_row("SELECT count(*) FROM bet", &[], |row| row.get::<_, i64>(0)).unwrap();
+    assert_eq!(count, 1);
+}
+
+#[test]
+fn test_backup() {
+    use std::env;
+    use std::fs;
+
+    let mut db = Connection::open_in_memory().unwrap();
+    init(&db);
+    save_to(&mut db, make_combo(100, 2));
+
+    let path = env::temp_dir().join("aladdin-test-backup.db");
+    backup_from(&db, &path).unwrap();
+
+    let copy = Connection::open(&path).unwrap();
+    let combos = load_recent_from(&copy, 10);
+
+    assert_eq!(combos.len(), 1);
+    assert_eq!(combos[0].bets.len(), 2);
+
+    drop(copy);
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_load_titles() {
+    let mut db = Connection::open_in_memory().unwrap();
+    init(&db);
+
+    save_to(&mut db, make_combo(100, 3));
+
+    // The ids aren't considered, so a reissued offer is still found.
+    let titles = load_titles_from(&db, "bookie0.com", "Dota2", 3600, 3800);
+    assert_eq!(titles, vec!["Team 0".to_owned()]);
+
+    assert!(load_titles_from(&db, "bookie1.com", "Dota2", 3600, 3800).is_empty());
+    assert!(load_titles_from(&db, "bookie0.com", "Smite", 3600, 3800).is_empty());
+    assert!(load_titles_from(&db, "bookie0.com", "Dota2", 3800, 4000).is_empty());
+}
+
+#[test]
+fn test_aggregate_stats() {
+    let mut db = Connection::open_in_memory().unwrap();
+    init(&db);
+
+    assert_eq!(aggregate_stats_from(&db, 0), Stats { total: Summary::new(), by_game: vec![] });
+
+    save_to(&mut db, make_combo(100, 2));
+    save_to(&mut db, make_combo(200, 2));
+
+    let mut football = make_combo(300, 2);
+    football.game = "Football".to_owned();
+    save_to(&mut db, football);
+
+    // Both legs of the first combo and one leg of the football one are placed.
+    for &(date, legs) in &[(100, 2), (300, 1)] {
+        for bet in make_combo(date, 2).bets.iter().take(legs) {
+            let stake: f64 = bet.stake.into();
+
+            // Bypass `mark_as_placed`, which uses the global database.
+            db.execute("UPDATE bet SET placed = 1 WHERE id = ?", &[&(bet.id as i64)]).unwrap();
+            db.execute("INSERT INTO receipt(bet, id, stake, coef, date)
+                        SELECT rowid, NULL, ?, ?, 0 FROM bet WHERE id = ?",
+                       &[&stake, &bet.coef, &(bet.id as i64)]).unwrap();
+        }
+    }
+
+    let stats = aggregate_stats_from(&db, 0);
+
+    // The placed combo: 1.00 at x2 and 2.00 at x3, the least return is 2.00.
+    assert_eq!(stats.total.combos, 3);
+    assert_eq!(stats.total.placed, 1);
+    assert_eq!(stats.total.staked, Currency::from(4.));
+    assert_eq!(stats.total.profit, Currency::from(-1.));
+    assert_eq!(stats.total.success_rate(), Some(1. / 3.));
+
+    assert_eq!(stats.by_game.len(), 2);
+    assert_eq!(stats.by_game[0].0, "Dota2");
+    assert_eq!(stats.by_game[0].1.combos, 2);
+    assert_eq!(stats.by_game[0].1.staked, Currency::from(3.));
+    assert_eq!(stats.by_game[1].0, "Football");
+    assert_eq!(stats.by_game[1].1.placed, 0);
+
+    assert_eq!(aggregate_stats_from(&db, 250).total.combos, 1);
+}
+
+#[test]
+fn test_raw_bet() {
+    let mut db = Connection::open_in_memory().unwrap();
+    init(&db);
+    save_to(&mut db, make_combo(100, 2));
+
+    let mut receipt = BetReceipt::new(Currency::from(1.), 2.);
+    receipt.raw_request = Some("POST /bets\n{\"stake\":1}".to_owned());
+    receipt.raw_response = Some("200 OK\n{\"id\":42}".to_owned());
+
+    mark_as_placed_in(&db, "bookie0.com", 1000, Some("Team 0"), &receipt);
+
+    let raw = db.query_row("SELECT raw_request, raw_response FROM bet WHERE id = 1000", &[],
+                           |row| (row.get::<_, Option<String>>(0), row.get::<_, Option<String>>(1)))
+        .unwrap();
+
+    assert_eq!(raw, (receipt.raw_request.clone(), receipt.raw_response.clone()));
+    assert!(load_recent_from(&db, 1)[0].bets[0].placed);
+
+    // The other leg isn't placed.
+    let raw = db.query_row("SELECT raw_request FROM bet WHERE id = 1001", &[],
+                           |row| row.get::<_, Option<String>>(0))
+        .unwrap();
+
+    assert_eq!(raw, None);
+
+    // Failed placements are stored too.
+    save_raw_bet_in(&db, "bookie1.com", 1001, None, "POST /bets", "500 Internal Error");
+
+    let raw = db.query_row("SELECT raw_request, raw_response FROM bet WHERE id = 1001", &[],
+                           |row| (row.get::<_, Option<String>>(0), row.get::<_, Option<String>>(1)))
+        .unwrap();
+
+    assert_eq!(raw, (Some("POST /bets".to_owned()), Some("500 Internal Error".to_owned())));
+    assert!(!load_recent_from(&db, 1)[0].bets[1].placed);
+}
+
+#[test]
+fn test_pending_receipt() {
+    let mut db = Connection::open_in_memory().unwrap();
+    init(&db);
+    save_to(&mut db, make_combo(100, 2));
+
+    let mut receipt = BetReceipt::new(Currency::from(1.), 2.);
+    receipt.id = Some("42".to_owned());
+    receipt.pending = true;
+
+    mark_as_placed_in(&db, "bookie0.com", 1000, Some("Team 0"), &receipt);
+
+    assert_eq!(load_receipt_ids_from(&db, "bookie0.com", 0, false), vec!["42".to_owned()]);
+    assert_eq!(load_receipt_ids_from(&db, "bookie0.com", 0, true), vec!["42".to_owned()]);
+
+    confirm_receipt_in(&db, "bookie0.com", "42");
+
+    assert_eq!(load_receipt_ids_from(&db, "bookie0.com", 0, false), vec!["42".to_owned()]);
+    assert!(load_receipt_ids_from(&db, "bookie0.com", 0, true).is_empty());
+
+    // Rejected bets are resolved once and for all.
+    mark_as_placed_in(&db, "bookie1.com", 1001, None, &receipt);
+    assert!(load_recent_from(&db, 1)[0].bets[1].placed);
+
+    reject_receipt_in(&db, "bookie1.com", "42");
+
+    assert!(load_receipt_ids_from(&db, "bookie1.com", 0, false).is_empty());
+    assert!(!load_recent_from(&db, 1)[0].bets[1].placed);
+    assert_eq!(load_receipt_ids_from(&db, "bookie0.com", 0, false), vec!["42".to_owned()]);
 }
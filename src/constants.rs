@@ -1,26 +1,290 @@
 use std::time::Duration;
 
 use base::currency::Currency;
+use markets::{Game, GameSelector, Kind, OddsStyle, SpuriousDraw, LiveMode};
+use arbitrer::{Strategy, Objective};
 
 // TODO(loyd): reconsider after `const fn` stabilization.
 lazy_static! {
     pub static ref MIN_RETRY_DELAY: Duration = Duration::new(30 * 60, 0);
     pub static ref MAX_RETRY_DELAY: Duration = Duration::new(3 * 60 * 60, 0);
     pub static ref CHECK_TIMEOUT: Duration = Duration::new(2, 0);
+    // In-play odds move fast, so combos on live markets are given less time for the checks.
+    pub static ref LIVE_CHECK_TIMEOUT: Duration = Duration::from_millis(1000);
+    // If the checks take longer, all offers are glanced once again before placing.
+    pub static ref MAX_CHECK_DURATION: Duration = Duration::from_millis(1500);
+    // Offers reported by the gambler earlier than that are rechecked before betting.
+    pub static ref STALENESS_THRESHOLD: Duration = Duration::from_millis(500);
+    // Offers are briefly absent while being updated, so glances are retried a few times
+    // (within a half of the check timeout) before the offer is considered rotten.
+    pub static ref GLANCE_RETRY_INTERVAL: Duration = Duration::from_millis(150);
+    // Offers of a disconnected bookie are kept for a while in case of a quick reconnection.
+    // Zero drops them immediately.
+    pub static ref DEGRADATION_GRACE: Duration = Duration::new(0, 0);
+    // Offers kept over the reconnection, but not reported again within that, are removed.
+    pub static ref RESYNC_WINDOW: Duration = Duration::new(30, 0);
+    // Bets accepted asynchronously are polled until confirmed or rejected (see `Features`).
+    pub static ref BET_STATUS_INTERVAL: Duration = Duration::from_millis(500);
+    pub static ref BET_STATUS_TIMEOUT: Duration = Duration::new(15, 0);
 
     pub static ref BASE_STAKE: Currency = Currency::from(1.00);
     pub static ref MAX_STAKE: Currency = Currency::from(5.00);
+    // Markets involving a bookie with the balance below it aren't even evaluated.
+    pub static ref MIN_BOOKIE_BALANCE: Currency = Currency::from(1.00);
+    // The least profit (in addition to `MIN_PROFIT`) of the sized combo whichever leg wins.
+    // It doesn't scale with stakes, so it's disabled by default.
+    pub static ref MIN_ABSOLUTE_PROFIT: Currency = Currency(0);
+    // Combos with the total stake above it are placed only if confirmed by the next evaluation.
+    pub static ref LARGE_COMBO_STAKE: Currency = Currency::from(8.00);
+    // A balance not above it right after authorization is likely misreported by a broken session.
+    pub static ref SUSPICIOUS_BALANCE: Currency = Currency::from(0.00);
 }
 
+pub const GLANCE_RETRIES: u32 = 2;
+
+// Timeouts (read, write) in seconds of requests made with the fast and long profiles.
+pub const FAST_TIMEOUTS: (u64, u64) = (4, 2);
+pub const LONG_TIMEOUTS: (u64, u64) = (60, 10);
+// Cookies (name, value, domain) sent from the very first request of the session with the host,
+// e.g. a consent or a region one.
+pub const SEED_COOKIES: &[(&str, &[(&str, &str, &str)])] = &[];
+// Headers (name, value) added to every request of the session with the host (e.g. `Referer`).
+pub const HEADERS: &[(&str, &[(&str, &str)])] = &[];
+// Default headers which aren't sent to the host, some bookies reject even them.
+pub const REMOVED_HEADERS: &[(&str, &[&str])] = &[];
+
+// Responses with a larger body (in bytes) are rejected instead of being buffered.
+pub const MAX_BODY_SIZE: u64 = 32 * 1024 * 1024;
+// Bodies are cut to this length (in bytes) when written to logs and traces.
+pub const MAX_LOGGED_BODY: usize = 64 * 1024;
+
 pub const HISTORY_SIZE: u32 = 20;
+// Append warnings and errors to `LOG_FILE` as well, which is rotated once it exceeds
+// `LOG_FILE_SIZE` bytes. Only `LOG_FILE_COUNT` rotated files (".1" is the newest) are kept.
+pub const PERSIST_HISTORY: bool = true;
+pub const LOG_FILE: &str = "logs/aladdin.log";
+pub const LOG_FILE_SIZE: u64 = 10 * 1024 * 1024;
+pub const LOG_FILE_COUNT: usize = 5;
+// Authorize once again instead of trusting a suspicious balance.
+pub const REAUTH_ON_SUSPICIOUS_BALANCE: bool = true;
+// Alert if the number of offers by a running bookie falls by more than `OFFER_DROP_FRACTION`
+// of its peak (at least `OFFER_DROP_MIN_PEAK`) within `OFFER_DROP_WINDOW` seconds.
+pub const ALERT_ON_OFFER_DROP: bool = true;
+pub const OFFER_DROP_WINDOW: u32 = 5 * 60;
+pub const OFFER_DROP_FRACTION: f64 = 0.5;
+pub const OFFER_DROP_MIN_PEAK: usize = 20;
+// Alerts are also posted as `{"text": "..."}` to this HTTPS endpoint (e.g. a Slack webhook).
+pub const ALERT_WEBHOOK: Option<&str> = None;
+// The bookie is aborted after so many failed authorizations in a row (until it's restarted).
+pub const MAX_CONSECUTIVE_FAILURES: usize = 5;
+// Bookies authorize in a wave of at most so many at once (all together if `None`), so that
+// the burst at startup doesn't trip rate limits shared by bookies behind the same anti-bot.
+pub const MAX_CONCURRENT_AUTHORIZATIONS: Option<u32> = None;
+// Stakes on a bookie are cut to `SLOW_START_FRACTION` of the base stake for its first
+// `SLOW_START_COMBOS` combos and `SLOW_START_DURATION` seconds after it's started or returned
+// from an outage of at least `SLOW_START_OUTAGE` seconds, to confirm that placement works.
+// Zeros disable it.
+pub const SLOW_START_COMBOS: usize = 0;
+pub const SLOW_START_DURATION: u32 = 0;
+pub const SLOW_START_OUTAGE: u32 = 60 * 60;
+pub const SLOW_START_FRACTION: f64 = 0.2;
+pub const BALANCE_AUDIT_SIZE: usize = 10;
 pub const TABLE_CAPACITY: usize = 5000;
 
+// Combos placed simultaneously, `None` means unlimited.
+pub const MAX_OPEN_COMBOS: Option<u32> = None;
+// Threads placing legs of combos. All legs of a combo are placed at once, so the combo is
+// skipped if there are not enough idle threads.
+pub const PLACEMENT_THREADS: usize = 6;
+
+// Pursue only markets with the effective margin below `1 - MARGIN_BUFFER`.
+pub const MARGIN_BUFFER: f64 = 0.;
+
+// Coefs above it are considered to be feed errors and clamped before calculating the margin.
+pub const MAX_COEF: f64 = 1000.;
+// Opportunities requiring a leg with a lower coef are skipped: backing heavy favorites ties up
+// the stake for a tiny return. Unlike `MAX_COEF`, it's a preference, not a sanity check.
+// Disabled by default.
+pub const MIN_LEG_COEF: f64 = 1.;
+// Offers are matched within adjacent 30-minute buckets, but markets whose offers' dates differ
+// by more seconds are skipped: the fixture has likely been rescheduled by some bookies.
+pub const MAX_DATE_DELTA: u32 = 20 * 60;
+// Markets are bet on only after their offers have kept matching for so many seconds, so
+// a borderline similarity of titles matching for a moment isn't enough.
+pub const MIN_MATCH_AGE: u32 = 0;
+
+// Evaluate offers mispriced by a single bookie on their own, even if other bookies don't list them.
+pub const WITHIN_BOOK_ARBS: bool = false;
+// Never choose selections which can only be laid (on exchanges) as legs.
+pub const REQUIRE_BACKABLE_LEGS: bool = true;
+// Instead, treat such offers as stale or erroneous quotes and drop them. Takes precedence over
+// `WITHIN_BOOK_ARBS`.
+pub const DROP_NEGATIVE_OVERROUND: bool = false;
+
+// Reuse collations of unchanged offers while evaluating a market again.
+pub const INCREMENTAL_COLLATION: bool = false;
+
+// Coefs equal up to this number of decimal digits are considered to be the same.
+pub const COEF_PRECISION: i32 = 3;
+
+// Log margin-free probabilities of each evaluated market to spot outlier bookies.
+pub const LOG_FAIR_ODDS: bool = false;
+
+// Decimal digits of displayed profits, which are always rounded toward zero.
+pub const PROFIT_DECIMALS: usize = 1;
+
+// Initial values of the profit range, it and the stakes can be changed by `POST /config`.
 pub const MIN_PROFIT: f64 = 0.02;
 pub const MAX_PROFIT: f64 = 0.20;
+// The same for markets with in-play offers.
+pub const LIVE_MIN_PROFIT: f64 = 0.03;
+pub const LIVE_MAX_PROFIT: f64 = 0.15;
+
+// Size stakes to give the same absolute profit whichever leg wins instead of by the rates.
+pub const EQUAL_PROFIT_STAKES: bool = false;
+// Rounding of such stakes may raise them above the base by at most this fraction.
+pub const MAX_STAKE_INFLATION: f64 = 0.1;
+
+// Use the smallest set of bookies whose margin is at most `FEWER_BOOKIES_TOLERANCE` worse.
+pub const PREFER_FEWER_BOOKIES: bool = false;
+pub const FEWER_BOOKIES_TOLERANCE: f64 = 0.;
+
+// Strategies to size legs by. If there are several of them, the one with the highest least
+// profit is used, and `STRATEGY_OBJECTIVE` decides between profits within the tolerance
+// (`PREFER_FEWER_BOOKIES` isn't applied then).
+pub const STRATEGIES: &[Strategy] = &[Strategy::Unbiased];
+pub const STRATEGY_OBJECTIVE: Objective = Objective::MinStake;
+pub const STRATEGY_TIE_TOLERANCE: f64 = 0.001;
+
+// Exclude bookies failed to check an offer or to place a bet within `FAILURE_COOLDOWN` seconds.
+pub const SKIP_FAILED_BOOKIES: bool = false;
+pub const FAILURE_COOLDOWN: u32 = 10 * 60;
+
+// Place one leg of a combo using free-bet funds if the bookie has enough of them.
+pub const USE_FREE_BETS: bool = false;
+
+// Offers with other number of outcomes (e.g. outrights) are dropped on intake.
+pub const ALLOWED_OUTCOME_COUNTS: &[usize] = &[2, 3];
+
+// Titles (case-insensitive) known to be the same team, mapped to the canonical one before matching.
+// `TITLE_ALIASES` are added to the defaults and override them.
+pub const USE_TITLE_ALIASES: bool = false;
+pub const DEFAULT_TITLE_ALIASES: &[(&str, &str)] = &[
+    ("Man Utd", "Manchester United"), ("Man United", "Manchester United"),
+    ("Man City", "Manchester City"), ("Spurs", "Tottenham Hotspur"),
+    ("PSG", "Paris Saint-Germain"), ("Inter", "Internazionale"),
+    ("NaVi", "Natus Vincere"), ("Na`Vi", "Natus Vincere"), ("Na'Vi", "Natus Vincere"),
+    ("VP", "Virtus.pro"), ("NiP", "Ninjas in Pyjamas"), ("EG", "Evil Geniuses")
+];
+pub const TITLE_ALIASES: &[(&str, &str)] = &[];
+
+// Offers with known leagues (tournaments) are matched only if the leagues are similar enough,
+// which prevents matching reserves or youth teams with the main ones.
+pub const REQUIRE_LEAGUE_MATCH: bool = false;
+pub const MIN_LEAGUE_SIMILARITY: f64 = 0.7;
+// Series of different formats (e.g. best of 3 and best of 5) are different markets. Offers
+// without the known format are matched with any other.
+pub const DISTINGUISH_SERIES_FORMATS: bool = true;
+
+// Games where a two-way offer may be matched with a three-way one (the draw is simply missing).
+pub const DRAW_OPTIONAL_GAMES: &[GameSelector] = &[
+    GameSelector::Game(Game::CounterStrike), GameSelector::Game(Game::Dota2),
+    GameSelector::Game(Game::LeagueOfLegends), GameSelector::Game(Game::HeroesOfTheStorm),
+    GameSelector::Game(Game::Overwatch), GameSelector::Game(Game::Smite),
+    GameSelector::Game(Game::StarCraft2), GameSelector::Game(Game::StarCraftBW),
+    GameSelector::Game(Game::Hearthstone)
+];
+
+// Games without draws at all, so a draw outcome of their offers is a parsing error.
+// eSports aren't here: bo2 series and some maps can end in a draw, see `DRAW_OPTIONAL_GAMES`.
+pub const NO_DRAW_GAMES: &[GameSelector] = &[
+    GameSelector::Game(Game::Tennis), GameSelector::Game(Game::TableTennis),
+    GameSelector::Game(Game::Badminton), GameSelector::Game(Game::Volleyball)
+];
+pub const SPURIOUS_DRAW: SpuriousDraw = SpuriousDraw::DropOffer;
+
+// Whether in-play offers, pre-match ones or both are taken. The first matching selector wins.
+pub const LIVE_MODES: &[(GameSelector, LiveMode)] = &[];
+pub const DEFAULT_LIVE_MODE: LiveMode = LiveMode::PreMatchOnly;
+// Offers for events starting later (in seconds) are dropped on intake: they rarely make arbs.
+pub const MAX_TIME_TO_EVENT: u32 = 14 * 24 * 60 * 60;
+
+// Also skip markets if a combo was placed on the same fixture by teams and date, not only by ids.
+pub const FUZZY_DEDUPLICATION: bool = true;
+
+// Sports polled by bookies without a feed of all sports. Ids without events are reported.
+pub const SPORTS_IDS: &[(&str, &[u32])] = &[
+    ("1xsporta", &[1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 14, 15, 16, 17, 18, 19, 21, 22, 23, 24, 26,
+                   27, 28, 30, 31, 32, 36, 38, 40, 41, 46, 49, 56, 66, 67, 80]),
+    ("betclub", &[1, 2, 3, 4, 5, 6, 8, 9, 12, 15, 16, 257, 279, 296, 300])
+];
+
+// Markets of bookies (by their own identifiers) which are converted to offers of the kind.
+// Other markets are skipped.
+pub const MARKET_KINDS: &[(&str, &[(&str, Kind)])] = &[
+    ("betway", &[("to-win", Kind::Series), ("win-draw-win", Kind::Series)]),
+    ("ebettle", &[("Match Odds", Kind::Series), ("Match Winner", Kind::Series),
+                  ("Match Odds (3 Way)", Kind::Series), ("Series Winner", Kind::Series)])
+];
+
+// Bookies quoting odds other than decimal ones (e.g. probabilities), they're converted on intake.
+pub const ODDS_STYLES: &[(&str, OddsStyle)] = &[];
+
+// Opportunities are still logged, but no bets are placed during these local time windows.
+pub const BLACKOUT_WINDOWS: &[(&str, &str)] = &[];
+
+// Bookies with higher priority win ties between equal coefs, then the balance decides.
+pub const BOOKIE_PRIORITIES: &[(&str, i32)] = &[];
+
+// Probabilities of bets to be voided by bookies (e.g. by palpable error rules), which reduce
+// the expected profit of their legs.
+pub const VOID_RISKS: &[(&str, f64)] = &[];
+
+// Amounts (in dollars) kept on bookies to cover withdrawals and fees, they're never staked.
+pub const BALANCE_RESERVES: &[(&str, f64)] = &[];
+
+// How often placed bets are compared with the ones that bookies report as open.
+pub const RECONCILIATION_PERIOD: u32 = 30 * 60;
+
+// No bets are placed while the local clock differs from bookies' ones by more (in seconds),
+// because expiries and staleness can't be trusted then. Checked with the period too.
+pub const CHECK_CLOCK_SKEW: bool = true;
+pub const MAX_CLOCK_SKEW: u32 = 60;
+pub const CLOCK_CHECK_PERIOD: u32 = 5 * 60;
+// Alert (while reconciling) if the bookie offers to cash out an open bet for more than
+// this fraction of its payout.
+pub const CHECK_CASHOUTS: bool = true;
+pub const CASHOUT_ALERT_FRACTION: f64 = 0.95;
+
+// Dump the HTTP traffic of every placement (all legs and steps) to `TRACE_DIR`.
+pub const TRACE_PLACEMENTS: bool = false;
+// Store requests and responses of placing bets (both placed and failed) in the database alongside
+// the bets. Credentials and tokens are redacted, but the rest of the traffic is stored as is.
+pub const PERSIST_RAW_BETS: bool = false;
+pub const TRACE_DIR: &str = "traces";
+
+// The same opportunity is reported at most once per this period (in seconds).
+pub const REPORT_COOLDOWN: u32 = 5 * 60;
+// Large combos must be confirmed within this period (in seconds).
+pub const CONFIRMATION_WINDOW: u32 = 30;
 
 pub const DATABASE: &str = "aladdin.db";
 
+// Use "127.0.0.1" to serve the status page only locally.
+pub const BIND_ADDRESS: &str = "0.0.0.0";
 pub const PORT: u16 = 3042;
+// Routes changing the state (and the backup) require the `X-Admin-Token` header with the token.
+// Without the token configured they're allowed only to local clients.
+pub const ADMIN_TOKEN: Option<&str> = None;
+// Clients of the live updates (each occupies a thread of the server) and their queue size.
+pub const EVENT_SUBSCRIBERS: usize = 4;
+pub const EVENT_QUEUE_SIZE: usize = 64;
+// Publish all upserts and removals of offers as newline-delimited JSON, either to
+// `tcp://host:port` or to `unix:///path/to/socket`. Messages beyond the queue are dropped.
+pub const OFFER_SINK: Option<&str> = None;
+pub const OFFER_SINK_QUEUE: usize = 4096;
 pub const COMBO_COUNT: u32 = 32;
+// Only the most imminent markets of each game are shown on the status page.
+pub const MARKETS_PER_GAME: usize = 50;
 
 pub const ACCOUNTS: &[(&str, &str, &str)] = &include!("../accounts");
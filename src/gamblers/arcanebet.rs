@@ -0,0 +1,357 @@
+use std::collections::HashMap;
+use hyper::status::StatusCode;
+use parking_lot::RwLock;
+use time;
+
+use base::currency::Currency;
+use base::timers::Periodic;
+use base::error::{Result, Error};
+use base::error::ErrorKind::Status;
+use base::session::{Session, Type, Profile};
+use gamblers::{Gambler, Features, Message, BetReceipt, Placement};
+use gamblers::Message::*;
+use markets::{OID, Offer, Outcome, Game, Kind, DRAW};
+
+const PERIOD: u32 = 20;
+
+// The API uses OAuth2 client credentials: the username is the client id, the password is secret.
+pub struct ArcaneBet {
+    session: Session,
+    credentials: RwLock<Option<(String, String)>>,
+    token: RwLock<Option<String>>
+}
+
+impl ArcaneBet {
+    pub fn new() -> ArcaneBet {
+        ArcaneBet {
+            session: Session::new("api.arcanebet.com"),
+            credentials: RwLock::new(None),
+            token: RwLock::new(None)
+        }
+    }
+
+    fn obtain_token(&self) -> Result<String> {
+        let (client_id, client_secret) = try!(self.credentials.read().clone()
+            .ok_or("Not authorized"));
+
+        let response: TokenResponse = try!(self.session.request("/oauth/token")
+            .content_type(Type::Form)
+            .post(vec![
+                ("grant_type", "client_credentials"),
+                ("client_id", client_id.as_str()),
+                ("client_secret", client_secret.as_str())
+            ]));
+
+        if response.token_type.to_lowercase() != "bearer" {
+            return Err(Error::from(format!("Unsupported token type: {}", response.token_type)));
+        }
+
+        debug!("The token is obtained, expires in {}s", response.expires_in);
+
+        Ok(response.access_token)
+    }
+
+    // Performs the request with the current token, which is refreshed once if it's rejected.
+    fn authorized<T, F>(&self, request: F) -> Result<T>
+        where F: Fn(&str) -> Result<T>
+    {
+        with_token(&self.token, &|| self.obtain_token(), &request)
+    }
+
+    fn get_events(&self) -> Result<Vec<Event>> {
+        self.authorized(|token| {
+            let auth = format!("Bearer {}", token);
+            self.session.request("/v1/events?status=upcoming")
+                .headers(&[("Authorization", &*auth)])
+                .get()
+        })
+    }
+}
+
+impl Gambler for ArcaneBet {
+    fn features(&self) -> Features {
+        Features {
+            bet_status: true,
+            ..Features::default()
+        }
+    }
+
+    fn authorize(&self, username: &str, password: &str) -> Result<()> {
+        *self.credentials.write() = Some((username.to_owned(), password.to_owned()));
+
+        let token = try!(self.obtain_token());
+        *self.token.write() = Some(token);
+
+        Ok(())
+    }
+
+    fn check_balance(&self) -> Result<Currency> {
+        let balance: Balance = try!(self.authorized(|token| {
+            let auth = format!("Bearer {}", token);
+            self.session.request("/v1/account/balance")
+                .profile(Profile::Fast)
+                .headers(&[("Authorization", &*auth)])
+                .get()
+        }));
+
+        Ok(Currency::from(balance.available))
+    }
+
+    fn watch(&self, cb: &Fn(Message) -> Result<()>) -> Result<()> {
+        let mut table = HashMap::new();
+
+        for _ in Periodic::new(PERIOD) {
+            let events = try!(self.get_events());
+            let mut actual = HashMap::new();
+
+            for event in events {
+                if let Some(offer) = convert_event(event) {
+                    actual.insert(offer.oid, offer);
+                }
+            }
+
+            for oid in table.keys() {
+                if !actual.contains_key(oid) {
+                    try!(cb(Remove(*oid)));
+                }
+            }
+
+            for (oid, offer) in &actual {
+                if table.get(oid) != Some(offer) {
+                    try!(cb(Upsert(offer.clone())));
+                }
+            }
+
+            table = actual;
+        }
+
+        Ok(())
+    }
+
+    fn place_bet(&self, offer: Offer, outcome: Outcome, stake: Currency) -> Result<BetReceipt> {
+        let request = PlaceBetRequest {
+            event_id: offer.oid,
+            selection: if outcome.0 == DRAW { "draw".to_owned() } else { outcome.0.clone() },
+            odds: outcome.1,
+            stake: stake.into()
+        };
+
+        let response: PlaceBetResponse = try!(self.authorized(|token| {
+            let auth = format!("Bearer {}", token);
+            self.session.request("/v1/bets")
+                .headers(&[("Authorization", &*auth)])
+                .post(&request)
+        }));
+
+        make_receipt(response)
+    }
+
+    fn bet_status(&self, bet_id: &str) -> Result<Placement> {
+        let path = format!("/v1/bets/{}", bet_id);
+
+        let bet: BetState = try!(self.authorized(|token| {
+            let auth = format!("Bearer {}", token);
+            self.session.request(&path)
+                .profile(Profile::Fast)
+                .headers(&[("Authorization", &*auth)])
+                .get()
+        }));
+
+        Ok(convert_bet_state(bet))
+    }
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    token_type: String,
+    expires_in: u32
+}
+
+#[derive(Deserialize)]
+struct Balance {
+    available: f64
+}
+
+#[derive(Deserialize)]
+struct Event {
+    id: OID,
+    start: u32,
+    sport: String,
+    competitors: Vec<Competitor>,
+    draw: Option<f64>
+}
+
+#[derive(Deserialize)]
+struct Competitor {
+    name: String,
+    odds: f64
+}
+
+#[derive(Serialize)]
+struct PlaceBetRequest {
+    event_id: OID,
+    selection: String,
+    odds: f64,
+    stake: f64
+}
+
+#[derive(Deserialize)]
+struct PlaceBetResponse {
+    id: Option<String>,
+    status: String,
+    stake: Option<f64>,
+    odds: Option<f64>,
+    reason: Option<String>
+}
+
+#[derive(Deserialize)]
+struct BetState {
+    status: String,
+    reason: Option<String>
+}
+
+fn with_token<T>(token: &RwLock<Option<String>>, refresh: &Fn() -> Result<String>,
+                 request: &Fn(&str) -> Result<T>) -> Result<T>
+{
+    let current = try!(token.read().clone().ok_or("Not authorized"));
+
+    match request(&current) {
+        Err(Error { kind: Status(StatusCode::Unauthorized), .. }) => {
+            info!("The token is rejected, refreshing...");
+
+            let fresh = try!(refresh());
+            *token.write() = Some(fresh.clone());
+
+            request(&fresh)
+        },
+        result => result
+    }
+}
+
+fn convert_event(event: Event) -> Option<Offer> {
+    let game = match event.sport.as_str() {
+        "csgo" => Game::CounterStrike,
+        "dota2" => Game::Dota2,
+        "lol" => Game::LeagueOfLegends,
+        "overwatch" => Game::Overwatch,
+        "hearthstone" => Game::Hearthstone,
+        "sc2" => Game::StarCraft2,
+        "football" => Game::Football,
+        "tennis" => Game::Tennis,
+        "basketball" => Game::Basketball,
+        "ice-hockey" => Game::IceHockey,
+        sport => {
+            warn!("Unknown sport: {}", sport);
+            return None;
+        }
+    };
+
+    if event.competitors.len() != 2 || event.start <= time::get_time().sec as u32 {
+        return None;
+    }
+
+    let mut outcomes = event.competitors.into_iter()
+        .map(|c| Outcome::new(&c.name, c.odds))
+        .collect::<Vec<_>>();
+
+    if let Some(draw) = event.draw {
+        outcomes.push(Outcome::new(DRAW, draw));
+    }
+
+    Some(Offer {
+        oid: event.id,
+        date: event.start,
+        game: game,
+        kind: Kind::Series,
+        outcomes: outcomes,
+        league: None,
+        is_live: false,
+        best_of: None
+    })
+}
+
+// Bets are usually "pending" right after placing, they're confirmed by polling `bet_status`.
+fn make_receipt(response: PlaceBetResponse) -> Result<BetReceipt> {
+    if response.status != "accepted" && response.status != "pending" {
+        let reason = response.reason.unwrap_or(response.status);
+        return Err(Error::from(format!("Bet is rejected: {}", reason)));
+    }
+
+    let stake = try!(response.stake.ok_or("There is no stake in the response"));
+    let odds = try!(response.odds.ok_or("There are no odds in the response"));
+
+    let mut receipt = BetReceipt::new(Currency::from(stake), odds);
+    receipt.id = response.id;
+
+    Ok(receipt)
+}
+
+fn convert_bet_state(bet: BetState) -> Placement {
+    match bet.status.as_str() {
+        "accepted" => Placement::Confirmed,
+        "pending" => Placement::Pending,
+        _ => Placement::Rejected(bet.reason.unwrap_or(bet.status))
+    }
+}
+
+#[test]
+fn test_refresh_on_unauthorized() {
+    use std::cell::Cell;
+
+    let token = RwLock::new(Some("stale".to_owned()));
+    let refreshes = Cell::new(0);
+    let requests = Cell::new(0);
+
+    let refresh = || {
+        refreshes.set(refreshes.get() + 1);
+        Ok("fresh".to_owned())
+    };
+
+    let request = |token: &str| {
+        requests.set(requests.get() + 1);
+
+        match token {
+            "fresh" => Ok(42),
+            _ => Err(Error::from(StatusCode::Unauthorized))
+        }
+    };
+
+    assert_eq!(with_token(&token, &refresh, &request).unwrap(), 42);
+    assert_eq!((refreshes.get(), requests.get()), (1, 2));
+    assert_eq!(*token.read(), Some("fresh".to_owned()));
+
+    // The refreshed token is reused.
+    assert_eq!(with_token(&token, &refresh, &request).unwrap(), 42);
+    assert_eq!((refreshes.get(), requests.get()), (1, 3));
+}
+
+#[test]
+fn test_make_receipt() {
+    let response = PlaceBetResponse {
+        id: Some("b-17".to_owned()),
+        status: "accepted".to_owned(),
+        stake: Some(1.5),
+        odds: Some(2.05),
+        reason: None
+    };
+
+    let receipt = make_receipt(response).unwrap();
+
+    assert_eq!(receipt.id, Some("b-17".to_owned()));
+    assert_eq!(receipt.stake, Currency(150));
+    assert_eq!(receipt.coef, 2.05);
+}
+
+#[test]
+fn test_convert_bet_state() {
+    let state = |status: &str, reason: Option<&str>| BetState {
+        status: status.to_owned(),
+        reason: reason.map(|r| r.to_owned())
+    };
+
+    assert_eq!(convert_bet_state(state("pending", None)), Placement::Pending);
+    assert_eq!(convert_bet_state(state("accepted", None)), Placement::Confirmed);
+    assert_eq!(convert_bet_state(state("rejected", Some("odds changed"))),
+               Placement::Rejected("odds changed".to_owned()));
+}
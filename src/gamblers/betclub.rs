@@ -4,17 +4,17 @@ use std::collections::{HashMap, HashSet};
 use parking_lot::Mutex;
 
 use base::error::{Result, Error};
-use base::session::Session;
+use base::session::{Session, Profile};
 use base::timers::Periodic;
 use base::currency::Currency;
-use gamblers::{Gambler, Message};
+use base::steps::step;
+use gamblers::{self, Gambler, Features, Message, BetReceipt};
 use gamblers::Message::*;
-use markets::{OID, Offer, Outcome, Game, Kind, DRAW};
-
-static SPORTS_IDS: &[u32] = &[1, 2, 3, 4, 5, 6, 8, 9, 12, 15, 16, 257, 279, 296, 300];
+use markets::{OID, Offer, Outcome, Role, Game, Kind, DRAW};
 
 pub struct BetClub {
     session: Session,
+    sports_ids: Vec<u32>,
     events: Mutex<HashMap<OID, Event>>
 }
 
@@ -22,6 +22,7 @@ impl BetClub {
     pub fn new() -> BetClub {
         BetClub {
             session: Session::new("betclub3.com"),
+            sports_ids: gamblers::sports_ids("betclub").to_vec(),
             // TODO(universome): store only necessary info about the events.
             events: Mutex::new(HashMap::new())
         }
@@ -40,9 +41,75 @@ impl BetClub {
 
         Ok(events)
     }
+
+    fn add_to_betslip(&self, basket: &Basket) -> Result<BetslipLine> {
+        let hand_size = match basket.fs { Some(v) => v.to_string(), _ => "null".to_string() };
+        let add_1 = match basket.a1 { Some(v) => v.to_string(), _ => "null".to_string() };
+        let add_2 = match basket.a2 { Some(v) => v.to_string(), _ => "null".to_string() };
+
+        let body = format!(r#"{{
+            "eId": {event_id},
+            "bId": {bet_id},
+            "r": {coef},
+            "fs": {hand_size},
+            "a1": {add_1},
+            "a2": {add_2},
+            "isLive": {is_live},
+            "culture":"en-us"
+        }}"#,
+            event_id = basket.eId,
+            bet_id = basket.bId,
+            hand_size = hand_size,
+            add_1 = add_1,
+            add_2 = add_2,
+            coef = basket.r,
+            is_live = basket.isLive
+        );
+
+        let path = "/WebServices/BRService.asmx/AddToBetslip";
+        let response: String = try!(self.session.request(path).post(body));
+
+        if !response.contains("LinesID") {
+            return Err(From::from(response));
+        }
+
+        Ok(BetslipLine {
+            status_key: format!("{}_{}_{}_{}_{}", basket.eId, basket.bId, hand_size, add_1, add_2)
+        })
+    }
+
+    fn place_betslip(&self, line: &BetslipLine, stake: Currency) -> Result<()> {
+        let stake: f64 = stake.into();
+
+        let body = format!(r#"{{
+            "betAmount": {stake},
+            "systemIndex": -1,
+            "statuses": {{"{status_key}": true}},
+            "doAcceptOddsChanges": false
+        }}"#,
+            stake = stake,
+            status_key = line.status_key
+        );
+
+        let path = "/WebServices/BRService.asmx/PlaceBet";
+        let response: String = try!(self.session.request(path).post(body));
+
+        if !response.contains("AmountIn") {
+            return Err(From::from(response));
+        }
+
+        Ok(())
+    }
 }
 
 impl Gambler for BetClub {
+    fn features(&self) -> Features {
+        Features {
+            check_offer: true,
+            ..Features::default()
+        }
+    }
+
     fn authorize(&self, username: &str, password: &str) -> Result<()> {
         let path = "/WebServices/BRService.asmx/LogIn";
         let request_data = AuthRequest {
@@ -59,18 +126,24 @@ impl Gambler for BetClub {
 
     fn check_balance(&self) -> Result<Currency> {
         let path = "/WebServices/BRService.asmx/GetUserBalance";
-        let balance: BalanceResponse = try!(self.session.request(path).post("".to_string()));
+        let request = self.session.request(path).profile(Profile::Fast);
+        let balance: BalanceResponse = try!(request.post("".to_string()));
 
         Ok(Currency::from(balance.d.Amount))
     }
 
-    fn watch(&self, cb: &Fn(Message)) -> Result<()> {
-        let mut active = SPORTS_IDS.iter().map(|_| HashSet::new()).collect::<Vec<_>>();
+    fn watch(&self, cb: &Fn(Message) -> Result<()>) -> Result<()> {
+        let mut active = self.sports_ids.iter().map(|_| HashSet::new()).collect::<Vec<_>>();
+        let mut first = true;
 
         for _ in Periodic::new(24) {
-            for (sport_id, active) in SPORTS_IDS.iter().zip(active.iter_mut()) {
+            for (sport_id, active) in self.sports_ids.iter().zip(active.iter_mut()) {
                 let recent = try!(self.fetch_events(*sport_id));
 
+                if first && recent.is_empty() {
+                    warn!("There are no events of the sport #{}, is the id correct?", sport_id);
+                }
+
                 let data = recent.into_iter()
                     .filter_map(|event| get_offer(&event).map(|offer| (offer, event)))
                     .collect::<Vec<_>>();
@@ -85,16 +158,18 @@ impl Gambler for BetClub {
                 // Now `active` contains inactive.
                 for oid in active.drain() {
                     events.remove(&oid);
-                    cb(Remove(oid));
+                    try!(cb(Remove(oid)));
                 }
 
                 // Add/update offers.
                 for (offer, event) in data {
                     active.insert(offer.oid);
                     events.insert(offer.oid, event);
-                    cb(Upsert(offer));
+                    try!(cb(Upsert(offer)));
                 }
             }
+
+            first = false;
         }
 
         Ok(())
@@ -117,7 +192,7 @@ impl Gambler for BetClub {
         }
     }
 
-    fn place_bet(&self, offer: Offer, outcome: Outcome, stake: Currency) -> Result<()> {
+    fn place_bet(&self, offer: Offer, outcome: Outcome, stake: Currency) -> Result<BetReceipt> {
         let events = self.events.lock();
 
         let event = try!(events.get(&offer.oid).ok_or("No such event"));
@@ -130,61 +205,21 @@ impl Gambler for BetClub {
                      else if x2 == 2 { &market.Rates[1].AddToBasket }
                      else { return Err(Error::from("Basket is not found")) };
 
-        // Add bet to betslip.
-        let body = format!(r#"{{
-            "eId": {event_id},
-            "bId": {bet_id},
-            "r": {coef},
-            "fs": {hand_size},
-            "a1": {add_1},
-            "a2": {add_2},
-            "isLive": {is_live},
-            "culture":"en-us"
-        }}"#,
-            event_id = basket.eId,
-            bet_id = basket.bId,
-            hand_size = match basket.fs { Some(v) => v.to_string(), _ => "null".to_string() },
-            add_1 = match basket.a1 { Some(v) => v.to_string(), _ => "null".to_string() },
-            add_2 = match basket.a2 { Some(v) => v.to_string(), _ => "null".to_string() },
-            coef = basket.r,
-            is_live = basket.isLive
-        );
+        let line = try!(step("add to betslip", || self.add_to_betslip(basket)));
 
-        let path = "/WebServices/BRService.asmx/AddToBetslip";
-        let response: String = try!(self.session.request(path).post(body));
+        let receipt = BetReceipt::new(stake, outcome.1);
 
-        if !response.contains("LinesID") {
-            return Err(From::from(response));
-        }
-
-        let stake: f64 = stake.into();
+        try!(step("place bet", || self.place_betslip(&line, stake)));
 
-        // Place bet
-        let body = format!(r#"{{
-            "betAmount": {stake},
-            "systemIndex": -1,
-            "statuses": {{"{event_id}_{bet_id}_{hand_size}_{add_1}_{add_2}": true}},
-            "doAcceptOddsChanges": false
-        }}"#,
-            stake = stake,
-            event_id = basket.eId,
-            bet_id = basket.bId,
-            hand_size = match basket.fs { Some(v) => v.to_string(), _ => "null".to_string() },
-            add_1 = match basket.a1 { Some(v) => v.to_string(), _ => "null".to_string() },
-            add_2 = match basket.a2 { Some(v) => v.to_string(), _ => "null".to_string() }
-        );
-
-        let path = "/WebServices/BRService.asmx/PlaceBet";
-        let response: String = try!(self.session.request(path).post(body));
-
-        if !response.contains("AmountIn") {
-            return Err(From::from(response));
-        }
-
-        Ok(())
+        Ok(receipt)
     }
 }
 
+// A line added to the betslip, which is required to place the bet.
+struct BetslipLine {
+    status_key: String
+}
+
 #[derive(Serialize)]
 struct AuthRequest<'a> {
     login: &'a str,
@@ -301,7 +336,10 @@ fn get_offer(event: &Event) -> Option<Offer> {
         outcomes: outcomes,
         game: game,
         kind: Kind::Series,
-        date: date
+        date: date,
+        league: Some(event.CountryName.clone()),
+        is_live: market.Rates[0].AddToBasket.isLive,
+        best_of: None
     })
 }
 
@@ -312,16 +350,17 @@ fn get_outcomes(event: &Event, market: &Market) -> Option<Vec<Outcome>> {
         return None;
     }
 
+    // `TeamsGroup` is ordered as home and away.
     let mut outcomes = vec![
-        Outcome(event.TeamsGroup[0].clone(), market.Rates[0].AddToBasket.r),
-        Outcome(event.TeamsGroup[1].clone(), market.Rates[x2].AddToBasket.r)
+        Outcome::new(&event.TeamsGroup[0], market.Rates[0].AddToBasket.r).role(Role::Home),
+        Outcome::new(&event.TeamsGroup[1], market.Rates[x2].AddToBasket.r).role(Role::Away)
     ];
 
     if x2 == 2 {
         let draw_odds = market.Rates[1].AddToBasket.r;
 
         if draw_odds > 1. {
-            outcomes.push(Outcome(DRAW.to_owned(), draw_odds));
+            outcomes.push(Outcome::new(DRAW, draw_odds).role(Role::Draw));
         }
     }
 
@@ -12,12 +12,12 @@ use time;
 use base::error::{Result, Error};
 use base::timers::Periodic;
 use base::parsing::{NodeRefExt, ElementDataExt};
-use base::session::Session;
+use base::session::{Session, Profile};
 use base::currency::Currency;
 use base::websocket::Connection as Connection;
-use gamblers::{Gambler, Message};
+use gamblers::{self, Gambler, Features, Message, BetReceipt, OpenBet, BetStatus};
 use gamblers::Message::*;
-use markets::{OID, Offer, Outcome, DRAW, Game, Kind};
+use markets::{OID, Offer, Outcome, Role, DRAW, Game};
 
 pub struct BetWay {
     session: Session,
@@ -93,7 +93,9 @@ impl BetWay {
         Ok(())
     }
 
-    fn try_place_bet(&self, offer: &Offer, outcome: &Outcome, stake: Currency) -> Result<PlaceBetResponse> {
+    fn try_place_bet(&self, offer: &Offer, outcome: &Outcome, stake: Currency,
+                     free: bool) -> Result<PlaceBetResponse>
+    {
         let state = self.state.lock();
 
         let event_id = try!(state.markets_to_events.get(&(offer.oid as u32)).ok_or("No such market"));
@@ -127,7 +129,7 @@ impl BetWay {
                     ],
                     stakePerLine: stake.0 as u32,
                     systemCname: "single",
-                    useFreeBet: false,
+                    useFreeBet: free,
                     eachWay: false
                 }
             ],
@@ -154,6 +156,16 @@ impl BetWay {
 }
 
 impl Gambler for BetWay {
+    fn features(&self) -> Features {
+        Features {
+            check_offer: true,
+            free_bets: true,
+            open_bets: true,
+            cashout: true,
+            bet_status: false
+        }
+    }
+
     fn authorize(&self, username: &str, password: &str) -> Result<()> {
         let main_page: String = try!(self.session.request("/").get());
 
@@ -178,7 +190,7 @@ impl Gambler for BetWay {
         Ok(Currency(customer_info.sbBalance))
     }
 
-    fn watch(&self, cb: &Fn(Message)) -> Result<()> {
+    fn watch(&self, cb: &Fn(Message) -> Result<()>) -> Result<()> {
         try!(self.set_user_state());
 
         let mut timer = Periodic::new(3600);
@@ -203,7 +215,7 @@ impl Gambler for BetWay {
                         .collect::<Vec<_>>();
 
                     for offer in offers {
-                        cb(Upsert(offer));
+                        try!(cb(Upsert(offer)));
                     }
 
                     let event_subscription = EventSubscription {
@@ -233,9 +245,9 @@ impl Gambler for BetWay {
                 if apply_update(&mut event, &update) {
                     for market in &event.markets {
                         if let Some(offer) = convert_market_to_offer(&market, &event) {
-                            cb(Upsert(offer));
+                            try!(cb(Upsert(offer)));
                         } else {
-                            cb(Remove(market.marketId as OID));
+                            try!(cb(Remove(market.marketId as OID)));
                         }
                     }
                 }
@@ -243,18 +255,14 @@ impl Gambler for BetWay {
         }
     }
 
-    fn place_bet(&self, offer: Offer, outcome: Outcome, stake: Currency) -> Result<()> {
-        let response = try!(self.try_place_bet(&offer, &outcome, stake));
-
-        if !response.success || response.error.is_some() {
-            return Err(Error::from(format!("Placing bet failed: {:?}", response)));
-        }
+    fn place_bet(&self, offer: Offer, outcome: Outcome, stake: Currency) -> Result<BetReceipt> {
+        let response = try!(self.try_place_bet(&offer, &outcome, stake, false));
 
-        Ok(())
+        make_receipt(response, stake, outcome.1)
     }
 
     fn check_offer(&self, offer: &Offer, outcome: &Outcome, stake: Currency) -> Result<bool> {
-        let response = try!(self.try_place_bet(&offer, &outcome, Currency(1)));
+        let response = try!(self.try_place_bet(&offer, &outcome, Currency(1), false));
 
         if response.error.is_none() {
             warn!("We have placed a bet in check_offer :| — {:?}", response);
@@ -275,6 +283,65 @@ impl Gambler for BetWay {
 
         Ok(stake >= Currency(error.details.as_ref().unwrap().get(0).unwrap().min as i64))
     }
+
+    // The minimum stake is found out only by probing the bet, that checks the odds as well.
+    fn check_stake(&self, offer: &Offer, outcome: &Outcome, stake: Currency) -> Result<bool> {
+        self.check_offer(offer, outcome, stake)
+    }
+
+    fn open_bets(&self) -> Result<Vec<OpenBet>> {
+        let (user_id, server_id) = {
+            let state = self.state.lock();
+            (state.user_id, state.server_id)
+        };
+
+        let body = BetHistoryRequest {
+            userId: user_id,
+            serverId: server_id,
+            lang: "en",
+            status: "open"
+        };
+
+        // The history is slow to be collected, especially with many bets.
+        let response: BetHistoryResponse = try!(self.session.request("/betapi/v4/getBets")
+            .profile(Profile::Long)
+            .post(body));
+
+        convert_bet_history(response)
+    }
+
+    fn cashout_value(&self, bet_id: &str) -> Result<Option<Currency>> {
+        let bet_id = try!(bet_id.parse().map_err(|_| format!("Invalid bet id: {}", bet_id)));
+
+        let (user_id, server_id) = {
+            let state = self.state.lock();
+            (state.user_id, state.server_id)
+        };
+
+        let body = CashOutRequest {
+            userId: user_id,
+            serverId: server_id,
+            lang: "en",
+            betIds: vec![bet_id]
+        };
+
+        let response: CashOutResponse = try!(self.session.request("/betapi/v4/getCashOutValues")
+            .post(body));
+
+        convert_cashout(response, bet_id)
+    }
+
+    fn check_free_balance(&self) -> Result<Currency> {
+        let customer_info = try!(self.get_customer_info());
+
+        Ok(Currency(customer_info.sbFreeBetBalance.unwrap_or(0)))
+    }
+
+    fn place_free_bet(&self, offer: Offer, outcome: Outcome, stake: Currency) -> Result<BetReceipt> {
+        let response = try!(self.try_place_bet(&offer, &outcome, stake, true));
+
+        make_receipt(response, stake, outcome.1)
+    }
 }
 
 struct State {
@@ -308,10 +375,70 @@ struct CustomerInfo {
 #[derive(Deserialize, Debug)]
 struct CustomerInfoResponse {
     sbBalance: i64,
+    sbFreeBetBalance: Option<i64>,
     userId: u32,
     serverId: u32
 }
 
+#[derive(Serialize, Debug)]
+struct BetHistoryRequest<'a> {
+    userId: u32,
+    serverId: u32,
+    lang: &'a str,
+    status: &'a str
+}
+
+#[derive(Serialize, Debug)]
+struct CashOutRequest<'a> {
+    userId: u32,
+    serverId: u32,
+    lang: &'a str,
+    betIds: Vec<u64>
+}
+
+#[derive(Deserialize, Debug)]
+struct CashOutResponse {
+    success: bool,
+    response: Option<CashOutValues>
+}
+
+#[derive(Deserialize, Debug)]
+struct CashOutValues {
+    cashOuts: Vec<CashOut>
+}
+
+#[derive(Deserialize, Debug)]
+struct CashOut {
+    betId: u64,
+    available: bool,
+    value: Option<i64>
+}
+
+#[derive(Deserialize, Debug)]
+struct BetHistoryResponse {
+    success: bool,
+    response: Option<BetHistory>
+}
+
+#[derive(Deserialize, Debug)]
+struct BetHistory {
+    bets: Vec<HistoryBet>
+}
+
+#[derive(Deserialize, Debug)]
+struct HistoryBet {
+    betId: u64,
+    stakePerLine: i64,
+    status: String,
+    selections: Vec<HistorySelection>
+}
+
+#[derive(Deserialize, Debug)]
+struct HistorySelection {
+    outcomeName: String,
+    priceDec: f64
+}
+
 #[derive(Serialize, Debug)]
 struct EventsRequestData<'a> {
     eventIds: &'a Vec<u32>,
@@ -479,10 +606,9 @@ fn convert_market_to_offer(market: &Market, event: &Event) -> Option<Offer> {
     let outcomes = get_outcomes(market);
     let ts = get_time(event);
     let game = get_game(event);
-    let kind = get_kind(event);
+    let kind = gamblers::market_kind("betway", &market.typeCname);
 
     if !market.active || !market.displayed
-    || !["to-win", "win-draw-win"].contains(&market.typeCname.as_str())
     || !event.active  || !event.displayed
     || outcomes.is_none() || ts.is_none() || game.is_none() || kind.is_none() {
         return None;
@@ -493,10 +619,17 @@ fn convert_market_to_offer(market: &Market, event: &Event) -> Option<Offer> {
         date: ts.unwrap(),
         game: game.unwrap(),
         kind: kind.unwrap(),
-        outcomes: outcomes.unwrap()
+        outcomes: outcomes.unwrap(),
+        league: get_league(event),
+        is_live: event.live,
+        best_of: None
     })
 }
 
+fn get_league(event: &Event) -> Option<String> {
+    event.keywords.iter().find(|kw| kw.typeCname == "group").map(|group| group.cname.clone())
+}
+
 fn get_game(event: &Event) -> Option<Game> {
     event.keywords.iter().find(|kw| kw.typeCname == "sport").and_then(|sport| {
         Some(match sport.cname.as_str() {
@@ -578,10 +711,6 @@ fn get_game(event: &Event) -> Option<Game> {
     })
 }
 
-fn get_kind(event: &Event) -> Option<Kind> {
-    Some(Kind::Series)
-}
-
 fn get_time(event: &Event) -> Option<u32> {
     match time::strptime(&event.startAt, "%Y-%m-%dT%H:%M:%SZ") {
         Err(err) => {
@@ -593,7 +722,11 @@ fn get_time(event: &Event) -> Option<u32> {
 }
 
 fn get_outcomes(market: &Market) -> Option<Vec<Outcome>> {
-    if market.outcomes.iter().any(|o| o.priceDec.is_none()) {
+    if market.outcomes.iter().any(|o| o.active && o.priceDec.is_none()) {
+        return None;
+    }
+
+    if !market.outcomes.iter().any(|o| o.active) {
         return None;
     }
 
@@ -603,10 +736,26 @@ fn get_outcomes(market: &Market) -> Option<Vec<Outcome>> {
             title => title
         };
 
-        Outcome(title.to_owned(), outcome.priceDec.unwrap())
+        // Suspended selections are kept, the arbitrer can back them by other bookies.
+        let coef = if outcome.active { outcome.priceDec.unwrap() } else { 0. };
+
+        match get_role(outcome) {
+            Some(role) => Outcome::new(title, coef).role(role),
+            None => Outcome::new(title, coef)
+        }
     }).collect())
 }
 
+fn get_role(outcome: &BetwayOutcome) -> Option<Role> {
+    match outcome.typeCname.as_str() {
+        "home" => Some(Role::Home),
+        "draw" => Some(Role::Draw),
+        "away" => Some(Role::Away),
+        "" => None,
+        _ => Some(Role::Other)
+    }
+}
+
 fn apply_update(event: &mut Event, update: &Update) -> bool {
     match update {
         &Update::EventUpdate(ref u) => apply_event_update(event, u),
@@ -751,7 +900,20 @@ struct PlaceBetRequest {
 #[derive(Deserialize, Debug)]
 struct PlaceBetResponse {
     success: bool,
-    error: Option<PlaceBetError>
+    error: Option<PlaceBetError>,
+    response: Option<PlaceBetResponseData>
+}
+
+#[derive(Deserialize, Debug)]
+struct PlaceBetResponseData {
+    bets: Vec<PlacedBet>
+}
+
+#[derive(Deserialize, Debug)]
+struct PlacedBet {
+    betId: u64,
+    stakePerLine: i64,
+    priceDec: Option<f64>
 }
 
 #[derive(Deserialize, Debug)]
@@ -765,3 +927,166 @@ struct PlaceBetErrorDetail {
     min: u32,
     max: u32
 }
+
+fn make_receipt(response: PlaceBetResponse, stake: Currency, coef: f64) -> Result<BetReceipt> {
+    if !response.success || response.error.is_some() {
+        return Err(Error::from(format!("Placing bet failed: {:?}", response)));
+    }
+
+    let mut receipt = BetReceipt::new(stake, coef);
+
+    if let Some(bet) = response.response.as_ref().and_then(|data| data.bets.get(0)) {
+        receipt.id = Some(bet.betId.to_string());
+        receipt.stake = Currency(bet.stakePerLine);
+        receipt.coef = bet.priceDec.unwrap_or(coef);
+    }
+
+    Ok(receipt)
+}
+
+fn convert_bet_history(response: BetHistoryResponse) -> Result<Vec<OpenBet>> {
+    if !response.success {
+        return Err(Error::from(format!("Fetching bets failed: {:?}", response)));
+    }
+
+    let bets = response.response.map_or_else(Vec::new, |history| history.bets);
+
+    Ok(bets.into_iter().filter_map(|bet| {
+        // Only singles are placed by us.
+        if bet.selections.len() != 1 {
+            return None;
+        }
+
+        let selection = &bet.selections[0];
+
+        Some(OpenBet {
+            id: bet.betId.to_string(),
+            selection: selection.outcomeName.trim_left_matches("[").trim_right_matches("]").to_owned(),
+            stake: Currency(bet.stakePerLine),
+            coef: selection.priceDec,
+            status: match bet.status.as_str() {
+                "open" => BetStatus::Open,
+                "won" => BetStatus::Won,
+                "lost" => BetStatus::Lost,
+                "void" | "cancelled" => BetStatus::Void,
+                _ => BetStatus::Unknown
+            }
+        })
+    }).collect())
+}
+
+fn convert_cashout(response: CashOutResponse, bet_id: u64) -> Result<Option<Currency>> {
+    if !response.success {
+        return Err(Error::from(format!("Fetching cash-out values failed: {:?}", response)));
+    }
+
+    let cashouts = response.response.map_or_else(Vec::new, |values| values.cashOuts);
+
+    Ok(cashouts.into_iter()
+        .find(|cashout| cashout.betId == bet_id && cashout.available)
+        .and_then(|cashout| cashout.value)
+        .map(Currency))
+}
+
+#[test]
+fn test_make_receipt() {
+    let response = json::from_str(r#"{
+        "success": true,
+        "response": {"bets": [{"betId": 9003214, "stakePerLine": 150, "priceDec": 1.83}]}
+    }"#);
+
+    let receipt = make_receipt(response.unwrap(), Currency(200), 1.9).unwrap();
+
+    assert_eq!(receipt.id, Some("9003214".to_owned()));
+    assert_eq!(receipt.stake, Currency(150));
+    assert_eq!(receipt.coef, 1.83);
+}
+
+#[test]
+fn test_convert_bet_history() {
+    let response = json::from_str(r#"{
+        "success": true,
+        "response": {"bets": [
+            {"betId": 77, "stakePerLine": 250, "status": "open",
+             "selections": [{"outcomeName": "[NaVi]", "priceDec": 2.1}]},
+            {"betId": 78, "stakePerLine": 100, "status": "open",
+             "selections": [{"outcomeName": "A", "priceDec": 1.5}, {"outcomeName": "B", "priceDec": 1.4}]}
+        ]}
+    }"#);
+
+    let bets = convert_bet_history(response.unwrap()).unwrap();
+
+    assert_eq!(bets, vec![OpenBet {
+        id: "77".to_owned(),
+        selection: "NaVi".to_owned(),
+        stake: Currency(250),
+        coef: 2.1,
+        status: BetStatus::Open
+    }]);
+}
+
+#[test]
+fn test_features() {
+    let features = BetWay::new().features();
+
+    assert!(features.check_offer);
+    assert!(features.free_bets);
+    assert!(features.open_bets);
+    assert!(features.cashout);
+}
+
+#[test]
+fn test_suspended_outcome() {
+    let outcome = |id, name: &str, active| BetwayOutcome {
+        outcomeId: id,
+        priceDec: Some(2.5),
+        priceNum: None,
+        priceDen: None,
+        name: name.to_owned(),
+        active: active,
+        typeCname: String::new()
+    };
+
+    let mut market = Market {
+        marketId: 1,
+        outcomes: vec![
+            outcome(1, "[NaVi]", true),
+            outcome(2, "Draw", false),
+            outcome(3, "VP", true)
+        ],
+        active: true,
+        cname: String::new(),
+        typeCname: "win-draw-win".to_owned(),
+        displayed: true
+    };
+
+    assert_eq!(get_outcomes(&market), Some(vec![
+        Outcome::new("NaVi", 2.5),
+        Outcome::new(DRAW, 0.),
+        Outcome::new("VP", 2.5)
+    ]));
+
+    for outcome in &mut market.outcomes {
+        outcome.active = false;
+    }
+
+    assert_eq!(get_outcomes(&market), None);
+}
+
+#[test]
+fn test_convert_cashout() {
+    let response = || json::from_str(r#"{
+        "success": true,
+        "response": {"cashOuts": [
+            {"betId": 77, "available": true, "value": 480},
+            {"betId": 78, "available": false, "value": null}
+        ]}
+    }"#).unwrap();
+
+    assert_eq!(convert_cashout(response(), 77).unwrap(), Some(Currency(480)));
+    assert_eq!(convert_cashout(response(), 78).unwrap(), None);
+    assert_eq!(convert_cashout(response(), 79).unwrap(), None);
+
+    let failed = json::from_str(r#"{"success": false}"#).unwrap();
+    assert!(convert_cashout(failed, 77).is_err());
+}
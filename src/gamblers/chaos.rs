@@ -0,0 +1,148 @@
+use std::thread;
+use std::sync::Arc;
+use std::time::Duration;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering::Relaxed;
+use std::collections::{HashMap, VecDeque};
+use parking_lot::Mutex;
+
+use base::error::{Result, Error};
+use base::currency::Currency;
+use gamblers::{Gambler, Features, Message, BetReceipt};
+use gamblers::Message::*;
+use markets::{Offer, Outcome};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Operation { Authorize, CheckBalance, Watch, CheckOffer, CheckStake, PlaceBet }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Succeed,
+    Fail,
+    // Succeed after the delay (in milliseconds).
+    Delay(u64)
+}
+
+// Actions are performed in the order they're pushed, then all operations succeed.
+pub struct Script {
+    offers: Mutex<Vec<Offer>>,
+    actions: Mutex<HashMap<Operation, VecDeque<Action>>>,
+    placed: AtomicUsize
+}
+
+impl Script {
+    pub fn new(offers: Vec<Offer>) -> Script {
+        Script {
+            offers: Mutex::new(offers),
+            actions: Mutex::new(HashMap::new()),
+            placed: AtomicUsize::new(0)
+        }
+    }
+
+    pub fn push(&self, operation: Operation, action: Action) {
+        self.actions.lock().entry(operation).or_insert_with(VecDeque::new).push_back(action);
+    }
+
+    // Offers sent by next watchings.
+    pub fn set_offers(&self, offers: Vec<Offer>) {
+        *self.offers.lock() = offers;
+    }
+
+    #[inline]
+    pub fn placed_count(&self) -> usize {
+        self.placed.load(Relaxed)
+    }
+
+    fn perform(&self, operation: Operation) -> Result<()> {
+        let action = self.actions.lock()
+            .get_mut(&operation)
+            .and_then(|actions| actions.pop_front())
+            .unwrap_or(Action::Succeed);
+
+        match action {
+            Action::Succeed => Ok(()),
+            Action::Fail => Err(Error::from(format!("Chaos at {:?}", operation))),
+            Action::Delay(ms) => {
+                thread::sleep(Duration::from_millis(ms));
+                Ok(())
+            }
+        }
+    }
+}
+
+// The bookie simulator to test resilience of the arbitrer. Watching sends all offers at once
+// and finishes as if the connection is closed.
+pub struct Chaos {
+    script: Arc<Script>
+}
+
+impl Chaos {
+    pub fn new() -> Chaos {
+        Chaos::with_script(Arc::new(Script::new(vec![])))
+    }
+
+    pub fn with_script(script: Arc<Script>) -> Chaos {
+        Chaos {
+            script: script
+        }
+    }
+}
+
+impl Gambler for Chaos {
+    fn features(&self) -> Features {
+        Features {
+            check_offer: true,
+            ..Features::default()
+        }
+    }
+
+    fn authorize(&self, _: &str, _: &str) -> Result<()> {
+        self.script.perform(Operation::Authorize)
+    }
+
+    fn check_balance(&self) -> Result<Currency> {
+        try!(self.script.perform(Operation::CheckBalance));
+        Ok(Currency::from(100.))
+    }
+
+    fn watch(&self, cb: &Fn(Message) -> Result<()>) -> Result<()> {
+        try!(self.script.perform(Operation::Watch));
+
+        let offers = self.script.offers.lock().clone();
+
+        for offer in offers {
+            try!(cb(Upsert(offer)));
+        }
+
+        Ok(())
+    }
+
+    fn check_offer(&self, _: &Offer, _: &Outcome, _: Currency) -> Result<bool> {
+        try!(self.script.perform(Operation::CheckOffer));
+        Ok(true)
+    }
+
+    fn check_stake(&self, _: &Offer, _: &Outcome, _: Currency) -> Result<bool> {
+        try!(self.script.perform(Operation::CheckStake));
+        Ok(true)
+    }
+
+    fn place_bet(&self, _: Offer, outcome: Outcome, stake: Currency) -> Result<BetReceipt> {
+        try!(self.script.perform(Operation::PlaceBet));
+        self.script.placed.fetch_add(1, Relaxed);
+        Ok(BetReceipt::new(stake, outcome.1))
+    }
+}
+
+#[test]
+fn test_script() {
+    let script = Script::new(vec![]);
+
+    script.push(Operation::CheckOffer, Action::Fail);
+    script.push(Operation::CheckOffer, Action::Delay(1));
+
+    assert!(script.perform(Operation::CheckOffer).is_err());
+    assert!(script.perform(Operation::CheckOffer).is_ok());
+    assert!(script.perform(Operation::CheckOffer).is_ok());
+    assert!(script.perform(Operation::PlaceBet).is_ok());
+}
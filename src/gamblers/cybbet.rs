@@ -9,9 +9,9 @@ use time;
 use base::error::{Result, Error};
 use base::timers::Periodic;
 use base::parsing::{NodeRefExt, ElementDataExt};
-use base::session::{Session, Type};
+use base::session::{Session, Type, Profile};
 use base::currency::Currency;
-use gamblers::{Gambler, Message};
+use gamblers::{Gambler, Features, Message, BetReceipt};
 use gamblers::Message::*;
 use markets::{Offer, Outcome, DRAW, Game, Kind};
 
@@ -66,6 +66,13 @@ impl CybBet {
 }
 
 impl Gambler for CybBet {
+    fn features(&self) -> Features {
+        Features {
+            check_offer: true,
+            ..Features::default()
+        }
+    }
+
     fn authorize(&self, username: &str, password: &str) -> Result<()> {
         self.session.request("/user/login")
             .content_type(Type::Form)
@@ -79,7 +86,7 @@ impl Gambler for CybBet {
 
     fn check_balance(&self) -> Result<Currency> {
         let path = "/account/usercash/UpdateBlockMainUserInfo";
-        let html: NodeRef = try!(self.session.request(path).get());
+        let html: NodeRef = try!(self.session.request(path).profile(Profile::Fast).get());
         let text = try!(html.query(r#"a[href="/account/usercash/cash"]"#)).text_contents();
         let on_invalid_cash = || format!("Invalid cash: \"{}\"", text);
         let cash_str = try!(text.split(' ').next().ok_or_else(on_invalid_cash));
@@ -88,7 +95,7 @@ impl Gambler for CybBet {
         Ok(Currency::from(cash))
     }
 
-    fn watch(&self, cb: &Fn(Message)) -> Result<()> {
+    fn watch(&self, cb: &Fn(Message) -> Result<()>) -> Result<()> {
         let html = try!(self.session.request("/").get::<NodeRef>());
         let offers = try!(extract_offers(html));
 
@@ -96,7 +103,7 @@ impl Gambler for CybBet {
 
         for offer in offers {
             table.insert(offer.oid as u32, offer.clone());
-            cb(Upsert(offer));
+            try!(cb(Upsert(offer)));
         }
 
         for _ in Periodic::new(PERIOD) {
@@ -123,7 +130,7 @@ impl Gambler for CybBet {
                         debug_assert_eq!(offer.outcomes.len(), 2);
                     }
 
-                    cb(Upsert(offer.clone()));
+                    try!(cb(Upsert(offer.clone())));
                 }
             }
 
@@ -133,7 +140,7 @@ impl Gambler for CybBet {
                     let id = try!(id.parse());
 
                     if let Some(offer) = table.remove(&id) {
-                        cb(Remove(offer.oid));
+                        try!(cb(Remove(offer.oid)));
                     }
                 }
             }
@@ -146,7 +153,7 @@ impl Gambler for CybBet {
                     if table.contains_key(&id) {
                         if table[&id].date != date {
                             table.get_mut(&id).map(|o| o.date = date);
-                            cb(Upsert(table[&id].clone()))
+                            try!(cb(Upsert(table[&id].clone())));
                         }
 
                         continue;
@@ -164,7 +171,7 @@ impl Gambler for CybBet {
 
                     if !offers.is_empty() {
                         let offer = offers.drain(..).next().unwrap();
-                        cb(Upsert(offer.clone()));
+                        try!(cb(Upsert(offer.clone())));
                         table.insert(id, offer);
                     }
                 }
@@ -174,11 +181,11 @@ impl Gambler for CybBet {
         Ok(())
     }
 
-    fn place_bet(&self, offer: Offer, outcome: Outcome, stake: Currency) -> Result<()> {
+    fn place_bet(&self, offer: Offer, outcome: Outcome, stake: Currency) -> Result<BetReceipt> {
         let response = try!(self.try_place_bet("/games/bet", &offer, &outcome, stake));
 
         if response.contains("messageSuccess") {
-            Ok(())
+            Ok(BetReceipt::new(stake, outcome.1))
         } else {
             Err(Error::from(response))
         }
@@ -274,12 +281,12 @@ fn extract_offers(html: NodeRef) -> Result<Vec<Offer>> {
         let coef_draw = try!(trn.query_all(".draw .price")).next().map(|s| s.text_contents());
 
         let mut outcomes = vec![
-            Outcome(team_1.to_owned(), try!(coef_1.trim().parse())),
-            Outcome(team_2.to_owned(), try!(coef_2.trim().parse()))
+            Outcome::new(&team_1, try!(coef_1.trim().parse())),
+            Outcome::new(&team_2, try!(coef_2.trim().parse()))
         ];
 
         if let Some(coef_draw) = coef_draw {
-            outcomes.push(Outcome(DRAW.to_owned(), try!(coef_draw.trim().parse())));
+            outcomes.push(Outcome::new(DRAW, try!(coef_draw.trim().parse())));
         }
 
         offers.push(Offer {
@@ -287,7 +294,10 @@ fn extract_offers(html: NodeRef) -> Result<Vec<Offer>> {
             date: try!(date.parse()),
             game: game,
             kind: Kind::Series,
-            outcomes: outcomes
+            outcomes: outcomes,
+            league: None,
+            is_live: false,
+            best_of: None
         })
     }
 
@@ -4,14 +4,14 @@ use std::sync::atomic::Ordering::Relaxed;
 use std::collections::{BinaryHeap, HashMap};
 use kuchiki::NodeRef;
 use parking_lot::Mutex;
-use time;
 
 use base::error::{Result, Error};
 use base::timers::Periodic;
 use base::parsing::{NodeRefExt, ElementDataExt};
-use base::session::{Session, Type};
+use base::session::{Session, Type, Profile};
 use base::currency::Currency;
-use gamblers::{Gambler, Message};
+use base::clock::{Clock, BoxedClock, RealClock};
+use gamblers::{Gambler, Features, Message, BetReceipt};
 use gamblers::Message::*;
 use markets::{OID, Offer, Outcome, DRAW, Game, Kind};
 
@@ -19,7 +19,8 @@ pub struct EGB {
     session: Session,
     csrf: Mutex<String>,
     user_time: AtomicUsize,
-    update_time: AtomicUsize
+    update_time: AtomicUsize,
+    clock: BoxedClock
 }
 
 impl EGB {
@@ -28,12 +29,20 @@ impl EGB {
             session: Session::new("egb.com"),
             csrf: Mutex::new(String::new()),
             user_time: AtomicUsize::new(0),
-            update_time: AtomicUsize::new(0)
+            update_time: AtomicUsize::new(0),
+            clock: Box::new(RealClock)
         }
     }
 }
 
 impl Gambler for EGB {
+    fn features(&self) -> Features {
+        Features {
+            check_offer: true,
+            ..Features::default()
+        }
+    }
+
     fn authorize(&self, username: &str, password: &str) -> Result<()> {
         let html: NodeRef = try!(self.session.request("/").get());
         let csrf = try!(extract_csrf(html));
@@ -59,18 +68,16 @@ impl Gambler for EGB {
     }
 
     fn check_balance(&self) -> Result<Currency> {
-        let balance = try!(self.session.request("/user/info?m=1&b=1").get::<Balance>());
+        let request = self.session.request("/user/info?m=1&b=1").profile(Profile::Fast);
+        let balance = try!(request.get::<Balance>());
         let money = try!(balance.bets.parse::<f64>());
 
         Ok(Currency::from(money))
     }
 
-    fn watch(&self, cb: &Fn(Message)) -> Result<()> {
-        #[derive(PartialEq, Eq, PartialOrd, Ord)]
-        struct TimeMarker(i32, OID);
-
+    fn watch(&self, cb: &Fn(Message) -> Result<()>) -> Result<()> {
         let mut map = HashMap::new();
-        let mut heap = BinaryHeap::new();
+        let mut expiry = Expiry::new();
 
         let table: Table = try!(self.session.request("/bets?st=0&ut=0&f=").get());
         let mut user_time = table.user_time;
@@ -81,10 +88,10 @@ impl Gambler for EGB {
                 let id = bet.id;
                 update_time = cmp::max(update_time, bet.ut);
 
-                if let Some(offer) = try!(extract_offer(bet)) {
+                if let Some(offer) = try!(extract_offer(bet, &*self.clock)) {
                     map.insert(id, offer.clone());
-                    heap.push(TimeMarker(-(offer.date as i32), id));
-                    cb(Upsert(offer))
+                    expiry.push(offer.date, id);
+                    try!(cb(Upsert(offer)));
                 }
             }
         }
@@ -105,7 +112,7 @@ impl Gambler for EGB {
                     let id = bet.id;
                     update_time = cmp::max(update_time, bet.ut);
 
-                    let offer = match try!(extract_offer(bet)) {
+                    let offer = match try!(extract_offer(bet, &*self.clock)) {
                         Some(offer) => offer,
                         None => continue
                     };
@@ -113,18 +120,18 @@ impl Gambler for EGB {
                     // Short case: a new offer.
                     if !map.contains_key(&id) {
                         map.insert(id, offer.clone());
-                        heap.push(TimeMarker(-(offer.date as i32), id));
-                        cb(Upsert(offer));
+                        expiry.push(offer.date, id);
+                        try!(cb(Upsert(offer)));
                         continue;
                     }
 
                     let stored = map.remove(&id).unwrap();
 
                     if stored.date != offer.date {
-                        heap.push(TimeMarker(-(offer.date as i32), id));
+                        expiry.push(offer.date, id);
                     }
 
-                    cb(Upsert(offer.clone()));
+                    try!(cb(Upsert(offer.clone())));
                     map.insert(id, offer);
                 }
             }
@@ -133,21 +140,11 @@ impl Gambler for EGB {
             self.update_time.store(update_time as usize, Relaxed);
 
             // Remove old offers.
-            let threshold = time::get_time().sec as u32 + period as u32;
-
-            while !heap.is_empty() {
-                let &TimeMarker(date, id) = heap.peek().unwrap();
-
-                if -date as u32 > threshold {
-                    break;
-                }
-
-                heap.pop();
-
+            for (date, id) in expiry.pop_expired(self.clock.now() + period as u32) {
                 // Remove offer only if the time marker corresponds to the last modification.
-                if map.get(&id).map_or(false, |o| o.date == -date as u32) {
+                if map.get(&id).map_or(false, |o: &Offer| o.date == date) {
                     let offer = map.remove(&id).unwrap();
-                    cb(Remove(offer.oid));
+                    try!(cb(Remove(offer.oid)));
                 }
             }
         }
@@ -155,7 +152,8 @@ impl Gambler for EGB {
         Ok(())
     }
 
-    fn place_bet(&self, offer: Offer, outcome: Outcome, stake: Currency) -> Result<()> {
+    fn place_bet(&self, offer: Offer, outcome: Outcome, stake: Currency) -> Result<BetReceipt> {
+        let receipt = BetReceipt::new(stake, outcome.1);
         let stake: f64 = stake.into();
         let idx = 1 + offer.outcomes.iter().position(|o| o == &outcome).unwrap();
 
@@ -175,7 +173,7 @@ impl Gambler for EGB {
         ]));
 
         if response.success {
-            Ok(())
+            Ok(receipt)
         } else {
             Err(Error::from(response.message))
         }
@@ -251,10 +249,41 @@ struct Gamer {
     nick: String
 }
 
-fn extract_offer(bet: Bet) -> Result<Option<Offer>> {
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+struct TimeMarker(i32, OID);
+
+// Markers of offers' dates ordered from the earliest one.
+struct Expiry(BinaryHeap<TimeMarker>);
+
+impl Expiry {
+    fn new() -> Expiry {
+        Expiry(BinaryHeap::new())
+    }
+
+    fn push(&mut self, date: u32, id: OID) {
+        self.0.push(TimeMarker(-(date as i32), id));
+    }
+
+    fn pop_expired(&mut self, threshold: u32) -> Vec<(u32, OID)> {
+        let mut expired = Vec::new();
+
+        while let Some(&TimeMarker(date, id)) = self.0.peek() {
+            if -date as u32 > threshold {
+                break;
+            }
+
+            self.0.pop();
+            expired.push((-date as u32, id));
+        }
+
+        expired
+    }
+}
+
+fn extract_offer(bet: Bet, clock: &Clock) -> Result<Option<Offer>> {
     let irrelevant = bet.winner > 0                            // Ended or cancelled.
                   || bet.live == 1                             // Exactly live.
-                  || time::get_time().sec as u32 >= bet.date   // Started.
+                  || clock.now() >= bet.date                   // Started.
                   || bet.gamer_1.nick.contains("(Live)")       // Live.
                   || bet.gamer_2.nick.contains("(Live)");
 
@@ -283,12 +312,12 @@ fn extract_offer(bet: Bet) -> Result<Option<Offer>> {
     let coef_draw = if bet.coef_draw == "" { 0. } else { try!(bet.coef_draw.parse()) };
 
     let mut outcomes = vec![
-        Outcome(bet.gamer_1.nick, coef_1),
-        Outcome(bet.gamer_2.nick, coef_2)
+        Outcome::new(&bet.gamer_1.nick, coef_1),
+        Outcome::new(&bet.gamer_2.nick, coef_2)
     ];
 
     if coef_draw > 0. {
-        outcomes.push(Outcome(DRAW.to_owned(), coef_draw));
+        outcomes.push(Outcome::new(DRAW, coef_draw));
     }
 
     Ok(Some(Offer {
@@ -296,6 +325,37 @@ fn extract_offer(bet: Bet) -> Result<Option<Offer>> {
         date: bet.date,
         game: game,
         kind: Kind::Series,
-        outcomes: outcomes
+        outcomes: outcomes,
+        league: None,
+        is_live: false,
+        best_of: None
     }))
 }
+
+#[test]
+fn test_expiry() {
+    use base::clock::TestClock;
+
+    let clock = TestClock::new(1000);
+    let mut expiry = Expiry::new();
+
+    expiry.push(1200, 2);
+    expiry.push(1100, 1);
+    expiry.push(1100, 3);
+
+    assert!(expiry.pop_expired(clock.now() + 5).is_empty());
+
+    clock.advance(100);
+    assert_eq!(expiry.pop_expired(clock.now()), vec![(1100, 3), (1100, 1)]);
+
+    clock.advance(99);
+    assert!(expiry.pop_expired(clock.now()).is_empty());
+
+    clock.advance(1);
+    assert_eq!(expiry.pop_expired(clock.now()), vec![(1200, 2)]);
+}
+
+#[test]
+fn test_features() {
+    assert_eq!(EGB::new().features(), Features { check_offer: true, ..Features::default() });
+}
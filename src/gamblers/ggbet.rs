@@ -0,0 +1,545 @@
+use std::collections::HashMap;
+use hyper::status::StatusCode;
+use serde::{Serialize, Deserialize};
+use serde_json as json;
+use parking_lot::{Mutex, RwLock};
+use time;
+
+use base::currency::Currency;
+use base::timers::Periodic;
+use base::error::{Result, Error};
+use base::error::ErrorKind::Status;
+use base::session::Session;
+use gamblers::{Gambler, Features, Message, BetReceipt};
+use gamblers::Message::*;
+use markets::{OID, Offer, Outcome, Game, Kind, DRAW, coef_eq};
+
+const PERIOD: u32 = 20;
+
+// The only API is GraphQL: all requests are POSTed to the single endpoint.
+pub struct GGBet {
+    session: Session,
+    credentials: RwLock<Option<(String, String)>>,
+    token: RwLock<Option<String>>,
+    matches: Mutex<HashMap<OID, Match>>
+}
+
+impl GGBet {
+    pub fn new() -> GGBet {
+        GGBet {
+            session: Session::new("gg.bet"),
+            credentials: RwLock::new(None),
+            token: RwLock::new(None),
+            matches: Mutex::new(HashMap::new())
+        }
+    }
+
+    fn log_in(&self) -> Result<String> {
+        let (login, password) = try!(self.credentials.read().clone().ok_or("Not authorized"));
+
+        let query = operation("mutation", "LogIn", &[("login", "String!"), ("password", "String!")],
+                              &[Field::new("logIn")
+                                  .arg("login", "$login")
+                                  .arg("password", "$password")
+                                  .fields(&["token"])]);
+
+        let data: LogInData = try!(self.send(&query, &Credentials {
+            login: &login,
+            password: &password
+        }, None));
+
+        Ok(data.logIn.token)
+    }
+
+    // Performs the request with the current token, which is refreshed once if it's rejected.
+    fn execute<V: Serialize, T: Deserialize>(&self, query: &str, variables: V) -> Result<T> {
+        let token = self.token.read().clone();
+
+        match self.send(query, &variables, token.as_ref().map(|token| token.as_str())) {
+            Err(Error { kind: Status(StatusCode::Unauthorized), .. }) if token.is_some() => {
+                info!("The token is rejected, logging in again...");
+
+                let fresh = try!(self.log_in());
+                *self.token.write() = Some(fresh.clone());
+
+                self.send(query, &variables, Some(&fresh))
+            },
+            result => result
+        }
+    }
+
+    fn send<V: Serialize, T: Deserialize>(&self, query: &str, variables: &V,
+                                          token: Option<&str>) -> Result<T>
+    {
+        let request = GraphQLRequest {
+            query: query,
+            variables: variables
+        };
+
+        let response: GraphQLResponse<T> = match token {
+            Some(token) => {
+                let auth = format!("Bearer {}", token);
+                try!(self.session.request("/graphql")
+                    .headers(&[("Authorization", &*auth)])
+                    .post(request))
+            },
+            None => try!(self.session.request("/graphql").post(request))
+        };
+
+        response.into_result()
+    }
+
+    fn find_selection(&self, offer: &Offer, outcome: &Outcome) -> Result<String> {
+        let matches = self.matches.lock();
+        let match_ = try!(matches.get(&offer.oid).ok_or("No such match"));
+
+        let selection = try!(match_.selections.iter()
+            .find(|s| get_title(s) == outcome.0)
+            .ok_or("No such selection"));
+
+        Ok(selection.id.clone())
+    }
+}
+
+impl Gambler for GGBet {
+    fn features(&self) -> Features {
+        Features {
+            check_offer: true,
+            ..Features::default()
+        }
+    }
+
+    fn authorize(&self, username: &str, password: &str) -> Result<()> {
+        *self.credentials.write() = Some((username.to_owned(), password.to_owned()));
+
+        let token = try!(self.log_in());
+        *self.token.write() = Some(token);
+
+        Ok(())
+    }
+
+    fn check_balance(&self) -> Result<Currency> {
+        let query = operation("query", "Balance", &[],
+                              &[Field::new("me").fields(&["balance"])]);
+
+        let data: BalanceData = try!(self.execute(&query, ()));
+
+        Ok(Currency::from(data.me.balance))
+    }
+
+    fn watch(&self, cb: &Fn(Message) -> Result<()>) -> Result<()> {
+        let query = operation("query", "Matches", &[("status", "MatchStatus!")], &[
+            Field::new("matches")
+                .arg("status", "$status")
+                .fields(&["id", "startTime", "sport", "bestOf"])
+                .field(Field::new("tournament").fields(&["name"]))
+                .field(Field::new("selections")
+                    .arg("market", "WINNER")
+                    .fields(&["id", "name", "odds", "active"]))
+        ]);
+
+        for _ in Periodic::new(PERIOD) {
+            let data: MatchesData = try!(self.execute(&query, MatchesVariables {
+                status: "UPCOMING"
+            }));
+
+            let mut matches = self.matches.lock();
+            let mut actual = HashMap::new();
+
+            for match_ in data.matches {
+                if let Some(offer) = convert_match(&match_) {
+                    actual.insert(offer.oid, (offer, match_));
+                }
+            }
+
+            for oid in matches.keys() {
+                if !actual.contains_key(oid) {
+                    try!(cb(Remove(*oid)));
+                }
+            }
+
+            for (oid, &(ref offer, _)) in &actual {
+                let outdated = matches.get(oid)
+                    .and_then(|match_| convert_match(match_))
+                    .map_or(true, |stored| stored != *offer);
+
+                if outdated {
+                    try!(cb(Upsert(offer.clone())));
+                }
+            }
+
+            *matches = actual.into_iter().map(|(oid, (_, match_))| (oid, match_)).collect();
+        }
+
+        Ok(())
+    }
+
+    fn check_offer(&self, offer: &Offer, outcome: &Outcome, _: Currency) -> Result<bool> {
+        let selection_id = try!(self.find_selection(offer, outcome));
+
+        let query = operation("query", "Selection", &[("id", "ID!")], &[
+            Field::new("selection").arg("id", "$id").fields(&["odds", "active"])
+        ]);
+
+        let data: SelectionData = try!(self.execute(&query, SelectionVariables {
+            id: &selection_id
+        }));
+
+        let selection = data.selection;
+        Ok(selection.active && selection.odds.map_or(false, |odds| coef_eq(odds, outcome.1)))
+    }
+
+    fn place_bet(&self, offer: Offer, outcome: Outcome, stake: Currency) -> Result<BetReceipt> {
+        let selection_id = try!(self.find_selection(&offer, &outcome));
+
+        let query = operation("mutation", "PlaceBet",
+                              &[("selectionId", "ID!"), ("odds", "Float!"), ("stake", "Float!")],
+                              &[Field::new("placeBet")
+                                  .arg("selectionId", "$selectionId")
+                                  .arg("odds", "$odds")
+                                  .arg("stake", "$stake")
+                                  .fields(&["id", "stake", "odds"])]);
+
+        let data: PlaceBetData = try!(self.execute(&query, PlaceBetVariables {
+            selectionId: &selection_id,
+            odds: outcome.1,
+            stake: stake.into()
+        }));
+
+        let mut receipt = BetReceipt::new(Currency::from(data.placeBet.stake), data.placeBet.odds);
+        receipt.id = Some(data.placeBet.id);
+
+        Ok(receipt)
+    }
+}
+
+// Renders `field(arg: $var) { subfield ... }`.
+struct Field {
+    name: &'static str,
+    args: Vec<(&'static str, &'static str)>,
+    fields: Vec<Field>
+}
+
+impl Field {
+    fn new(name: &'static str) -> Field {
+        Field {
+            name: name,
+            args: Vec::new(),
+            fields: Vec::new()
+        }
+    }
+
+    fn arg(mut self, name: &'static str, value: &'static str) -> Field {
+        self.args.push((name, value));
+        self
+    }
+
+    fn field(mut self, field: Field) -> Field {
+        self.fields.push(field);
+        self
+    }
+
+    fn fields(mut self, names: &[&'static str]) -> Field {
+        self.fields.extend(names.iter().map(|name| Field::new(name)));
+        self
+    }
+
+    fn render(&self, buffer: &mut String) {
+        buffer.push_str(self.name);
+
+        if !self.args.is_empty() {
+            let args = self.args.iter()
+                .map(|&(name, value)| format!("{}: {}", name, value))
+                .collect::<Vec<_>>();
+
+            buffer.push('(');
+            buffer.push_str(&args.join(", "));
+            buffer.push(')');
+        }
+
+        render_selection_set(&self.fields, buffer);
+    }
+}
+
+fn render_selection_set(fields: &[Field], buffer: &mut String) {
+    if fields.is_empty() {
+        return;
+    }
+
+    buffer.push_str(" {");
+
+    for field in fields {
+        buffer.push(' ');
+        field.render(buffer);
+    }
+
+    buffer.push_str(" }");
+}
+
+// Renders `kind Name($var: Type) { fields }`.
+fn operation(kind: &str, name: &str, variables: &[(&str, &str)], fields: &[Field]) -> String {
+    let mut buffer = format!("{} {}", kind, name);
+
+    if !variables.is_empty() {
+        let variables = variables.iter()
+            .map(|&(name, type_)| format!("${}: {}", name, type_))
+            .collect::<Vec<_>>();
+
+        buffer.push('(');
+        buffer.push_str(&variables.join(", "));
+        buffer.push(')');
+    }
+
+    render_selection_set(fields, &mut buffer);
+    buffer
+}
+
+#[derive(Serialize)]
+struct GraphQLRequest<'a, V> {
+    query: &'a str,
+    variables: V
+}
+
+#[derive(Deserialize)]
+struct GraphQLResponse<T> {
+    data: Option<T>,
+    errors: Option<Vec<GraphQLError>>
+}
+
+#[derive(Deserialize)]
+struct GraphQLError {
+    message: String
+}
+
+impl<T> GraphQLResponse<T> {
+    // Errors are reported even with partial data, which isn't trusted then. An expired token is
+    // reported as an error too, it's turned into the status to be refreshed as usual.
+    fn into_result(self) -> Result<T> {
+        if let Some(errors) = self.errors {
+            if errors.iter().any(|e| e.message == "Unauthorized") {
+                return Err(Error::from(StatusCode::Unauthorized));
+            }
+
+            if !errors.is_empty() {
+                let messages = errors.into_iter().map(|e| e.message).collect::<Vec<_>>();
+                return Err(Error::from(messages.join("; ")));
+            }
+        }
+
+        self.data.ok_or_else(|| Error::from("There is no data in the response"))
+    }
+}
+
+#[derive(Serialize)]
+struct Credentials<'a> {
+    login: &'a str,
+    password: &'a str
+}
+
+#[derive(Serialize)]
+struct MatchesVariables<'a> {
+    status: &'a str
+}
+
+#[derive(Serialize)]
+struct SelectionVariables<'a> {
+    id: &'a str
+}
+
+#[derive(Serialize)]
+#[allow(non_snake_case)]
+struct PlaceBetVariables<'a> {
+    selectionId: &'a str,
+    odds: f64,
+    stake: f64
+}
+
+#[derive(Deserialize)]
+#[allow(non_snake_case)]
+struct LogInData {
+    logIn: Token
+}
+
+#[derive(Deserialize)]
+struct Token {
+    token: String
+}
+
+#[derive(Deserialize)]
+struct BalanceData {
+    me: Me
+}
+
+#[derive(Deserialize)]
+struct Me {
+    balance: f64
+}
+
+#[derive(Deserialize)]
+struct MatchesData {
+    matches: Vec<Match>
+}
+
+#[derive(Deserialize, Debug)]
+#[allow(non_snake_case)]
+struct Match {
+    id: String,
+    startTime: u32,
+    sport: String,
+    bestOf: Option<u8>,
+    tournament: Option<Tournament>,
+    selections: Vec<Selection>
+}
+
+#[derive(Deserialize, Debug)]
+struct Tournament {
+    name: String
+}
+
+#[derive(Deserialize, Debug)]
+struct Selection {
+    id: String,
+    name: String,
+    odds: Option<f64>,
+    active: bool
+}
+
+#[derive(Deserialize)]
+struct SelectionData {
+    selection: Selection
+}
+
+#[derive(Deserialize)]
+#[allow(non_snake_case)]
+struct PlaceBetData {
+    placeBet: PlacedBet
+}
+
+#[derive(Deserialize)]
+struct PlacedBet {
+    id: String,
+    stake: f64,
+    odds: f64
+}
+
+fn get_title(selection: &Selection) -> &str {
+    match selection.name.as_str() {
+        "Draw" => DRAW,
+        name => name
+    }
+}
+
+fn convert_match(match_: &Match) -> Option<Offer> {
+    let game = match match_.sport.as_str() {
+        "CS:GO" => Game::CounterStrike,
+        "Dota 2" => Game::Dota2,
+        "League of Legends" => Game::LeagueOfLegends,
+        "Overwatch" => Game::Overwatch,
+        "Hearthstone" => Game::Hearthstone,
+        "StarCraft 2" => Game::StarCraft2,
+        "Football" => Game::Football,
+        "Tennis" => Game::Tennis,
+        "Basketball" => Game::Basketball,
+        "Ice Hockey" => Game::IceHockey,
+        // Matches are converted on every poll, so it would flood the log as a warning.
+        sport => {
+            debug!("Unknown sport: {}", sport);
+            return None;
+        }
+    };
+
+    // Ids are numeric strings, as usual for GraphQL `ID`.
+    let oid = match match_.id.parse::<OID>() {
+        Ok(oid) => oid,
+        Err(_) => return None
+    };
+
+    if match_.startTime <= time::get_time().sec as u32 {
+        return None;
+    }
+
+    let count = match_.selections.len();
+
+    if count < 2 || count > 3 || !match_.selections.iter().any(|s| s.active) {
+        return None;
+    }
+
+    // Suspended selections are kept with the zero coef.
+    let outcomes = match_.selections.iter().map(|selection| {
+        let coef = if selection.active { selection.odds.unwrap_or(0.) } else { 0. };
+        Outcome::new(get_title(selection), coef)
+    }).collect();
+
+    Some(Offer {
+        oid: oid,
+        date: match_.startTime,
+        game: game,
+        kind: Kind::Series,
+        outcomes: outcomes,
+        league: match_.tournament.as_ref().map(|t| t.name.clone()),
+        is_live: false,
+        best_of: match_.bestOf
+    })
+}
+
+#[test]
+fn test_operation() {
+    let query = operation("query", "Selection", &[("id", "ID!")], &[
+        Field::new("selection").arg("id", "$id").fields(&["odds", "active"])
+    ]);
+
+    assert_eq!(query, "query Selection($id: ID!) { selection(id: $id) { odds active } }");
+    assert_eq!(operation("query", "Balance", &[], &[Field::new("me").fields(&["balance"])]),
+               "query Balance { me { balance } }");
+}
+
+#[test]
+fn test_convert_response() {
+    let response = r#"{
+        "data": {
+            "matches": [{
+                "id": "1042",
+                "startTime": 4000000000,
+                "sport": "Dota 2",
+                "bestOf": 3,
+                "tournament": {"name": "The International"},
+                "selections": [
+                    {"id": "s1", "name": "NaVi", "odds": 1.85, "active": true},
+                    {"id": "s2", "name": "VP", "odds": 1.95, "active": true}
+                ]
+            }]
+        }
+    }"#;
+
+    let response: GraphQLResponse<MatchesData> = json::from_str(response).unwrap();
+    let data = response.into_result().unwrap();
+    let offer = convert_match(&data.matches[0]).unwrap();
+
+    assert_eq!(offer.oid, 1042);
+    assert_eq!(offer.game, Game::Dota2);
+    assert_eq!(offer.league, Some("The International".to_owned()));
+    assert_eq!(offer.best_of, Some(3));
+    assert_eq!(offer.outcomes, vec![Outcome::new("NaVi", 1.85),
+                                    Outcome::new("VP", 1.95)]);
+
+    let response = r#"{
+        "data": null,
+        "errors": [{"message": "Unauthorized", "path": ["matches"]}]
+    }"#;
+
+    let response: GraphQLResponse<MatchesData> = json::from_str(response).unwrap();
+    let error = response.into_result().err().unwrap();
+
+    assert!(format!("{}", error).ends_with("Unauthorized"));
+    assert!(match error.kind { Status(StatusCode::Unauthorized) => true, _ => false });
+
+    let response = r#"{
+        "data": null,
+        "errors": [{"message": "Match not found", "path": ["matches"]}]
+    }"#;
+
+    let response: GraphQLResponse<MatchesData> = json::from_str(response).unwrap();
+    let error = response.into_result().err().unwrap();
+
+    assert!(format!("{}", error).ends_with("Match not found"));
+}
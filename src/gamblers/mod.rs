@@ -1,8 +1,14 @@
 #![allow(unused_variables)]
 
-use base::error::Result;
+use std::thread;
+use std::time::{Duration, Instant};
+use time;
+
+use constants::{SPORTS_IDS, ODDS_STYLES, MARKET_KINDS};
+
+use base::error::{Result, Error};
 use base::currency::Currency;
-use markets::{OID, Offer, Outcome};
+use markets::{OID, Offer, Outcome, Kind, OddsStyle};
 
 mod egamingbets;
 mod vitalbet;
@@ -10,6 +16,10 @@ mod xsporta;
 mod cybbet;
 mod betway;
 mod betclub;
+mod arcanebet;
+mod ggbet;
+#[cfg(test)]
+pub mod chaos;
 
 #[derive(Debug)]
 pub enum Message {
@@ -17,14 +27,91 @@ pub enum Message {
     Remove(OID)
 }
 
+// What the bookie has actually accepted.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BetReceipt {
+    pub id: Option<String>,
+    pub stake: Currency,
+    pub coef: f64,
+    pub date: u32,
+    // The bookie hasn't confirmed the bet in time, its status is found out by the reconciliation.
+    pub pending: bool,
+    // The HTTP traffic of placing, filled in by the arbitrer if `PERSIST_RAW_BETS` is enabled.
+    pub raw_request: Option<String>,
+    pub raw_response: Option<String>
+}
+
+impl BetReceipt {
+    pub fn new(stake: Currency, coef: f64) -> BetReceipt {
+        BetReceipt {
+            id: None,
+            stake: stake,
+            coef: coef,
+            date: time::get_time().sec as u32,
+            pending: false,
+            raw_request: None,
+            raw_response: None
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BetStatus { Open, Won, Lost, Void, Unknown }
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OpenBet {
+    pub id: String,
+    pub selection: String,
+    pub stake: Currency,
+    pub coef: f64,
+    pub status: BetStatus
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Placement { Pending, Confirmed, Rejected(String) }
+
+// Optional capabilities of a gambler, the arbitrer consults them before trying optional operations.
+// `bet_status` means that placed bets are accepted asynchronously and must be confirmed by polling.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Features {
+    pub check_offer: bool,
+    pub free_bets: bool,
+    pub open_bets: bool,
+    pub cashout: bool,
+    pub bet_status: bool
+}
+
 pub trait Gambler {
+    fn features(&self) -> Features {
+        Features::default()
+    }
     fn authorize(&self, username: &str, password: &str) -> Result<()>;
     fn check_balance(&self) -> Result<Currency>;
-    fn watch(&self, cb: &Fn(Message)) -> Result<()>;
-    fn place_bet(&self, offer: Offer, outcome: Outcome, stake: Currency) -> Result<()>;
+    fn watch(&self, cb: &Fn(Message) -> Result<()>) -> Result<()>;
+    fn place_bet(&self, offer: Offer, outcome: Outcome, stake: Currency) -> Result<BetReceipt>;
     fn check_offer(&self, offer: &Offer, outcome: &Outcome, stake: Currency) -> Result<bool> {
         Ok(true)
     }
+    // Limits of the stake only, without refreshing the odds. Used for recently seen offers.
+    fn check_stake(&self, offer: &Offer, outcome: &Outcome, stake: Currency) -> Result<bool> {
+        Ok(true)
+    }
+    fn check_free_balance(&self) -> Result<Currency> {
+        Ok(Currency(0))
+    }
+    fn place_free_bet(&self, offer: Offer, outcome: Outcome, stake: Currency) -> Result<BetReceipt> {
+        Err(Error::from("Free bets are not supported"))
+    }
+    fn open_bets(&self) -> Result<Vec<OpenBet>> {
+        Ok(vec![])
+    }
+    // `None` if the cash-out isn't offered for the bet at the moment.
+    fn cashout_value(&self, bet_id: &str) -> Result<Option<Currency>> {
+        Err(Error::from("Cash-out is not supported"))
+    }
+    fn bet_status(&self, bet_id: &str) -> Result<Placement> {
+        Err(Error::from("Bet status is not supported"))
+    }
 }
 
 pub type BoxedGambler = Box<Gambler + Send + Sync>;
@@ -41,6 +128,30 @@ macro_rules! gambler_map {
     }
 }
 
+pub fn sports_ids(host: &str) -> &'static [u32] {
+    SPORTS_IDS.iter()
+        .find(|&&(h, _)| h == host)
+        .map(|&(_, ids)| ids)
+        .unwrap_or(&[])
+}
+
+pub fn market_kind(host: &str, market: &str) -> Option<Kind> {
+    find_market_kind(MARKET_KINDS, host, market)
+}
+
+fn find_market_kind(table: &[(&str, &[(&str, Kind)])], host: &str, market: &str) -> Option<Kind> {
+    table.iter()
+        .find(|&&(h, _)| h == host)
+        .and_then(|&(_, kinds)| kinds.iter().find(|&&(m, _)| m == market))
+        .map(|&(_, kind)| kind)
+}
+
+pub fn odds_style(host: &str) -> OddsStyle {
+    ODDS_STYLES.iter()
+        .find(|&&(h, _)| h == host)
+        .map_or(OddsStyle::Decimal, |&(_, style)| style)
+}
+
 pub fn new(host: &str) -> (&'static str, BoxedGambler) {
     gambler_map!(host,
         "egamingbets" => egamingbets::EGB,
@@ -48,6 +159,106 @@ pub fn new(host: &str) -> (&'static str, BoxedGambler) {
         "1xsporta" => xsporta::XBet,
         "cybbet" => cybbet::CybBet,
         "betway" => betway::BetWay,
-        "betclub" => betclub::BetClub
+        "betclub" => betclub::BetClub,
+        "arcanebet" => arcanebet::ArcaneBet,
+        "ggbet" => ggbet::GGBet
     )
 }
+
+// Polls the status of the placed bet until it's settled one way or the other.
+// Only an explicit rejection is an error: the bet still can be accepted after the timeout or
+// failed polls, so it's returned as pending then.
+pub fn await_confirmation(gambler: &Gambler, receipt: &BetReceipt, interval: Duration,
+                          timeout: Duration) -> Result<Placement>
+{
+    let bet_id = try!(receipt.id.as_ref().ok_or("There is no bet id to poll"));
+    let started = Instant::now();
+
+    loop {
+        match gambler.bet_status(bet_id) {
+            Ok(Placement::Confirmed) => return Ok(Placement::Confirmed),
+            Ok(Placement::Rejected(reason)) => {
+                return Err(Error::from(format!("Bet #{} is rejected: {}", bet_id, reason)));
+            },
+            Ok(Placement::Pending) => {},
+            Err(error) => warn!("While polling the status of bet #{}: {}", bet_id, error)
+        }
+
+        if started.elapsed() + interval > timeout {
+            return Ok(Placement::Pending);
+        }
+
+        thread::sleep(interval);
+    }
+}
+
+#[test]
+fn test_sports_ids() {
+    assert!(sports_ids("betclub").contains(&1));
+    assert!(sports_ids("unknown").is_empty());
+}
+
+#[test]
+fn test_market_kind() {
+    let table: &[(&str, &[(&str, Kind)])] = &[("bookie", &[("Match Winner", Kind::Series)])];
+
+    assert_eq!(find_market_kind(table, "bookie", "Match Winner"), Some(Kind::Series));
+    assert_eq!(find_market_kind(table, "bookie", "Total Maps"), None);
+    assert_eq!(find_market_kind(table, "other", "Match Winner"), None);
+
+    assert_eq!(market_kind("betway", "win-draw-win"), Some(Kind::Series));
+}
+
+#[test]
+fn test_await_confirmation() {
+    use std::cell::Cell;
+
+    struct Stub {
+        polls: Cell<u32>,
+        confirm_at: u32,
+        fail: bool
+    }
+
+    impl Gambler for Stub {
+        fn authorize(&self, _: &str, _: &str) -> Result<()> { Ok(()) }
+        fn check_balance(&self) -> Result<Currency> { Ok(Currency(0)) }
+        fn watch(&self, _: &Fn(Message) -> Result<()>) -> Result<()> { Ok(()) }
+
+        fn place_bet(&self, _: Offer, _: Outcome, stake: Currency) -> Result<BetReceipt> {
+            Ok(BetReceipt::new(stake, 2.))
+        }
+
+        fn bet_status(&self, _: &str) -> Result<Placement> {
+            self.polls.set(self.polls.get() + 1);
+
+            if self.fail {
+                return Err(Error::from("Connection reset"));
+            }
+
+            Ok(if self.polls.get() >= self.confirm_at { Placement::Confirmed }
+               else { Placement::Pending })
+        }
+    }
+
+    let mut receipt = BetReceipt::new(Currency(100), 2.);
+    receipt.id = Some("42".to_owned());
+
+    let interval = Duration::from_millis(1);
+    let stub = Stub { polls: Cell::new(0), confirm_at: 2, fail: false };
+
+    let placement = await_confirmation(&stub, &receipt, interval, Duration::from_millis(100));
+    assert_eq!(placement.unwrap(), Placement::Confirmed);
+    assert_eq!(stub.polls.get(), 2);
+
+    // Neither the timeout nor failed polls mean the rejection.
+    let stub = Stub { polls: Cell::new(0), confirm_at: 1000, fail: false };
+
+    let placement = await_confirmation(&stub, &receipt, interval, Duration::from_millis(5));
+    assert_eq!(placement.unwrap(), Placement::Pending);
+    assert!(stub.polls.get() < 10);
+
+    let stub = Stub { polls: Cell::new(0), confirm_at: 1, fail: true };
+
+    let placement = await_confirmation(&stub, &receipt, interval, Duration::from_millis(5));
+    assert_eq!(placement.unwrap(), Placement::Pending);
+}
@@ -3,6 +3,7 @@
 use std::result::Result as StdResult;
 use std::collections::HashMap;
 use serde::{Deserialize, Deserializer};
+use serde::de::Error as DeError;
 use serde_json as json;
 use time;
 use url::percent_encoding::{utf8_percent_encode, USERINFO_ENCODE_SET};
@@ -11,8 +12,8 @@ use parking_lot::Mutex;
 use base::currency::Currency;
 use base::timers::Periodic;
 use base::error::{Result, Error};
-use base::session::Session;
-use gamblers::{Gambler, Message};
+use base::session::{Session, Profile};
+use gamblers::{self, Gambler, Features, Message, BetReceipt, OpenBet, BetStatus};
 use gamblers::Message::*;
 use markets::{OID, Offer, Outcome, Game, Kind, DRAW};
 
@@ -101,6 +102,14 @@ impl VitalBet {
 }
 
 impl Gambler for VitalBet {
+    fn features(&self) -> Features {
+        Features {
+            check_offer: true,
+            open_bets: true,
+            ..Features::default()
+        }
+    }
+
     fn authorize(&self, username: &str, password: &str) -> Result<()> {
         let body = format!(r#"{{
             "BrowserFingerPrint": 1697977978,
@@ -121,12 +130,13 @@ impl Gambler for VitalBet {
     }
 
     fn check_balance(&self) -> Result<Currency> {
-        let balance: Balance = try!(self.session.request("/api/account").get());
+        let request = self.session.request("/api/account").profile(Profile::Fast);
+        let balance: Balance = try!(request.get());
 
         Ok(Currency::from(balance.Balance))
     }
 
-    fn watch(&self, cb: &Fn(Message)) -> Result<()> {
+    fn watch(&self, cb: &Fn(Message) -> Result<()>) -> Result<()> {
         // First of all, we should get initial page to get session cookie.
         try!(self.session.request("/").get::<String>());
 
@@ -147,7 +157,7 @@ impl Gambler for VitalBet {
 
                 for event in current_events {
                     if let Some(offer) = try!(create_offer(&event)) {
-                        cb(Upsert(offer));
+                        try!(cb(Upsert(offer)));
                     }
 
                     // Save data into state.
@@ -174,9 +184,9 @@ impl Gambler for VitalBet {
                 if let Some(mut event) = find_event_for_update(&mut state, &update) {
                     if apply_update(&mut event, &update) {
                         if let Some(offer) = try!(create_offer(&event)) {
-                            cb(Upsert(offer));
+                            try!(cb(Upsert(offer)));
                         } else {
-                            cb(Remove(event.ID as OID));
+                            try!(cb(Remove(event.ID as OID)));
                         }
                     }
                 }
@@ -199,7 +209,7 @@ impl Gambler for VitalBet {
 
                 for id in outdated {
                     state.events.remove(&id);
-                    cb(Remove(id as OID));
+                    try!(cb(Remove(id as OID)));
                 }
             }
         }
@@ -233,16 +243,26 @@ impl Gambler for VitalBet {
         }
     }
 
-    fn place_bet(&self, offer: Offer, outcome: Outcome, stake: Currency) -> Result<()> {
+    // The minimum stake is found out only by probing the bet, that checks the odds as well.
+    fn check_stake(&self, offer: &Offer, outcome: &Outcome, stake: Currency) -> Result<bool> {
+        self.check_offer(offer, outcome, stake)
+    }
+
+    fn place_bet(&self, offer: Offer, outcome: Outcome, stake: Currency) -> Result<BetReceipt> {
         let state = self.state.lock();
 
         let event = try!(state.events.get(&(offer.oid as u32)).ok_or("No such event"));
         let response = try!(self.try_place_bet(event, &outcome, stake));
 
-        match response.ErrorMessage {
-            Some(m) => Err(Error::from(m)),
-            None => Ok(())
-        }
+        make_receipt(response, stake, outcome.1)
+    }
+
+    fn open_bets(&self) -> Result<Vec<OpenBet>> {
+        let history: Vec<HistoryBet> = try!(self.session.request("/api/betslip/history?status=open")
+            .profile(Profile::Long)
+            .get());
+
+        Ok(history.into_iter().filter_map(convert_history_bet).collect())
     }
 }
 
@@ -310,8 +330,10 @@ struct PollingAuthResponse {
     ConnectionToken: String
 }
 
+// Keepalive responses are empty objects.
 #[derive(Deserialize)]
 struct PollingResponse {
+    #[serde(default)]
     M: Vec<PollingMessage>
 }
 
@@ -329,24 +351,28 @@ enum PollingMessage {
 
 impl Deserialize for PollingMessage {
     fn deserialize<D>(d: &mut D) -> StdResult<PM, D::Error> where D: Deserializer {
-        let result: json::Value = try!(Deserialize::deserialize(d));
+        let value: json::Value = try!(Deserialize::deserialize(d));
+        parse_polling_message(value).map_err(|error| D::Error::custom(error.to_string()))
+    }
+}
 
-        if result.find("M").map_or(false, json::Value::is_string) {
-            return Ok(PM::UnsupportedUpdateMessage(UnsupportedUpdateMessage("Even no M".to_string())));
-        }
+// The SignalR envelope is `{"H": hub, "M": method, "A": arguments}`. Messages of unknown shape
+// are unsupported, but malformed messages of known methods are errors.
+fn parse_polling_message(value: json::Value) -> Result<PollingMessage> {
+    let method = match value.find("M").and_then(json::Value::as_str) {
+        Some(method) => method.to_owned(),
+        None => return Ok(PM::UnsupportedUpdateMessage(UnsupportedUpdateMessage(value.to_string())))
+    };
 
-        let update_type = result.find("M").unwrap().as_str().unwrap_or("No update type").to_string();
-
-        Ok(match update_type.as_ref() {
-            "oddsUpdated" => PM::OddsUpdateMessage( json::from_value(result).unwrap() ),
-            "marketsUpdated" => PM::MarketsUpdateMessage( json::from_value(result).unwrap() ),
-            "matchesUpdated" => PM::MatchesUpdateMessage( json::from_value(result).unwrap() ),
-            "prematchOddsUpdated" => PM::PrematchOddsUpdateMessage( json::from_value(result).unwrap() ),
-            "prematchMarketsUpdated" => PM::PrematchMarketsUpdateMessage( json::from_value(result).unwrap() ),
-            "prematchMatchesUpdated" => PM::PrematchMatchesUpdateMessage( json::from_value(result).unwrap() ),
-            _ => PM::UnsupportedUpdateMessage( UnsupportedUpdateMessage(update_type) )
-        })
-    }
+    Ok(match method.as_ref() {
+        "oddsUpdated" => PM::OddsUpdateMessage(try!(json::from_value(value))),
+        "marketsUpdated" => PM::MarketsUpdateMessage(try!(json::from_value(value))),
+        "matchesUpdated" => PM::MatchesUpdateMessage(try!(json::from_value(value))),
+        "prematchOddsUpdated" => PM::PrematchOddsUpdateMessage(try!(json::from_value(value))),
+        "prematchMarketsUpdated" => PM::PrematchMarketsUpdateMessage(try!(json::from_value(value))),
+        "prematchMatchesUpdated" => PM::PrematchMatchesUpdateMessage(try!(json::from_value(value))),
+        _ => PM::UnsupportedUpdateMessage(UnsupportedUpdateMessage(method))
+    })
 }
 
 #[derive(Deserialize)]
@@ -432,6 +458,60 @@ struct BetOutcome {
 #[derive(Deserialize, Debug)]
 struct PlaceBetResponse {
     ErrorMessage: Option<String>,
+    BetID: Option<u64>,
+    Stake: Option<f64>,
+    Odds: Option<f64>
+}
+
+#[derive(Deserialize, Debug)]
+struct HistoryBet {
+    ID: u64,
+    Stake: f64,
+    Status: String,
+    Selections: Vec<HistorySelection>
+}
+
+#[derive(Deserialize, Debug)]
+struct HistorySelection {
+    Title: String,
+    Odds: f64
+}
+
+fn convert_history_bet(bet: HistoryBet) -> Option<OpenBet> {
+    // Only singles are placed by us.
+    if bet.Selections.len() != 1 {
+        return None;
+    }
+
+    let selection = &bet.Selections[0];
+
+    Some(OpenBet {
+        id: bet.ID.to_string(),
+        selection: if selection.Title == "Draw" { DRAW.to_owned() } else { selection.Title.clone() },
+        stake: Currency::from(bet.Stake),
+        coef: selection.Odds,
+        status: match bet.Status.as_str() {
+            "Open" | "Pending" => BetStatus::Open,
+            "Won" => BetStatus::Won,
+            "Lost" => BetStatus::Lost,
+            "Void" | "Cancelled" => BetStatus::Void,
+            _ => BetStatus::Unknown
+        }
+    })
+}
+
+fn make_receipt(response: PlaceBetResponse, stake: Currency, coef: f64) -> Result<BetReceipt> {
+    if let Some(message) = response.ErrorMessage {
+        return Err(Error::from(message));
+    }
+
+    let mut receipt = BetReceipt::new(stake, coef);
+
+    receipt.id = response.BetID.map(|id| id.to_string());
+    receipt.stake = response.Stake.map_or(stake, Currency::from);
+    receipt.coef = response.Odds.unwrap_or(coef);
+
+    Ok(receipt)
 }
 
 fn event_is_live(event: &Event) -> bool {
@@ -490,11 +570,6 @@ fn create_offer(event: &Event) -> Result<Option<Offer>> {
             if market.IsSuspended || !market.IsActive {
                 return Ok(None);
             }
-
-            match market.Name.as_ref().unwrap().trim() {
-                "Match Odds" | "Match Winner" | "Match Odds (3 Way)" | "Series Winner" => {},
-                _ => return Ok(None)
-            }
         },
         None => return Ok(None)
     }
@@ -511,9 +586,9 @@ fn create_offer(event: &Event) -> Result<Option<Offer>> {
     let odds = match event.PreviewOdds {
         Some(ref odds) => odds.iter()
             .map(|odd| {
-                let title = if odd.Title == "Draw" { DRAW.to_owned() } else { odd.Title.clone() };
+                let title = if odd.Title == "Draw" { DRAW } else { odd.Title.as_str() };
 
-                Outcome(title, odd.Value)
+                Outcome::new(title, odd.Value)
             })
             .collect::<Vec<_>>(),
         None => return Ok(None)
@@ -524,7 +599,10 @@ fn create_offer(event: &Event) -> Result<Option<Offer>> {
         date: try!(parse_date(&event.DateOfMatch)),
         game: game.unwrap(),
         kind: kind.unwrap(),
-        outcomes: odds
+        outcomes: odds,
+        league: None,
+        is_live: event_is_live(event),
+        best_of: None
     }))
 }
 
@@ -586,7 +664,9 @@ fn get_game(event: &Event) -> Option<Game> {
 }
 
 fn get_kind(event: &Event) -> Option<Kind> {
-    Some(Kind::Series)
+    event.PreviewMarket.as_ref()
+        .and_then(|market| market.Name.as_ref())
+        .and_then(|name| gamblers::market_kind("ebettle", name.trim()))
 }
 
 fn parse_date(date: &String) -> Result<u32> {
@@ -682,3 +762,73 @@ fn apply_event_update(event: &mut Event, event_update: &Event) -> bool {
 
     true
 }
+
+#[test]
+fn test_make_receipt() {
+    let response = json::from_str(r#"{"ErrorMessage":null,"BetID":5531,"Stake":1.5,"Odds":2.05}"#);
+    let receipt = make_receipt(response.unwrap(), Currency(200), 2.1).unwrap();
+
+    assert_eq!(receipt.id, Some("5531".to_owned()));
+    assert_eq!(receipt.stake, Currency(150));
+    assert_eq!(receipt.coef, 2.05);
+
+    let response = json::from_str(r#"{"ErrorMessage":"Bet not accepted"}"#);
+    assert!(make_receipt(response.unwrap(), Currency(200), 2.1).is_err());
+}
+
+#[test]
+fn test_convert_history_bet() {
+    let history: Vec<HistoryBet> = json::from_str(r#"[
+        {"ID": 12, "Stake": 1.25, "Status": "Open", "Selections": [{"Title": "Draw", "Odds": 3.4}]}
+    ]"#).unwrap();
+
+    let bets = history.into_iter().filter_map(convert_history_bet).collect::<Vec<_>>();
+
+    assert_eq!(bets, vec![OpenBet {
+        id: "12".to_owned(),
+        selection: DRAW.to_owned(),
+        stake: Currency(125),
+        coef: 3.4,
+        status: BetStatus::Open
+    }]);
+}
+
+#[test]
+fn test_parse_keepalive() {
+    let response: PollingResponse = json::from_str("{}").unwrap();
+    assert!(response.M.is_empty());
+
+    let response: PollingResponse = json::from_str(r#"{"C": "d-1,0|B,0|C,1", "M": []}"#).unwrap();
+    assert!(response.M.is_empty());
+}
+
+#[test]
+fn test_parse_unsupported_messages() {
+    let response: PollingResponse = json::from_str(r#"{"C": "d-1", "M": [
+        {"H": "sportTypeHub", "M": "scoreUpdated", "A": []},
+        {"H": "sportTypeHub", "A": []},
+        {"H": "sportTypeHub", "M": 42},
+        []
+    ]}"#).unwrap();
+
+    assert_eq!(response.M.len(), 4);
+    assert!(response.M.iter().all(|m| match *m {
+        PM::UnsupportedUpdateMessage(_) => true,
+        _ => false
+    }));
+}
+
+#[test]
+fn test_parse_malformed_message() {
+    let result = json::from_str::<PollingResponse>(r#"{"M": [
+        {"H": "sportTypeHub", "M": "oddsUpdated", "A": "garbage"}
+    ]}"#);
+
+    assert!(result.is_err());
+
+    let response: PollingResponse = json::from_str(r#"{"M": [
+        {"H": "sportTypeHub", "M": "prematchOddsUpdated", "A": [[[15, 1.85, 1]]]}
+    ]}"#).unwrap();
+
+    assert!(match response.M[0] { PM::PrematchOddsUpdateMessage(_) => true, _ => false });
+}
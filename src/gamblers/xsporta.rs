@@ -6,26 +6,35 @@ use kuchiki::NodeRef;
 use base::error::{Result, Error};
 use base::timers::Periodic;
 use base::parsing::{NodeRefExt, ElementDataExt};
-use base::session::{Session, Type};
+use base::session::{Session, Type, Profile};
 use base::currency::Currency;
-use gamblers::{Gambler, Message};
+use gamblers::{self, Gambler, Features, Message, BetReceipt};
 use gamblers::Message::*;
 use markets::{OID, Offer, Outcome, DRAW, Game, Kind};
 
-static SPORTS_IDS: &[u32] = &[1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 14, 15, 16, 17, 18, 19, 21, 22,
-                              23, 24, 26, 27, 28, 30, 31, 32, 36, 38, 40, 41, 46, 49, 56, 66, 67, 80];
-
 pub struct XBet {
-    session: Session
+    session: Session,
+    sports_ids: Vec<u32>
 }
 
 impl XBet {
     pub fn new() -> XBet {
+        XBet::with_sports_ids(gamblers::sports_ids("1xsporta"))
+    }
+
+    fn with_sports_ids(sports_ids: &[u32]) -> XBet {
         XBet {
-            session: Session::new("1xsporta.space")
+            session: Session::new("1xsporta.space"),
+            sports_ids: sports_ids.to_vec()
         }
     }
 
+    fn feed_paths(&self) -> Vec<(u32, String)> {
+        self.sports_ids.iter()
+            .map(|&id| (id, format!("/LineFeed/Get1x2?sportId={}&count=50&cnt=10&lng=en", id)))
+            .collect()
+    }
+
     fn try_place_bet(&self, offer: &Offer, outcome: &Outcome, stake: Currency) -> Result<PlaceBetResponse> {
         let stake: f64 = stake.into();
         let hash = self.session.get_cookie("uhash").unwrap();
@@ -59,6 +68,13 @@ impl XBet {
 }
 
 impl Gambler for XBet {
+    fn features(&self) -> Features {
+        Features {
+            check_offer: true,
+            ..Features::default()
+        }
+    }
+
     fn authorize(&self, username: &str, password: &str) -> Result<()> {
         let html: NodeRef = try!(self.session.request("/").get());
 
@@ -83,7 +99,8 @@ impl Gambler for XBet {
     }
 
     fn check_balance(&self) -> Result<Currency> {
-        let text: String = try!(self.session.request("/en/user/checkUserBalance.php").get());
+        let path = "/en/user/checkUserBalance.php";
+        let text: String = try!(self.session.request(path).profile(Profile::Fast).get());
         let on_invalid_balance = || format!("Invalid balance: {}", text);
         let balance_str = try!(text.split(' ').next().ok_or_else(on_invalid_balance));
         let balance = try!(balance_str.parse::<f64>());
@@ -91,23 +108,26 @@ impl Gambler for XBet {
         Ok(Currency::from(balance))
     }
 
-    fn watch(&self, cb: &Fn(Message)) -> Result<()> {
-        let mut state = SPORTS_IDS.iter()
-            .map(|id| (
-                format!("/LineFeed/Get1x2?sportId={}&count=50&cnt=10&lng=en", id),
-                HashSet::new()
-            ))
+    fn watch(&self, cb: &Fn(Message) -> Result<()>) -> Result<()> {
+        let mut state = self.feed_paths().into_iter()
+            .map(|(id, path)| (id, path, HashSet::new()))
             .collect::<Vec<_>>();
 
+        let mut first = true;
+
         // The site uses 1-minute period, but for us it's too long.
         for _ in Periodic::new(24) {
-            for &mut (ref path, ref mut active) in &mut state {
+            for &mut (id, ref path, ref mut active) in &mut state {
                 let message = try!(self.session.request(&path).get::<Get1x2Response>());
 
                 if !message.Success {
                     return Err(Error::from(message.Error));
                 }
 
+                if first && message.Value.is_empty() {
+                    warn!("There are no events of the sport #{}, is the id correct?", id);
+                }
+
                 let offers = message.Value.into_iter().filter_map(grab_offer).collect::<Vec<_>>();
 
                 // Deactivate active offers.
@@ -117,28 +137,30 @@ impl Gambler for XBet {
 
                 // Now `active` contains inactive.
                 for oid in active.drain() {
-                    cb(Remove(oid));
+                    try!(cb(Remove(oid)));
                 }
 
                 // Add/update offers.
                 for offer in offers {
                     active.insert(offer.oid);
-                    cb(Upsert(offer));
+                    try!(cb(Upsert(offer)));
                 }
             }
+
+            first = false;
         }
 
         Ok(())
     }
 
-    fn place_bet(&self, offer: Offer, outcome: Outcome, stake: Currency) -> Result<()> {
+    fn place_bet(&self, offer: Offer, outcome: Outcome, stake: Currency) -> Result<BetReceipt> {
         let response = try!(self.try_place_bet(&offer, &outcome, stake));
 
         if !response.Success {
             return Err(From::from(response.Error));
         }
 
-        Ok(())
+        Ok(BetReceipt::new(stake, outcome.1))
     }
 
     fn check_offer(&self, offer: &Offer, outcome: &Outcome, stake: Currency) -> Result<bool> {
@@ -155,6 +177,11 @@ impl Gambler for XBet {
             Ok(false)
         }
     }
+
+    // The minimum stake is found out only by probing the bet, that checks the odds as well.
+    fn check_stake(&self, offer: &Offer, outcome: &Outcome, stake: Currency) -> Result<bool> {
+        self.check_offer(offer, outcome, stake)
+    }
 }
 
 #[derive(Deserialize)]
@@ -227,14 +254,15 @@ fn grab_offer(info: Info) -> Option<Offer> {
     let coef_draw = info.Events.iter().find(|ev| ev.T == 2).map(|ev| ev.C);
     let date = info.Start;
     let id = info.Id;
+    let league = info.ChampEng;
 
     let mut outcomes = vec![
-        Outcome(info.Opp1, coef_1.unwrap()),
-        Outcome(info.Opp2, coef_2.unwrap())
+        Outcome::new(&info.Opp1, coef_1.unwrap()),
+        Outcome::new(&info.Opp2, coef_2.unwrap())
     ];
 
     if let Some(coef) = coef_draw {
-        outcomes.push(Outcome(DRAW.to_owned(), coef));
+        outcomes.push(Outcome::new(DRAW, coef));
     }
 
     Some(Offer {
@@ -242,7 +270,10 @@ fn grab_offer(info: Info) -> Option<Offer> {
         date: date,
         game: game,
         kind: Kind::Series,
-        outcomes: outcomes
+        outcomes: outcomes,
+        league: Some(league),
+        is_live: false,
+        best_of: None
     })
 }
 
@@ -309,3 +340,11 @@ fn game_from_info(info: &Info) -> Option<Game> {
         }
     })
 }
+
+#[test]
+fn test_feed_paths() {
+    let paths = XBet::with_sports_ids(&[42]).feed_paths();
+    assert_eq!(paths, vec![(42, "/LineFeed/Get1x2?sportId=42&count=50&cnt=10&lng=en".to_owned())]);
+
+    assert_eq!(XBet::new().feed_paths().len(), gamblers::sports_ids("1xsporta").len());
+}
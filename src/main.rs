@@ -1,9 +1,12 @@
 #![feature(proc_macro, custom_derive, static_in_const, specialization, conservative_impl_trait)]
+#![cfg_attr(test, feature(test))]
 
 #[macro_use]
 extern crate log;
 extern crate env_logger;
 extern crate time;
+#[cfg(test)]
+extern crate test;
 #[macro_use]
 extern crate hyper;
 extern crate kuchiki;
@@ -22,6 +25,7 @@ extern crate websocket;
 extern crate rusqlite;
 extern crate backtrace;
 extern crate parking_lot;
+extern crate encoding;
 
 use std::thread;
 
@@ -2,6 +2,8 @@ use std::fmt::{Display, Formatter};
 use std::fmt::Result as FmtResult;
 use time;
 
+use constants::{COEF_PRECISION, REQUIRE_BACKABLE_LEGS};
+
 pub type OID = u64;
 
 #[derive(Debug, Clone, PartialEq)]
@@ -10,14 +12,130 @@ pub struct Offer {
     pub date: u32,
     pub game: Game,
     pub kind: Kind,
-    pub outcomes: Vec<Outcome>
+    pub outcomes: Vec<Outcome>,
+    // A league or a tournament, if the bookie provides it.
+    pub league: Option<String>,
+    // Whether the event is in play.
+    pub is_live: bool,
+    // The number of maps (games) of a series, if the bookie provides it.
+    pub best_of: Option<u8>
 }
 
-#[derive(Debug, Clone, PartialEq)]
-pub struct Outcome(pub String, pub f64);
+// The zero coef stands for a suspended (or missing) selection.
+// The role is provided by bookies labelling selections explicitly and isn't compared.
+// The flag is reset for selections which can only be laid (on exchanges), they're never backed.
+#[derive(Debug, Clone)]
+pub struct Outcome(pub String, pub f64, pub Option<Role>, pub bool);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role { Home, Away, Draw, Other }
+
+impl Outcome {
+    pub fn new(title: &str, coef: f64) -> Outcome {
+        Outcome(title.to_owned(), coef, None, true)
+    }
+
+    pub fn role(mut self, role: Role) -> Outcome {
+        self.2 = Some(role);
+        self
+    }
+
+    // Only exchanges have such selections, none of them is supported yet.
+    #[cfg(test)]
+    pub fn lay_only(mut self) -> Outcome {
+        self.3 = false;
+        self
+    }
+
+    #[inline]
+    pub fn is_active(&self) -> bool {
+        self.1 > 0.
+    }
+
+    // See `REQUIRE_BACKABLE_LEGS`.
+    #[inline]
+    pub fn is_backable(&self) -> bool {
+        (self.3 || !REQUIRE_BACKABLE_LEGS) && self.is_active()
+    }
+}
+
+impl PartialEq for Outcome {
+    #[inline]
+    fn eq(&self, other: &Outcome) -> bool {
+        self.0 == other.0 && coef_eq(self.1, other.1) && self.3 == other.3
+    }
+}
+
+// Coefs are parsed from strings and sometimes recomputed, so raw `==` is too brittle.
+#[inline]
+pub fn coef_eq(lhs: f64, rhs: f64) -> bool {
+    (lhs - rhs).abs() < 0.5 * 10f64.powi(-COEF_PRECISION)
+}
 
 pub static DRAW: &str = "(draw)";
 
+// What to do with a draw outcome of an offer for a game without draws.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpuriousDraw { DropDraw, DropOffer }
+
+// Which offers (in play or not) are taken for a game.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LiveMode { PreMatchOnly, LiveOnly, Both }
+
+impl LiveMode {
+    pub fn admits(self, is_live: bool) -> bool {
+        match self {
+            LiveMode::PreMatchOnly => !is_live,
+            LiveMode::LiveOnly => is_live,
+            LiveMode::Both => true
+        }
+    }
+}
+
+// Decimal odds include the stake, profit multipliers (e.g. 1.5 instead of 2.5) don't, so
+// fractional odds (3/2) are quoted as profit multipliers. Probabilities are implied by odds
+// (0.4 instead of 2.5), American odds are +150 instead of 2.5 and -200 instead of 1.5.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OddsStyle { Decimal, ProfitMultiplier, Probability, American }
+
+impl OddsStyle {
+    // Suspended selections (zero coefs) are left as is, invalid quotes are considered suspended.
+    pub fn to_decimal(self, coef: f64) -> f64 {
+        match self {
+            OddsStyle::ProfitMultiplier if coef > 0. => coef + 1.,
+            OddsStyle::Probability if 0. < coef && coef < 1. => 1. / coef,
+            OddsStyle::Probability => 0.,
+            OddsStyle::American if coef >= 100. => coef / 100. + 1.,
+            OddsStyle::American if coef <= -100. => 100. / -coef + 1.,
+            OddsStyle::American => 0.,
+            _ => coef
+        }
+    }
+
+    pub fn from_decimal(self, coef: f64) -> f64 {
+        match self {
+            OddsStyle::ProfitMultiplier if coef > 0. => coef - 1.,
+            OddsStyle::Probability if coef > 1. => 1. / coef,
+            OddsStyle::American if coef >= 2. => (coef - 1.) * 100.,
+            OddsStyle::American if coef > 1. => -100. / (coef - 1.),
+            OddsStyle::Probability | OddsStyle::American => 0.,
+            _ => coef
+        }
+    }
+
+    pub fn normalize(self, outcomes: &mut [Outcome]) {
+        for outcome in outcomes {
+            outcome.1 = self.to_decimal(outcome.1);
+        }
+    }
+
+    pub fn denormalize(self, outcomes: &mut [Outcome]) {
+        for outcome in outcomes {
+            outcome.1 = self.from_decimal(outcome.1);
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Game {
     CounterStrike,
@@ -77,6 +195,80 @@ pub enum Game {
     Hurling
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum GameCategory {
+    ESports,
+    TeamSports,
+    IndividualSports,
+    Racing
+}
+
+pub static GAMES: &[Game] = &[
+    Game::CounterStrike, Game::CrossFire, Game::Dota2, Game::GearsOfWar, Game::Halo,
+    Game::Hearthstone, Game::HeroesOfTheStorm, Game::LeagueOfLegends, Game::Overwatch, Game::Smite,
+    Game::StarCraftBW, Game::StarCraft2, Game::Vainglory, Game::WorldOfTanks, Game::Fifa,
+
+    Game::Football, Game::Tennis, Game::Basketball, Game::IceHockey, Game::Volleyball,
+    Game::TableTennis, Game::Handball, Game::Badminton, Game::Baseball, Game::Snooker, Game::Pool,
+    Game::Futsal, Game::WaterPolo, Game::Rugby, Game::Chess, Game::Boxing, Game::AmericanFootball,
+    Game::Bandy, Game::Motorsport, Game::Biathlon, Game::Darts, Game::AlpineSkiing,
+    Game::SkiJumping, Game::Skiing, Game::Formula, Game::FieldHockey, Game::Motorbikes, Game::Bowls,
+    Game::BicycleRacing, Game::Poker, Game::Golf, Game::Curling, Game::Netball, Game::MartialArts,
+    Game::Cricket, Game::Floorball, Game::GaelicFootball, Game::HorseRacing, Game::Hurling
+];
+
+impl Game {
+    pub fn category(&self) -> GameCategory {
+        use self::Game::*;
+        use self::GameCategory::*;
+
+        // Don't add a wildcard here: new games must be classified explicitly.
+        match *self {
+            CounterStrike | CrossFire | Dota2 | GearsOfWar | Halo | Hearthstone | HeroesOfTheStorm |
+            LeagueOfLegends | Overwatch | Smite | StarCraftBW | StarCraft2 | Vainglory |
+            WorldOfTanks | Fifa => ESports,
+
+            Football | Basketball | IceHockey | Volleyball | Handball | Baseball | Futsal |
+            WaterPolo | Rugby | AmericanFootball | Bandy | FieldHockey | Curling | Netball |
+            Cricket | Floorball | GaelicFootball | Hurling => TeamSports,
+
+            Tennis | TableTennis | Badminton | Snooker | Pool | Chess | Boxing | Biathlon | Darts |
+            AlpineSkiing | SkiJumping | Skiing | Bowls | Poker | Golf | MartialArts => IndividualSports,
+
+            Motorsport | Formula | Motorbikes | BicycleRacing | HorseRacing => Racing
+        }
+    }
+}
+
+impl GameCategory {
+    pub fn games(&self) -> Vec<Game> {
+        GAMES.iter().cloned().filter(|game| game.category() == *self).collect()
+    }
+}
+
+// Used by config to refer either to a single game or to the whole category.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GameSelector {
+    Game(Game),
+    Category(GameCategory)
+}
+
+impl GameSelector {
+    pub fn matches(&self, game: Game) -> bool {
+        match *self {
+            GameSelector::Game(g) => g == game,
+            GameSelector::Category(category) => game.category() == category
+        }
+    }
+
+    pub fn games(&self) -> Vec<Game> {
+        match *self {
+            GameSelector::Game(game) => vec![game],
+            GameSelector::Category(category) => category.games()
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Kind {
     Series
@@ -96,3 +288,88 @@ impl Display for Offer {
         write!(f, ")")
     }
 }
+
+#[test]
+fn test_every_game_is_classified() {
+    // Catch variants that are missing in `GAMES` (discriminants are sequential).
+    assert_eq!(GAMES.len(), Game::Hurling as usize + 1);
+
+    for (idx, game) in GAMES.iter().enumerate() {
+        assert_eq!(*game as usize, idx);
+        game.category();
+    }
+}
+
+#[test]
+fn test_game_selector() {
+    let esports = GameSelector::Category(GameCategory::ESports);
+
+    assert!(esports.matches(Game::Dota2));
+    assert!(!esports.matches(Game::Football));
+    assert!(esports.games().contains(&Game::Fifa));
+    assert!(GameSelector::Game(Game::Darts).matches(Game::Darts));
+    assert_eq!(GameCategory::Racing.games().len(), 5);
+}
+
+#[test]
+fn test_coef_eq() {
+    assert!(coef_eq(1.3, 1.2999999));
+    assert!(coef_eq(2.05, 2.05));
+    assert!(!coef_eq(1.30, 1.31));
+    assert!(!coef_eq(1.300, 1.301));
+
+    assert_eq!(Outcome::new("A", "1.30".parse().unwrap()),
+               Outcome::new("A", 0.1 + 1.2));
+    assert_eq!(Outcome::new("A", 1.3).role(Role::Home),
+               Outcome::new("A", 1.3));
+    assert!(Outcome::new("A", 1.3) != Outcome::new("A", 1.3).lay_only());
+    assert!(Outcome::new("A", 1.3) != Outcome::new("B", 1.3));
+    assert!(Outcome::new("A", 1.3) != Outcome::new("A", 1.35));
+}
+
+#[test]
+fn test_odds_style() {
+    let mut outcomes = vec![Outcome::new("A", 1.5),
+                            Outcome::new("B", 0.)];
+
+    OddsStyle::ProfitMultiplier.normalize(&mut outcomes);
+    assert_eq!(outcomes, vec![Outcome::new("A", 2.5),
+                              Outcome::new("B", 0.)]);
+
+    OddsStyle::ProfitMultiplier.denormalize(&mut outcomes);
+    assert_eq!(outcomes[0], Outcome::new("A", 1.5));
+
+    assert_eq!(OddsStyle::Decimal.to_decimal(2.5), 2.5);
+    assert_eq!(OddsStyle::Decimal.from_decimal(2.5), 2.5);
+}
+
+#[test]
+fn test_odds_input_styles() {
+    let cases = [
+        (OddsStyle::Decimal, 2.5, 2.5),
+        // Fractional 3/2.
+        (OddsStyle::ProfitMultiplier, 1.5, 2.5),
+        (OddsStyle::Probability, 0.4, 2.5),
+        (OddsStyle::Probability, 0.8, 1.25),
+        (OddsStyle::American, 150., 2.5),
+        (OddsStyle::American, -200., 1.5),
+        (OddsStyle::American, 100., 2.)
+    ];
+
+    for &(style, quote, decimal) in &cases {
+        assert!((style.to_decimal(quote) - decimal).abs() < 1e-9, "{:?} {}", style, quote);
+        assert!((style.from_decimal(decimal) - quote).abs() < 1e-9, "{:?} {}", style, quote);
+    }
+
+    // Suspended or invalid quotes.
+    for &style in &[OddsStyle::Decimal, OddsStyle::ProfitMultiplier, OddsStyle::Probability,
+                    OddsStyle::American] {
+        assert_eq!(style.to_decimal(0.), 0.);
+    }
+
+    assert_eq!(OddsStyle::Probability.to_decimal(1.), 0.);
+    assert_eq!(OddsStyle::Probability.to_decimal(1.5), 0.);
+    assert_eq!(OddsStyle::Probability.to_decimal(-0.2), 0.);
+    assert_eq!(OddsStyle::American.to_decimal(50.), 0.);
+    assert_eq!(OddsStyle::American.to_decimal(-99.), 0.);
+}
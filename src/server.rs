@@ -1,21 +1,56 @@
 #![allow(unused_must_use)]
 
 use std::iter;
+use std::u32;
+use std::io::{self, Read, Write as IoWrite};
+use std::env;
+use std::fs::{self, File};
 use std::fmt::Write;
+use std::net::{IpAddr, SocketAddr};
 use std::time::{Duration, Instant};
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::atomic::{AtomicUsize, ATOMIC_USIZE_INIT};
+use std::sync::atomic::Ordering::Relaxed;
 use std::collections::{VecDeque, HashMap};
-use hyper::{Get, NotFound};
+use hyper::{Get, Post, NotFound, BadRequest};
+use hyper::method::Method;
+use hyper::status::StatusCode::{ServiceUnavailable, Forbidden};
 use hyper::server::{Server, Request, Response};
 use hyper::uri::RequestUri::AbsolutePath;
-use log::LogLevel;
+use hyper::header::ContentType;
+use log::{LogLevel, LogLevelFilter};
+use url::form_urlencoded;
 use time;
 
-use constants::{PORT, COMBO_COUNT};
-use base::error::Result;
+use constants::{BIND_ADDRESS, PORT, COMBO_COUNT, EVENT_SUBSCRIBERS, MARKETS_PER_GAME};
+use constants::ADMIN_TOKEN;
+use base::error::{Result, Error};
 use base::logger;
 use base::currency::Currency;
-use arbitrer::{self, Bookie, BookieStage, Table, MarkedOffer};
-use combo::{self, Combo};
+use base::profit::Profit;
+use arbitrer::{self, Bookie, BookieStage, Table, MarkedOffer, Event};
+use combo::{self, Combo, Stats, Summary};
+
+// Upper bounds (in seconds before the start) and labels of the offer age histogram.
+const AGE_BUCKETS: &[(u32, &str)] = &[
+    (0, "started"),
+    (60 * 60, "< 1h"),
+    (6 * 60 * 60, "1h-6h"),
+    (24 * 60 * 60, "6h-1d"),
+    (3 * 24 * 60 * 60, "1d-3d"),
+    (7 * 24 * 60 * 60, "3d-7d"),
+    (u32::MAX, "> 7d")
+];
+
+const HISTOGRAM_WIDTH: usize = 40;
+
+const MAX_CONFIG_SIZE: u64 = 4096;
+
+// Comments are sent to idle clients of live updates to detect gone ones.
+const EVENTS_KEEPALIVE: u64 = 15;
+
+// Backups requested concurrently must not share the temporary file.
+static BACKUP_SEQ: AtomicUsize = ATOMIC_USIZE_INIT;
 
 lazy_static! {
     static ref START_DATE: u32 = time::get_time().sec as u32;
@@ -24,20 +59,61 @@ lazy_static! {
 pub fn run() {
     *START_DATE;
 
-    let mut server = Server::http(("0.0.0.0", PORT)).unwrap();
+    let addr = match parse_address(BIND_ADDRESS, PORT) {
+        Ok(addr) => addr,
+        Err(error) => {
+            error!("The status page is disabled: {}", error);
+            return;
+        }
+    };
+
+    let mut server = match Server::http(addr) {
+        Ok(server) => server,
+        Err(error) => {
+            error!("Cannot bind the status page to {}: {}", addr, error);
+            return;
+        }
+    };
 
     server.keep_alive(None);
     server.set_read_timeout(Some(Duration::new(2, 0)));
     server.set_write_timeout(Some(Duration::new(5, 0)));
-    server.handle_threads(handle, 1).unwrap();
+    server.handle_threads(handle, 1 + EVENT_SUBSCRIBERS).unwrap();
+}
+
+fn parse_address(address: &str, port: u16) -> Result<SocketAddr> {
+    match address.parse::<IpAddr>() {
+        Ok(ip) => Ok(SocketAddr::new(ip, port)),
+        Err(_) => Err(Error::from(format!("Invalid bind address \"{}\", expected an IP address \
+                                            like \"0.0.0.0\" or \"127.0.0.1\"", address)))
+    }
 }
 
-fn handle(req: Request, res: Response) {
+fn handle(mut req: Request, res: Response) {
     debug!("{} {}", req.method, req.uri);
 
-    let result = match req.uri {
-        AbsolutePath(ref path) => match (&req.method, &path[..]) {
+    // The request is borrowed mutably to read the body of some routes.
+    let (method, uri) = (req.method.clone(), req.uri.clone());
+    let is_admin = is_authorized(&req);
+
+    let result = match uri {
+        AbsolutePath(ref path) if is_protected(&method, path) && !is_admin => send_403(res),
+        AbsolutePath(ref path) => match (&method, &path[..]) {
             (&Get, "/") => send_index(res),
+            (&Get, "/stats") => send_stats(res),
+            (&Get, "/metrics") => send_metrics(res),
+            (&Get, "/backup.db") => send_backup(res),
+            (&Get, "/events") => send_events(res),
+            (&Get, "/integrity") => send_integrity(res, false),
+            (&Get, path) if path.starts_with("/analyze?") => send_analysis(res, &path[9..]),
+            (&Post, "/integrity") => send_integrity(res, true),
+            (&Post, "/config") => send_config(&mut req, res),
+            (&Get, "/logging") => send_logging(&mut io::empty(), res),
+            (&Post, "/logging") => send_logging(&mut req, res),
+            (&Post, path) if path.starts_with("/restart/") => send_restart(res, &path[9..]),
+            (&Post, path) if path.starts_with("/market/") && path.ends_with("/refresh") => {
+                send_refresh(res, path.trim_left_matches("/market/").trim_right_matches("/refresh"))
+            },
             _ => send_404(res)
         },
         _ => send_404(res)
@@ -48,6 +124,41 @@ fn handle(req: Request, res: Response) {
     }
 }
 
+// Routes changing the state or exposing the database.
+fn is_protected(method: &Method, path: &str) -> bool {
+    match (method, path) {
+        (&Post, "/config") | (&Post, "/integrity") | (&Post, "/logging") => true,
+        (&Get, "/backup.db") => true,
+        (&Post, path) if path.starts_with("/market/") => true,
+        (&Post, path) if path.starts_with("/restart/") => true,
+        _ => false
+    }
+}
+
+fn is_authorized(req: &Request) -> bool {
+    let token = req.headers.get_raw("X-Admin-Token")
+        .and_then(|values| values.first())
+        .map(|value| &value[..]);
+
+    is_admin(req.remote_addr.ip(), token, ADMIN_TOKEN)
+}
+
+fn is_admin(ip: IpAddr, token: Option<&[u8]>, expected: Option<&str>) -> bool {
+    match expected {
+        Some(expected) => token.map_or(false, |token| constant_time_eq(token, expected.as_bytes())),
+        None => ip.is_loopback()
+    }
+}
+
+fn constant_time_eq(lhs: &[u8], rhs: &[u8]) -> bool {
+    lhs.len() == rhs.len() && lhs.iter().zip(rhs).fold(0, |acc, (l, r)| acc | (l ^ r)) == 0
+}
+
+fn send_403(mut res: Response) -> Result<()> {
+    *res.status_mut() = Forbidden;
+    res.send(b"Forbidden").map_err(From::from)
+}
+
 fn send_404(mut res: Response) -> Result<()> {
     *res.status_mut() = NotFound;
     Ok(())
@@ -65,10 +176,12 @@ fn send_index(res: Response) -> Result<()> {
     }
 
     render_bookies(&mut buffer, &arbitrer::BOOKIES);
+    render_balance_changes(&mut buffer, &arbitrer::BOOKIES);
 
     let combos = combo::load_recent(COMBO_COUNT);
     render_combos(&mut buffer, &combos);
 
+    render_age_histogram(&mut buffer, &arbitrer::TABLE);
     render_table(&mut buffer, &arbitrer::TABLE);
 
     render_footer(&mut buffer, now.elapsed());
@@ -76,12 +189,297 @@ fn send_index(res: Response) -> Result<()> {
     res.send(buffer.as_bytes()).map_err(From::from)
 }
 
+fn send_stats(res: Response) -> Result<()> {
+    let now = Instant::now();
+    let mut buffer = String::new();
+
+    render_header(&mut buffer);
+    render_stats(&mut buffer, &combo::aggregate_stats(0));
+    render_footer(&mut buffer, now.elapsed());
+
+    res.send(buffer.as_bytes()).map_err(From::from)
+}
+
+fn send_metrics(res: Response) -> Result<()> {
+    let mut buffer = String::new();
+
+    for bookie in arbitrer::BOOKIES.iter() {
+        writeln!(buffer, "offers_per_minute{{host=\"{}\"}} {}", bookie.host, bookie.offers_per_minute());
+        writeln!(buffer, "offer_count{{host=\"{}\"}} {}", bookie.host, bookie.offer_count());
+    }
+
+    res.send(buffer.as_bytes()).map_err(From::from)
+}
+
+// Lists legs referencing missing bets, `POST` marks their combos as damaged.
+fn send_integrity(res: Response, repair: bool) -> Result<()> {
+    let dangling = combo::verify_integrity(repair);
+    let mut buffer = String::new();
+
+    for leg in &dangling {
+        writeln!(buffer, "combo #{}, leg #{}: missing bet #{}", leg.combo, leg.idx, leg.bet);
+    }
+
+    if dangling.is_empty() {
+        buffer.push_str("No dangling legs\n");
+    } else if repair {
+        writeln!(buffer, "Marked combos with {} dangling legs as damaged", dangling.len());
+    }
+
+    res.send(buffer.as_bytes()).map_err(From::from)
+}
+
+fn send_restart(mut res: Response, host: &str) -> Result<()> {
+    if !arbitrer::restart_gambler(host) {
+        *res.status_mut() = BadRequest;
+        return res.send(b"No such bookie").map_err(From::from);
+    }
+
+    res.send(b"Restarting").map_err(From::from)
+}
+
+// Takes form-encoded thresholds, e.g. `min_profit=0.03&max_stake=4`.
+fn send_config(req: &mut Read, mut res: Response) -> Result<()> {
+    let mut body = Vec::new();
+    try!(req.take(MAX_CONFIG_SIZE).read_to_end(&mut body));
+
+    let pairs = form_urlencoded::parse(&body).into_owned().collect::<Vec<_>>();
+
+    match arbitrer::update_thresholds(&pairs) {
+        Ok(thresholds) => res.send(format!("{:?}", thresholds).as_bytes()).map_err(From::from),
+        Err(error) => {
+            *res.status_mut() = BadRequest;
+            res.send(format!("{}", error).as_bytes()).map_err(From::from)
+        }
+    }
+}
+
+// Takes form-encoded levels of targets, e.g. `betway=trace&vitalbet=default`, and lists them.
+fn send_logging(req: &mut Read, mut res: Response) -> Result<()> {
+    let mut body = Vec::new();
+    try!(req.take(MAX_CONFIG_SIZE).read_to_end(&mut body));
+
+    let mut levels = Vec::new();
+
+    // Either all the levels are applied or none of them.
+    for (target, level) in form_urlencoded::parse(&body).into_owned() {
+        let level = match &level[..] {
+            "default" => None,
+            level => match level.parse::<LogLevelFilter>() {
+                Ok(level) => Some(level),
+                Err(_) => {
+                    *res.status_mut() = BadRequest;
+                    return res.send(format!("Unknown level: {}", level).as_bytes())
+                        .map_err(From::from);
+                }
+            }
+        };
+
+        levels.push((target, level));
+    }
+
+    for (target, level) in levels {
+        info!("The level of {} is set to {}", target,
+              level.map_or("default".to_owned(), |level| format!("{}", level)));
+        logger::set_target_level(&target, level);
+    }
+
+    let mut buffer = String::new();
+
+    for (target, level) in logger::target_levels() {
+        writeln!(buffer, "{}={}", target, level);
+    }
+
+    res.send(buffer.as_bytes()).map_err(From::from)
+}
+
+// Takes `<host>/<oid>` of any offer of the market.
+fn send_refresh(mut res: Response, target: &str) -> Result<()> {
+    let market = match parse_offer_ref(target) {
+        Some((host, oid)) => arbitrer::refresh_market(host, oid),
+        None => None
+    };
+
+    let market = match market {
+        Some(market) => market,
+        None => {
+            *res.status_mut() = NotFound;
+            return res.send(b"No such market").map_err(From::from);
+        }
+    };
+
+    let mut buffer = String::new();
+
+    for &MarkedOffer(bookie, ref offer) in &market {
+        writeln!(buffer, "{} by {}", offer, bookie.host);
+    }
+
+    res.send(buffer.as_bytes()).map_err(From::from)
+}
+
+fn parse_offer_ref(target: &str) -> Option<(&str, u64)> {
+    let mut parts = target.splitn(2, '/');
+
+    match (parts.next(), parts.next().and_then(|oid| oid.parse().ok())) {
+        (Some(host), Some(oid)) if !host.is_empty() => Some((host, oid)),
+        _ => None
+    }
+}
+
+// Takes the query, e.g. `oid=42`.
+fn send_analysis(mut res: Response, query: &str) -> Result<()> {
+    let oid = form_urlencoded::parse(query.as_bytes())
+        .find(|&(ref key, _)| key == "oid")
+        .and_then(|(_, value)| value.parse().ok());
+
+    let analyses = oid.map(arbitrer::analyze_offer).unwrap_or_else(Vec::new);
+
+    if analyses.is_empty() {
+        *res.status_mut() = NotFound;
+        return res.send(b"No such offer").map_err(From::from);
+    }
+
+    let mut buffer = String::new();
+
+    for &(MarkedOffer(bookie, ref offer), ref analysis) in &analyses {
+        writeln!(buffer, "{} by {}", offer, bookie.host);
+
+        for &(ref title, ref tokens) in analysis {
+            writeln!(buffer, "    {:?}", title);
+
+            for token in tokens {
+                let abbr = if token.is_abbr { ", abbreviation" } else { "" };
+
+                writeln!(buffer, "        {:?} -> {:?} (length: {}{})",
+                         token.text, token.normalized, token.len, abbr);
+            }
+        }
+    }
+
+    res.send(buffer.as_bytes()).map_err(From::from)
+}
+
+fn send_backup(mut res: Response) -> Result<()> {
+    let seq = BACKUP_SEQ.fetch_add(1, Relaxed);
+    let name = format!("aladdin-backup-{}-{}.db", time::get_time().sec, seq);
+    let path = env::temp_dir().join(name);
+
+    try!(combo::backup(&path));
+
+    let result = File::open(&path).map_err(From::from).and_then(|mut file| {
+        res.headers_mut().set(ContentType(mime!(Application/OctetStream)));
+        res.headers_mut().set_raw("Content-Disposition",
+                                  vec![b"attachment; filename=\"aladdin.db\"".to_vec()]);
+
+        let mut res = try!(res.start());
+        try!(io::copy(&mut file, &mut res));
+        res.end().map_err(From::from)
+    });
+
+    if let Err(error) = fs::remove_file(&path) {
+        warn!("Cannot remove the backup {}: {}", path.display(), error);
+    }
+
+    result
+}
+
+fn send_events(mut res: Response) -> Result<()> {
+    let events = match arbitrer::EVENTS.subscribe() {
+        Some(events) => events,
+        None => {
+            *res.status_mut() = ServiceUnavailable;
+            return res.send(b"Too many subscribers").map_err(From::from);
+        }
+    };
+
+    res.headers_mut().set_raw("Content-Type", vec![b"text/event-stream".to_vec()]);
+    res.headers_mut().set_raw("Cache-Control", vec![b"no-cache".to_vec()]);
+
+    let mut res = try!(res.start());
+
+    // Writing to a gone client fails, which drops the subscription.
+    loop {
+        let frame = match events.recv_timeout(Duration::from_secs(EVENTS_KEEPALIVE)) {
+            Ok(event) => format_event(&event),
+            Err(RecvTimeoutError::Timeout) => ": keepalive\n\n".to_owned(),
+            Err(RecvTimeoutError::Disconnected) => break
+        };
+
+        try!(res.write_all(frame.as_bytes()));
+        try!(res.flush());
+    }
+
+    res.end().map_err(From::from)
+}
+
+fn format_event(event: &Event) -> String {
+    let (name, data) = match *event {
+        Event::Opportunity(ref text) => ("opportunity", text.clone()),
+        Event::Combo(ref text) => ("combo", text.clone()),
+        Event::Stage(ref host, stage) => ("stage", format!("{} {:?}", host, stage))
+    };
+
+    let mut frame = format!("event: {}\n", name);
+
+    for line in data.split('\n') {
+        frame.push_str("data: ");
+        frame.push_str(line);
+        frame.push('\n');
+    }
+
+    frame.push('\n');
+    frame
+}
+
 fn render_header(b: &mut String) {
     b.push_str(r#"
 <!DOCTYPE html>
 <meta charset="utf-8">
 <title>Aladdin</title>
 <script src="http://ndossougbe.github.io/strapdown/dist/strapdown.js" defer></script>
+<script>
+  // Events are applied in place: reloading would leave dead subscriptions on the server.
+  if (window.EventSource) {
+    var source = new EventSource('/events'), feed;
+
+    var icons = {Initial: '', Preparing: '⌚', Running: '✓', Aborted: '✗'};
+
+    source.addEventListener('stage', function(event) {
+      var parts = event.data.split(' '), cell = document.getElementById('stage-' + parts[0]);
+      if (!cell) return;
+
+      var sleeping = /^Sleeping\((\d+)\)$/.exec(parts[1]);
+
+      if (sleeping) {
+        var delay = Math.max(0, sleeping[1] - Date.now() / 1000) / 60 | 0;
+        cell.textContent = ('0' + (delay / 60 | 0)).slice(-2) + ':' + ('0' + delay % 60).slice(-2);
+      } else {
+        cell.textContent = icons[parts[1]] || parts[1];
+      }
+    });
+
+    ['opportunity', 'combo'].forEach(function(name) {
+      source.addEventListener(name, function(event) {
+        if (!feed) {
+          feed = document.createElement('ul');
+          feed.className = 'list-group';
+          feed.style.cssText = 'position:fixed;right:1em;bottom:1em;width:30em;z-index:100;';
+          document.body.appendChild(feed);
+        }
+
+        var item = document.createElement('li');
+        item.className = 'list-group-item';
+        item.style.whiteSpace = 'pre-wrap';
+        item.textContent = event.data;
+        feed.insertBefore(item, feed.firstChild);
+
+        while (feed.childNodes.length > 5) {
+          feed.removeChild(feed.lastChild);
+        }
+      });
+    });
+  }
+</script>
 <xmp style="display:none;" toc>
     "#);
 }
@@ -124,8 +522,8 @@ fn render_bookies(b: &mut String, bookies: &[Bookie]) {
     write!(b, "
 # Bookies
 
-| Host | Balance | Stage | Offers |
-| ---- | -------:|:-----:| ------:|
+| Host | Balance | Usable | Stage | Failures | Offers | Offers/min |
+| ---- | -------:| ------:|:-----:| --------:| ------:| ----------:|
     ");
 
     for bookie in bookies {
@@ -142,11 +540,47 @@ fn render_bookies(b: &mut String, bookies: &[Bookie]) {
             }
         };
 
-        writeln!(b, "|{host}|{balance}|{stage}|{offers}|",
+        // The span is updated in place by stage events.
+        let stage = format!(r#"<span id="stage-{}">{}</span>"#, bookie.host, stage);
+
+        writeln!(b, "|{host}|{balance}|{usable}|{stage}|{failures}|{offers}|{rate}|",
                  host = bookie.host,
                  balance = bookie.balance(),
+                 usable = bookie.usable_balance(),
                  stage = stage,
-                 offers = bookie.offer_count());
+                 failures = bookie.consecutive_failures(),
+                 offers = bookie.offer_count(),
+                 rate = bookie.offers_per_minute());
+    }
+}
+
+fn render_balance_changes(b: &mut String, bookies: &[Bookie]) {
+    if bookies.iter().all(|bookie| bookie.balance_changes().is_empty()) {
+        return;
+    }
+
+    writeln!(b, "# Balance changes");
+
+    for bookie in bookies {
+        let changes = bookie.balance_changes();
+
+        if changes.is_empty() {
+            continue;
+        }
+
+        writeln!(b, "## {}", bookie.host);
+        writeln!(b, "|Date|Reason|Old|New|");
+        writeln!(b, "|-|-|-:|-:|");
+
+        for change in changes.iter().rev() {
+            writeln!(b, "|`{date}`|{reason:?}|{old}|{new}|",
+                     date = format_date(change.date, "%d/%m %R"),
+                     reason = change.reason,
+                     old = change.old,
+                     new = change.new);
+        }
+
+        writeln!(b, "");
     }
 }
 
@@ -171,19 +605,86 @@ fn render_combos(b: &mut String, combos: &[Combo]) {
         writeln!(b, "|-|-|-:|:-:|-:|");
 
         for bet in &combo.bets {
-            writeln!(b, "|{title} `{coef:.2}`|{host}|{stake}|{placed}|{profit:+.1}%|",
+            writeln!(b, "|{title} `{coef:.2}`|{host}|{stake}|{placed}|{profit}|",
                      title = if let Some(ref s) = bet.title { s } else { "*draw*" },
                      coef = bet.coef,
                      host = bet.host,
                      stake = bet.stake,
                      placed = if bet.placed { ' ' } else { '✘' },
-                     profit = bet.profit * 100.);
+                     profit = Profit(bet.profit));
         }
 
         writeln!(b, "");
     }
 }
 
+fn render_stats(b: &mut String, stats: &Stats) {
+    writeln!(b, "# Statistics");
+
+    if stats.total.combos == 0 {
+        writeln!(b, "There are no combos yet.\n");
+        return;
+    }
+
+    writeln!(b, "|Game|Combos|Placed|Success|Staked|Profit|");
+    writeln!(b, "|-|-:|-:|-:|-:|-:|");
+
+    for &(ref game, ref summary) in &stats.by_game {
+        render_summary(b, game, summary);
+    }
+
+    render_summary(b, "**Total**", &stats.total);
+
+    writeln!(b, "");
+}
+
+fn render_summary(b: &mut String, label: &str, summary: &Summary) {
+    writeln!(b, "|{label}|{combos}|{placed}|{rate:.0}%|{staked}|{profit}|",
+             label = label,
+             combos = summary.combos,
+             placed = summary.placed,
+             rate = 100. * summary.success_rate().unwrap_or(0.),
+             staked = summary.staked,
+             profit = summary.profit);
+}
+
+fn render_age_histogram(b: &mut String, table: &Table) {
+    let dates = table.iter()
+        .flat_map(|market| market.iter().map(|m| m.1.date).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+
+    if dates.is_empty() {
+        return;
+    }
+
+    let counts = bucket_ages(&dates, time::get_time().sec as u32);
+    let max = *counts.iter().max().unwrap();
+
+    writeln!(b, "# Offer ages");
+    writeln!(b, "|Until start|Offers||");
+    writeln!(b, "|-|-:|-|");
+
+    for (&(_, label), &count) in AGE_BUCKETS.iter().zip(&counts) {
+        let width = (count * HISTOGRAM_WIDTH + max - 1) / max;
+        let bar = iter::repeat('█').take(width).collect::<String>();
+        writeln!(b, "|{}|{}|`{}`|", label, count, bar);
+    }
+
+    writeln!(b, "");
+}
+
+fn bucket_ages(dates: &[u32], now: u32) -> Vec<usize> {
+    let mut counts = vec![0; AGE_BUCKETS.len()];
+
+    for &date in dates {
+        let until = date.saturating_sub(now);
+        let index = AGE_BUCKETS.iter().position(|&(bound, _)| until <= bound).unwrap();
+        counts[index] += 1;
+    }
+
+    counts
+}
+
 fn render_table(b: &mut String, table: &Table) {
     let mut groups = HashMap::new();
 
@@ -199,12 +700,17 @@ fn render_table(b: &mut String, table: &Table) {
 
     writeln!(b, "# Markets");
 
+    let mut groups = groups.into_iter().collect::<Vec<_>>();
+    groups.sort_by_key(|&((game, _), _)| (game.category(), format!("{:?}", game)));
+
     for ((game, _kind), mut markets) in groups {
         //writeln!(b, "## {:?} [{:?}]", game, kind);  // TODO(loyd): enable after nested.
-        writeln!(b, "## {:?}", game);
+        writeln!(b, "## {:?} / {:?}", game.category(), game);
 
         markets.sort_by_key(|market| market[0].1.date);
 
+        let hidden = truncate(&mut markets, MARKETS_PER_GAME);
+
         for market in markets {
             let outcome_count = market[0].1.outcomes.len();
 
@@ -228,9 +734,20 @@ fn render_table(b: &mut String, table: &Table) {
 
             writeln!(b, "");
         }
+
+        if hidden > 0 {
+            writeln!(b, "{} more…\n", hidden);
+        }
     }
 }
 
+// Returns the number of dropped items.
+fn truncate<T>(items: &mut Vec<T>, cap: usize) -> usize {
+    let hidden = items.len().saturating_sub(cap);
+    items.truncate(cap);
+    hidden
+}
+
 fn render_footer(b: &mut String, spent: Duration) {
     let ms = spent.as_secs() as u32 * 1_000 + spent.subsec_nanos() / 1_000_000;
     writeln!(b, "---");
@@ -243,3 +760,77 @@ fn format_date(date: u32, format: &str) -> String {
     let tm = time::at_utc(time::Timespec::new(date as i64, 0)).to_local();
     time::strftime(format, &tm).unwrap()
 }
+
+#[test]
+fn test_bucket_ages() {
+    let now = 1_000_000;
+    let hour = 60 * 60;
+    let dates = [now - hour, now, now + 1, now + hour, now + 2 * hour, now + 30 * hour,
+                 now + 100 * hour, now + 1000 * hour];
+
+    assert_eq!(bucket_ages(&dates, now), vec![2, 2, 1, 0, 1, 1, 1]);
+}
+
+#[test]
+fn test_truncate() {
+    let mut markets = vec![10, 20, 30, 40, 50];
+
+    assert_eq!(truncate(&mut markets, 8), 0);
+    assert_eq!(markets.len(), 5);
+
+    assert_eq!(truncate(&mut markets, 3), 2);
+    assert_eq!(markets, vec![10, 20, 30]);
+}
+
+#[test]
+fn test_parse_address() {
+    assert_eq!(parse_address("127.0.0.1", 3042).unwrap(), "127.0.0.1:3042".parse().unwrap());
+    assert_eq!(parse_address("::1", 80).unwrap(), "[::1]:80".parse().unwrap());
+
+    let error = parse_address("localhost:3042", 3042).unwrap_err();
+    assert!(error.to_string().contains("Invalid bind address \"localhost:3042\""));
+}
+
+#[test]
+fn test_is_admin() {
+    let local = "127.0.0.1".parse().unwrap();
+    let remote = "10.0.0.1".parse().unwrap();
+
+    assert!(is_admin(local, None, None));
+    assert!(!is_admin(remote, None, None));
+    assert!(!is_admin(remote, Some(b"secret"), None));
+
+    // Once configured, the token is required even locally.
+    assert!(is_admin(remote, Some(b"secret"), Some("secret")));
+    assert!(!is_admin(local, None, Some("secret")));
+    assert!(!is_admin(local, Some(b"secret!"), Some("secret")));
+    assert!(!is_admin(remote, Some(b""), Some("secret")));
+
+    assert!(is_protected(&Post, "/config"));
+    assert!(is_protected(&Post, "/integrity"));
+    assert!(!is_protected(&Get, "/integrity"));
+    assert!(is_protected(&Post, "/market/cybbet/42/refresh"));
+    assert!(is_protected(&Post, "/restart/cybbet"));
+    assert!(is_protected(&Post, "/logging"));
+    assert!(!is_protected(&Get, "/logging"));
+    assert!(is_protected(&Get, "/backup.db"));
+    assert!(!is_protected(&Get, "/"));
+}
+
+#[test]
+fn test_parse_offer_ref() {
+    assert_eq!(parse_offer_ref("cybbet/42"), Some(("cybbet", 42)));
+    assert_eq!(parse_offer_ref("42"), None);
+    assert_eq!(parse_offer_ref("/42"), None);
+    assert_eq!(parse_offer_ref("cybbet/x"), None);
+    assert_eq!(parse_offer_ref("cybbet/42/1"), None);
+}
+
+#[test]
+fn test_format_event() {
+    let event = Event::Stage("cybbet".to_owned(), BookieStage::Running);
+    assert_eq!(format_event(&event), "event: stage\ndata: cybbet Running\n\n");
+
+    let event = Event::Combo("first\nsecond".to_owned());
+    assert_eq!(format_event(&event), "event: combo\ndata: first\ndata: second\n\n");
+}